@@ -0,0 +1,47 @@
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use actix_web::{
+    web::{Data, Query},
+    HttpResponse,
+};
+use log::{error, info};
+use msg_store_server_api::changes::get::handle;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Info {
+    from: Option<u64>,
+    limit: Option<usize>
+}
+
+const ROUTE: &str = "GET /api/changes";
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    let from_seq = info.from.unwrap_or(0);
+    info!("{} from: {}", ROUTE, from_seq);
+    match handle(&data.changes, from_seq, info.limit).await {
+        Ok(batch) => {
+            info!("{} 200 {} changes", ROUTE, batch.changes.len());
+            HttpResponse::Ok().json(batch)
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    match handle(&data.changes, info.from.unwrap_or(0), info.limit).await {
+        Ok(batch) => Reply::Ok(json!(batch)),
+        Err(err) => {
+            error!("WS {} {}", command::CHANGES_GET, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::AppData;
+use crate::api::ws::{command, Reply};
+use actix_web::{web::Data, HttpResponse};
+use log::{error, info};
+use msg_store_server_api::cluster::get::handle;
+use serde_json::json;
+use std::process::exit;
+
+/// The reason given when the server is not a member of a cluster
+pub const NOT_CLUSTERED: &str = "The server is not a member of a cluster";
+
+const ROUTE: &str = "GET /api/cluster";
+pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
+    info!("{}", ROUTE);
+    let cluster = match &data.cluster {
+        Some(cluster) => cluster,
+        None => {
+            info!("{} 404 {}", ROUTE, NOT_CLUSTERED);
+            return HttpResponse::NotFound().body(NOT_CLUSTERED)
+        }
+    };
+    match handle(cluster).await {
+        Ok(stats) => {
+            info!("{} 200 role: {}, term: {}", ROUTE, stats.role, stats.term);
+            HttpResponse::Ok().json(stats)
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>) -> Reply {
+    let cluster = match &data.cluster {
+        Some(cluster) => cluster,
+        None => return Reply::NotFound(NOT_CLUSTERED.to_string())
+    };
+    match handle(cluster).await {
+        Ok(stats) => Reply::Ok(json!(stats)),
+        Err(err) => {
+            error!("WS {} {}", command::CLUSTER_GET, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::AppData;
+use crate::api::cluster::get::NOT_CLUSTERED;
+use crate::cluster;
+use actix_web::web::{Data, Json};
+use actix_web::HttpResponse;
+use log::error;
+use msg_store_server_api::cluster::Command;
+use msg_store_server_api::cluster::raft::Envelope;
+use std::process::exit;
+
+/// The largest message taken from a peer, a message holds up to raft::MAX_ENTRIES msgs
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Steps the raft node with a message from a peer, replies are sent with the next tick
+///
+/// Not logged, the leader sends a message to every peer with each heartbeat.
+pub async fn http_handle(data: Data<AppData>, envelope: Json<Envelope<Command>>) -> HttpResponse {
+    match cluster::lock(&data) {
+        Some(mut cluster) => {
+            if let Err(err) = cluster.raft.step(envelope.into_inner()) {
+                error!("CLUSTER_ERROR: Could not step the raft node. {}", err);
+                exit(1);
+            }
+            HttpResponse::Ok().finish()
+        },
+        None => HttpResponse::NotFound().body(NOT_CLUSTERED)
+    }
+}
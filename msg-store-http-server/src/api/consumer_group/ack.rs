@@ -0,0 +1,63 @@
+use actix_web::HttpResponse;
+use actix_web::web::{Data, Json};
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use log::{error, info};
+use msg_store_server_api::consumer_group::ack::{handle, ErrTy};
+use msg_store_uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Info {
+    name: String,
+    uuid: String,
+}
+
+const ROUTE: &str = "POST /api/consumer-group/ack";
+pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse {
+    info!("{} name: {}, uuid: {}", ROUTE, info.name, info.uuid);
+    let uuid = match Uuid::from_string(&info.uuid) {
+        Ok(uuid) => uuid,
+        Err(_error) => {
+            info!("{} 400 {}", ROUTE, "InvalidUUID");
+            return HttpResponse::BadRequest().body("InvalidUUID")
+        }
+    };
+    match handle(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, &info.name, uuid).await {
+        Ok(()) => {
+            info!("{} 200", ROUTE);
+            HttpResponse::Ok().finish()
+        },
+        Err(err) => {
+            if let ErrTy::ConsumerGroupNotFound = err.err_ty {
+                info!("{} 404 {}", ROUTE, "ConsumerGroupNotFound");
+                return HttpResponse::NotFound().body("ConsumerGroupNotFound");
+            }
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let uuid = match Uuid::from_string(&info.uuid) {
+        Ok(uuid) => uuid,
+        Err(_error) => return Reply::BadRequest("InvalidUUID".to_string())
+    };
+    match handle(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, &info.name, uuid).await {
+        Ok(()) => Reply::Ok(Value::Null),
+        Err(err) => {
+            if let ErrTy::ConsumerGroupNotFound = err.err_ty {
+                return Reply::NotFound("ConsumerGroupNotFound".to_string());
+            }
+            error!("WS {} {}", command::CONSUMER_GROUP_ACK, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use actix_web::web::{Data, Query};
+use actix_web::HttpResponse;
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use log::{error, info};
+use msg_store_server_api::consumer_group::rm::handle;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Info {
+    name: String,
+}
+
+const ROUTE: &str = "DEL /api/consumer-group";
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    info!("{} name: {}", ROUTE, info.name);
+    match handle(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, &info.name).await {
+        Ok(()) => {
+            info!("{} 200", ROUTE);
+            HttpResponse::Ok().finish()
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    match handle(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, &info.name).await {
+        Ok(()) => Reply::Ok(Value::Null),
+        Err(err) => {
+            error!("WS {} {}", command::CONSUMER_GROUP_DELETE, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use actix_web::{
+    web::{Data, Query},
+    HttpResponse,
+};
+use log::{error, info};
+use msg_store_server_api::consumer_group::get::handle;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Info {
+    name: Option<String>,
+}
+
+const ROUTE: &str = "GET /api/consumer-group";
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    {
+        let name_string = match &info.name {
+            Some(name) => name.as_str(),
+            None => "N/A"
+        };
+        info!("{} name: {}", ROUTE, name_string);
+    }
+    match handle(&data.store, info.name.as_deref()).await {
+        Ok(consumer_groups) => {
+            info!("{} 200", ROUTE);
+            HttpResponse::Ok().json(consumer_groups)
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    match handle(&data.store, info.name.as_deref()).await {
+        Ok(consumer_groups) => Reply::Ok(json!(consumer_groups)),
+        Err(err) => {
+            error!("WS {} {}", command::CONSUMER_GROUP_GET, err);
+            exit(1);
+        }
+    }
+}
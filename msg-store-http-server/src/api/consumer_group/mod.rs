@@ -0,0 +1,5 @@
+pub mod ack;
+pub mod delete;
+pub mod get;
+pub mod msg;
+pub mod post;
@@ -0,0 +1,53 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::ACCEPT;
+use actix_web::web::{Data, Query};
+use crate::AppData;
+use crate::api::msg::get::{http_response, ws_reply};
+use crate::api::ws::{command, from_data, Reply};
+use log::{error, info};
+use msg_store_server_api::consumer_group::next::{handle, ErrTy};
+use msg_store_server_api::msg::frame::WireFormat;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Info {
+    name: String,
+    priority: Option<u16>,
+}
+
+const ROUTE: &str = "GET /api/consumer-group/msg";
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    info!("{} name: {}", ROUTE, info.name);
+    let accept = req.headers().get(ACCEPT).and_then(|value| value.to_str().ok());
+    let format = WireFormat::from_content_type(accept);
+    match handle(&data.store, &data.db, &data.file_storage, &info.name, info.priority, format).await {
+        Ok(msg_option) => http_response(ROUTE, format, &data.corruption, msg_option),
+        Err(err) => {
+            if let ErrTy::ConsumerGroupNotFound = err.err_ty {
+                info!("{} 404 {}", ROUTE, "ConsumerGroupNotFound");
+                return HttpResponse::NotFound().body("ConsumerGroupNotFound");
+            }
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    match handle(&data.store, &data.db, &data.file_storage, &info.name, info.priority, WireFormat::Framed).await {
+        Ok(msg_option) => ws_reply(command::CONSUMER_GROUP_MSG_GET, msg_option),
+        Err(err) => {
+            if let ErrTy::ConsumerGroupNotFound = err.err_ty {
+                return Reply::NotFound("ConsumerGroupNotFound".to_string());
+            }
+            error!("WS {} {}", command::CONSUMER_GROUP_MSG_GET, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use actix_web::HttpResponse;
+use actix_web::web::{Data, Json};
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use msg_store_server_api::consumer_group::add::{handle, ErrTy};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Info {
+    name: String,
+}
+
+const ROUTE: &str = "POST /api/consumer-group";
+pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse {
+    info!("{} name: {}", ROUTE, info.name);
+    if info.name.is_empty() {
+        info!("{} 400 {}", ROUTE, "MissingName");
+        return HttpResponse::BadRequest().body("MissingName");
+    }
+    match handle(&data.store, &data.db, &info.name).await {
+        Ok(()) => {
+            info!("{} 200", ROUTE);
+            HttpResponse::Ok().finish()
+        },
+        Err(err) => {
+            if let ErrTy::ConsumerGroupExists = err.err_ty {
+                info!("{} 409 {}", ROUTE, "ConsumerGroupExists");
+                return HttpResponse::Conflict().body("ConsumerGroupExists");
+            }
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    if info.name.is_empty() {
+        return Reply::BadRequest("MissingName".to_string());
+    }
+    match handle(&data.store, &data.db, &info.name).await {
+        Ok(()) => Reply::Ok(Value::Null),
+        Err(err) => {
+            if let ErrTy::ConsumerGroupExists = err.err_ty {
+                return Reply::Conflict("ConsumerGroupExists".to_string());
+            }
+            error!("WS {} {}", command::CONSUMER_GROUP_POST, err);
+            exit(1);
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
 use actix_web::HttpResponse;
 use actix_web::web::{Data, Query};
 use log::{error, info};
 use msg_store_server_api::export::handle;
 // use msg_store::api::error_codes::{self, log_err};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::process::exit;
@@ -39,6 +40,7 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
         &data.db, 
         &data.file_storage, 
         &data.stats, 
+        &data.changes,
         // &data.configuration, 
         &output_path).await;
     if let Err(error) = result {
@@ -48,3 +50,26 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     }
     return HttpResponse::Ok().finish()
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let output_path = match PathBuf::from_str(&info.output_directory) {
+        Ok(output_path) => output_path,
+        Err(_error) => return Reply::BadRequest("Invalid Path".to_string())
+    };
+    let result = handle(
+        &data.store, 
+        &data.db, 
+        &data.file_storage, 
+        &data.stats, 
+        &data.changes,
+        &output_path).await;
+    if let Err(error) = result {
+        error!("WS {} {}", command::EXPORT, error);
+        exit(1);
+    }
+    Reply::Ok(Value::Null)
+}
@@ -0,0 +1,32 @@
+use crate::AppData;
+use crate::api::ws::{command, Reply};
+use actix_web::{web::Data, HttpResponse};
+use log::{error, info};
+use msg_store_server_api::forward::get::handle;
+use serde_json::json;
+use std::process::exit;
+
+const ROUTE: &str = "GET /api/forward";
+pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
+    info!("{}", ROUTE);
+    match handle(&data.store, &data.forwarder).await {
+        Ok(stats) => {
+            info!("{} 200 backlog: {}", ROUTE, stats.backlog_count);
+            HttpResponse::Ok().json(stats)
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>) -> Reply {
+    match handle(&data.store, &data.forwarder).await {
+        Ok(stats) => Reply::Ok(json!(stats)),
+        Err(err) => {
+            error!("WS {} {}", command::FORWARD_GET, err);
+            exit(1);
+        }
+    }
+}
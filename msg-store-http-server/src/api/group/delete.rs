@@ -3,11 +3,13 @@ use actix_web::{
     HttpResponse,
 };
 use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
 use log::{error, info};
 use msg_store_server_api::{
     group::rm::handle
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -22,7 +24,9 @@ pub async fn handle_http(data: Data<AppData>, info: Query<Info>) -> HttpResponse
         &data.store, 
         &data.db, 
         &data.file_storage, 
-        &data.stats, info.priority).await {
+        &data.stats,
+        &data.changes,
+        info.priority).await {
             error!("{} {}", ROUTE, err);
             exit(1);
     }
@@ -30,15 +34,20 @@ pub async fn handle_http(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     HttpResponse::Ok().finish()
 }
 
-// pub fn handle_ws(ctx: &mut WebsocketContext<Websocket>, data: Data<AppData>, info: Value) {
-//     http_route_hit_log(GROUP_DELETE, Some(info.clone()));
-//     let mut reply = ws_reply_with(ctx, GROUP_DELETE);
-//     let priority = match from_value_prop_required(&info, "priority", "number") {
-//         Ok(priority) => priority,
-//         Err(message) => {
-//             reply(Reply::BadRequest(message));
-//             return;
-//         }
-//     };
-//     reply(handle(data, Info { priority }));
-// }
+pub async fn handle_ws(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    if let Err(err) = handle(
+        &data.store, 
+        &data.db, 
+        &data.file_storage, 
+        &data.stats,
+        &data.changes,
+        info.priority).await {
+            error!("WS {} {}", command::GROUP_DELETE, err);
+            exit(1);
+    }
+    Reply::Ok(Value::Null)
+}
@@ -1,4 +1,5 @@
 use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
 use actix_web::{
     web::{Data, Query},
     HttpResponse,
@@ -6,6 +7,7 @@ use actix_web::{
 use log::{error, info};
 use msg_store_server_api::group::get;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -41,15 +43,20 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     }
 }
 
-// pub fn ws_handle(ctx: &mut WebsocketContext<Websocket>, data: Data<AppData>, info: Value) {
-//     http_route_hit_log(GROUP_GET, Some(info.clone()));
-//     let mut reply = ws_reply_with(ctx, GROUP_GET);
-//     let info = match validate_params(info) {
-//         Ok(info) => info,
-//         Err(message) => {
-//             reply(Reply::BadRequest(message));
-//             return;
-//         }
-//     };
-//     reply(handle(data, info));
-// }
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let include_msg_data = match info.include_msg_data {
+        Some(include_msg_data) => include_msg_data,
+        None => false
+    };
+    match get::handle(&data.store, info.priority, include_msg_data).await {
+        Ok(groups) => Reply::Ok(json!(groups)),
+        Err(err) => {
+            error!("WS {} {}", command::GROUP_GET, err);
+            exit(1);
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web::web::{Data, Query};
 use crate::AppData;
+use crate::cluster;
+use crate::api::ws::{command, from_data, Reply};
+use msg_store_server_api::cluster::Command;
 use msg_store_server_api::group_defaults::rm::handle;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -12,10 +16,16 @@ pub struct Info {
 }
 
 const ROUTE: &'static str = "DEL /api/group-defaults";
-pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Query<Info>) -> HttpResponse {
     info!("{} priority: {}", ROUTE, info.priority);
+    if data.cluster.is_some() {
+        let command = Command::GroupDefaults { priority: info.priority, max_byte_size: None };
+        let proposal = cluster::propose(&data, command).await;
+        return cluster::respond(ROUTE, &req, proposal, HttpResponse::Ok().finish());
+    }
     let result = handle(
         &data.store, 
+        &data.changes,
         &data.configuration, 
         &data.configuration_path, 
         info.priority).await;
@@ -26,3 +36,21 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     info!("{} 200", ROUTE);
     HttpResponse::Ok().finish()
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let result = handle(
+        &data.store, 
+        &data.changes,
+        &data.configuration, 
+        &data.configuration_path, 
+        info.priority).await;
+    if let Err(err) = result {
+        error!("WS {} {}", command::GROUP_DEFAULTS_DELETE, err);
+        exit(1);
+    }
+    Reply::Ok(Value::Null)
+}
@@ -1,9 +1,11 @@
 use actix_web::HttpResponse;
 use actix_web::web::{Data, Query};
 use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
 use msg_store_server_api::group_defaults::get::handle;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -25,3 +27,17 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
         }
     }
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    match handle(&data.store, info.priority).await {
+        Ok(groups) => Reply::Ok(json!(groups)),
+        Err(err) => {
+            error!("WS {} {}", command::GROUP_DEFAULTS_GET, err);
+            exit(1)
+        }
+    }
+}
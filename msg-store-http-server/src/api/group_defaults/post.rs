@@ -1,9 +1,13 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web::web::{Data, Json};
 use crate::AppData;
+use crate::cluster;
+use crate::api::ws::{command, from_data, Reply};
+use msg_store_server_api::cluster::Command;
 use msg_store_server_api::group_defaults::set::handle;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -14,7 +18,7 @@ pub struct Info {
 }
 
 const ROUTE: &'static str = "POST /api/group-defaults";
-pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Json<Info>) -> HttpResponse {
     {
         let max_byte_size_string = if let Some(max_byte_size) = info.max_byte_size {
             max_byte_size.to_string()
@@ -23,11 +27,17 @@ pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse
         };
         info!("{} priority: {}, max byte size: {}", ROUTE, info.priority, max_byte_size_string);
     }
+    if data.cluster.is_some() {
+        let command = Command::GroupDefaults { priority: info.priority, max_byte_size: info.max_byte_size };
+        let proposal = cluster::propose(&data, command).await;
+        return cluster::respond(ROUTE, &req, proposal, HttpResponse::Ok().finish());
+    }
     let result = handle(
         &data.store, 
         &data.db, 
         &data.file_storage, 
         &data.stats, 
+        &data.changes,
         &data.configuration, 
         &data.configuration_path, 
         info.priority, 
@@ -39,3 +49,25 @@ pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse
     info!("{} 200", ROUTE);
     HttpResponse::Ok().finish()
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let result = handle(
+        &data.store, 
+        &data.db, 
+        &data.file_storage, 
+        &data.stats, 
+        &data.changes,
+        &data.configuration, 
+        &data.configuration_path, 
+        info.priority, 
+        info.max_byte_size).await;
+    if let Err(err) = result {
+        error!("WS {} {}", command::GROUP_DEFAULTS_POST, err);
+        exit(1);
+    }
+    Reply::Ok(Value::Null)
+}
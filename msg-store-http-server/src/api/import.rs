@@ -0,0 +1,95 @@
+use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
+use actix_web::HttpResponse;
+use actix_web::web::{Data, Query};
+use log::{error, info};
+use msg_store_server_api::import::{handle, ErrTy};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Info {
+    input_directory: String
+}
+
+impl Display for Info {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", json!(self))
+    }
+}
+
+const ROUTE: &str = "POST /api/import";
+
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    info!("{} {}", ROUTE, info);
+    let input_path = match PathBuf::from_str(&info.input_directory) {
+        Ok(input_path) => input_path,
+        Err(error) => {
+            info!("{} 400 {}: {}", ROUTE, "Invalid Path", error.to_string());
+            return HttpResponse::BadRequest().body("Invalid Path")
+        }
+    };
+    let result = handle(
+        &data.store,
+        &data.db,
+        &data.file_storage,
+        &data.stats,
+        &data.notifier,
+        &data.changes,
+        &input_path).await;
+    match result {
+        Ok(import_result) => {
+            info!("{} 200 inserted: {}, duplicates: {}", ROUTE, import_result.inserted_count, import_result.duplicate_count);
+            HttpResponse::Ok().json(import_result)
+        },
+        Err(error) => match error.err_ty {
+            ErrTy::ExportNotFound => {
+                info!("{} 404 {}", ROUTE, error);
+                HttpResponse::NotFound().body("ExportNotFound")
+            },
+            ErrTy::FileStorageNotConfigured => {
+                info!("{} 403 {}", ROUTE, error);
+                HttpResponse::Forbidden().body("FileStorageNotConfigured")
+            },
+            _ => {
+                error!("{} {}", ROUTE, error);
+                exit(1);
+            }
+        }
+    }
+}
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let input_path = match PathBuf::from_str(&info.input_directory) {
+        Ok(input_path) => input_path,
+        Err(_error) => return Reply::BadRequest("Invalid Path".to_string())
+    };
+    let result = handle(
+        &data.store,
+        &data.db,
+        &data.file_storage,
+        &data.stats,
+        &data.notifier,
+        &data.changes,
+        &input_path).await;
+    match result {
+        Ok(import_result) => Reply::Ok(json!(import_result)),
+        Err(error) => match error.err_ty {
+            ErrTy::ExportNotFound => Reply::NotFound("ExportNotFound".to_string()),
+            ErrTy::FileStorageNotConfigured => Reply::Forbidden("FileStorageNotConfigured".to_string()),
+            _ => {
+                error!("WS {} {}", command::IMPORT, error);
+                exit(1);
+            }
+        }
+    }
+}
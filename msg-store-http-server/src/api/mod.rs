@@ -1,6 +1,13 @@
+pub mod changes;
+pub mod cluster;
+pub mod consumer_group;
 pub mod export;
+pub mod forward;
 pub mod group;
 pub mod group_defaults;
+pub mod import;
 pub mod msg;
+pub mod replication;
 pub mod stats;
 pub mod store;
+pub mod ws;
@@ -1,10 +1,14 @@
 use actix_web::web::{Data, Query};
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use crate::AppData;
+use crate::cluster;
+use crate::api::ws::{command, from_data, Reply};
 use log::{error, info};
+use msg_store_server_api::cluster::Command;
 use msg_store_server_api::msg::rm::handle;
 use msg_store_uuid::Uuid;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::process::exit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -13,7 +17,7 @@ pub struct Info {
 }
 
 const ROUTE: &'static str = "DEL /api/msg";
-pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Query<Info>) -> HttpResponse {
     info!("{}", ROUTE);
     let uuid = match Uuid::from_string(&info.uuid) {
         Ok(uuid) => uuid,
@@ -22,7 +26,11 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
             return HttpResponse::BadRequest().body("InvalidUUID")
         }
     };
-    match handle(&data.store,&data.db,&data.file_storage, &data.stats, uuid).await {
+    if data.cluster.is_some() {
+        let proposal = cluster::propose(&data, Command::Delete { uuid }).await;
+        return cluster::respond(ROUTE, &req, proposal, HttpResponse::Ok().finish());
+    }
+    match handle(&data.store,&data.db,&data.file_storage, &data.stats, &data.changes, uuid).await {
         Ok(_) => {
             info!("{} 200", ROUTE);
             return HttpResponse::Ok().finish()
@@ -34,14 +42,20 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     }
 }
 
-// pub fn ws_handle(ctx: &mut ws::WebsocketContext<Websocket>, data: Data<AppData>, info: Value) {
-//     http_route_hit_log(MSG_DELETE, Some(info.clone()));
-//     let mut reply = ws_reply_with(ctx, MSG_DELETE);
-//     let uuid = match get_required_uuid(&info) {
-//         Ok(uuid) => uuid,
-//         Err(message) => {
-//             return reply(Reply::BadRequest(format!("/data/{}", message)));
-//         }
-//     };
-//     reply(handle(data, uuid));
-// }
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let uuid = match Uuid::from_string(&info.uuid) {
+        Ok(uuid) => uuid,
+        Err(_error) => return Reply::BadRequest("InvalidUUID".to_string())
+    };
+    match handle(&data.store,&data.db,&data.file_storage, &data.stats, &data.changes, uuid).await {
+        Ok(_) => Reply::Ok(Value::Null),
+        Err(err) => {
+            error!("WS {} {}", command::MSG_DELETE, err);
+            exit(1);
+        }
+    }
+}
@@ -1,15 +1,22 @@
-use actix_web::{ HttpResponse, Error };
+use actix_web::{ HttpRequest, HttpResponse, Error };
+use actix_web::http::header::{ACCEPT, WARNING};
 use actix_web::web::{ Data, Query, Bytes };
 use crate::AppData;
+use crate::api::ws::{command, from_data, MsgBody, Reply};
 use futures::stream::{Stream, StreamExt};
 use futures::task::{Context, Poll};
-use log::{error, info};
-use msg_store_server_api::msg::get::{handle, ReturnBody as ApiReturn};
+use log::{error, info, warn};
+use msg_store_database_checksum_plugin::Corruption;
+use msg_store_server_api::msg::frame::{self, CONTENT_TYPE, WireFormat};
+use msg_store_server_api::msg::get::{handle, handle_wait, GetError, ReturnBody as ApiReturn, StoredMsg};
 use msg_store_server_api::Either;
 use msg_store_uuid::Uuid;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::pin::Pin;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MsgData {
@@ -22,6 +29,34 @@ pub struct Info {
     uuid: Option<String>,
     priority: Option<u16>,
     reverse: Option<bool>,
+    /// Seconds to wait for a msg to be added when there is none
+    wait: Option<u64>,
+}
+
+/// Gets a msg, waiting for one to be added if a wait was requested and none is available
+async fn get_msg(data: &AppData, uuid: Option<Arc<Uuid>>, priority: Option<u16>, reverse: bool, wait: Option<u64>, format: WireFormat) -> Result<Option<Either<ApiReturn, StoredMsg>>, GetError> {
+    match (uuid, wait) {
+        (None, Some(wait)) if wait > 0 => {
+            let timeout = actix::clock::delay_for(Duration::from_secs(wait));
+            handle_wait(
+                &data.store,
+                &data.db,
+                &data.file_storage,
+                &data.notifier,
+                priority,
+                reverse,
+                format,
+                timeout).await
+        },
+        (uuid, _) => handle(
+            &data.store, 
+            &data.db, 
+            &data.file_storage, 
+            uuid, 
+            priority, 
+            reverse,
+            format).await
+    }
 }
 
 pub struct ReturnBody {
@@ -58,7 +93,7 @@ impl Stream for ReturnBody {
 }
 
 const ROUTE: &'static str = "GET /api/msg";
-pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Query<Info>) -> HttpResponse {
     info!("{}", ROUTE);
     let uuid = if let Some(uuid_string) = &info.uuid {
         match Uuid::from_string(&uuid_string) {
@@ -77,33 +112,112 @@ pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse
     } else {
         false
     };
-    let msg_option = match handle(
-        &data.store, 
-        &data.db, 
-        &data.file_storage, 
-        uuid, 
-        priority, 
-        reverse).await {
+    let accept = req.headers().get(ACCEPT).and_then(|value| value.to_str().ok());
+    let format = WireFormat::from_content_type(accept);
+    let msg_option = match get_msg(&data, uuid, priority, reverse, info.wait, format).await {
         Ok(message_option) => message_option,
         Err(err) => {
             error!("{} {}", ROUTE, err);
             exit(1);
         }
     };
+    http_response(ROUTE, format, &data.corruption, msg_option)
+}
+
+/// The warning sent along with a msg that is served even though it is corrupt
+const CORRUPT_MSG_WARNING: &str = "199 - \"The msg does not match its checksum\"";
+
+/// Responds with the msg in the requested format, or with an empty body if there is no msg
+///
+/// A msg that was found to be corrupt is only served if the corruption policy is to serve
+/// it, the response then carries a warning header.
+pub fn http_response(route: &str, format: WireFormat, corruption: &Corruption, msg_option: Option<Either<ApiReturn, StoredMsg>>) -> HttpResponse {
+    let content_type = match format {
+        WireFormat::Framed => CONTENT_TYPE,
+        WireFormat::QueryString => "text/plain"
+    };
     let msg_type = match msg_option {
         Some(msg_type) => msg_type,
         None => {
-            info!("{} 200 No Message", ROUTE);
+            info!("{} 200 No Message", route);
             return HttpResponse::Ok().finish()
         }
     };
     let buffer = match msg_type {
         Either::A(buffer) => buffer,
         Either::B(msg) => {
-            info!("{} 200 {}", ROUTE, msg);
-            return HttpResponse::Ok().body(msg)
+            info!("{} 200 uuid={}", route, msg.uuid.to_string());
+            let mut response = HttpResponse::Ok();
+            if corruption.is_corrupt(&msg.uuid) {
+                warn!("{} serving corrupt msg uuid={}", route, msg.uuid.to_string());
+                response.header(WARNING, CORRUPT_MSG_WARNING);
+            }
+            return response.content_type(content_type).body(Bytes::copy_from_slice(&msg.to_bytes()))
+        }
+    };
+    info!("{} 200 uuid={}", route, buffer.uuid.to_string());
+    let mut response = HttpResponse::Ok();
+    if corruption.is_corrupt(&buffer.uuid) {
+        warn!("{} serving corrupt msg uuid={}", route, buffer.uuid.to_string());
+        response.header(WARNING, CORRUPT_MSG_WARNING);
+    }
+    response.content_type(content_type).streaming(ReturnBody::new(buffer))
+}
+
+/// Replies with the uuid, headers and body length of the msg, the body follows as binary frames
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let uuid = if let Some(uuid_string) = &info.uuid {
+        match Uuid::from_string(uuid_string) {
+            Ok(uuid) => Some(uuid),
+            Err(err) => return Reply::BadRequest(err.err_ty.to_string())
+        }
+    } else {
+        None
+    };
+    let reverse = if let Some(reverse) = info.reverse {
+        reverse
+    } else {
+        false
+    };
+    let msg_option = match get_msg(&data, uuid, info.priority, reverse, info.wait, WireFormat::Framed).await {
+        Ok(message_option) => message_option,
+        Err(err) => {
+            error!("WS {} {}", command::MSG_GET, err);
+            exit(1);
+        }
+    };
+    ws_reply(command::MSG_GET, msg_option)
+}
+
+/// Replies with the uuid, headers and body length of the msg, or with null data if there is no msg
+pub fn ws_reply(cmd: &str, msg_option: Option<Either<ApiReturn, StoredMsg>>) -> Reply {
+    let msg_type = match msg_option {
+        Some(msg_type) => msg_type,
+        None => return Reply::Ok(Value::Null)
+    };
+    let (header, body) = match msg_type {
+        Either::A(mut buffer) => {
+            // the head is sent as json instead
+            buffer.headers_sent = true;
+            (buffer.header.clone(), MsgBody::Streamed(Box::new(buffer)))
+        },
+        Either::B(msg) => (msg.header, MsgBody::Stored(msg.msg))
+    };
+    let (mut headers, body_length, _head_len) = match frame::decode_head(&header) {
+        Ok(Some(head)) => head,
+        _ => {
+            error!("WS {} Could not decode msg header", cmd);
+            exit(1);
         }
     };
-    info!("{} 200 {}", ROUTE, buffer.header);
-    HttpResponse::Ok().streaming(ReturnBody::new(buffer))
+    let uuid = headers.remove("uuid");
+    Reply::Msg(json!({
+        "uuid": uuid,
+        "headers": headers,
+        "bodyLength": body_length
+    }), body)
 }
@@ -1,11 +1,20 @@
 use actix_web::web::{ Data, Payload };
-use actix_web::{HttpResponse};
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::CONTENT_TYPE;
 use bytes::Bytes;
 use crate::AppData;
-use msg_store_server_api::msg::add::{handle, Chunky, AddErrorTy, MsgError};
+use crate::cluster;
+use crate::api::ws::{command, Reply};
+use msg_store_server_api::cluster::Command;
+use msg_store_server_api::file_storage::{FileStorageError, FileStorageErrorTy};
+use msg_store_server_api::msg::add::{handle, read_msg, AddError, Chunky, AddErrorTy, MsgError};
+use msg_store_server_api::msg::frame::{self, WireFormat};
+use futures::stream::BoxStream;
 use futures::{Stream, StreamExt};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use std::process::exit;
 use std::task::Poll;
 
@@ -33,49 +42,145 @@ impl Stream for PayloadBridge {
 
 impl Chunky for PayloadBridge { }
 
+/// The frame of a msg/post command, the body is passed on as it arrives
+///
+/// A body that ends before its length is reached is refused by the frame reader.
+pub struct WsPayload {
+    head: Option<Bytes>,
+    body: BoxStream<'static, Bytes>
+}
+
+impl WsPayload {
+    pub fn new(headers: &BTreeMap<String, String>, body_length: u64, body: BoxStream<'static, Bytes>) -> Result<WsPayload, Reply> {
+        match frame::encode_head(headers, body_length) {
+            Ok(head) => Ok(WsPayload { head: Some(head), body }),
+            Err(error) => Err(Reply::BadRequest(error.to_string()))
+        }
+    }
+}
+
+impl Stream for WsPayload {
+    type Item = Result<Bytes, &'static str>;
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(head) = self.head.take() {
+            return Poll::Ready(Some(Ok(head)));
+        }
+        self.body.poll_next_unpin(cx).map(|chunk| chunk.map(Ok))
+    }
+}
+
+impl Chunky for WsPayload { }
+
+/// The data of a msg/post command
+///
+/// The body is either given as msg or follows as binary frames totalling body_length bytes.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WsInfo {
+    pub headers: BTreeMap<String, Value>,
+    pub msg: Option<String>,
+    pub body_length: Option<u64>
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ReturnBody {
     uuid: String,
 }
 
 const ROUTE: &'static str = "POST /api/msg";
-pub async fn http_handle(data: Data<AppData>, body: Payload) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, body: Payload) -> HttpResponse {
     info!("{}", ROUTE);
-    match handle(&data.store, &data.file_storage, &data.stats, &data.db, PayloadBridge(body)).await {
+    let content_type = req.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    let format = WireFormat::from_content_type(content_type);
+    if data.cluster.is_some() {
+        return cluster_handle(&req, &data, format, body).await;
+    }
+    match handle(&data.store, &data.file_storage, &data.stats, &data.db, &data.notifier, &data.changes, data.stored_byte_sizes, format, PayloadBridge(body)).await {
         Ok(uuid) => HttpResponse::Ok().json(ReturnBody { uuid: uuid.to_string() }),
+        Err(error) => error_response(error)
+    }
+}
+
+/// Proposes the msg to the cluster, the leader gives the msg its uuid
+async fn cluster_handle(req: &HttpRequest, data: &AppData, format: WireFormat, body: Payload) -> HttpResponse {
+    let (priority, msg) = match read_msg(format, PayloadBridge(body)).await {
+        Ok(msg) => msg,
+        Err(error) => return error_response(error)
+    };
+    let uuid = match data.store.lock() {
+        Ok(mut store) => match store.uuid(priority) {
+            Ok(uuid) => uuid,
+            Err(error) => {
+                error!("{} {}", ROUTE, error);
+                exit(1)
+            }
+        },
+        Err(error) => {
+            error!("{} Could not lock store. {}", ROUTE, error);
+            exit(1)
+        }
+    };
+    let applied = HttpResponse::Ok().json(ReturnBody { uuid: uuid.to_string() });
+    let proposal = cluster::propose(data, Command::Add { uuid, msg }).await;
+    cluster::respond(ROUTE, req, proposal, applied)
+}
+
+fn error_response(error: AddError) -> HttpResponse {
+    match error.err_ty {
+        AddErrorTy::MsgError(msg_error) => {
+            match msg_error {
+                MsgError::InvalidBytesizeOverride |
+                MsgError::InvalidPriority |
+                MsgError::MalformedFrame |
+                MsgError::MalformedHeaders |
+                MsgError::MissingBytesizeOverride |
+                MsgError::MissingHeaders |
+                MsgError::MissingPriority |
+                MsgError::UnsupportedFrameVersion => {
+                    info!("{} 400 {}", ROUTE, msg_error);
+                    HttpResponse::BadRequest().body(msg_error.to_string())
+                },
+                MsgError::CouldNotGetNextChunkFromPayload |
+                MsgError::CouldNotParseChunk => {
+                    info!("{} 400 {}", ROUTE, msg_error);
+                    HttpResponse::BadRequest().body(msg_error.to_string())
+                },
+                MsgError::FileStorageNotConfigured => {
+                    info!("{} 403 {}", ROUTE, msg_error);
+                    HttpResponse::Forbidden().body(msg_error.to_string())
+                },
+                MsgError::MsgExceedesGroupMax |
+                MsgError::MsgExceedesStoreMax |
+                MsgError::MsgLacksPriority => {
+                    info!("{} 409 {}", ROUTE, msg_error);
+                    HttpResponse::BadRequest().body(msg_error.to_string())
+                }
+            }
+        },
+        AddErrorTy::CouldNotFindFileStorage |
+        _ => {
+            error!("ROUTE {}", error);
+            exit(1)
+        }
+    }
+}
+
+/// Adds the msg of a msg/post command
+///
+/// Returns None if the body was cut off, the connection has then replied already or closed.
+pub async fn ws_handle(data: Data<AppData>, payload: WsPayload) -> Option<Reply> {
+    match handle(&data.store, &data.file_storage, &data.stats, &data.db, &data.notifier, &data.changes, data.stored_byte_sizes, WireFormat::Framed, payload).await {
+        Ok(uuid) => Some(Reply::Ok(json!({ "uuid": uuid.to_string() }))),
         Err(error) => {
             match error.err_ty {
-                AddErrorTy::MsgError(msg_error) => {
-                    match msg_error {
-                        MsgError::InvalidBytesizeOverride |
-                        MsgError::InvalidPriority |
-                        MsgError::MalformedHeaders |
-                        MsgError::MissingBytesizeOverride |
-                        MsgError::MissingHeaders |
-                        MsgError::MissingPriority => {
-                            info!("{} 400 {}", ROUTE, msg_error);
-                            HttpResponse::BadRequest().body(msg_error.to_string())
-                        },
-                        MsgError::CouldNotGetNextChunkFromPayload |
-                        MsgError::CouldNotParseChunk => {
-                            info!("{} 400 {}", ROUTE, msg_error);
-                            HttpResponse::BadRequest().body(msg_error.to_string())
-                        },
-                        MsgError::FileStorageNotConfigured => {
-                            info!("{} 403 {}", ROUTE, msg_error);
-                            HttpResponse::Forbidden().body(msg_error.to_string())
-                        },
-                        MsgError::MsgExceedesGroupMax |
-                        MsgError::MsgExceedesStoreMax |
-                        MsgError::MsgLacksPriority => {
-                            info!("{} 409 {}", ROUTE, msg_error);
-                            HttpResponse::BadRequest().body(msg_error.to_string())
-                        }
-                    }
+                AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload) |
+                AddErrorTy::FileStorageError(FileStorageError { err_ty: FileStorageErrorTy::CouldNotGetChunkFromPayload, .. }) => None,
+                AddErrorTy::MsgError(MsgError::FileStorageNotConfigured) => {
+                    Some(Reply::Forbidden(MsgError::FileStorageNotConfigured.to_string()))
                 },
-                AddErrorTy::CouldNotFindFileStorage |
+                AddErrorTy::MsgError(msg_error) => Some(Reply::BadRequest(msg_error.to_string())),
                 _ => {
-                    error!("ROUTE {}", error);
+                    error!("WS {} {}", command::MSG_POST, error);
                     exit(1)
                 }
             }
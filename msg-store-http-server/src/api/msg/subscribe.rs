@@ -0,0 +1,79 @@
+use actix_web::{HttpResponse, Error};
+use actix_web::web::{Data, Query, Bytes};
+use crate::AppData;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use log::{error, info};
+use msg_store_server_api::notify::{Event, PriorityRange, Subscription, DEFAULT_CAPACITY};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Info {
+    pub priority: Option<u16>,
+    pub min_priority: Option<u16>,
+    pub max_priority: Option<u16>,
+}
+impl Info {
+    pub fn priorities(&self) -> PriorityRange {
+        PriorityRange::from_options(self.priority, self.min_priority, self.max_priority)
+    }
+}
+
+/// Formats the events of a subscription as server-sent events
+pub struct EventStream {
+    subscription: Subscription,
+    opened: bool
+}
+impl EventStream {
+    pub fn new(subscription: Subscription) -> EventStream {
+        EventStream {
+            subscription,
+            opened: false
+        }
+    }
+}
+impl Stream for EventStream {
+    type Item = Result<Bytes, Error>;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Option<Self::Item>> {
+        if !self.opened {
+            // a comment lets the client know the subscription is open
+            self.opened = true;
+            return Poll::Ready(Some(Ok(Bytes::from_static(b": subscribed\n\n"))));
+        }
+        match self.subscription.poll_next_unpin(cx) {
+            Poll::Ready(Some(event)) => {
+                if let Event::Lagged(count) = event {
+                    info!("{} subscriber lagged, {} notifications dropped", ROUTE, count);
+                }
+                let text = format!("event: {}\ndata: {}\n\n", event.name(), event.to_json());
+                Poll::Ready(Some(Ok(Bytes::from(text))))
+            },
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+const ROUTE: &'static str = "GET /api/msg/subscribe";
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    let priorities = info.priorities();
+    info!("{} priorities: {}-{}", ROUTE, priorities.start, priorities.end);
+    let subscription = match data.notifier.lock() {
+        Ok(mut notifier) => notifier.subscribe(priorities, DEFAULT_CAPACITY),
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    };
+    info!("{} 200", ROUTE);
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(EventStream::new(subscription))
+}
@@ -0,0 +1,46 @@
+use crate::AppData;
+use crate::api::ws::{command, Reply};
+use actix_web::{web::Data, HttpResponse};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplicationStatus {
+    /// primary or replica
+    role: String,
+    primary: Option<String>,
+    bootstrapped: bool,
+    /// The sequence number of the next change of the primary to apply
+    seq: u64
+}
+
+fn get_status(data: &AppData) -> ReplicationStatus {
+    match data.replication.lock() {
+        Ok(replication) => ReplicationStatus {
+            role: if replication.is_replica() { "replica".to_string() } else { "primary".to_string() },
+            primary: replication.primary.clone(),
+            bootstrapped: replication.bootstrapped,
+            seq: replication.seq
+        },
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+            exit(1);
+        }
+    }
+}
+
+const ROUTE: &str = "GET /api/replication";
+pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
+    info!("{}", ROUTE);
+    let status = get_status(&data);
+    info!("{} 200 {}", ROUTE, status.role);
+    HttpResponse::Ok().json(status)
+}
+
+pub async fn ws_handle(data: Data<AppData>) -> Reply {
+    info!("WS {}", command::REPLICATION_GET);
+    Reply::Ok(json!(get_status(&data)))
+}
@@ -0,0 +1,4 @@
+pub mod get;
+pub mod msg;
+pub mod promote;
+pub mod snapshot;
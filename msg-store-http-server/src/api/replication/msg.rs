@@ -0,0 +1,74 @@
+use crate::AppData;
+use crate::api::msg::get::ReturnBody;
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::HttpResponse;
+use bytes::BytesMut;
+use log::{error, info};
+use msg_store_server_api::msg::frame::{self, Headers, CONTENT_TYPE};
+use msg_store_server_api::msg::get::ReturnBody as ApiReturn;
+use msg_store_server_api::replication::msg::handle;
+use msg_store_uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use std::process::exit;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Info {
+    uuid: String
+}
+
+const ROUTE: &str = "GET /api/replication/msg";
+/// Responds with a frame holding the msg exactly as it is stored
+///
+/// The headers hold the byteSize of the msg and the storedLen of the value held in the
+/// database. The body is that value, followed by the contents of the file when the
+/// file header is true.
+pub async fn http_handle(data: Data<AppData>, info: Query<Info>) -> HttpResponse {
+    info!("{} uuid: {}", ROUTE, info.uuid);
+    let uuid = match Uuid::from_string(&info.uuid) {
+        Ok(uuid) => uuid,
+        Err(err) => {
+            info!("{} 400 {}", ROUTE, err);
+            return HttpResponse::BadRequest().body(err.err_ty.to_string());
+        }
+    };
+    let msg = match handle(&data.store, &data.db, &data.file_storage, uuid).await {
+        Ok(Some(msg)) => msg,
+        Ok(None) => {
+            info!("{} 404 uuid: {}", ROUTE, info.uuid);
+            return HttpResponse::NotFound().finish();
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    };
+    let mut headers = Headers::new();
+    headers.insert("uuid".to_string(), msg.uuid.to_string());
+    headers.insert("byteSize".to_string(), msg.byte_size.to_string());
+    headers.insert("storedLen".to_string(), msg.stored.len().to_string());
+    let file_size = match &msg.file {
+        Some((_file, file_size)) => {
+            headers.insert("file".to_string(), "true".to_string());
+            *file_size
+        },
+        None => 0
+    };
+    let head = match frame::encode_head(&headers, msg.stored.len() as u64 + file_size) {
+        Ok(head) => head,
+        Err(err) => {
+            error!("{} {:?}", ROUTE, err);
+            exit(1);
+        }
+    };
+    let mut header = BytesMut::with_capacity(head.len() + msg.stored.len());
+    header.extend_from_slice(&head);
+    header.extend_from_slice(&msg.stored);
+    info!("{} 200 uuid: {}", ROUTE, info.uuid);
+    match msg.file {
+        Some((file, file_size)) => {
+            let body = ApiReturn::new(msg.uuid, header.freeze(), file_size, file);
+            HttpResponse::Ok().content_type(CONTENT_TYPE).streaming(ReturnBody::new(body))
+        },
+        None => HttpResponse::Ok().content_type(CONTENT_TYPE).body(Bytes::copy_from_slice(&header))
+    }
+}
@@ -0,0 +1,51 @@
+use crate::AppData;
+use crate::api::ws::{command, Reply};
+use actix_web::{web::Data, HttpResponse};
+use log::{error, info};
+use msg_store_server_api::config::update_config;
+use serde_json::Value;
+use std::process::exit;
+
+/// Stops following the primary and accepts writes
+///
+/// The replica keeps every change it applied before it was promoted. The replicate_from
+/// property is removed from the configuration so that the server starts as a primary.
+fn promote(data: &AppData) -> Option<String> {
+    let primary = match data.replication.lock() {
+        Ok(mut replication) => replication.primary.take(),
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+            exit(1);
+        }
+    };
+    let mut config = match data.configuration.lock() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock configuration. {}", err);
+            exit(1);
+        }
+    };
+    config.replicate_from = None;
+    if let Err(err) = update_config(&config, &data.configuration_path) {
+        error!("REPLICATION_ERROR: {}", err);
+        exit(1);
+    }
+    primary
+}
+
+const ROUTE: &str = "POST /api/replication/promote";
+pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
+    info!("{}", ROUTE);
+    match promote(&data) {
+        Some(primary) => info!("{} 200 stopped following {}", ROUTE, primary),
+        None => info!("{} 200 already primary", ROUTE)
+    };
+    HttpResponse::Ok().finish()
+}
+
+pub async fn ws_handle(data: Data<AppData>) -> Reply {
+    if let Some(primary) = promote(&data) {
+        info!("WS {} stopped following {}", command::REPLICATION_PROMOTE, primary);
+    }
+    Reply::Ok(Value::Null)
+}
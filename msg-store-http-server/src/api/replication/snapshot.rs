@@ -0,0 +1,20 @@
+use crate::AppData;
+use actix_web::{web::Data, HttpResponse};
+use log::{error, info};
+use msg_store_server_api::replication::snapshot::handle;
+use std::process::exit;
+
+const ROUTE: &str = "GET /api/replication/snapshot";
+pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
+    info!("{}", ROUTE);
+    match handle(&data.store, &data.changes).await {
+        Ok(snapshot) => {
+            info!("{} 200 seq: {}, {} msgs", ROUTE, snapshot.seq, snapshot.msgs.len());
+            HttpResponse::Ok().json(snapshot)
+        },
+        Err(err) => {
+            error!("{} {}", ROUTE, err);
+            exit(1);
+        }
+    }
+}
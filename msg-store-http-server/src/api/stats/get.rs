@@ -1,7 +1,9 @@
 use actix_web::{web::Data, HttpResponse};
 use crate::AppData;
+use crate::api::ws::{command, Reply};
 use msg_store_server_api::stats::get::handle;
 use log::{error, info};
+use serde_json::json;
 use std::process::exit;
 
 const ROUTE: &'static str = "GET /api/stats";
@@ -18,3 +20,13 @@ pub async fn http_handle(data: Data<AppData>) -> HttpResponse {
         }
     }
 }
+
+pub async fn ws_handle(data: Data<AppData>) -> Reply {
+    match handle(&data.stats).await {
+        Ok(stats) => Reply::Ok(json!(stats)),
+        Err(err) => {
+            error!("WS {} {}", command::STATS_GET, err);
+            exit(1);
+        }
+    }
+}
@@ -3,10 +3,11 @@ use actix_web::{
     HttpResponse,
 };
 use crate::AppData;
+use crate::api::ws::{command, from_data, Reply};
 use msg_store_server_api::stats::set::handle;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fmt::Display;
 use std::process::exit;
 
@@ -43,3 +44,22 @@ pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse
         }
     }
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let add = if let Some(add) = info.add {
+        add
+    } else {
+        false
+    };
+    match handle(&data.stats, add, info.inserted, info.deleted, info.pruned).await {
+        Ok(stats) => Reply::Ok(json!(stats)),
+        Err(err) => {
+            error!("WS {} {}", command::STATS_PUT, err);
+            exit(1);
+        }
+    }
+}
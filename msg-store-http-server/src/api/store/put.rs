@@ -1,10 +1,13 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web::web::{Data, Json};
 use crate::AppData;
+use crate::cluster;
+use crate::api::ws::{command, from_data, Reply};
+use msg_store_server_api::cluster::Command;
 use msg_store_server_api::store::set::handle;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fmt::Display;
 use std::process::exit;
 
@@ -21,13 +24,18 @@ impl Display for Info {
 }
 
 const ROUTE: &'static str = "PUT /api/store";
-pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse {
+pub async fn http_handle(req: HttpRequest, data: Data<AppData>, info: Json<Info>) -> HttpResponse {
     info!("{} {}", ROUTE, info);
+    if data.cluster.is_some() {
+        let proposal = cluster::propose(&data, Command::StoreDefaults { max_byte_size: info.max_byte_size }).await;
+        return cluster::respond(ROUTE, &req, proposal, HttpResponse::Ok().finish());
+    }
     let result = handle(
         &data.store, 
         &data.db,
         &data.file_storage, 
         &data.stats, 
+        &data.changes,
         &data.configuration, 
         &data.configuration_path, 
         info.max_byte_size).await;
@@ -37,3 +45,24 @@ pub async fn http_handle(data: Data<AppData>, info: Json<Info>) -> HttpResponse
     }
     HttpResponse::Ok().finish()
 }
+
+pub async fn ws_handle(data: Data<AppData>, info: Value) -> Reply {
+    let info: Info = match from_data(info) {
+        Ok(info) => info,
+        Err(reply) => return reply
+    };
+    let result = handle(
+        &data.store, 
+        &data.db,
+        &data.file_storage, 
+        &data.stats, 
+        &data.changes,
+        &data.configuration, 
+        &data.configuration_path, 
+        info.max_byte_size).await;
+    if let Err(err) = result {
+        error!("WS {} {}", command::STORE_PUT, err);
+        exit(1);
+    }
+    Reply::Ok(Value::Null)
+}
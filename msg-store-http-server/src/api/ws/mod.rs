@@ -1,34 +1,230 @@
 use actix::prelude::*;
-// use actix_files as fs;
-use crate::{api, AppData};
+use crate::{api, cluster, replica, AppData};
 use actix_web::{
-    // middleware,
-    web,
-    // HttpServer,
-    web::Data,
-    // App,
+    web::{self, Bytes, Data},
     Error,
     HttpRequest,
     HttpResponse,
 };
 use actix_web_actors::ws;
-use log::error;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use futures::channel::mpsc;
+use futures::future::{AbortHandle, Abortable};
+use futures::{stream, SinkExt, StreamExt};
+use log::{error, info};
+use api::msg::post::WsPayload;
+use msg_store_server_api::msg::get::ReturnBody;
+use msg_store_server_api::notify::DEFAULT_CAPACITY;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::process::exit;
+use std::time::{Duration, Instant};
 
-use super::{from_value_prop_required, get_required_string, ws_bad_request, http_route_hit_log, ws_not_found};
+/// How often heartbeat pings are sent
+#[cfg(not(test))]
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+#[cfg(test)]
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long before lack of client response causes a timeout
+#[cfg(not(test))]
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+/// The largest binary frame that will be sent, which matches the default frame size
+/// limit of the websocket codec
+const MAX_BINARY_FRAME_SIZE: usize = 65_536;
+/// The most msg/post commands of a connection that can wait for their body at a time
+const MAX_UPLOADS: usize = 8;
+/// The chunks of a body that are passed on ahead of the msg/post command reading them
+const UPLOAD_BUFFER: usize = 16;
 
-/// do websocket handshake and start `MyWebSocket` actor
+/// do websocket handshake and start `Websocket` actor
 pub async fn ws_index(
     r: HttpRequest,
     stream: web::Payload,
     data: Data<AppData>,
 ) -> Result<HttpResponse, Error> {
-    // println!("{:?}", r);
-    let res = ws::start(Websocket::new(data), &r, stream);
-    // println!("{:?}", res);
-    res
+    ws::start(Websocket::new(data), &r, stream)
+}
+
+pub mod command {
+
+    pub const MSG_GET: &str = "msg/get";
+    pub const MSG_POST: &str = "msg/post";
+    pub const MSG_DELETE: &str = "msg/delete";
+    pub const MSG_SUBSCRIBE: &str = "msg/subscribe";
+    pub const MSG_UNSUBSCRIBE: &str = "msg/unsubscribe";
+
+    pub const GROUP_GET: &str = "group/get";
+    pub const GROUP_DELETE: &str = "group/delete";
+
+    pub const GROUP_DEFAULTS_GET: &str = "group-defaults/get";
+    pub const GROUP_DEFAULTS_POST: &str = "group-defaults/post";
+    pub const GROUP_DEFAULTS_DELETE: &str = "group-defaults/delete";
+
+    pub const STORE_GET: &str = "store/get";
+    pub const STORE_PUT: &str = "store/put";
+
+    pub const STATS_GET: &str = "stats/get";
+    pub const STATS_PUT: &str = "stats/put";
+    pub const STATS_DELETE: &str = "stats/delete";
+
+    pub const CHANGES_GET: &str = "changes/get";
+
+    pub const CONSUMER_GROUP_GET: &str = "consumer-group/get";
+    pub const CONSUMER_GROUP_POST: &str = "consumer-group/post";
+    pub const CONSUMER_GROUP_DELETE: &str = "consumer-group/delete";
+    pub const CONSUMER_GROUP_MSG_GET: &str = "consumer-group/msg/get";
+    pub const CONSUMER_GROUP_ACK: &str = "consumer-group/ack";
+
+    pub const CLUSTER_GET: &str = "cluster/get";
+
+    pub const EXPORT: &str = "export";
+
+    pub const FORWARD_GET: &str = "forward/get";
+
+    pub const IMPORT: &str = "import";
+
+    pub const REPLICATION_GET: &str = "replication/get";
+    pub const REPLICATION_PROMOTE: &str = "replication/promote";
+}
+
+/// The body of a msg that follows a msg/get reply as binary frames
+pub enum MsgBody {
+    Stored(bytes::Bytes),
+    /// Read from a file, or in chunks from the database
+    Streamed(Box<ReturnBody>)
+}
+
+/// The result of a websocket command
+pub enum Reply {
+    Ok(Value),
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// A msg/get reply, the data is followed by the msg body
+    Msg(Value, MsgBody)
+}
+
+/// Deserializes the data of a command into the parameters of a route
+pub fn from_data<T: DeserializeOwned>(data: Value) -> Result<T, Reply> {
+    match serde_json::from_value(data) {
+        Ok(info) => Ok(info),
+        Err(error) => Err(Reply::BadRequest(format!("/data {}", error)))
+    }
+}
+
+/// A text or binary frame that is sent from outside of the actor
+#[derive(Message)]
+#[rtype(result = "()")]
+enum Outgoing {
+    Text(String),
+    Binary(Bytes)
+}
+
+/// A msg/post command that has finished, the id of the command is given
+#[derive(Message)]
+#[rtype(result = "()")]
+struct UploadEnded(String);
+
+/// A msg/post command that is waiting for its body
+struct Upload {
+    id: Value,
+    /// Passes the body on to the command as it arrives
+    body: mpsc::Sender<bytes::Bytes>,
+    received: u64,
+    body_length: u64
+}
+
+/// Converts the request id to the string used to prefix binary frames
+fn request_id(id: &Value) -> Option<String> {
+    match id {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None
+    }
+}
+
+/// Prefixes a chunk with the request id: [id length: u16][id: utf-8][chunk]
+fn binary_frame(id: &str, chunk: &[u8]) -> Bytes {
+    let mut frame = Vec::with_capacity(2 + id.len() + chunk.len());
+    frame.extend_from_slice(&(id.len() as u16).to_be_bytes());
+    frame.extend_from_slice(id.as_bytes());
+    frame.extend_from_slice(chunk);
+    Bytes::from(frame)
+}
+
+/// Splits a binary frame into the request id and the chunk
+fn split_binary_frame(frame: &[u8]) -> Option<(String, &[u8])> {
+    let id_len = u16::from_be_bytes(<[u8; 2]>::try_from(frame.get(..2)?).ok()?) as usize;
+    let id = std::str::from_utf8(frame.get(2..2 + id_len)?).ok()?;
+    Some((id.to_string(), &frame[2 + id_len..]))
+}
+
+fn reply_text(id: &Value, cmd: &str, code: u16, body: (&str, Value)) -> String {
+    let mut reply = json!({
+        "id": id,
+        "cmd": cmd,
+        "code": code
+    });
+    reply[body.0] = body.1;
+    reply.to_string()
+}
+
+/// Sends a reply, followed by the msg body as binary frames for msg/get
+async fn send_reply(addr: Addr<Websocket>, id: Value, cmd: String, reply: Reply) {
+    let (code, body) = match reply {
+        Reply::Ok(data) => (200, ("data", data)),
+        Reply::BadRequest(message) => (400, ("message", Value::String(message))),
+        Reply::Forbidden(message) => (403, ("message", Value::String(message))),
+        Reply::NotFound(message) => (404, ("message", Value::String(message))),
+        Reply::Conflict(message) => (409, ("message", Value::String(message))),
+        Reply::Msg(data, body) => {
+            info!("WS {} 200 id: {}", cmd, id);
+            addr.do_send(Outgoing::Text(reply_text(&id, &cmd, 200, ("data", data))));
+            send_msg_body(addr, id, cmd, body).await;
+            return;
+        }
+    };
+    info!("WS {} {} id: {}", cmd, code, id);
+    addr.do_send(Outgoing::Text(reply_text(&id, &cmd, code, body)));
+}
+
+async fn send_msg_body(addr: Addr<Websocket>, id: Value, cmd: String, body: MsgBody) {
+    let id_str = match request_id(&id) {
+        Some(id_str) => id_str,
+        None => return
+    };
+    let chunk_size = MAX_BINARY_FRAME_SIZE - 2 - id_str.len();
+    match body {
+        MsgBody::Stored(msg) => {
+            for chunk in msg.chunks(chunk_size) {
+                if addr.send(Outgoing::Binary(binary_frame(&id_str, chunk))).await.is_err() {
+                    return;
+                }
+            }
+        },
+        MsgBody::Streamed(mut streamed_body) => {
+            while let Some(chunk) = streamed_body.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        error!("WS {} {}", cmd, err);
+                        addr.do_send(Outgoing::Text(reply_text(&id, &cmd, 500, ("message", Value::from("Could not read msg")))));
+                        return;
+                    }
+                };
+                for chunk in chunk.chunks(chunk_size) {
+                    if addr.send(Outgoing::Binary(binary_frame(&id_str, chunk))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// websocket connection is long running connection, it easier
@@ -36,56 +232,48 @@ pub async fn ws_index(
 pub struct Websocket {
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
-    // hb: Instant,
+    hb: Instant,
     app_data: Data<AppData>,
+    /// msg/post commands waiting for their body, by request id
+    uploads: HashMap<String, Upload>,
+    /// Active msg/subscribe commands, by request id
+    subscriptions: HashMap<String, AbortHandle>
 }
 
 impl Actor for Websocket {
     type Context = ws::WebsocketContext<Self>;
 
     /// Method is called on actor start. We start the heartbeat process here.
-    fn started(&mut self, _ctx: &mut Self::Context) {
-        // self.hb(ctx);
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
     }
-}
-
-pub mod command {
-
-    pub const MSG_GET: &'static str = "msg/get";
-    pub const MSG_POST: &'static str = "msg/post";
-    pub const MSG_DELETE: &'static str = "msg/delete";
-
-    pub const GROUP_GET: &'static str = "group/get";
-    pub const GROUP_DELETE: &'static str = "group/delete";
-
-    pub const GROUP_DEFAULTS_GET: &'static str = "group-defaults/get";
-    pub const GROUP_DEFAULTS_POST: &'static str = "group-defaults/post";
-    pub const GROUP_DEFAULTS_DELETE: &'static str = "group-defaults/delete";
-
-    pub const STORE_GET: &'static str = "store/get";
-    pub const STORE_PUT: &'static str = "store/put";
-
-    pub const STATS_GET: &'static str = "stats/get";
-    pub const STATS_PUT: &'static str = "stats/put";
-    pub const STATS_DELETE: &'static str = "stats/delete";
 
-    pub const EXPORT: &'static str = "export";
+    /// Ends the subscriptions of the connection
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for (_id, subscription) in self.subscriptions.drain() {
+            subscription.abort();
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
-enum Response {
-    BadRequest { code: u16, message: &'static str },
-    NotFound { code: u16, message: &'static str },
+impl Handler<Outgoing> for Websocket {
+    type Result = ();
+    fn handle(&mut self, msg: Outgoing, ctx: &mut Self::Context) {
+        match msg {
+            Outgoing::Text(text) => ctx.text(text),
+            Outgoing::Binary(bin) => ctx.binary(bin)
+        }
+    }
 }
 
-impl From<Response> for String {
-    fn from(response: Response) -> Self {
-        match serde_json::to_string(&response) {
-            Ok(text) => text,
-            Err(error) => {
-                error!("ERROR_CODE: 4806076e-4ce9-45a2-b753-c423046d8263. Could not convert response into string: {}", error.to_string());
-                exit(1);
+impl Handler<UploadEnded> for Websocket {
+    type Result = ();
+    fn handle(&mut self, msg: UploadEnded, _ctx: &mut Self::Context) {
+        // a command that failed before its body arrived no longer takes it, the id may have
+        // been used again by a command that still does
+        if let Some(upload) = self.uploads.get(&msg.0) {
+            if upload.body.is_closed() {
+                self.uploads.remove(&msg.0);
             }
         }
     }
@@ -96,80 +284,518 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Websocket {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => {
+                self.hb = Instant::now();
                 ctx.pong(&msg);
             }
-            Ok(ws::Message::Pong(_)) => {}
+            Ok(ws::Message::Pong(_)) => {
+                self.hb = Instant::now();
+            }
             Ok(ws::Message::Text(text)) => {
-
-                http_route_hit_log("/api/ws", Some(text.clone()));
-
-                let obj = match serde_json::from_str::<Value>(&text) {
-                    Ok(obj) => obj,
-                    Err(_error) => {
-                        ctx.text(Response::BadRequest {
-                            code: 400,
-                            message: "body must be a json object",
-                        });
-                        return;
-                    }
-                };
-                let route = match get_required_string(&obj, "cmd") {
-                    Ok(route) => route,
-                    Err(message) => {
-                        return ctx.text(ws_bad_request("unknown", format!("/{}", message)))
-                    }
-                };
-                let data = match from_value_prop_required::<Value>(&obj, "data", "object") {
-                    Ok(data) => data,
-                    Err(message) => {
-                        return ctx.text(ws_bad_request("unknown", format!("/{}", message)));
-                    }
-                };
-                let app_data = self.app_data.clone();
-                if route == command::MSG_GET {
-                    // api::msg::get::ws_handle(ctx, app_data, data);
-                } else if route == command::MSG_POST {
-                    // api::msg::post::ws_handle(ctx, app_data, data);
-                } else if route == command::MSG_DELETE {
-                    api::msg::delete::ws_handle(ctx, app_data, data);
-                } else if route == command::GROUP_GET {
-                    api::group::get::ws_handle(ctx, app_data, data);
-                } else if route == command::GROUP_DELETE {
-                    api::group::delete::handle_ws(ctx, app_data, data);
-                } else if route == command::GROUP_DEFAULTS_GET {
-                    api::group_defaults::get::ws_handle(ctx, app_data, data);
-                } else if route == command::GROUP_DEFAULTS_POST {
-                    api::group_defaults::post::ws_handle(ctx, app_data, data);
-                } else if route == command::GROUP_DEFAULTS_DELETE {
-                    api::group_defaults::delete::ws_handle(ctx, app_data, data);
-                } else if route == command::STORE_GET {
-                    api::store::get::ws_handle(ctx, app_data);
-                } else if route == command::STORE_PUT {
-                    api::store::put::ws_handle(ctx, app_data, data);
-                } else if route == command::STATS_GET {
-                    api::stats::get::ws_handle(ctx, app_data);
-                } else if route == command::STATS_PUT {
-                    api::stats::put::ws_handle(ctx, app_data, data);
-                } else if route == command::STATS_DELETE {
-                    api::stats::delete::ws_handle(ctx, app_data);
-                } else if route == command::EXPORT {
-                    api::export::ws_handle(ctx, app_data, data);
-                } else {
-                    ctx.text(ws_not_found(&route, "/cmd is unknown".to_string()));
-                }
+                self.hb = Instant::now();
+                self.handle_text(&text, ctx);
+            }
+            Ok(ws::Message::Binary(bin)) => {
+                self.hb = Instant::now();
+                self.handle_binary(&bin, ctx);
+            }
+            Ok(ws::Message::Continuation(_)) => {
+                ctx.text(reply_text(&Value::Null, "unknown", 400, ("message", Value::from("continuation frames are not supported"))));
             }
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
                 ctx.stop();
             }
-            _ => ctx.stop(),
+            Ok(ws::Message::Nop) => {}
+            Err(_error) => ctx.stop()
         }
     }
 }
 
 impl Websocket {
     fn new(app_data: Data<AppData>) -> Self {
-        Self { app_data }
+        Self {
+            hb: Instant::now(),
+            app_data,
+            uploads: HashMap::new(),
+            subscriptions: HashMap::new()
+        }
+    }
+
+    /// Sends a ping to the client every HEARTBEAT_INTERVAL and drops the connection
+    /// once nothing has been heard from it for CLIENT_TIMEOUT.
+    fn hb(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                info!("WS client heartbeat failed, disconnecting");
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    /// Handles a command, e.g. { "id": 1, "cmd": "msg/get", "data": { "priority": 1 } }
+    fn handle_text(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+        let obj = match serde_json::from_str::<Value>(text) {
+            Ok(obj) if obj.is_object() => obj,
+            _ => {
+                return ctx.text(reply_text(&Value::Null, "unknown", 400, ("message", Value::from("body must be a json object"))));
+            }
+        };
+        let id = obj["id"].clone();
+        let id_str = match request_id(&id) {
+            Some(id_str) => id_str,
+            None => {
+                return ctx.text(reply_text(&id, "unknown", 400, ("message", Value::from("/id must be a string or number"))));
+            }
+        };
+        let cmd = match obj["cmd"].as_str() {
+            Some(cmd) => cmd.to_string(),
+            None => {
+                return ctx.text(reply_text(&id, "unknown", 400, ("message", Value::from("/cmd must be a string"))));
+            }
+        };
+        let data = match &obj["data"] {
+            Value::Null => json!({}),
+            data => data.clone()
+        };
+        info!("WS {} id: {}", cmd, id_str);
+        if replica::is_write_command(&cmd) && replica::is_read_only(&self.app_data) {
+            return self.reply_to(id, &cmd, Reply::Forbidden(replica::READ_ONLY.to_string()), ctx);
+        }
+        if replica::is_write_command(&cmd) && self.app_data.cluster.is_some() {
+            return self.reply_to(id, &cmd, Reply::Forbidden(cluster::HTTP_ONLY.to_string()), ctx);
+        }
+        if cmd == command::MSG_POST {
+            return self.start_upload(id, id_str, data, ctx);
+        }
+        if cmd == command::MSG_SUBSCRIBE {
+            return self.subscribe(id, id_str, data, ctx);
+        }
+        if cmd == command::MSG_UNSUBSCRIBE {
+            return self.unsubscribe(id, data, ctx);
+        }
+        let app_data = self.app_data.clone();
+        let addr = ctx.address();
+        actix::spawn(async move {
+            let reply = match cmd.as_str() {
+                command::MSG_GET => api::msg::get::ws_handle(app_data, data).await,
+                command::MSG_DELETE => api::msg::delete::ws_handle(app_data, data).await,
+                command::GROUP_GET => api::group::get::ws_handle(app_data, data).await,
+                command::GROUP_DELETE => api::group::delete::handle_ws(app_data, data).await,
+                command::GROUP_DEFAULTS_GET => api::group_defaults::get::ws_handle(app_data, data).await,
+                command::GROUP_DEFAULTS_POST => api::group_defaults::post::ws_handle(app_data, data).await,
+                command::GROUP_DEFAULTS_DELETE => api::group_defaults::delete::ws_handle(app_data, data).await,
+                command::STORE_GET => api::store::get::ws_handle(app_data).await,
+                command::STORE_PUT => api::store::put::ws_handle(app_data, data).await,
+                command::STATS_GET => api::stats::get::ws_handle(app_data).await,
+                command::STATS_PUT => api::stats::put::ws_handle(app_data, data).await,
+                command::STATS_DELETE => api::stats::delete::ws_handle(app_data).await,
+                command::CHANGES_GET => api::changes::get::ws_handle(app_data, data).await,
+                command::CONSUMER_GROUP_GET => api::consumer_group::get::ws_handle(app_data, data).await,
+                command::CONSUMER_GROUP_POST => api::consumer_group::post::ws_handle(app_data, data).await,
+                command::CONSUMER_GROUP_DELETE => api::consumer_group::delete::ws_handle(app_data, data).await,
+                command::CONSUMER_GROUP_MSG_GET => api::consumer_group::msg::ws_handle(app_data, data).await,
+                command::CONSUMER_GROUP_ACK => api::consumer_group::ack::ws_handle(app_data, data).await,
+                command::CLUSTER_GET => api::cluster::get::ws_handle(app_data).await,
+                command::EXPORT => api::export::ws_handle(app_data, data).await,
+                command::FORWARD_GET => api::forward::get::ws_handle(app_data).await,
+                command::IMPORT => api::import::ws_handle(app_data, data).await,
+                command::REPLICATION_GET => api::replication::get::ws_handle(app_data).await,
+                command::REPLICATION_PROMOTE => api::replication::promote::ws_handle(app_data).await,
+                _ => Reply::NotFound("/cmd is unknown".to_string())
+            };
+            send_reply(addr, id, cmd, reply).await;
+        });
+    }
+
+    /// Starts a msg/post command
+    ///
+    /// The body is either given inline as a string or follows as binary frames that are
+    /// prefixed with the request id.
+    fn start_upload(&mut self, id: Value, id_str: String, data: Value, ctx: &mut <Self as Actor>::Context) {
+        let info: api::msg::post::WsInfo = match from_data(data) {
+            Ok(info) => info,
+            Err(reply) => return self.reply_to(id, command::MSG_POST, reply, ctx)
+        };
+        let headers = info.headers.into_iter().map(|(k, v)| {
+            match v {
+                Value::String(v) => (k, v),
+                v => (k, v.to_string())
+            }
+        }).collect::<BTreeMap<String, String>>();
+        if let Some(msg) = info.msg {
+            let body_length = msg.len() as u64;
+            return self.add_msg(id, WsPayload::new(&headers, body_length, stream::iter(vec![bytes::Bytes::from(msg)]).boxed()), ctx);
+        }
+        let body_length = match info.body_length {
+            Some(0) => return self.add_msg(id, WsPayload::new(&headers, 0, stream::empty().boxed()), ctx),
+            Some(body_length) => body_length,
+            None => return self.reply_to(id, command::MSG_POST, Reply::BadRequest("/data/msg or /data/bodyLength is required".to_string()), ctx)
+        };
+        if body_length > self.app_data.ws_max_body_length {
+            let message = format!("/data/bodyLength exceeds the maximum of {} bytes", self.app_data.ws_max_body_length);
+            return self.reply_to(id, command::MSG_POST, Reply::BadRequest(message), ctx);
+        }
+        if self.uploads.contains_key(&id_str) {
+            return self.reply_to(id, command::MSG_POST, Reply::BadRequest("/id is already in use".to_string()), ctx);
+        }
+        if self.uploads.len() >= MAX_UPLOADS {
+            let message = format!("no more than {} msg/post commands can wait for their body", MAX_UPLOADS);
+            return self.reply_to(id, command::MSG_POST, Reply::BadRequest(message), ctx);
+        }
+        let (sender, body) = mpsc::channel(UPLOAD_BUFFER);
+        let payload = match WsPayload::new(&headers, body_length, body.boxed()) {
+            Ok(payload) => payload,
+            Err(reply) => return self.reply_to(id, command::MSG_POST, reply, ctx)
+        };
+        self.uploads.insert(id_str, Upload {
+            id: id.clone(),
+            body: sender,
+            received: 0,
+            body_length
+        });
+        self.add_msg(id, Ok(payload), ctx);
+    }
+
+    /// Passes a binary frame on to the msg/post command it belongs to
+    ///
+    /// The connection waits for the command to take the chunk, so that a body is not
+    /// received faster than it is written.
+    fn handle_binary(&mut self, frame: &[u8], ctx: &mut <Self as Actor>::Context) {
+        let (id_str, chunk) = match split_binary_frame(frame) {
+            Some(split) => split,
+            None => return self.reply_to(Value::Null, command::MSG_POST, Reply::BadRequest("binary frames must start with a request id".to_string()), ctx)
+        };
+        let (received, body_length) = match self.uploads.get_mut(&id_str) {
+            Some(upload) => {
+                upload.received += chunk.len() as u64;
+                (upload.received, upload.body_length)
+            },
+            None => return self.reply_to(Value::String(id_str), command::MSG_POST, Reply::NotFound("/id has no pending msg/post".to_string()), ctx)
+        };
+        // the body ends once the upload is removed, the command is cut off without a reply
+        // of its own if it is short of its length
+        let mut body = match (received.cmp(&body_length), self.uploads.remove(&id_str)) {
+            (Ordering::Greater, Some(upload)) => {
+                return self.reply_to(upload.id, command::MSG_POST, Reply::BadRequest("msg exceeds /data/bodyLength".to_string()), ctx);
+            },
+            (Ordering::Equal, Some(upload)) => upload.body,
+            (Ordering::Less, Some(upload)) => {
+                let body = upload.body.clone();
+                self.uploads.insert(id_str, upload);
+                body
+            },
+            (_, None) => return
+        };
+        let chunk = bytes::Bytes::copy_from_slice(chunk);
+        ctx.wait(async move {
+            // the command is gone once it failed, it replied then
+            let _ = body.send(chunk).await;
+        }.into_actor(self));
+    }
+
+    /// Adds the msg of a msg/post command, replying once it is added
+    ///
+    /// The upload of the command is removed once the command is done, so that a command that
+    /// fails before its body arrived does not keep waiting for it.
+    fn add_msg(&mut self, id: Value, payload: Result<WsPayload, Reply>, ctx: &mut <Self as Actor>::Context) {
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(reply) => return self.reply_to(id, command::MSG_POST, reply, ctx)
+        };
+        let app_data = self.app_data.clone();
+        let addr = ctx.address();
+        actix::spawn(async move {
+            let reply = api::msg::post::ws_handle(app_data, payload).await;
+            if let Some(id_str) = request_id(&id) {
+                addr.do_send(UploadEnded(id_str));
+            }
+            if let Some(reply) = reply {
+                send_reply(addr, id, command::MSG_POST.to_string(), reply).await;
+            }
+        });
+    }
+
+    /// Starts a msg/subscribe command
+    ///
+    /// After the reply, every event of the subscription is sent with the id of the command
+    /// until it is ended by msg/unsubscribe or the connection closes.
+    fn subscribe(&mut self, id: Value, id_str: String, data: Value, ctx: &mut <Self as Actor>::Context) {
+        let info: api::msg::subscribe::Info = match from_data(data) {
+            Ok(info) => info,
+            Err(reply) => return self.reply_to(id, command::MSG_SUBSCRIBE, reply, ctx)
+        };
+        if self.subscriptions.contains_key(&id_str) {
+            return self.reply_to(id, command::MSG_SUBSCRIBE, Reply::BadRequest("/id is already in use".to_string()), ctx);
+        }
+        let mut subscription = match self.app_data.notifier.lock() {
+            Ok(mut notifier) => notifier.subscribe(info.priorities(), DEFAULT_CAPACITY),
+            Err(err) => {
+                error!("WS {} {}", command::MSG_SUBSCRIBE, err);
+                exit(1);
+            }
+        };
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.subscriptions.insert(id_str, abort_handle);
+        let addr = ctx.address();
+        let forward = async move {
+            send_reply(addr.clone(), id.clone(), command::MSG_SUBSCRIBE.to_string(), Reply::Ok(Value::Null)).await;
+            while let Some(event) = subscription.next().await {
+                let text = reply_text(&id, command::MSG_SUBSCRIBE, 200, ("data", event.to_json()));
+                // waiting for the actor keeps a slow connection from draining the subscription
+                if addr.send(Outgoing::Text(text)).await.is_err() {
+                    return;
+                }
+            }
+        };
+        actix::spawn(async move {
+            let _ = Abortable::new(forward, abort_registration).await;
+        });
+    }
+
+    /// Ends a msg/subscribe command, e.g. { "id": 2, "cmd": "msg/unsubscribe", "data": { "id": 1 } }
+    fn unsubscribe(&mut self, id: Value, data: Value, ctx: &mut <Self as Actor>::Context) {
+        let subscription_id = match request_id(&data["id"]) {
+            Some(subscription_id) => subscription_id,
+            None => return self.reply_to(id, command::MSG_UNSUBSCRIBE, Reply::BadRequest("/data/id must be a string or number".to_string()), ctx)
+        };
+        match self.subscriptions.remove(&subscription_id) {
+            Some(subscription) => {
+                subscription.abort();
+                self.reply_to(id, command::MSG_UNSUBSCRIBE, Reply::Ok(Value::Null), ctx);
+            },
+            None => self.reply_to(id, command::MSG_UNSUBSCRIBE, Reply::NotFound("/data/id has no subscription".to_string()), ctx)
+        }
+    }
+
+    /// Replies to a command from within the actor
+    fn reply_to(&mut self, id: Value, cmd: &str, reply: Reply, ctx: &mut <Self as Actor>::Context) {
+        let addr = ctx.address();
+        actix::spawn(send_reply(addr, id, cmd.to_string(), reply));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{binary_frame, request_id, split_binary_frame, ws, ws_index, MAX_UPLOADS};
+    use crate::replica::Replication;
+    use crate::AppData;
+    use actix_web::rt::System;
+    use actix_web::{test, web, App};
+    use bytes::Bytes;
+    use futures::{Future, SinkExt, Stream, StreamExt};
+    use msg_store::Store;
+    use msg_store_database_checksum_plugin::{Corruption, CorruptionPolicy};
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::BlockingDb;
+    use msg_store_server_api::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use msg_store_server_api::config::StoreConfig;
+    use msg_store_server_api::file_storage::{get_file_path_from_id, FileStorage};
+    use msg_store_server_api::forward::Forwarder;
+    use msg_store_server_api::notify::Notifier;
+    use msg_store_server_api::stats::Stats;
+    use msg_store_uuid::Uuid;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::fmt::Debug;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    fn app_data(ws_max_body_length: u64) -> web::Data<AppData> {
+        app_data_with_file_storage(ws_max_body_length, None)
+    }
+
+    fn app_data_with_file_storage(ws_max_body_length: u64, file_storage: Option<FileStorage>) -> web::Data<AppData> {
+        web::Data::new(AppData {
+            store: Mutex::new(Store::new(None, None).unwrap()),
+            configuration: Mutex::new(StoreConfig::new()),
+            configuration_path: None,
+            db: Box::new(BlockingDb::new(Box::new(MemDb::new()))),
+            file_storage: file_storage.map(Mutex::new),
+            stored_byte_sizes: false,
+            stats: Mutex::new(Stats::new()),
+            notifier: Mutex::new(Notifier::new()),
+            changes: Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY)),
+            replication: Mutex::new(Replication::new(None)),
+            forwarder: Mutex::new(Forwarder::new(None)),
+            cluster: None,
+            corruption: Corruption::new(CorruptionPolicy::Serve),
+            ws_max_body_length
+        })
+    }
+
+    fn server(data: web::Data<AppData>) -> test::TestServer {
+        test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(web::resource("/ws").route(web::get().to(ws_index)))
+        })
+    }
+
+    fn run<F: Future<Output = ()> + 'static>(test: F) {
+        System::new("test").block_on(test)
+    }
+
+    /// Reads frames up to the next reply, skipping the heartbeat pings
+    async fn reply<S: Stream<Item = Result<ws::Frame, E>> + Unpin, E: Debug>(connection: &mut S) -> Value {
+        loop {
+            if let ws::Frame::Text(text) = connection.next().await.unwrap().unwrap() {
+                return serde_json::from_slice(&text).unwrap();
+            }
+        }
+    }
+
+    fn post(id: Value, body_length: usize) -> ws::Message {
+        post_with_headers(id, json!({ "priority": 1 }), body_length)
+    }
+
+    fn post_with_headers(id: Value, headers: Value, body_length: usize) -> ws::Message {
+        let command = json!({ "id": id, "cmd": "msg/post", "data": { "headers": headers, "bodyLength": body_length } });
+        ws::Message::Text(command.to_string())
+    }
+
+    fn chunk(id: &str, chunk: &[u8]) -> ws::Message {
+        ws::Message::Binary(binary_frame(id, chunk))
+    }
+
+    #[test]
+    fn should_prefix_binary_frames_with_the_request_id() {
+        assert_eq!(Some("7".to_string()), request_id(&json!(7)));
+        assert_eq!(Some("a".to_string()), request_id(&json!("a")));
+        assert_eq!(None, request_id(&json!({ "id": 7 })));
+        let frame = binary_frame("my-id", b"my chunk");
+        assert_eq!(&[0, 5], &frame[..2]);
+        assert_eq!(Some(("my-id".to_string(), &b"my chunk"[..])), split_binary_frame(&frame));
+        assert_eq!(Some(("my-id".to_string(), &b""[..])), split_binary_frame(&binary_frame("my-id", b"")));
+        // the id is longer than the frame
+        assert_eq!(None, split_binary_frame(&frame[..4]));
+        assert_eq!(None, split_binary_frame(&[0]));
+    }
+
+    #[test]
+    fn should_add_interleaved_uploads() {
+        run(async {
+            let data = app_data(1024);
+            let mut srv = server(data.clone());
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            connection.send(post(json!("a"), 6)).await.unwrap();
+            connection.send(post(json!(2), 4)).await.unwrap();
+            connection.send(chunk("a", b"abc")).await.unwrap();
+            connection.send(chunk("2", b"wx")).await.unwrap();
+            connection.send(chunk("a", b"def")).await.unwrap();
+            connection.send(chunk("2", b"yz")).await.unwrap();
+            let mut uuids = BTreeMap::new();
+            for _ in 0..2 {
+                let reply = reply(&mut connection).await;
+                assert_eq!(200, reply["code"]);
+                let uuid = Uuid::from_string(reply["data"]["uuid"].as_str().unwrap()).unwrap();
+                uuids.insert(reply["id"].to_string(), uuid);
+            }
+            assert_eq!(Bytes::from_static(b"abcdef"), data.db.get(uuids["\"a\""].clone()).await.unwrap());
+            assert_eq!(Bytes::from_static(b"wxyz"), data.db.get(uuids["2"].clone()).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn should_drop_a_client_that_misses_the_heartbeat() {
+        run(async {
+            let mut srv = server(app_data(1024));
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            // the pings are never answered
+            let closed = actix_web::rt::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    match connection.next().await {
+                        Some(Ok(ws::Frame::Ping(_))) => continue,
+                        Some(Ok(ws::Frame::Close(_))) | Some(Err(_)) | None => return,
+                        Some(Ok(frame)) => panic!("unexpected frame {:?}", frame)
+                    }
+                }
+            });
+            assert!(closed.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn should_refuse_a_body_that_overruns_its_length() {
+        run(async {
+            let data = app_data(8);
+            let mut srv = server(data.clone());
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            connection.send(post(json!(1), 9)).await.unwrap();
+            let refused = reply(&mut connection).await;
+            assert_eq!(json!(1), refused["id"]);
+            assert_eq!(400, refused["code"]);
+            assert_eq!("/data/bodyLength exceeds the maximum of 8 bytes", refused["message"]);
+
+            connection.send(post(json!(2), 3)).await.unwrap();
+            connection.send(chunk("2", b"ab")).await.unwrap();
+            connection.send(chunk("2", b"cd")).await.unwrap();
+            let overrun = reply(&mut connection).await;
+            assert_eq!(json!(2), overrun["id"]);
+            assert_eq!(400, overrun["code"]);
+            assert_eq!("msg exceeds /data/bodyLength", overrun["message"]);
+            // the id is free again and the cut off msg was not added
+            connection.send(post(json!(2), 3)).await.unwrap();
+            connection.send(chunk("2", b"abc")).await.unwrap();
+            assert_eq!(200, reply(&mut connection).await["code"]);
+            assert_eq!(1, data.store.lock().unwrap().id_to_group_map.len());
+        });
+    }
+
+    #[test]
+    fn should_free_the_id_of_a_command_that_failed_before_its_body() {
+        run(async {
+            let data = app_data(1024);
+            let mut srv = server(data.clone());
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            connection.send(post_with_headers(json!(1), json!({ "priority": "high" }), 3)).await.unwrap();
+            let failed = reply(&mut connection).await;
+            assert_eq!(json!(1), failed["id"]);
+            assert_eq!(400, failed["code"]);
+            connection.send(chunk("1", b"abc")).await.unwrap();
+            assert_eq!(404, reply(&mut connection).await["code"]);
+            connection.send(post(json!(1), 3)).await.unwrap();
+            connection.send(chunk("1", b"abc")).await.unwrap();
+            assert_eq!(200, reply(&mut connection).await["code"]);
+            assert_eq!(1, data.store.lock().unwrap().id_to_group_map.len());
+        });
+    }
+
+    #[test]
+    fn should_cap_the_uploads_waiting_for_their_body() {
+        run(async {
+            let mut srv = server(app_data(1024));
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            for id in 0..=MAX_UPLOADS {
+                connection.send(post(json!(id), 1)).await.unwrap();
+            }
+            let refused = reply(&mut connection).await;
+            assert_eq!(json!(MAX_UPLOADS), refused["id"]);
+            assert_eq!(400, refused["code"]);
+            connection.send(chunk("0", b"a")).await.unwrap();
+            let added = reply(&mut connection).await;
+            assert_eq!(json!(0), added["id"]);
+            assert_eq!(200, added["code"]);
+        });
+    }
+
+    #[test]
+    fn should_stream_a_body_to_file_storage() {
+        run(async {
+            let tmp_dir = TempDir::new("should_stream_a_body_to_file_storage").unwrap();
+            let data = app_data_with_file_storage(1024, Some(FileStorage::new(tmp_dir.path()).unwrap()));
+            let mut srv = server(data.clone());
+            let mut connection = srv.ws_at("/ws").await.unwrap();
+            connection.send(post_with_headers(json!(1), json!({ "priority": 1, "saveToFile": true }), 6)).await.unwrap();
+            connection.send(chunk("1", b"abc")).await.unwrap();
+            connection.send(chunk("1", b"def")).await.unwrap();
+            let reply = reply(&mut connection).await;
+            assert_eq!(200, reply["code"]);
+            let uuid = Uuid::from_string(reply["data"]["uuid"].as_str().unwrap()).unwrap();
+            assert!(data.file_storage.as_ref().unwrap().lock().unwrap().index.contains(&uuid));
+            assert!(get_file_path_from_id(tmp_dir.path(), &uuid).exists());
+        });
     }
 }
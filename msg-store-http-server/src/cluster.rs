@@ -0,0 +1,234 @@
+use actix_web::client::Client;
+use actix_web::http::header::LOCATION;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use crate::AppData;
+use futures::future::{select, Either};
+use log::{error, info};
+use msg_store_server_api::cluster::{Cluster, Command, Outcome};
+use msg_store_server_api::cluster::apply::handle as apply_command;
+use msg_store_server_api::cluster::raft::{Entry, Envelope, Snapshot};
+use msg_store_server_api::cluster::snapshot;
+use msg_store_server_api::msg::add::MsgError;
+use std::process::exit;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+/// The interval between the ticks of the raft node
+const TICK: Duration = Duration::from_millis(50);
+
+/// The time to wait for a peer to take a message, a message that is lost is sent again
+const SEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The time to wait for a proposal to be applied
+const PROPOSAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The reason given when a request that is not replicated is refused
+pub const NOT_REPLICATED: &str = "The request is not replicated across the cluster";
+
+/// The reason given when a websocket command would change the store of a cluster member
+pub const HTTP_ONLY: &str = "Writes to a cluster are taken over http";
+
+/// The reason given when there is no leader to take a write
+pub const NO_LEADER: &str = "The cluster has no leader";
+
+/// The reason given when a write was not applied in time
+pub const NOT_APPLIED: &str = "The write was not applied, it may be retried";
+
+/// What became of a write that was proposed to the cluster
+pub enum Proposal {
+    Applied(Option<MsgError>),
+    /// This node is not the leader, holds the address of the leader if known
+    NotLeader(Option<String>),
+    /// The write was lost to a new leader or was not applied in time
+    NotApplied
+}
+
+/// Whether a request changes the store without going through the log of the cluster
+///
+/// Msgs, group defaults and store defaults are replicated. Removing groups, consumer groups
+/// and exports are refused, so every node holds the same msgs.
+pub fn is_unreplicated_request(method: &actix_web::http::Method, path: &str) -> bool {
+    match path {
+        "/api/msg" | "/api/group-defaults" | "/api/store" | "/api/cluster/raft" | "/api/stats" => false,
+        "/api/export" => true,
+        _ => method != actix_web::http::Method::GET
+    }
+}
+
+pub fn lock(data: &AppData) -> Option<MutexGuard<'_, Cluster>> {
+    match &data.cluster {
+        Some(cluster) => match cluster.lock() {
+            Ok(cluster) => Some(cluster),
+            Err(err) => {
+                error!("CLUSTER_ERROR: Could not lock cluster. {}", err);
+                exit(1);
+            }
+        },
+        None => None
+    }
+}
+
+/// Drives the raft node of this server, sending its messages and applying committed entries
+///
+/// The log is compacted into a snapshot of the store once enough entries are applied.
+pub async fn run(data: Data<AppData>) {
+    // requests close their connection, a pooled connection to a peer that restarted would
+    // wait for the timeout
+    let client = Client::builder().timeout(SEND_TIMEOUT).finish();
+    loop {
+        actix::clock::delay_for(TICK).await;
+        let (messages, snapshot, committed) = match lock(&data) {
+            Some(mut cluster) => {
+                if let Err(err) = cluster.raft.tick() {
+                    error!("CLUSTER_ERROR: Could not tick the raft node. {}", err);
+                    exit(1);
+                }
+                let messages = cluster.raft.take_messages()
+                    .into_iter()
+                    .filter_map(|envelope| cluster.members.get(&envelope.to).map(|address| (address.clone(), envelope)))
+                    .collect::<Vec<(String, Envelope<Command>)>>();
+                (messages, cluster.raft.take_snapshot(), cluster.raft.take_committed())
+            },
+            None => return
+        };
+        for (address, envelope) in messages {
+            let client = client.clone();
+            actix_web::rt::spawn(async move {
+                // a peer that is offline is retried with the next heartbeat
+                let _ = client
+                    .post(format!("http://{}/api/cluster/raft", address))
+                    .force_close()
+                    .send_json(&envelope)
+                    .await;
+            });
+        }
+        if let Some(snapshot) = snapshot {
+            install(&data, snapshot).await;
+        }
+        let applied = committed.last().map(|entry| entry.index);
+        for entry in committed {
+            apply(&data, entry).await;
+        }
+        if let Some(index) = applied {
+            compact(&data, index).await;
+        }
+    }
+}
+
+/// Resets the store to a snapshot the raft node was given
+async fn install(data: &AppData, snapshot: Snapshot) {
+    let result = snapshot::install(
+        &data.store,
+        &data.db,
+        &data.file_storage,
+        &data.stats,
+        &data.notifier,
+        &data.changes,
+        &data.configuration,
+        &data.configuration_path,
+        &snapshot.data).await;
+    if let Err(err) = result {
+        error!("CLUSTER_ERROR: Could not install the snapshot at {}. {}", snapshot.index, err);
+        exit(1);
+    }
+    info!("Installed the snapshot of the cluster at {}", snapshot.index);
+}
+
+/// Compacts the log up to the entry that was applied last, once enough entries were applied
+async fn compact(data: &AppData, index: u64) {
+    match lock(data) {
+        Some(cluster) if cluster.raft.should_compact() => (),
+        _ => return
+    }
+    // only the loop of the raft node changes the store, so the store is at index
+    let snapshot_data = match snapshot::take(&data.store, &data.db).await {
+        Ok(snapshot_data) => snapshot_data,
+        Err(err) => {
+            error!("CLUSTER_ERROR: Could not take a snapshot at {}. {}", index, err);
+            exit(1);
+        }
+    };
+    if let Some(mut cluster) = lock(data) {
+        if let Err(err) = cluster.raft.compact(index, snapshot_data) {
+            error!("CLUSTER_ERROR: Could not compact the log at {}. {}", index, err);
+            exit(1);
+        }
+    }
+}
+
+/// Applies a committed entry and resolves the proposal waiting on it
+async fn apply(data: &AppData, entry: Entry<Command>) {
+    let refusal = match entry.command.clone() {
+        Some(command) => {
+            let result = apply_command(
+                &data.store,
+                &data.db,
+                &data.file_storage,
+                &data.stats,
+                &data.notifier,
+                &data.changes,
+                &data.configuration,
+                &data.configuration_path,
+                command).await;
+            match result {
+                Ok(refusal) => refusal,
+                Err(err) => {
+                    error!("CLUSTER_ERROR: Could not apply entry {}. {}", entry.index, err);
+                    exit(1);
+                }
+            }
+        },
+        None => None
+    };
+    if let Some(mut cluster) = lock(data) {
+        cluster.resolve(&entry, refusal);
+    }
+}
+
+/// Proposes a command to the cluster and waits for it to be applied
+pub async fn propose(data: &AppData, command: Command) -> Proposal {
+    let receiver = match lock(data) {
+        Some(mut cluster) => match cluster.propose(command) {
+            Ok(Ok(receiver)) => receiver,
+            Ok(Err(leader)) => return Proposal::NotLeader(leader),
+            Err(err) => {
+                error!("CLUSTER_ERROR: Could not propose a command. {}", err);
+                exit(1);
+            }
+        },
+        None => return Proposal::NotApplied
+    };
+    let timeout = actix::clock::delay_for(PROPOSAL_TIMEOUT);
+    match select(receiver, timeout).await {
+        Either::Left((Ok(Outcome::Applied(refusal)), _)) => Proposal::Applied(refusal),
+        Either::Left((Ok(Outcome::Lost), _)) | Either::Left((Err(_), _)) | Either::Right(_) => Proposal::NotApplied
+    }
+}
+
+/// Responds to a proposed write, sending the client to the leader if known
+pub fn respond(route: &str, req: &HttpRequest, proposal: Proposal, applied: HttpResponse) -> HttpResponse {
+    match proposal {
+        Proposal::Applied(None) => {
+            info!("{} 200", route);
+            applied
+        },
+        Proposal::NotLeader(Some(leader)) => {
+            let location = format!("http://{}{}", leader, req.uri());
+            info!("{} 307 {}", route, location);
+            HttpResponse::TemporaryRedirect().header(LOCATION, location).finish()
+        },
+        Proposal::NotLeader(None) => {
+            info!("{} 503 {}", route, NO_LEADER);
+            HttpResponse::ServiceUnavailable().body(NO_LEADER)
+        },
+        Proposal::Applied(Some(msg_error)) => {
+            info!("{} 400 {}", route, msg_error);
+            HttpResponse::BadRequest().body(msg_error.to_string())
+        },
+        Proposal::NotApplied => {
+            info!("{} 503 {}", route, NOT_APPLIED);
+            HttpResponse::ServiceUnavailable().body(NOT_APPLIED)
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data};
+use bytes::BytesMut;
+use crate::AppData;
+use crate::api::msg::get::ReturnBody;
+use crate::replica;
+use log::{error, info};
+use msg_store_server_api::forward::is_permanent_rejection;
+use msg_store_server_api::msg::frame::{self, FrameError, CONTENT_TYPE, WireFormat};
+use msg_store_server_api::msg::get::{handle_wait, GetError};
+use msg_store_server_api::msg::rm::{handle as rm_msg, RemoveError};
+use msg_store_server_api::Either;
+use std::fmt::Display;
+use std::process::exit;
+use std::time::Duration;
+
+/// The first wait after a failed attempt, doubled with each failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The seconds to wait for a msg before checking whether forwarding is still enabled
+const WAIT: u64 = 30;
+
+#[derive(Debug)]
+pub enum ForwardErrorTy {
+    FrameError(FrameError),
+    GetError(GetError),
+    RemoveError(RemoveError),
+    CouldNotDecodeHeader,
+    CouldNotReachUpstream,
+    Rejected
+}
+impl Display for ForwardErrorTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FrameError(err) => write!(f, "({:?})", err),
+            Self::GetError(err) => write!(f, "({})", err),
+            Self::RemoveError(err) => write!(f, "({})", err),
+            Self::CouldNotDecodeHeader |
+            Self::CouldNotReachUpstream |
+            Self::Rejected => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ForwardError {
+    pub err_ty: ForwardErrorTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ForwardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "FORWARD_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "FORWARD_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! forward_error {
+    ($err_ty:expr) => {
+        ForwardError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ForwardError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Forwards msgs to the upstream until forwarding is disabled
+///
+/// A replica does not forward msgs, its msgs are removed by the primary. Forwarding starts
+/// once the replica is promoted.
+pub async fn forward(data: Data<AppData>, max_backoff: Duration) {
+    // requests close their connection, a pooled connection to an upstream that restarted
+    // would wait for the timeout
+    let client = Client::builder().timeout(Duration::from_secs(300)).finish();
+    loop {
+        let (upstream, backoff) = match data.forwarder.lock() {
+            Ok(forwarder) => match &forwarder.upstream {
+                Some(upstream) => (upstream.clone(), forwarder.backoff(INITIAL_BACKOFF, max_backoff)),
+                None => return
+            },
+            Err(err) => {
+                error!("FORWARD_ERROR: Could not lock forwarder. {}", err);
+                exit(1);
+            }
+        };
+        if replica::is_read_only(&data) {
+            actix::clock::delay_for(INITIAL_BACKOFF).await;
+            continue;
+        }
+        if backoff > Duration::from_secs(0) {
+            actix::clock::delay_for(backoff).await;
+        }
+        if let Err(err) = forward_next(&data, &client, &upstream).await {
+            error!("{}", err);
+            match data.forwarder.lock() {
+                Ok(mut forwarder) => forwarder.record_failure(err.to_string()),
+                Err(err) => {
+                    error!("FORWARD_ERROR: Could not lock forwarder. {}", err);
+                    exit(1);
+                }
+            };
+        }
+    }
+}
+
+/// Sends the next msg to the upstream and removes it once the upstream added it, or rejected
+/// it with a status it would respond with to every attempt
+async fn forward_next(data: &AppData, client: &Client, upstream: &str) -> Result<(), ForwardError> {
+    let timeout = actix::clock::delay_for(Duration::from_secs(WAIT));
+    let msg_type = match handle_wait(&data.store, &data.db, &data.file_storage, &data.notifier, None, false, WireFormat::Framed, timeout).await {
+        Ok(Some(msg_type)) => msg_type,
+        Ok(None) => return Ok(()),
+        Err(err) => return Err(forward_error!(ForwardErrorTy::GetError(err)))
+    };
+    let (uuid, header) = match &msg_type {
+        Either::A(buffer) => (buffer.uuid.clone(), buffer.header.clone()),
+        Either::B(msg) => (msg.uuid.clone(), msg.header.clone())
+    };
+    // the upstream gives the msg a uuid of its own, the priority is taken from the uuid
+    let (mut headers, body_len, _head_len) = match frame::decode_head(&header) {
+        Ok(Some(head)) => head,
+        Ok(None) => return Err(forward_error!(ForwardErrorTy::CouldNotDecodeHeader)),
+        Err(err) => return Err(forward_error!(ForwardErrorTy::FrameError(err)))
+    };
+    headers.remove("uuid");
+    headers.insert("priority".to_string(), uuid.priority.to_string());
+    let head = match frame::encode_head(&headers, body_len) {
+        Ok(head) => head,
+        Err(err) => return Err(forward_error!(ForwardErrorTy::FrameError(err)))
+    };
+    let byte_size = match data.store.lock() {
+        Ok(store) => match store.id_to_group_map.get(&uuid) {
+            Some(priority) => store.groups_map.get(priority).and_then(|group| group.msgs_map.get(&uuid).cloned()),
+            None => None
+        },
+        Err(err) => {
+            error!("FORWARD_ERROR: Could not lock store. {}", err);
+            exit(1);
+        }
+    };
+    let byte_size = match byte_size {
+        Some(byte_size) => byte_size,
+        // removed while it was being read
+        None => return Ok(())
+    };
+    let request = client
+        .post(format!("http://{}/api/msg", upstream))
+        .force_close()
+        .content_type(CONTENT_TYPE);
+    let send_result = match msg_type {
+        Either::A(mut buffer) => {
+            buffer.header = head;
+            request.send_stream(ReturnBody::new(buffer)).await
+        },
+        Either::B(msg) => {
+            let mut body = BytesMut::with_capacity(head.len() + msg.msg.len());
+            body.extend_from_slice(&head);
+            body.extend_from_slice(&msg.msg);
+            request.send_body(Bytes::copy_from_slice(&body)).await
+        }
+    };
+    let mut response = match send_result {
+        Ok(response) => response,
+        Err(err) => return Err(forward_error!(ForwardErrorTy::CouldNotReachUpstream, err))
+    };
+    let mut rejection = None;
+    if response.status() != StatusCode::OK {
+        let reason = match response.body().await {
+            Ok(body) => String::from_utf8_lossy(&body).to_string(),
+            Err(err) => err.to_string()
+        };
+        let reason = format!("{} {}", response.status(), reason);
+        // a msg the upstream will never add would hold back the msgs after it
+        if !is_permanent_rejection(response.status().as_u16()) {
+            return Err(forward_error!(ForwardErrorTy::Rejected, reason));
+        }
+        rejection = Some(reason);
+    }
+    if let Err(err) = rm_msg(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, uuid.clone()).await {
+        return Err(forward_error!(ForwardErrorTy::RemoveError(err)));
+    }
+    match data.forwarder.lock() {
+        Ok(mut forwarder) => match &rejection {
+            Some(reason) => forwarder.record_rejected(uuid.priority, byte_size, reason.clone()),
+            None => forwarder.record_forwarded(uuid.priority, byte_size)
+        },
+        Err(err) => {
+            error!("FORWARD_ERROR: Could not lock forwarder. {}", err);
+            exit(1);
+        }
+    };
+    match rejection {
+        Some(reason) => error!("FORWARD_ERROR: Removed {}, {} rejected it. {}", uuid.to_string(), upstream, reason),
+        None => info!("Forwarded {} to {}", uuid.to_string(), upstream)
+    }
+    Ok(())
+}
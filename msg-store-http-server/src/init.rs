@@ -1,9 +1,24 @@
 use clap::{App, Arg};
 use dirs::home_dir;
 use msg_store::{Store, StoreDefaults, GroupDefaults, StoreError};
-use msg_store_database_plugin::{Db, DatabaseError};
+use log::info;
+#[cfg(feature = "leveldb")]
+use msg_store_database_plugin::copy_all;
+use msg_store_database_plugin::{Batch, BlockingDb, Db, DatabaseError, Log};
+use msg_store_database_checksum_plugin::{ChecksummedDb, Corruption, CorruptionPolicy};
+use msg_store_database_compressed_plugin::{Algorithm, CompressedDb, Compression, DEFAULT_THRESHOLD};
+use msg_store_database_encrypted_plugin::{EncryptedDb, Keyring};
 use msg_store_database_in_memory_plugin::MemDb;
+#[cfg(feature = "leveldb")]
 use msg_store_database_leveldb_plugin::Leveldb;
+#[cfg(feature = "sqlite")]
+use msg_store_database_sqlite_plugin::Sqlite;
+use msg_store_database_redb_plugin::{Redb, RedbLog};
+#[cfg(feature = "leveldb")]
+use msg_store_database_redb_plugin::DATABASE_FILE as REDB_DATABASE_FILE;
+use msg_store_server_api::changes::{Change, ChangeFeed, ChangeFeedError, DEFAULT_CAPACITY, DEFAULT_LOG_RETENTION};
+use msg_store_server_api::cluster::{Cluster, Command};
+use msg_store_server_api::cluster::storage::{LogStorage, Storage, StorageError};
 use msg_store_server_api::file_storage::{
     FileStorage,
     FileStorageError,
@@ -12,20 +27,41 @@ use msg_store_server_api::file_storage::{
     rm_from_file_storage
 };
 use msg_store_server_api::stats::Stats;
+use msg_store_server_api::Database;
 use msg_store_server_api::config::{StoreConfig, ConfigError};
+use msg_store_uuid::{Clock, SystemClock};
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
-use std::sync::Mutex;
+#[cfg(feature = "leveldb")]
+use std::fs::{remove_dir_all, rename};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub struct InitResult {
     pub host: String,
     pub store: Mutex<Store>,
     pub configuration: Mutex<StoreConfig>,
-    pub db: Mutex<Box<dyn Db>>,
+    pub db: Database,
     pub configuration_path: Option<PathBuf>,
     pub file_storage: Option<Mutex<FileStorage>>,
-    pub stats: Mutex<Stats>
+    /// Whether msgs are counted by the size they are stored at
+    pub stored_byte_sizes: bool,
+    pub stats: Mutex<Stats>,
+    pub changes: Mutex<ChangeFeed>,
+    pub replicate_from: Option<String>,
+    pub replication_interval: Duration,
+    pub forward_to: Option<String>,
+    pub forward_max_backoff: Duration,
+    pub cluster: Option<Mutex<Cluster>>,
+    pub maintenance_interval: Duration,
+    /// The msgs that were found to be corrupt
+    pub corruption: Corruption,
+    pub scrub_interval: Duration,
+    /// The clock of the store, the scrub interval is measured by it too
+    pub clock: Arc<dyn Clock>,
+    pub ws_max_body_length: u64
 }
 
 const HOST: &'static str = "host";
@@ -36,43 +72,134 @@ const UPDATE: &'static str = "update";
 const NO_UPDATE: &'static str = "no-update";
 const DATABASE: &'static str = "database";
 const LEVELDB_PATH: &'static str = "leveldb-path";
+const SQLITE_PATH: &str = "sqlite-path";
+const REDB_PATH: &str = "redb-path";
+#[cfg(feature = "leveldb")]
+const MIGRATE_FROM_LEVELDB: &str = "migrate-from-leveldb";
+const COMPRESSION: &str = "compression";
+const COMPRESSION_THRESHOLD: &str = "compression-threshold";
+const COMPRESS_FILE_STORAGE: &str = "compress-file-storage";
+const BYTE_SIZE_ACCOUNTING: &str = "byte-size-accounting";
+const ENCRYPTION_KEY_PATH: &str = "encryption-key-path";
+const MAINTENANCE_INTERVAL: &str = "maintenance-interval";
+const CORRUPTION_POLICY: &str = "corruption-policy";
+const QUARANTINE_PATH: &str = "quarantine-path";
+const SCRUB_INTERVAL: &str = "scrub-interval";
 const FILE_STORAGE: &'static str = "file-storage";
 const FILE_STORAGE_PATH: &'static str = "file-storage-path";
 const NODE_ID: &'static str = "node-id";
+const CHANGE_LOG: &str = "change-log";
+const CHANGE_LOG_PATH: &str = "change-log-path";
+const CHANGE_FEED_CAPACITY: &str = "change-feed-capacity";
+const CHANGE_LOG_RETENTION: &str = "change-log-retention";
+const REPLICATE_FROM: &str = "replicate-from";
+const REPLICATION_INTERVAL: &str = "replication-interval";
+const FORWARD_TO: &str = "forward-to";
+const FORWARD_MAX_BACKOFF: &str = "forward-max-backoff";
+const CLUSTER: &str = "cluster";
+const CLUSTER_PATH: &str = "cluster-path";
+const WS_MAX_BODY_LENGTH: &str = "ws-max-body-length";
+
+/// The milliseconds a replica waits before polling the primary again
+const DEFAULT_REPLICATION_INTERVAL: u64 = 1000;
+/// The most milliseconds the forwarder waits between attempts while the upstream is offline
+const DEFAULT_FORWARD_MAX_BACKOFF: u64 = 60_000;
+/// The largest body a msg/post command over a websocket may announce
+const DEFAULT_WS_MAX_BODY_LENGTH: u64 = 64 * 1024 * 1024;
+/// The milliseconds between runs of the upkeep of the database
+const DEFAULT_MAINTENANCE_INTERVAL: u64 = 60_000;
+/// The milliseconds between walks over every msg to find the corrupt ones
+const DEFAULT_SCRUB_INTERVAL: u64 = 86_400_000;
+/// How many msgs are loaded from the database between progress messages
+const LOAD_PROGRESS_INTERVAL: u64 = 1_000_000;
+/// How many msgs are committed at a time while a database is migrated
+#[cfg(feature = "leveldb")]
+const MIGRATION_BATCH_SIZE: usize = 1_000;
+/// The databases this build includes
+const DATABASES: &[&str] = &[
+    "mem",
+    "memory",
+    #[cfg(feature = "leveldb")]
+    "leveldb",
+    #[cfg(feature = "sqlite")]
+    "sqlite",
+    "redb"
+];
 
 #[derive(Debug)]
 pub enum InitErrorTy {
+    ChangeFeedError(ChangeFeedError),
+    ClusterStorageError(StorageError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
     ConfigError(ConfigError),
     StoreError(StoreError),
+    ClusterOptionConflict,
+    CouldNotCreateChangeLogPath,
+    CouldNotCreateClusterPath,
     CouldNotCreateDatabaseDirectory,
     CouldNotCreateDatabasePath,
     CouldNotCreateFileStoragePath,
     CouldNotCreateMsgStoreDirectory,
+    CouldNotCreateQuarantinePath,
+    #[cfg_attr(not(feature = "leveldb"), allow(dead_code))]
+    CouldNotMigrateDatabase,
     CouldNotWriteToConfigurationFile,
+    InvalidByteSizeAccountingOption,
+    InvalidChangeFeedCapacity,
+    InvalidChangeLogRetention,
+    InvalidCompressionOption,
+    InvalidCorruptionPolicy,
     InvalidDatabaseOption,
+    InvalidForwardMaxBackoff,
+    InvalidMaintenanceInterval,
     InvalidNodeId,
     InvalidPortOption,
+    InvalidReplicationInterval,
+    InvalidScrubInterval,
+    InvalidWsMaxBodyLength,
+    #[cfg_attr(not(feature = "leveldb"), allow(dead_code))]
     MissingLeveldbPath,
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    MissingSqlitePath,
+    MissingRedbPath,
     UpdateOptionConflict
 }
 impl Display for InitErrorTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
+            Self::ClusterStorageError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::ConfigError(err) => write!(f, "({})", err),
             Self::StoreError(err) => write!(f, "({})", err),
+            Self::ClusterOptionConflict |
+            Self::CouldNotCreateChangeLogPath |
+            Self::CouldNotCreateClusterPath |
             Self::CouldNotCreateDatabaseDirectory |
             Self::CouldNotCreateDatabasePath |
             Self::CouldNotCreateFileStoragePath |
             Self::CouldNotCreateMsgStoreDirectory |
+            Self::CouldNotCreateQuarantinePath |
+            Self::CouldNotMigrateDatabase |
             Self::CouldNotWriteToConfigurationFile |
+            Self::InvalidByteSizeAccountingOption |
+            Self::InvalidChangeFeedCapacity |
+            Self::InvalidChangeLogRetention |
+            Self::InvalidCompressionOption |
+            Self::InvalidCorruptionPolicy |
             Self::InvalidDatabaseOption |
+            Self::InvalidForwardMaxBackoff |
+            Self::InvalidMaintenanceInterval |
             Self::InvalidNodeId |
             Self::InvalidPortOption |
+            Self::InvalidReplicationInterval |
+            Self::InvalidScrubInterval |
+            Self::InvalidWsMaxBodyLength |
             Self::MissingLeveldbPath |
+            Self::MissingSqlitePath |
+            Self::MissingRedbPath |
             Self::UpdateOptionConflict => write!(f, "({:#?})", self)
         }
     }
@@ -115,8 +242,81 @@ macro_rules! init_error {
     };
 }
 
+/// Copies the msgs and cursors of a leveldb database into a new redb database
+///
+/// The copy is made in a directory of its own and only moved into the redb directory once it
+/// is complete, so an interrupted migration is started over the next time. Nothing is copied
+/// if the redb directory already holds a database. Returns the number of msgs copied.
+#[cfg(feature = "leveldb")]
+#[allow(clippy::result_large_err)]
+fn migrate_from_leveldb(leveldb_path: &Path, redb_path: &Path) -> Result<Option<u64>, InitError> {
+    let database_file = redb_path.join(REDB_DATABASE_FILE);
+    if database_file.exists() {
+        return Ok(None)
+    }
+    if !leveldb_path.exists() {
+        return Err(init_error!(InitErrorTy::CouldNotMigrateDatabase, format!("{} does not exist", leveldb_path.display())))
+    }
+    let migration_path = redb_path.join("migration");
+    if migration_path.exists() {
+        if let Err(error) = remove_dir_all(&migration_path) {
+            return Err(init_error!(InitErrorTy::CouldNotMigrateDatabase, error))
+        }
+    }
+    let mut leveldb = match Leveldb::new(leveldb_path) {
+        Ok(leveldb) => Ok(leveldb),
+        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+    }?;
+    let mut redb = match Redb::new(&migration_path) {
+        Ok(redb) => Ok(redb),
+        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+    }?;
+    info!("Migrating msgs from {}", leveldb_path.display());
+    let msg_count = match copy_all(&mut leveldb, &mut redb, MIGRATION_BATCH_SIZE) {
+        Ok(msg_count) => Ok(msg_count),
+        Err(error) => Err(init_error!(InitErrorTy::CouldNotMigrateDatabase, error))
+    }?;
+    // the copy is closed before it is moved
+    drop(redb);
+    if let Err(error) = rename(migration_path.join(REDB_DATABASE_FILE), &database_file) {
+        return Err(init_error!(InitErrorTy::CouldNotMigrateDatabase, error))
+    }
+    if let Err(error) = remove_dir_all(&migration_path) {
+        return Err(init_error!(InitErrorTy::CouldNotMigrateDatabase, error))
+    }
+    Ok(Some(msg_count))
+}
+
+/// Opens the change log, which is kept in redb
+#[allow(clippy::result_large_err)]
+fn open_change_log(change_log_path: &Path) -> Result<Box<dyn Log>, InitError> {
+    if let Err(error) = create_dir_all(change_log_path) {
+        return Err(init_error!(InitErrorTy::CouldNotCreateChangeLogPath, error));
+    }
+    match RedbLog::new(change_log_path) {
+        Ok(change_log) => Ok(Box::new(change_log)),
+        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+    }
+}
+
+/// Opens the storage of the raft node, its log, hard state and snapshots are kept in redb
+#[allow(clippy::result_large_err)]
+fn open_cluster_storage(cluster_path: &Path) -> Result<Box<dyn Storage<Command>>, InitError> {
+    let open_log = |name: &str| -> Result<Box<dyn Log>, InitError> {
+        let log_path = cluster_path.join(name);
+        if let Err(error) = create_dir_all(&log_path) {
+            return Err(init_error!(InitErrorTy::CouldNotCreateClusterPath, error));
+        }
+        match RedbLog::new(&log_path) {
+            Ok(log) => Ok(Box::new(log)),
+            Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+        }
+    };
+    Ok(Box::new(LogStorage::new(open_log("entries")?, open_log("states")?, open_log("snapshots")?)))
+}
+
 fn get_app<'a>() -> App<'a, 'a> {
-    App::new("msg-store-server")
+    let app = App::new("msg-store-server")
         .version("0.1.0")
         .author("Joshua Enokson <kilograhm@pm.me>")
         .about("A priority message store")
@@ -164,7 +364,7 @@ fn get_app<'a>() -> App<'a, 'a> {
                 .short("d")
                 .long(DATABASE)
                 .takes_value(true)
-                .help("Determines the database to use. (memory or leveldb)")
+                .help("Determines the database to use. (memory, leveldb, sqlite or redb)")
         )
         .arg(
             Arg::with_name(LEVELDB_PATH)
@@ -172,6 +372,71 @@ fn get_app<'a>() -> App<'a, 'a> {
                 .help("Sets the leveldb database path")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name(SQLITE_PATH)
+                .long(SQLITE_PATH)
+                .help("Sets the directory of the sqlite database")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name(REDB_PATH)
+                .long(REDB_PATH)
+                .help("Sets the directory of the redb database")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name(COMPRESSION)
+                .long(COMPRESSION)
+                .takes_value(true)
+                .help("Compresses the msgs held in the database. (lz4 or zstd)")
+        )
+        .arg(
+            Arg::with_name(COMPRESSION_THRESHOLD)
+                .long(COMPRESSION_THRESHOLD)
+                .takes_value(true)
+                .help("Sets the byte size a msg must reach to be compressed")
+        )
+        .arg(
+            Arg::with_name(COMPRESS_FILE_STORAGE)
+                .long(COMPRESS_FILE_STORAGE)
+                .help("Compresses the messages held in files as well")
+        )
+        .arg(
+            Arg::with_name(BYTE_SIZE_ACCOUNTING)
+                .long(BYTE_SIZE_ACCOUNTING)
+                .takes_value(true)
+                .help("Counts msgs by their logical size or the size they are stored at. (logical or stored)")
+        )
+        .arg(
+            Arg::with_name(ENCRYPTION_KEY_PATH)
+                .long(ENCRYPTION_KEY_PATH)
+                .takes_value(true)
+                .help("Encrypts the msgs held in the database and in files with the keys of the key file")
+        )
+        .arg(
+            Arg::with_name(MAINTENANCE_INTERVAL)
+                .long(MAINTENANCE_INTERVAL)
+                .takes_value(true)
+                .help("Sets the milliseconds between runs of the upkeep of the database")
+        )
+        .arg(
+            Arg::with_name(CORRUPTION_POLICY)
+                .long(CORRUPTION_POLICY)
+                .takes_value(true)
+                .help("Determines what is done with corrupt msgs. (quarantine, delete or serve)")
+        )
+        .arg(
+            Arg::with_name(QUARANTINE_PATH)
+                .long(QUARANTINE_PATH)
+                .takes_value(true)
+                .help("Sets the location corrupt msgs are copied to before they are removed")
+        )
+        .arg(
+            Arg::with_name(SCRUB_INTERVAL)
+                .long(SCRUB_INTERVAL)
+                .takes_value(true)
+                .help("Sets the milliseconds between checks of every msg for corruption")
+        )
         .arg(
             Arg::with_name(FILE_STORAGE)
                 .short("f")
@@ -192,6 +457,79 @@ fn get_app<'a>() -> App<'a, 'a> {
                 .takes_value(true)
                 .help("Sets the node id of the msg-store"),
         )
+        .arg(
+            Arg::with_name(CHANGE_LOG)
+                .long(CHANGE_LOG)
+                .help("Persists the change feed at $HOME/.msg-store/change-log"),
+        )
+        .arg(
+            Arg::with_name(CHANGE_LOG_PATH)
+                .long(CHANGE_LOG_PATH)
+                .takes_value(true)
+                .help("Sets the location of the persisted change feed"),
+        )
+        .arg(
+            Arg::with_name(CHANGE_FEED_CAPACITY)
+                .long(CHANGE_FEED_CAPACITY)
+                .takes_value(true)
+                .help("Sets the number of changes held in memory"),
+        )
+        .arg(
+            Arg::with_name(CHANGE_LOG_RETENTION)
+                .long(CHANGE_LOG_RETENTION)
+                .takes_value(true)
+                .help("Sets the number of changes retained by the change log"),
+        )
+        .arg(
+            Arg::with_name(REPLICATE_FROM)
+                .long(REPLICATE_FROM)
+                .takes_value(true)
+                .help("Runs as a read-only replica of the primary at host:port"),
+        )
+        .arg(
+            Arg::with_name(REPLICATION_INTERVAL)
+                .long(REPLICATION_INTERVAL)
+                .takes_value(true)
+                .help("Sets the milliseconds a replica waits before polling the primary again"),
+        )
+        .arg(
+            Arg::with_name(FORWARD_TO)
+                .long(FORWARD_TO)
+                .takes_value(true)
+                .help("Forwards every msg to the upstream msg-store at host:port"),
+        )
+        .arg(
+            Arg::with_name(FORWARD_MAX_BACKOFF)
+                .long(FORWARD_MAX_BACKOFF)
+                .takes_value(true)
+                .help("Sets the most milliseconds to wait between attempts to reach the upstream"),
+        )
+        .arg(
+            Arg::with_name(CLUSTER)
+                .long(CLUSTER)
+                .takes_value(true)
+                .help("Joins a cluster of the msg-stores at host:port,host:port,... including this one"),
+        )
+        .arg(
+            Arg::with_name(CLUSTER_PATH)
+                .long(CLUSTER_PATH)
+                .takes_value(true)
+                .help("Sets the location of the log of the cluster"),
+        )
+        .arg(
+            Arg::with_name(WS_MAX_BODY_LENGTH)
+                .long(WS_MAX_BODY_LENGTH)
+                .takes_value(true)
+                .help("Sets the largest body in bytes a msg/post command over a websocket may announce"),
+        );
+    #[cfg(feature = "leveldb")]
+    let app = app.arg(
+        Arg::with_name(MIGRATE_FROM_LEVELDB)
+            .long(MIGRATE_FROM_LEVELDB)
+            .help("Copies the msgs of a leveldb database into a new redb database")
+            .takes_value(true)
+    );
+    app
 }
 
 pub fn init() -> Result<InitResult, InitError> {
@@ -241,8 +579,8 @@ pub fn init() -> Result<InitResult, InitError> {
         // validate values from configuration file
         if let Some(database) = &saved_configuration.database {
             let database = database.to_ascii_lowercase();
-            if database != "mem" && database != "memory" && database != "leveldb" {
-                return Err(init_error!(InitErrorTy::InvalidDatabaseOption, "Expected mem, memory or leveldb"));
+            if !DATABASES.contains(&database.as_str()) {
+                return Err(init_error!(InitErrorTy::InvalidDatabaseOption, format!("Expected one of {}", DATABASES.join(", "))));
             }
         }
         if let Some(no_update) = configuration.no_update {
@@ -256,7 +594,7 @@ pub fn init() -> Result<InitResult, InitError> {
     }
 
     // update config from cli options
-    // get host, port, no-update, database, leveldb-path, file-storage, file-storage-path
+    // get host, port, no-update, database, leveldb-path, sqlite-path, redb-path, file-storage, file-storage-path
     // update host from cli
     let host = {
         if let Some(host) = matches.value_of(HOST) {
@@ -304,10 +642,13 @@ pub fn init() -> Result<InitResult, InitError> {
             }
         }
     }
-    // update database, leveldb-path from cli
+    // update database, leveldb-path, sqlite-path, redb-path from cli
     if let Some(database) = matches.value_of(DATABASE) {
         // validate database option
         let database_lower = database.to_ascii_lowercase();
+        if !DATABASES.contains(&database_lower.as_str()) {
+            return Err(init_error!(InitErrorTy::InvalidDatabaseOption, format!("Expected one of {}", DATABASES.join(", "))));
+        }
         if database_lower == "leveldb" {
             // validate leveldb path option
             let leveldb_path = {
@@ -333,10 +674,48 @@ pub fn init() -> Result<InitResult, InitError> {
             configuration.leveldb_path = Some(leveldb_path);
             configuration.database = Some("leveldb".to_string());
 
+        } else if database_lower == "sqlite" {
+            let sqlite_path = match matches.value_of(SQLITE_PATH) {
+                Some(sqlite_path) => PathBuf::from(sqlite_path),
+                None => match configuration.sqlite_path {
+                    Some(sqlite_path) => sqlite_path,
+                    None => match home_dir() {
+                        Some(mut sqlite_path) => {
+                            sqlite_path.push(".msg-store/sqlite");
+                            sqlite_path
+                        },
+                        None => return Err(init_error!(InitErrorTy::CouldNotCreateDatabaseDirectory))
+                    }
+                }
+            };
+            if let Err(error) = create_dir_all(&sqlite_path) {
+                return Err(init_error!(InitErrorTy::CouldNotCreateDatabasePath, error));
+            }
+            configuration.sqlite_path = Some(sqlite_path);
+            configuration.database = Some("sqlite".to_string());
+        } else if database_lower == "redb" {
+            let redb_path = match matches.value_of(REDB_PATH) {
+                Some(redb_path) => PathBuf::from(redb_path),
+                None => match configuration.redb_path {
+                    Some(redb_path) => redb_path,
+                    None => match home_dir() {
+                        Some(mut redb_path) => {
+                            redb_path.push(".msg-store/redb");
+                            redb_path
+                        },
+                        None => return Err(init_error!(InitErrorTy::CouldNotCreateDatabaseDirectory))
+                    }
+                }
+            };
+            if let Err(error) = create_dir_all(&redb_path) {
+                return Err(init_error!(InitErrorTy::CouldNotCreateDatabasePath, error));
+            }
+            configuration.redb_path = Some(redb_path);
+            configuration.database = Some("redb".to_string());
         } else if database_lower == "mem" || database_lower == "memory" {
             configuration.database = Some("memory".to_string());
         } else {
-            return Err(init_error!(InitErrorTy::InvalidDatabaseOption, "Expected mem, memory or leveldb"));
+            return Err(init_error!(InitErrorTy::InvalidDatabaseOption, format!("Expected one of {}", DATABASES.join(", "))));
         }
     }
     // update file-storage, file-storage-path from cli
@@ -356,34 +735,167 @@ pub fn init() -> Result<InitResult, InitError> {
             }
         }
     }
+    // update compression, compression-threshold, compress-file-storage, byte-size-accounting from cli
+    if let Some(compression) = matches.value_of(COMPRESSION) {
+        configuration.compression = Some(compression.to_string());
+    }
+    if let Some(threshold_str) = matches.value_of(COMPRESSION_THRESHOLD) {
+        let threshold = match threshold_str.parse::<usize>() {
+            Ok(threshold) => Ok(threshold),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidCompressionOption, error))
+        }?;
+        configuration.compression_threshold = Some(threshold);
+    }
+    if matches.is_present(COMPRESS_FILE_STORAGE) {
+        configuration.compress_file_storage = Some(true);
+    }
+    if let Some(byte_size_accounting) = matches.value_of(BYTE_SIZE_ACCOUNTING) {
+        configuration.byte_size_accounting = Some(byte_size_accounting.to_string());
+    }
+    let compression = match &configuration.compression {
+        Some(algorithm) => match Algorithm::from_name(algorithm) {
+            Some(algorithm) => Some(Compression {
+                algorithm,
+                threshold: configuration.compression_threshold.unwrap_or(DEFAULT_THRESHOLD)
+            }),
+            None => return Err(init_error!(InitErrorTy::InvalidCompressionOption, "Expected lz4 or zstd"))
+        },
+        None => None
+    };
+    // msgs are counted by their logical size unless asked otherwise
+    let stored_byte_sizes = match configuration.byte_size_accounting.as_ref().map(|accounting| accounting.to_ascii_lowercase()) {
+        Some(accounting) if accounting == "stored" => true,
+        Some(accounting) if accounting == "logical" => false,
+        Some(_) => return Err(init_error!(InitErrorTy::InvalidByteSizeAccountingOption, "Expected logical or stored")),
+        None => false
+    };
+    // update encryption-key-path, maintenance-interval from cli
+    if let Some(encryption_key_path) = matches.value_of(ENCRYPTION_KEY_PATH) {
+        configuration.encryption_key_path = Some(PathBuf::from(encryption_key_path));
+    }
+    if let Some(interval_str) = matches.value_of(MAINTENANCE_INTERVAL) {
+        let interval = match interval_str.parse::<u64>() {
+            Ok(interval) => Ok(interval),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidMaintenanceInterval, error))
+        }?;
+        configuration.maintenance_interval = Some(interval);
+    }
+    let maintenance_interval = Duration::from_millis(configuration.maintenance_interval.unwrap_or(DEFAULT_MAINTENANCE_INTERVAL));
+    let keyring = match &configuration.encryption_key_path {
+        Some(encryption_key_path) => match Keyring::open(encryption_key_path) {
+            Ok(keyring) => Some(keyring),
+            Err(error) => return Err(init_error!(InitErrorTy::DatabaseError(error)))
+        },
+        None => None
+    };
+    // update corruption-policy, quarantine-path, scrub-interval from cli
+    if let Some(corruption_policy) = matches.value_of(CORRUPTION_POLICY) {
+        configuration.corruption_policy = Some(corruption_policy.to_string());
+    }
+    if let Some(quarantine_path) = matches.value_of(QUARANTINE_PATH) {
+        configuration.quarantine_path = Some(PathBuf::from(quarantine_path));
+    }
+    if let Some(interval_str) = matches.value_of(SCRUB_INTERVAL) {
+        let interval = match interval_str.parse::<u64>() {
+            Ok(interval) => Ok(interval),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidScrubInterval, error))
+        }?;
+        configuration.scrub_interval = Some(interval);
+    }
+    let scrub_interval = Duration::from_millis(configuration.scrub_interval.unwrap_or(DEFAULT_SCRUB_INTERVAL));
+    // corrupt msgs are quarantined unless asked otherwise
+    let quarantine_path = match &configuration.quarantine_path {
+        Some(quarantine_path) => quarantine_path.clone(),
+        None => match home_dir() {
+            Some(mut quarantine_path) => {
+                quarantine_path.push(".msg-store/quarantine");
+                quarantine_path
+            },
+            None => return Err(init_error!(InitErrorTy::CouldNotCreateQuarantinePath, "Home directory does not exist"))
+        }
+    };
+    let corruption_policy = match &configuration.corruption_policy {
+        Some(name) => match CorruptionPolicy::from_name(name, &quarantine_path) {
+            Some(corruption_policy) => corruption_policy,
+            None => return Err(init_error!(InitErrorTy::InvalidCorruptionPolicy, "Expected quarantine, delete or serve"))
+        },
+        None => CorruptionPolicy::Quarantine(quarantine_path)
+    };
+    if let CorruptionPolicy::Quarantine(quarantine_path) = &corruption_policy {
+        if let Err(error) = create_dir_all(quarantine_path) {
+            return Err(init_error!(InitErrorTy::CouldNotCreateQuarantinePath, error));
+        }
+    }
+    let corruption = Corruption::new(corruption_policy);
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
     // get database
     let mut database: Box<dyn Db> = {
         if let Some(database_type) = &configuration.database {
             let database_type = database_type.to_ascii_lowercase();
-            if database_type == "mem" || database_type == "memory" {
-                Box::new(MemDb::new())
-            } else if database_type == "leveldb" {
-                let level_db_path = match &configuration.leveldb_path {
-                    Some(level_db_path) => Ok(level_db_path),
-                    None => Err(init_error!(InitErrorTy::MissingLeveldbPath))
-                }?;
-                let leveldb = match Leveldb::new(level_db_path) {
-                    Ok(leveldb) => Ok(leveldb),
-                    Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
-                }?;
-                Box::new(leveldb)
-            } else {
-                return Err(init_error!(InitErrorTy::InvalidDatabaseOption));
+            match database_type.as_str() {
+                "mem" | "memory" => Box::new(MemDb::new()),
+                #[cfg(feature = "leveldb")]
+                "leveldb" => {
+                    let level_db_path = match &configuration.leveldb_path {
+                        Some(level_db_path) => Ok(level_db_path),
+                        None => Err(init_error!(InitErrorTy::MissingLeveldbPath))
+                    }?;
+                    let leveldb = match Leveldb::new(level_db_path) {
+                        Ok(leveldb) => Ok(leveldb),
+                        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+                    }?;
+                    Box::new(leveldb)
+                },
+                #[cfg(feature = "sqlite")]
+                "sqlite" => {
+                    let sqlite_path = match &configuration.sqlite_path {
+                        Some(sqlite_path) => Ok(sqlite_path),
+                        None => Err(init_error!(InitErrorTy::MissingSqlitePath))
+                    }?;
+                    let sqlite = match Sqlite::new(sqlite_path) {
+                        Ok(sqlite) => Ok(sqlite),
+                        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+                    }?;
+                    Box::new(sqlite)
+                },
+                "redb" => {
+                    let redb_path = match &configuration.redb_path {
+                        Some(redb_path) => Ok(redb_path),
+                        None => Err(init_error!(InitErrorTy::MissingRedbPath))
+                    }?;
+                    #[cfg(feature = "leveldb")]
+                    if let Some(leveldb_path) = matches.value_of(MIGRATE_FROM_LEVELDB) {
+                        match migrate_from_leveldb(Path::new(leveldb_path), redb_path)? {
+                            Some(msg_count) => info!("Migrated {} msgs from {} to {}", msg_count, leveldb_path, redb_path.display()),
+                            None => info!("Skipping the migration from {}, {} already holds a database", leveldb_path, redb_path.display())
+                        };
+                    }
+                    let redb = match Redb::new(redb_path) {
+                        Ok(redb) => Ok(redb),
+                        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+                    }?;
+                    Box::new(redb)
+                },
+                _ => return Err(init_error!(InitErrorTy::InvalidDatabaseOption, format!("Expected one of {}", DATABASES.join(", "))))
             }
         } else {
             return Err(init_error!(InitErrorTy::InvalidDatabaseOption));
         }
     };
-    // get the stored messages from the database
-    let msgs = match database.fetch() {
-        Ok(msgs) => Ok(msgs),
-        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
-    }?;
+    // msgs added from now on are checksummed as they are stored, the msgs added before are
+    // read as they are
+    database = Box::new(ChecksummedDb::new(database, corruption.clone(), scrub_interval, Some(clock.clone())));
+    // msgs added from now on are encrypted, the msgs added before are read as they are until
+    // they are re-encrypted in the background
+    if let Some(keyring) = &keyring {
+        database = Box::new(EncryptedDb::new(database, keyring.clone()));
+    }
+    // msgs added from now on are compressed, the msgs added before are read as they are and
+    // msgs that were compressed are still read once compression is turned off
+    database = match compression {
+        Some(compression) => Box::new(CompressedDb::new(database, compression)),
+        None => Box::new(CompressedDb::decompressing(database))
+    };
     // get file list of all files stored on disk
     let mut file_storage: Option<FileStorage> = {
         if let Some(file_storage_path) = &configuration.file_storage_path {
@@ -401,11 +913,95 @@ pub fn init() -> Result<InitResult, InitError> {
                 }
             };
             discover_files(&mut file_storage, uuids);
+            if configuration.compress_file_storage == Some(true) {
+                file_storage.compression = compression;
+            }
+            file_storage.keyring = keyring.clone();
+            file_storage.corruption = corruption.clone();
             Some(file_storage)
         } else {
             None
         }
     };
+    // update change-log, change-log-path, change-feed-capacity from cli
+    if let Some(change_log_path) = matches.value_of(CHANGE_LOG_PATH) {
+        configuration.change_log_path = Some(PathBuf::from(change_log_path));
+    } else if matches.is_present(CHANGE_LOG) {
+        if let Some(home_dir) = home_dir() {
+            let mut change_log_path = home_dir;
+            change_log_path.push(".msg-store/change-log");
+            configuration.change_log_path = Some(change_log_path);
+        } else {
+            return Err(init_error!(InitErrorTy::CouldNotCreateChangeLogPath, "Home directory does not exist"));
+        }
+    }
+    if let Some(capacity_str) = matches.value_of(CHANGE_FEED_CAPACITY) {
+        let capacity = match capacity_str.parse::<usize>() {
+            Ok(capacity) => Ok(capacity),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidChangeFeedCapacity, error))
+        }?;
+        configuration.change_feed_capacity = Some(capacity);
+    }
+    if let Some(retention_str) = matches.value_of(CHANGE_LOG_RETENTION) {
+        let retention = match retention_str.parse::<u64>() {
+            Ok(retention) => Ok(retention),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidChangeLogRetention, error))
+        }?;
+        configuration.change_log_retention = Some(retention);
+    }
+    // the log has to keep its last change to continue its sequence numbers after a restart
+    if configuration.change_log_retention == Some(0) {
+        return Err(init_error!(InitErrorTy::InvalidChangeLogRetention, "The change log has to retain at least one change"));
+    }
+    // get change feed
+    let mut changes = {
+        let capacity = configuration.change_feed_capacity.unwrap_or(DEFAULT_CAPACITY);
+        if let Some(change_log_path) = &configuration.change_log_path {
+            let change_log = open_change_log(change_log_path)?;
+            let retention = configuration.change_log_retention.unwrap_or(DEFAULT_LOG_RETENTION);
+            match ChangeFeed::with_log(capacity, change_log, retention) {
+                Ok(changes) => Ok(changes),
+                Err(error) => Err(init_error!(InitErrorTy::ChangeFeedError(error)))
+            }?
+        } else {
+            ChangeFeed::new(capacity)
+        }
+    };
+    // update replicate-from, replication-interval from cli
+    if let Some(replicate_from) = matches.value_of(REPLICATE_FROM) {
+        configuration.replicate_from = Some(replicate_from.to_string());
+    }
+    if let Some(interval_str) = matches.value_of(REPLICATION_INTERVAL) {
+        let interval = match interval_str.parse::<u64>() {
+            Ok(interval) => Ok(interval),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidReplicationInterval, error))
+        }?;
+        configuration.replication_interval = Some(interval);
+    }
+    let replicate_from = configuration.replicate_from.clone();
+    let replication_interval = Duration::from_millis(configuration.replication_interval.unwrap_or(DEFAULT_REPLICATION_INTERVAL));
+    // update forward-to, forward-max-backoff from cli
+    if let Some(forward_to) = matches.value_of(FORWARD_TO) {
+        configuration.forward_to = Some(forward_to.to_string());
+    }
+    if let Some(max_backoff_str) = matches.value_of(FORWARD_MAX_BACKOFF) {
+        let max_backoff = match max_backoff_str.parse::<u64>() {
+            Ok(max_backoff) => Ok(max_backoff),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidForwardMaxBackoff, error))
+        }?;
+        configuration.forward_max_backoff = Some(max_backoff);
+    }
+    let forward_to = configuration.forward_to.clone();
+    let forward_max_backoff = Duration::from_millis(configuration.forward_max_backoff.unwrap_or(DEFAULT_FORWARD_MAX_BACKOFF));
+    // update ws-max-body-length from cli
+    if let Some(max_body_length_str) = matches.value_of(WS_MAX_BODY_LENGTH) {
+        let max_body_length = match max_body_length_str.parse::<u64>() {
+            Ok(max_body_length) => Ok(max_body_length),
+            Err(error) => Err(init_error!(InitErrorTy::InvalidWsMaxBodyLength, error))
+        }?;
+        configuration.ws_max_body_length = Some(max_body_length);
+    }
+    let ws_max_body_length = configuration.ws_max_body_length.unwrap_or(DEFAULT_WS_MAX_BODY_LENGTH);
     // get node_id
     // update configuration only if match is found
     if let Some(node_id_str) = matches.value_of(NODE_ID) {
@@ -415,8 +1011,70 @@ pub fn init() -> Result<InitResult, InitError> {
         }?;
         configuration.node_id = Some(node_id);
     }
+    // update cluster from cli
+    if let Some(cluster_str) = matches.value_of(CLUSTER) {
+        configuration.cluster = Some(cluster_str.split(',').map(|member| member.trim().to_string()).collect());
+    }
+    if let Some(cluster_path) = matches.value_of(CLUSTER_PATH) {
+        configuration.cluster_path = Some(PathBuf::from(cluster_path));
+    }
+    // the node id of a member is its position in the cluster
+    let (node_id, cluster) = match &configuration.cluster {
+        Some(addresses) => {
+            let address = format!("{}:{}", host, port);
+            let mut members = BTreeMap::new();
+            let mut node_id = None;
+            for (position, member) in addresses.iter().enumerate() {
+                let member_id = match u16::try_from(position + 1) {
+                    Ok(member_id) => Ok(member_id),
+                    Err(error) => Err(init_error!(InitErrorTy::InvalidNodeId, error))
+                }?;
+                if members.values().any(|other| other == member) {
+                    return Err(init_error!(InitErrorTy::ClusterOptionConflict, format!("{} is listed twice", member)));
+                }
+                if *member == address {
+                    node_id = Some(member_id);
+                }
+                members.insert(member_id, member.clone());
+            }
+            let node_id = match node_id {
+                Some(node_id) => Ok(node_id),
+                None => Err(init_error!(InitErrorTy::ClusterOptionConflict, format!("{} is not a member of the cluster", address)))
+            }?;
+            // every member applies the log of the cluster to an empty store
+            let in_memory = match &configuration.database {
+                Some(database) => {
+                    let database = database.to_ascii_lowercase();
+                    database == "mem" || database == "memory"
+                },
+                None => false
+            };
+            if !in_memory {
+                return Err(init_error!(InitErrorTy::ClusterOptionConflict, "A cluster requires the memory database"));
+            }
+            if configuration.file_storage_path.is_some() {
+                return Err(init_error!(InitErrorTy::ClusterOptionConflict, "A cluster does not replicate file storage"));
+            }
+            if replicate_from.is_some() || forward_to.is_some() {
+                return Err(init_error!(InitErrorTy::ClusterOptionConflict, "A cluster cannot replicate from a primary or forward to an upstream"));
+            }
+            // members that share a home directory keep their logs apart by node id
+            let cluster_path = match (&configuration.cluster_path, home_dir()) {
+                (Some(cluster_path), _) => cluster_path.clone(),
+                (None, Some(home_dir)) => home_dir.join(format!(".msg-store/cluster/{}", node_id)),
+                (None, None) => return Err(init_error!(InitErrorTy::CouldNotCreateClusterPath, "Home directory does not exist"))
+            };
+            let storage = open_cluster_storage(&cluster_path)?;
+            let cluster = match Cluster::new(node_id, members, storage) {
+                Ok(cluster) => Ok(cluster),
+                Err(error) => Err(init_error!(InitErrorTy::ClusterStorageError(error)))
+            }?;
+            (Some(node_id), Some(cluster))
+        },
+        None => (configuration.node_id, None)
+    };
 
-    let mut store = match Store::new(configuration.node_id) {
+    let mut store = match Store::new(node_id, Some(clock.clone())) {
         Ok(store) => Ok(store),
         Err(error) => Err(init_error!(InitErrorTy::StoreError(error)))
     }?;
@@ -435,28 +1093,73 @@ pub fn init() -> Result<InitResult, InitError> {
         }
     }
 
-    // add messages to store, prune excess
-    let (removed_uuids, pruned_count) = {
+    // add messages to store as they are read from the database, prune excess
+    let (mut removed_uuids, pruned_count) = {
+        let started = Instant::now();
         let mut removed_uuids = vec![];
-        let mut pruned_count = 0;
-        for (uuid, msg_byte_size) in &msgs {
-            let mut add_result = match store.add_with_uuid(uuid.clone(), *msg_byte_size) {
+        let mut msg_count: u64 = 0;
+        let msgs = match database.fetch() {
+            Ok(msgs) => Ok(msgs),
+            Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+        }?;
+        for msg in msgs {
+            let (uuid, msg_byte_size) = match msg {
+                Ok(msg) => Ok(msg),
+                Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+            }?;
+            let mut add_result = match store.add_with_uuid(uuid, msg_byte_size) {
                 Ok(add_result) => add_result,
                 Err(error) => {
                     // TODO: add error handling options to the configuration
                     return Err(init_error!(InitErrorTy::StoreError(error)));
                 }
             };
-            for uuid_removed in &add_result.msgs_removed {
-                if let Err(error) = database.del(uuid_removed.clone()) {
-                    return Err(init_error!(InitErrorTy::DatabaseError(error)));
-                }
-            }
             removed_uuids.append(&mut add_result.msgs_removed);
-            pruned_count += add_result.msgs_removed.len();
+            msg_count += 1;
+            if msg_count.is_multiple_of(LOAD_PROGRESS_INTERVAL) {
+                info!("Loaded {} msgs from the database", msg_count);
+            }
+        }
+        // the database can only be written to once every msg has been read
+        let mut batch = Batch::new();
+        for uuid in removed_uuids.iter() {
+            batch.del(uuid.clone());
         }
-        (removed_uuids, pruned_count as u64)
+        if let Err(error) = database.commit(batch) {
+            return Err(init_error!(InitErrorTy::DatabaseError(error)));
+        }
+        info!("Loaded {} msgs from the database in {:?}, {} pruned", msg_count, started.elapsed(), removed_uuids.len());
+        let pruned_count = removed_uuids.len() as u64;
+        (removed_uuids, pruned_count)
     };
+    // restore the consumer groups and their cursors
+    let consumer_groups = match database.fetch_cursors() {
+        Ok(consumer_groups) => Ok(consumer_groups),
+        Err(error) => Err(init_error!(InitErrorTy::DatabaseError(error)))
+    }?;
+    for (name, cursors) in consumer_groups {
+        if let Err(error) = store.add_consumer_group(&name, cursors) {
+            return Err(init_error!(InitErrorTy::StoreError(error)));
+        }
+    }
+    // remove msgs every consumer group acknowledged before they could be removed
+    let mut acknowledged_uuids = match store.remove_acknowledged() {
+        Ok(acknowledged_uuids) => Ok(acknowledged_uuids),
+        Err(error) => Err(init_error!(InitErrorTy::StoreError(error)))
+    }?;
+    for uuid in &acknowledged_uuids {
+        if let Err(error) = database.del(uuid.clone()) {
+            return Err(init_error!(InitErrorTy::DatabaseError(error)));
+        }
+    }
+    // record the msgs removed while starting up
+    let recorded_changes = removed_uuids.iter().map(|uuid| Change::Prune { uuid: uuid.clone() })
+        .chain(acknowledged_uuids.iter().map(|uuid| Change::Delete { uuid: uuid.clone() }))
+        .collect();
+    if let Err(error) = changes.record_all(recorded_changes) {
+        return Err(init_error!(InitErrorTy::ChangeFeedError(error)));
+    }
+    removed_uuids.append(&mut acknowledged_uuids);
     // removed pruned files if any
     if let Some(file_storage) = file_storage.as_mut() {
         for uuid in removed_uuids {
@@ -469,14 +1172,26 @@ pub fn init() -> Result<InitResult, InitError> {
     Ok(InitResult {
         host: format!("{}:{}", host, port),
         store: Mutex::new(store),
-        db: Mutex::new(database),
+        db: Box::new(BlockingDb::new(database)),
         file_storage: match file_storage {
             Some(file_storage) => Some(Mutex::new(file_storage)),
             None => None
         },
+        stored_byte_sizes,
         configuration: Mutex::new(configuration),
         configuration_path,
-        stats: Mutex::new(stats)
+        stats: Mutex::new(stats),
+        changes: Mutex::new(changes),
+        replicate_from,
+        replication_interval,
+        forward_to,
+        forward_max_backoff,
+        cluster: cluster.map(Mutex::new),
+        maintenance_interval,
+        corruption,
+        scrub_interval,
+        clock,
+        ws_max_body_length
     })
 
 }
@@ -1,14 +1,21 @@
 use actix_web::{
+    dev::Service,
     middleware,
     web::{self, Data},
-    App, HttpServer,
+    App, HttpResponse, HttpServer,
 };
-use log::error;
+use futures::future::{ok, Either};
+use log::{error, info};
+use msg_store_database_checksum_plugin::Corruption;
+use msg_store_server_api::changes::ChangeFeed;
+use msg_store_server_api::cluster::Cluster;
 use msg_store_server_api::config::StoreConfig;
 use msg_store_server_api::file_storage::FileStorage;
+use msg_store_server_api::forward::Forwarder;
+use msg_store_server_api::notify::Notifier;
 use msg_store_server_api::stats::Stats;
 use msg_store::Store;
-use msg_store_database_plugin::Db;
+use msg_store_server_api::Database;
 use env_logger::{Builder, Target};
 use std::{
     path::PathBuf, sync::Mutex
@@ -16,18 +23,34 @@ use std::{
 use std::process::exit;
 
 mod api;
+mod cluster;
+mod forward;
 mod init;
+mod maintenance;
+mod replica;
 
 use init::init;
+use replica::Replication;
 
 
 pub struct AppData {
     pub store: Mutex<Store>,
     pub configuration: Mutex<StoreConfig>,
     pub configuration_path: Option<PathBuf>,
-    pub db: Mutex<Box<dyn Db>>,
+    pub db: Database,
     pub file_storage: Option<Mutex<FileStorage>>,
-    pub stats: Mutex<Stats>
+    /// Whether msgs are counted by the size they are stored at
+    pub stored_byte_sizes: bool,
+    pub stats: Mutex<Stats>,
+    pub notifier: Mutex<Notifier>,
+    pub changes: Mutex<ChangeFeed>,
+    pub replication: Mutex<Replication>,
+    pub forwarder: Mutex<Forwarder>,
+    pub cluster: Option<Mutex<Cluster>>,
+    /// The msgs that were found to be corrupt
+    pub corruption: Corruption,
+    /// The largest body a msg/post command over a websocket may announce
+    pub ws_max_body_length: u64
 }
 
 #[actix_web::main]
@@ -51,17 +74,77 @@ async fn main() -> std::io::Result<()> {
         store: init_result.store,
         db: init_result.db,
         file_storage: init_result.file_storage, // TODO: Fix
+        stored_byte_sizes: init_result.stored_byte_sizes,
         configuration_path: init_result.configuration_path,
         configuration: init_result.configuration,
-        stats: init_result.stats
+        stats: init_result.stats,
+        notifier: Mutex::new(Notifier::new()),
+        changes: init_result.changes,
+        replication: Mutex::new(Replication::new(init_result.replicate_from)),
+        forwarder: Mutex::new(Forwarder::new(init_result.forward_to.clone())),
+        cluster: init_result.cluster,
+        corruption: init_result.corruption,
+        ws_max_body_length: init_result.ws_max_body_length
     });
 
+    if replica::is_read_only(&app_data) {
+        actix_web::rt::spawn(replica::replicate(app_data.clone(), init_result.replication_interval));
+    }
+    if init_result.forward_to.is_some() {
+        actix_web::rt::spawn(forward::forward(app_data.clone(), init_result.forward_max_backoff));
+    }
+    if app_data.cluster.is_some() {
+        actix_web::rt::spawn(cluster::run(app_data.clone()));
+    }
+    actix_web::rt::spawn(maintenance::maintain(app_data.clone(), init_result.maintenance_interval, init_result.scrub_interval, init_result.clock));
+
     HttpServer::new(move || {
         App::new()
+            // refuse writes while following a primary
+            .wrap_fn(|req, srv| {
+                let read_only = match req.app_data::<Data<AppData>>() {
+                    Some(data) => replica::is_read_only(data),
+                    None => false
+                };
+                if read_only && replica::is_write_request(req.method(), req.path()) {
+                    info!("{} {} 403 {}", req.method(), req.path(), replica::READ_ONLY);
+                    let response = HttpResponse::Forbidden().body(replica::READ_ONLY);
+                    Either::Left(ok(req.into_response(response.into_body())))
+                } else {
+                    Either::Right(srv.call(req))
+                }
+            })
+            // refuse writes that do not go through the log of the cluster
+            .wrap_fn(|req, srv| {
+                let clustered = match req.app_data::<Data<AppData>>() {
+                    Some(data) => data.cluster.is_some(),
+                    None => false
+                };
+                if clustered && cluster::is_unreplicated_request(req.method(), req.path()) {
+                    info!("{} {} 403 {}", req.method(), req.path(), cluster::NOT_REPLICATED);
+                    let response = HttpResponse::Forbidden().body(cluster::NOT_REPLICATED);
+                    Either::Left(ok(req.into_response(response.into_body())))
+                } else {
+                    Either::Right(srv.call(req))
+                }
+            })
             // enable logger
             .wrap(middleware::Logger::default())
             .app_data(app_data.clone())
+            .route("/api/changes", web::get().to(api::changes::get::http_handle))
+            .route("/api/cluster", web::get().to(api::cluster::get::http_handle))
+            .service(
+                web::resource("/api/cluster/raft")
+                    .app_data(web::JsonConfig::default().limit(api::cluster::raft::MAX_MESSAGE_SIZE))
+                    .route(web::post().to(api::cluster::raft::http_handle)),
+            )
+            .route("/api/consumer-group", web::delete().to(api::consumer_group::delete::http_handle))
+            .route("/api/consumer-group", web::get().to(api::consumer_group::get::http_handle))
+            .route("/api/consumer-group", web::post().to(api::consumer_group::post::http_handle))
+            .route("/api/consumer-group/ack", web::post().to(api::consumer_group::ack::http_handle))
+            .route("/api/consumer-group/msg", web::get().to(api::consumer_group::msg::http_handle))
             .route("/api/export", web::get().to(api::export::http_handle))
+            .route("/api/forward", web::get().to(api::forward::get::http_handle))
             .route(
                 "/api/group",
                 web::delete().to(api::group::delete::handle_http),
@@ -79,9 +162,15 @@ async fn main() -> std::io::Result<()> {
                 "/api/group-defaults",
                 web::post().to(api::group_defaults::post::http_handle),
             )
+            .route("/api/import", web::post().to(api::import::http_handle))
             .route("/api/msg", web::get().to(api::msg::get::http_handle))
             .route("/api/msg", web::delete().to(api::msg::delete::http_handle))
             .route("/api/msg", web::post().to(api::msg::post::http_handle))
+            .route("/api/msg/subscribe", web::get().to(api::msg::subscribe::http_handle))
+            .route("/api/replication", web::get().to(api::replication::get::http_handle))
+            .route("/api/replication/msg", web::get().to(api::replication::msg::http_handle))
+            .route("/api/replication/promote", web::post().to(api::replication::promote::http_handle))
+            .route("/api/replication/snapshot", web::get().to(api::replication::snapshot::http_handle))
             .route(
                 "/api/stats",
                 web::delete().to(api::stats::delete::http_handle),
@@ -90,7 +179,7 @@ async fn main() -> std::io::Result<()> {
             .route("/api/stats", web::put().to(api::stats::put::http_handle))
             .route("/api/store", web::get().to(api::store::get::http_handle))
             .route("/api/store", web::put().to(api::store::put::http_handle))
-            // .service(web::resource("/ws").route(web::get().to(api::ws::ws_index)))
+            .service(web::resource("/ws").route(web::get().to(api::ws::ws_index)))
     })
     // start http server on 127.0.0.1:8080
     .bind(init_result.host)?
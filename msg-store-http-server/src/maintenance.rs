@@ -0,0 +1,138 @@
+use actix_web::web::{self, Data};
+use crate::AppData;
+use log::{error, info, warn};
+use msg_store_database_checksum_plugin::CorruptionPolicy;
+use msg_store_server_api::file_storage::{reencrypt_file, scrub_file, FileStorage};
+use msg_store_server_api::msg::rm::handle as rm_msg;
+use msg_store_uuid::{Clock, Uuid};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Runs the upkeep of the database and the file storage in the background
+///
+/// The database is maintained until it has nothing left to do, e.g. re-encrypting the msgs
+/// that are not encrypted with the active key or checking the msgs against their checksums,
+/// and then again after each interval. The files are re-encrypted once after start up, the
+/// files written after that use the active key. The files are checked against their
+/// checksums after start up and then once every scrub interval, as measured by the clock. The
+/// msgs that were found to be corrupt are removed after each run unless they are served.
+pub async fn maintain(data: Data<AppData>, interval: Duration, scrub_interval: Duration, clock: Arc<dyn Clock>) {
+    off_runtime(&data, reencrypt_files).await;
+    // when the last pass over the files started, in microseconds since the unix epoch
+    let mut last_scrub: Option<u64> = None;
+    loop {
+        match data.db.maintain().await {
+            Ok(true) => continue,
+            Ok(false) => {},
+            Err(err) => error!("MAINTENANCE_ERROR: {}", err)
+        }
+        match clock.now_micros() {
+            Ok(now) => if last_scrub.is_none_or(|last_scrub| u128::from(now.saturating_sub(last_scrub)) >= scrub_interval.as_micros()) {
+                off_runtime(&data, scrub_files).await;
+                last_scrub = Some(now);
+            },
+            Err(err) => error!("MAINTENANCE_ERROR: {}", err)
+        }
+        rm_corrupt_msgs(&data).await;
+        actix::clock::delay_for(interval).await;
+    }
+}
+
+/// Runs a pass over the files on the blocking thread pool, reading every file would hold up
+/// the requests handled by the runtime
+async fn off_runtime(data: &Data<AppData>, pass: fn(&AppData)) {
+    let data = data.clone();
+    if let Err(err) = web::block(move || -> Result<(), ()> {
+        pass(&data);
+        Ok(())
+    }).await {
+        error!("MAINTENANCE_ERROR: Could not run the pass over the files. {}", err);
+    }
+}
+
+/// Gets the uuids of the files in the file storage, if the filter allows it
+fn file_uuids(data: &AppData, filter: fn(&FileStorage) -> bool) -> Vec<Arc<Uuid>> {
+    let file_storage_mutex = match &data.file_storage {
+        Some(file_storage_mutex) => file_storage_mutex,
+        None => return vec![]
+    };
+    match file_storage_mutex.lock() {
+        Ok(file_storage) if filter(&file_storage) => file_storage.index.iter().cloned().collect(),
+        Ok(_) => vec![],
+        Err(err) => {
+            error!("MAINTENANCE_ERROR: Could not lock file storage. {}", err);
+            exit(1);
+        }
+    }
+}
+
+/// Re-encrypts the files that are not encrypted with the active key
+///
+/// The file storage is locked for each file, so that a file is not removed while it is
+/// rewritten.
+fn reencrypt_files(data: &AppData) {
+    let file_storage_mutex = match &data.file_storage {
+        Some(file_storage_mutex) => file_storage_mutex,
+        None => return
+    };
+    let uuids = file_uuids(data, |file_storage| file_storage.keyring.is_some());
+    let mut reencrypted = 0;
+    for uuid in uuids {
+        let file_storage = match file_storage_mutex.lock() {
+            Ok(file_storage) => file_storage,
+            Err(err) => {
+                error!("MAINTENANCE_ERROR: Could not lock file storage. {}", err);
+                exit(1);
+            }
+        };
+        match reencrypt_file(&file_storage, &uuid) {
+            Ok(true) => reencrypted += 1,
+            Ok(false) => {},
+            Err(err) => error!("MAINTENANCE_ERROR: Could not re-encrypt the file of {}. {}", uuid.to_string(), err)
+        }
+    }
+    if reencrypted > 0 {
+        info!("Re-encrypted {} files with the active key", reencrypted);
+    }
+}
+
+/// Checks every file against its checksum, the corrupt files are recorded
+fn scrub_files(data: &AppData) {
+    let file_storage_mutex = match &data.file_storage {
+        Some(file_storage_mutex) => file_storage_mutex,
+        None => return
+    };
+    let uuids = file_uuids(data, |_| true);
+    let mut corrupt = 0;
+    for uuid in uuids {
+        let file_storage = match file_storage_mutex.lock() {
+            Ok(file_storage) => file_storage,
+            Err(err) => {
+                error!("MAINTENANCE_ERROR: Could not lock file storage. {}", err);
+                exit(1);
+            }
+        };
+        match scrub_file(&file_storage, &uuid) {
+            Ok(true) => corrupt += 1,
+            Ok(false) => {},
+            Err(err) => error!("MAINTENANCE_ERROR: Could not scrub the file of {}. {}", uuid.to_string(), err)
+        }
+    }
+    if corrupt > 0 {
+        warn!("Found {} corrupt files", corrupt);
+    }
+}
+
+/// Removes the msgs that were found to be corrupt, unless corrupt msgs are served
+async fn rm_corrupt_msgs(data: &AppData) {
+    if let CorruptionPolicy::Serve = data.corruption.policy() {
+        return
+    }
+    for uuid in data.corruption.take() {
+        warn!("Removing corrupt msg uuid={}", uuid.to_string());
+        if let Err(err) = rm_msg(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, uuid.clone()).await {
+            error!("MAINTENANCE_ERROR: Could not remove the corrupt msg {}. {}", uuid.to_string(), err);
+        }
+    }
+}
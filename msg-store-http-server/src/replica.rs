@@ -0,0 +1,491 @@
+use actix_web::client::Client;
+use actix_web::error::PayloadError;
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::{self, Data};
+use bytes::{Bytes, BytesMut};
+use crate::AppData;
+use crate::api::ws::command;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use log::{error, info, warn};
+use msg_store_server_api::changes::{Change, ChangeBatch};
+use msg_store_server_api::group_defaults::rm::{handle as rm_group_defaults, ApiError as RmGroupDefaultsError};
+use msg_store_server_api::group_defaults::set::{handle as set_group_defaults, ApiError as SetGroupDefaultsError};
+use msg_store_server_api::msg::add::Chunky;
+use msg_store_server_api::msg::frame::{self, BodyReader, FrameError};
+use msg_store_server_api::replication::apply::{self, ApiError as ApplyError};
+use msg_store_server_api::replication::snapshot::Snapshot;
+use msg_store_server_api::store::set::{handle as set_store_defaults, ApiError as SetStoreDefaultsError};
+use msg_store_uuid::{Uuid, UuidError};
+use std::fmt::Display;
+use std::pin::Pin;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The most bytes accepted for a snapshot or a batch of changes
+const RESPONSE_LIMIT: usize = 256 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ReplicationErrorTy {
+    ApplyError(ApplyError),
+    FrameError(FrameError),
+    RmGroupDefaultsError(RmGroupDefaultsError),
+    SetGroupDefaultsError(SetGroupDefaultsError),
+    SetStoreDefaultsError(SetStoreDefaultsError),
+    UuidError(UuidError),
+    CouldNotParseResponse,
+    CouldNotReachPrimary,
+    IncompleteMsg,
+    MissingHeader,
+    UnexpectedStatus
+}
+impl Display for ReplicationErrorTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ApplyError(err) => write!(f, "({})", err),
+            Self::FrameError(err) => write!(f, "({:?})", err),
+            Self::RmGroupDefaultsError(err) => write!(f, "({})", err),
+            Self::SetGroupDefaultsError(err) => write!(f, "({})", err),
+            Self::SetStoreDefaultsError(err) => write!(f, "({})", err),
+            Self::UuidError(err) => write!(f, "({})", err),
+            Self::CouldNotParseResponse |
+            Self::CouldNotReachPrimary |
+            Self::IncompleteMsg |
+            Self::MissingHeader |
+            Self::UnexpectedStatus => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplicationError {
+    pub err_ty: ReplicationErrorTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ReplicationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "REPLICATION_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "REPLICATION_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! replication_error {
+    ($err_ty:expr) => {
+        ReplicationError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ReplicationError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The replication role of the server
+pub struct Replication {
+    /// The address of the primary, None when this server is the primary
+    pub primary: Option<String>,
+    /// Whether the snapshot of the primary has been applied
+    pub bootstrapped: bool,
+    /// The sequence number of the next change of the primary to apply
+    pub seq: u64
+}
+impl Replication {
+    pub fn new(primary: Option<String>) -> Replication {
+        Replication {
+            primary,
+            bootstrapped: false,
+            seq: 0
+        }
+    }
+    pub fn is_replica(&self) -> bool {
+        self.primary.is_some()
+    }
+}
+
+/// The reason given when a replica refuses a request
+pub const READ_ONLY: &str = "The server is a read-only replica";
+
+/// Whether a request changes the store
+///
+/// The stats of a replica are its own, and a replica can always be promoted.
+pub fn is_write_request(method: &Method, path: &str) -> bool {
+    match path {
+        "/api/replication/promote" | "/api/stats" => false,
+        // exporting removes the msgs from the store
+        "/api/export" => true,
+        _ => method != Method::GET
+    }
+}
+
+/// Whether a websocket command changes the store
+pub fn is_write_command(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        command::MSG_POST |
+        command::MSG_DELETE |
+        command::GROUP_DELETE |
+        command::GROUP_DEFAULTS_POST |
+        command::GROUP_DEFAULTS_DELETE |
+        command::STORE_PUT |
+        command::CONSUMER_GROUP_POST |
+        command::CONSUMER_GROUP_DELETE |
+        command::CONSUMER_GROUP_ACK |
+        command::EXPORT |
+        command::IMPORT
+    )
+}
+
+/// Whether requests that change the store must be refused
+pub fn is_read_only(data: &AppData) -> bool {
+    match data.replication.lock() {
+        Ok(replication) => replication.is_replica(),
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+            exit(1);
+        }
+    }
+}
+
+/// The rest of a response body, starting with the bytes that were already read
+struct ResponseBody<S> {
+    first: Option<Bytes>,
+    response: S
+}
+impl<S: Stream<Item = Result<web::Bytes, PayloadError>> + Unpin> Stream for ResponseBody<S> {
+    type Item = Result<Bytes, &'static str>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(first) = self.first.take() {
+            if !first.is_empty() {
+                return Poll::Ready(Some(Ok(first)));
+            }
+        }
+        match self.response.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => Poll::Ready(Some(Ok(Bytes::copy_from_slice(&chunk)))),
+            Poll::Ready(Some(Err(_error))) => Poll::Ready(Some(Err("PayloadError"))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+impl<S: Stream<Item = Result<web::Bytes, PayloadError>> + Unpin> Chunky for ResponseBody<S> { }
+
+/// Follows the primary until the server is promoted
+///
+/// Errors are logged and the failed step is retried after the interval. A replica that can
+/// no longer follow the change feed of the primary bootstraps again.
+pub async fn replicate(data: Data<AppData>, interval: Duration) {
+    // requests close their connection, a pooled connection to a primary that restarted
+    // would wait for the timeout
+    let client = Client::builder().timeout(Duration::from_secs(30)).finish();
+    loop {
+        let (primary, bootstrapped, seq) = match data.replication.lock() {
+            Ok(replication) => match &replication.primary {
+                Some(primary) => (primary.clone(), replication.bootstrapped, replication.seq),
+                None => {
+                    info!("Promoted to primary, replication stopped");
+                    return;
+                }
+            },
+            Err(err) => {
+                error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+                exit(1);
+            }
+        };
+        let result = if bootstrapped {
+            follow(&data, &client, &primary, seq).await
+        } else {
+            bootstrap(&data, &client, &primary).await
+        };
+        match result {
+            Ok(true) => {},
+            Ok(false) => actix::clock::delay_for(interval).await,
+            Err(err) => {
+                error!("{}", err);
+                actix::clock::delay_for(interval).await
+            }
+        }
+    }
+}
+
+/// Replaces the state of the replica with a snapshot of the primary
+async fn bootstrap(data: &AppData, client: &Client, primary: &str) -> Result<bool, ReplicationError> {
+    info!("Bootstrapping from {}", primary);
+    let mut response = match client.get(format!("http://{}/api/replication/snapshot", primary)).force_close().send().await {
+        Ok(response) => Ok(response),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::CouldNotReachPrimary, err))
+    }?;
+    if response.status() != StatusCode::OK {
+        return Err(replication_error!(ReplicationErrorTy::UnexpectedStatus, response.status()));
+    }
+    let snapshot: Snapshot = match response.json().limit(RESPONSE_LIMIT).await {
+        Ok(snapshot) => Ok(snapshot),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::CouldNotParseResponse, err))
+    }?;
+    if let Err(err) = apply::reset(&data.store, &data.db, &data.file_storage, &data.changes).await {
+        return Err(replication_error!(ReplicationErrorTy::ApplyError(err)));
+    }
+    // defaults
+    let priorities = match data.store.lock() {
+        Ok(store) => store.group_defaults.keys().cloned().collect::<Vec<u16>>(),
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock store. {}", err);
+            exit(1);
+        }
+    };
+    for priority in priorities {
+        if !snapshot.groups.iter().any(|group| group.priority == priority) {
+            apply_group_defaults(data, priority, None).await?;
+        }
+    }
+    for group in snapshot.groups.iter() {
+        apply_group_defaults(data, group.priority, group.max_byte_size).await?;
+    }
+    apply_store_defaults(data, snapshot.max_byte_size).await?;
+    // msgs
+    for snapshot_msg in snapshot.msgs.iter() {
+        let uuid = match Uuid::from_string(&snapshot_msg.uuid) {
+            Ok(uuid) => Ok(uuid),
+            Err(err) => Err(replication_error!(ReplicationErrorTy::UuidError(err)))
+        }?;
+        copy_msg(data, client, primary, uuid).await?;
+    }
+    // consumer groups, after the msgs so that the msgs they passed are not taken as late
+    let mut consumer_groups = vec![];
+    for consumer_group in snapshot.consumer_groups.iter() {
+        let cursors = match consumer_group.cursors.iter().map(|cursor| Uuid::from_string(cursor)).collect::<Result<Vec<Arc<Uuid>>, UuidError>>() {
+            Ok(cursors) => Ok(cursors),
+            Err(err) => Err(replication_error!(ReplicationErrorTy::UuidError(err)))
+        }?;
+        consumer_groups.push((consumer_group.name.clone(), cursors));
+    }
+    if let Err(err) = apply::consumer_groups(&data.store, &data.db, consumer_groups).await {
+        return Err(replication_error!(ReplicationErrorTy::ApplyError(err)));
+    }
+    match data.replication.lock() {
+        Ok(mut replication) => {
+            replication.bootstrapped = true;
+            replication.seq = snapshot.seq;
+        },
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+            exit(1);
+        }
+    };
+    info!("Bootstrapped from {} with {} msgs, following changes from {}", primary, snapshot.msgs.len(), snapshot.seq);
+    Ok(true)
+}
+
+/// Applies the next batch of changes of the primary
+///
+/// Returns whether there may be more changes waiting.
+async fn follow(data: &AppData, client: &Client, primary: &str, seq: u64) -> Result<bool, ReplicationError> {
+    let mut response = match client.get(format!("http://{}/api/changes?from={}", primary, seq)).force_close().send().await {
+        Ok(response) => Ok(response),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::CouldNotReachPrimary, err))
+    }?;
+    if response.status() != StatusCode::OK {
+        return Err(replication_error!(ReplicationErrorTy::UnexpectedStatus, response.status()));
+    }
+    let batch: ChangeBatch = match response.json().limit(RESPONSE_LIMIT).await {
+        Ok(batch) => Ok(batch),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::CouldNotParseResponse, err))
+    }?;
+    if batch.first_seq > seq {
+        warn!("The changes from {} to {} are no longer held by the primary", seq, batch.first_seq);
+        set_bootstrapped(data, false);
+        return Ok(true);
+    }
+    if batch.next_seq < seq {
+        // the primary restarted without a change log
+        warn!("The primary is at change {}, behind the replica at {}", batch.next_seq, seq);
+        set_bootstrapped(data, false);
+        return Ok(true);
+    }
+    let has_changes = !batch.changes.is_empty();
+    for entry in batch.changes {
+        if let Err(err) = apply_change(data, client, primary, entry.change).await {
+            // the replica may no longer match the primary
+            set_bootstrapped(data, false);
+            return Err(err);
+        }
+        match data.replication.lock() {
+            Ok(mut replication) => {
+                if !replication.is_replica() {
+                    return Ok(false);
+                }
+                replication.seq = entry.seq + 1;
+            },
+            Err(err) => {
+                error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+                exit(1);
+            }
+        };
+    }
+    Ok(has_changes)
+}
+
+fn set_bootstrapped(data: &AppData, bootstrapped: bool) {
+    match data.replication.lock() {
+        Ok(mut replication) => replication.bootstrapped = bootstrapped,
+        Err(err) => {
+            error!("REPLICATION_ERROR: Could not lock replication. {}", err);
+            exit(1);
+        }
+    };
+}
+
+async fn apply_change(data: &AppData, client: &Client, primary: &str, change: Change) -> Result<(), ReplicationError> {
+    match change {
+        Change::Insert { uuid, .. } => copy_msg(data, client, primary, uuid).await,
+        Change::Delete { uuid } => remove_msg(data, uuid, false).await,
+        Change::Prune { uuid } => remove_msg(data, uuid, true).await,
+        Change::GroupDefaults { priority, max_byte_size } => apply_group_defaults(data, priority, max_byte_size).await,
+        Change::StoreDefaults { max_byte_size } => apply_store_defaults(data, max_byte_size).await
+    }
+}
+
+async fn remove_msg(data: &AppData, uuid: Arc<Uuid>, pruned: bool) -> Result<(), ReplicationError> {
+    match apply::remove(&data.store, &data.db, &data.file_storage, &data.stats, &data.changes, uuid, pruned).await {
+        Ok(()) => Ok(()),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::ApplyError(err)))
+    }
+}
+
+/// Sets the defaults of a priority group, or removes them when max_byte_size is None
+async fn apply_group_defaults(data: &AppData, priority: u16, max_byte_size: Option<u64>) -> Result<(), ReplicationError> {
+    match max_byte_size {
+        Some(max_byte_size) => {
+            let result = set_group_defaults(
+                &data.store,
+                &data.db,
+                &data.file_storage,
+                &data.stats,
+                &data.changes,
+                &data.configuration,
+                &data.configuration_path,
+                priority,
+                Some(max_byte_size)).await;
+            match result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(replication_error!(ReplicationErrorTy::SetGroupDefaultsError(err)))
+            }
+        },
+        None => {
+            let result = rm_group_defaults(
+                &data.store,
+                &data.changes,
+                &data.configuration,
+                &data.configuration_path,
+                priority).await;
+            match result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(replication_error!(ReplicationErrorTy::RmGroupDefaultsError(err)))
+            }
+        }
+    }
+}
+
+async fn apply_store_defaults(data: &AppData, max_byte_size: Option<u64>) -> Result<(), ReplicationError> {
+    let result = set_store_defaults(
+        &data.store,
+        &data.db,
+        &data.file_storage,
+        &data.stats,
+        &data.changes,
+        &data.configuration,
+        &data.configuration_path,
+        max_byte_size).await;
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::SetStoreDefaultsError(err)))
+    }
+}
+
+/// Fetches a msg from the primary and stores it the same way
+///
+/// A msg that the primary no longer holds is skipped, its removal follows in the change feed.
+async fn copy_msg(data: &AppData, client: &Client, primary: &str, uuid: Arc<Uuid>) -> Result<(), ReplicationError> {
+    let mut response = match client.get(format!("http://{}/api/replication/msg?uuid={}", primary, uuid.to_string())).force_close().send().await {
+        Ok(response) => Ok(response),
+        Err(err) => Err(replication_error!(ReplicationErrorTy::CouldNotReachPrimary, err))
+    }?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(());
+    }
+    if response.status() != StatusCode::OK {
+        return Err(replication_error!(ReplicationErrorTy::UnexpectedStatus, response.status()));
+    }
+    // read until the head and the stored value are complete
+    let mut buffer = BytesMut::new();
+    let mut head_option = None;
+    loop {
+        if head_option.is_none() {
+            head_option = match frame::decode_head(&buffer) {
+                Ok(head_option) => Ok(head_option),
+                Err(err) => Err(replication_error!(ReplicationErrorTy::FrameError(err)))
+            }?;
+        }
+        if let Some((headers, body_len, head_len)) = &head_option {
+            let stored_len = match headers.get("storedLen").map(|stored_len| stored_len.parse::<usize>()) {
+                Some(Ok(stored_len)) => Ok(stored_len),
+                _ => Err(replication_error!(ReplicationErrorTy::MissingHeader, "storedLen"))
+            }?;
+            if buffer.len() >= head_len + stored_len {
+                let byte_size = match headers.get("byteSize").map(|byte_size| byte_size.parse::<u64>()) {
+                    Some(Ok(byte_size)) => Ok(byte_size),
+                    _ => Err(replication_error!(ReplicationErrorTy::MissingHeader, "byteSize"))
+                }?;
+                let mut body = buffer.split_off(*head_len);
+                let rest = body.split_off(stored_len).freeze();
+                let stored = body.freeze();
+                let file_payload = match headers.get("file").map(|file| file.as_str()) {
+                    Some("true") => {
+                        let file_len = body_len - stored_len as u64;
+                        if rest.len() as u64 > file_len {
+                            return Err(replication_error!(ReplicationErrorTy::IncompleteMsg, "Body exceeds length"));
+                        }
+                        let remaining = file_len - rest.len() as u64;
+                        let body = ResponseBody { first: Some(rest), response };
+                        Some(BodyReader::new(body, remaining))
+                    },
+                    _ => None
+                };
+                let result = apply::insert(
+                    &data.store,
+                    &data.db,
+                    &data.file_storage,
+                    &data.stats,
+                    &data.notifier,
+                    &data.changes,
+                    uuid,
+                    byte_size,
+                    stored,
+                    file_payload).await;
+                return match result {
+                    Ok(()) => Ok(()),
+                    Err(err) => Err(replication_error!(ReplicationErrorTy::ApplyError(err)))
+                };
+            }
+        }
+        match response.next().await {
+            Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+            Some(Err(err)) => return Err(replication_error!(ReplicationErrorTy::CouldNotReachPrimary, err)),
+            None => return Err(replication_error!(ReplicationErrorTy::IncompleteMsg))
+        }
+    }
+}
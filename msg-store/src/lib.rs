@@ -1,6 +1,7 @@
-use msg_store_uuid::{UuidManager,Uuid, UuidManagerError};
-use std::collections::BTreeMap;
+use msg_store_uuid::{Clock, UuidManager,Uuid, UuidManagerError};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
+use std::ops::Bound::{Excluded, Unbounded};
 use std::sync::Arc;
 
 pub const DEFAULT_NODE_ID: Option<u16> = None;
@@ -11,6 +12,8 @@ pub enum StoreErrorTy {
     ExceedesStoreMax,
     ExceedesGroupMax,
     LacksPriority,
+    ConsumerGroupExists,
+    ConsumerGroupNotFound,
     SyncError
 }
 impl Display for StoreErrorTy {
@@ -20,6 +23,8 @@ impl Display for StoreErrorTy {
             Self::ExceedesStoreMax |
             Self::ExceedesGroupMax |
             Self::LacksPriority |
+            Self::ConsumerGroupExists |
+            Self::ConsumerGroupNotFound |
             Self::SyncError => write!(f, "{:#?}", self)            
         }
     }
@@ -102,6 +107,85 @@ impl Group {
     }
 }
 
+/// A named consumer of msgs that keeps its own read position
+/// 
+/// Within a priority, msgs are consumed oldest first, so a single cursor per priority is
+/// enough to track the position of the consumer group. The cursor holds the last msg that was
+/// acknowledged, acknowledging a msg also acknowledges every older msg of its priority.
+/// 
+/// A msg can still be added behind a cursor, e.g. when msgs are merged from another node. Such
+/// a msg is kept as late until the consumer group acknowledges it, so that it is not removed
+/// before the consumer group got it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    pub cursors: BTreeMap<u16, Arc<Uuid>>,
+    pub late: BTreeSet<Arc<Uuid>>
+}
+impl ConsumerGroup {
+    pub fn new() -> ConsumerGroup {
+        ConsumerGroup {
+            cursors: BTreeMap::new(),
+            late: BTreeSet::new()
+        }
+    }
+    /// Restores a consumer group from its cursors
+    /// 
+    /// A uuid that is older than the cursor saved before it is a late msg, see `to_cursors`.
+    pub fn from_cursors(cursors: Vec<Arc<Uuid>>) -> ConsumerGroup {
+        let mut consumer_group = ConsumerGroup::new();
+        for uuid in cursors {
+            if !consumer_group.add_late(uuid.clone()) {
+                consumer_group.ack(uuid);
+            }
+        }
+        consumer_group
+    }
+    /// Moves the cursor of the msg's priority up to the msg, a cursor is never moved back
+    /// 
+    /// A late msg is only acknowledged itself, moving the cursor acknowledges the late msgs of
+    /// its priority as well.
+    pub fn ack(&mut self, uuid: Arc<Uuid>) {
+        if self.late.remove(&uuid) {
+            return;
+        }
+        if let Some(cursor) = self.cursors.get(&uuid.priority) {
+            // older msgs are greater
+            if &uuid >= cursor {
+                return;
+            }
+        }
+        self.late.retain(|late| late.priority != uuid.priority);
+        self.cursors.insert(uuid.priority, uuid);
+    }
+    /// Keeps a msg that was added behind the cursor of its priority as late
+    /// 
+    /// Returns whether the msg is behind the cursor.
+    pub fn add_late(&mut self, uuid: Arc<Uuid>) -> bool {
+        match self.cursors.get(&uuid.priority) {
+            Some(cursor) if &uuid > cursor => {
+                self.late.insert(uuid);
+                true
+            },
+            _ => false
+        }
+    }
+    pub fn has_acknowledged(&self, uuid: &Arc<Uuid>) -> bool {
+        match self.cursors.get(&uuid.priority) {
+            Some(cursor) => uuid >= cursor && !self.late.contains(uuid),
+            None => false
+        }
+    }
+    /// The cursor of each priority followed by the late msgs of the priority
+    pub fn to_cursors(&self) -> Vec<Arc<Uuid>> {
+        let mut cursors = vec![];
+        for (priority, cursor) in self.cursors.iter() {
+            cursors.push(cursor.clone());
+            cursors.extend(self.late.iter().filter(|late| late.priority == *priority).cloned());
+        }
+        cursors
+    }
+}
+
 #[derive(Debug)]
 struct RemovedMsgs {
     priority: u16,
@@ -119,6 +203,14 @@ impl RemovedMsgs {
     }
 }
 
+/// A msg that was pruned, with what is needed to put it back
+#[derive(Debug)]
+struct PrunedMsg {
+    uuid: Arc<Uuid>,
+    byte_size: u64,
+    consumer_groups_behind: Vec<String>
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct PacketMetaData {
     pub uuid: Arc<Uuid>,
@@ -131,9 +223,26 @@ pub struct AddResult {
     pub uuid: Arc<Uuid>,
     pub bytes_removed: u64,
     pub groups_removed: Vec<u16>,
-    pub msgs_removed: Vec<Arc<Uuid>>
+    pub msgs_removed: Vec<Arc<Uuid>>,
+    /// The consumer groups that hold the msg as late, their cursors need to be saved again
+    pub consumer_groups_behind: Vec<String>,
+    pruned: Vec<PrunedMsg>
 } 
 
+#[derive(Debug)]
+pub struct MergeResult {
+    /// The merged msgs the store now holds
+    pub inserted: Vec<Arc<Uuid>>,
+    /// The merged msgs the store already held
+    pub duplicates: Vec<Arc<Uuid>>,
+    /// The merged msgs that are too large for the store or their group
+    pub refused: Vec<Arc<Uuid>>,
+    /// The msgs held before the merge that were pruned to make room
+    pub msgs_removed: Vec<Arc<Uuid>>,
+    /// The consumer groups that hold merged msgs as late, their cursors need to be saved again
+    pub consumer_groups_behind: Vec<String>
+}
+
 /// The base unit which stores information about inserted messages and priority groups
 /// to determine which messages should be forwarded or burned first.
 /// 
@@ -157,13 +266,15 @@ pub struct Store {
     pub group_defaults: BTreeMap<u16, GroupDefaults>,
     pub uuid_manager: UuidManager,
     pub id_to_group_map: BTreeMap<Arc<Uuid>, u16>,
-    pub groups_map: BTreeMap<u16, Group>
+    pub groups_map: BTreeMap<u16, Group>,
+    pub consumer_groups: BTreeMap<String, ConsumerGroup>
 }
 
 impl Store {
 
-    pub fn new(node_id: Option<u16>) -> Result<Store, StoreError> {
-        let uuid_manager = match UuidManager::new(node_id) {
+    /// Creates a store, giving uuids from the system clock if no clock is given
+    pub fn new(node_id: Option<u16>, clock: Option<Arc<dyn Clock>>) -> Result<Store, StoreError> {
+        let uuid_manager = match UuidManager::new(node_id, clock) {
             Ok(uuid_manager) => Ok(uuid_manager),
             Err(error) => Err(store_error!(StoreErrorTy::UuidManagerError(error)))
         }?;
@@ -173,7 +284,8 @@ impl Store {
             group_defaults: BTreeMap::new(),
             uuid_manager,
             id_to_group_map: BTreeMap::new(),
-            groups_map: BTreeMap::new()
+            groups_map: BTreeMap::new(),
+            consumer_groups: BTreeMap::new()
         })
     }
 
@@ -181,7 +293,7 @@ impl Store {
         &(byte_size + msg_byte_size) > max_byte_size
     }
 
-    fn remove_msg(&mut self, uuid: Arc<Uuid>, group: &mut Group) -> Result<(), StoreError> {
+    fn remove_msg(&mut self, uuid: Arc<Uuid>, group: &mut Group) -> Result<PrunedMsg, StoreError> {
         let byte_size = match group.msgs_map.remove(&uuid) {
             Some(byte_size) => Ok(byte_size),
             None => Err(store_error!(StoreErrorTy::SyncError))
@@ -189,7 +301,13 @@ impl Store {
         self.id_to_group_map.remove(&uuid);
         self.byte_size -= byte_size;
         group.byte_size -= byte_size;
-        Ok(())
+        let consumer_groups_behind = self.consumer_groups.iter_mut()
+            .filter_map(|(name, consumer_group)| match consumer_group.late.remove(&uuid) {
+                true => Some(name.clone()),
+                false => None
+            })
+            .collect();
+        Ok(PrunedMsg { uuid, byte_size, consumer_groups_behind })
     }
     
     fn get_group(&mut self, priority: u16) -> Group {
@@ -246,12 +364,13 @@ impl Store {
         Ok(group)
     }
 
-    fn prune_group(&mut self, group: &mut Group, msg_byte_size: u64, prune_type: PruneBy) -> Result<(u64, Vec<Arc<Uuid>>), StoreError> {
+    fn prune_group(&mut self, group: &mut Group, msg_byte_size: u64, prune_type: PruneBy) -> Result<(u64, Vec<PrunedMsg>), StoreError> {
         let (byte_size, max_byte_size) = match prune_type {
             PruneBy::Group => (group.byte_size, group.max_byte_size),
             PruneBy::Store => (self.byte_size, self.max_byte_size)
         };
         let mut removed_msgs = vec![];
+        let mut pruned = vec![];
         let mut bytes_removed = 0;
         if let Some(max_byte_size) = &max_byte_size {            
             if Self::msg_excedes_max_byte_size(&byte_size, max_byte_size, &msg_byte_size) {
@@ -263,17 +382,18 @@ impl Store {
                     bytes_removed += group_msg_byte_size;
                     removed_msgs.push(uuid.clone());
                 }
-                for uuid in removed_msgs.iter() {
-                    self.remove_msg(uuid.clone(), group)?;
+                for uuid in removed_msgs {
+                    pruned.push(self.remove_msg(uuid, group)?);
                 }
             }
         }
-        Ok((bytes_removed, removed_msgs))
+        Ok((bytes_removed, pruned))
     }
 
-    fn prune_store(&mut self, mut group: Option<&mut Group>, msg_priority: u16, msg_byte_size: u64) -> Result<(u64, Vec<u16>, Vec<Arc<Uuid>>), StoreError> {
+    fn prune_store(&mut self, mut group: Option<&mut Group>, msg_priority: u16, msg_byte_size: u64) -> Result<(u64, Vec<u16>, Vec<PrunedMsg>), StoreError> {
         let mut groups_removed = vec![];
         let mut all_removed_msgs = vec![];
+        let mut pruned = vec![];
         let mut bytes_removed = 0;
         {
             if let Some(store_max_byte_size) = self.max_byte_size.clone() {
@@ -326,13 +446,13 @@ impl Store {
                                 None => Err(store_error!(StoreErrorTy::SyncError))
                             }?;
                             for uuid in group_data.msgs.iter() {
-                                self.remove_msg(uuid.clone(), &mut group)?;
+                                pruned.push(self.remove_msg(uuid.clone(), &mut group)?);
                             }
                             self.groups_map.insert(group_data.priority, group);
                         } else {
                             if let Some(mut group) = group.as_mut() {
                                 for uuid in group_data.msgs.iter() {
-                                    self.remove_msg(uuid.clone(), &mut group)?;
+                                    pruned.push(self.remove_msg(uuid.clone(), &mut group)?);
                                 }
                             };
                         }
@@ -343,14 +463,15 @@ impl Store {
     
                     // prune group again
                     if let Some(group) = group {
-                        self.prune_group(group, msg_byte_size, PruneBy::Store)?;
+                        let (bytes_removed_from_group, mut pruned_from_group) = self.prune_group(group, msg_byte_size, PruneBy::Store)?;
+                        bytes_removed += bytes_removed_from_group;
+                        pruned.append(&mut pruned_from_group);
                     }
                 }            
             }
         }
         
-        let msgs_removed: Vec<Arc<Uuid>> = all_removed_msgs.into_iter().map(|removed_msgs| removed_msgs.msgs).flatten().collect();
-        Ok((bytes_removed, groups_removed, msgs_removed))
+        Ok((bytes_removed, groups_removed, pruned))
     }
 
     fn insert_msg(&mut self, mut group: Group, uuid: Arc<Uuid>, priority: u16, msg_byte_size: u64) {
@@ -392,7 +513,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// let uuid = store.add(1, "my message".len() as u64).unwrap().uuid;
     /// 
     /// ```
@@ -430,7 +551,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// let uuid = store.uuid(1).unwrap();
     /// let add_result = store.add_with_uuid(uuid, "my message".len() as u64).unwrap();
     /// 
@@ -440,6 +561,9 @@ impl Store {
         // let msg_byte_size = msg.len() as u64;
         let priority = uuid.priority;
 
+        // never give out a uuid this node gave before a restart
+        self.resume_uuids_after(&uuid);
+
         // check if the msg is too large for the store
         self.check_msg_size_agains_store(msg_byte_size)?;
 
@@ -452,26 +576,134 @@ impl Store {
 
         let mut bytes_removed = 0;
         let mut groups_removed = vec![];
-        let mut msgs_removed = vec![];
+        let mut pruned = vec![];
 
         // prune group if needed
-        let (bytes_removed_from_group, mut msgs_removed_from_group) = self.prune_group(&mut group, msg_byte_size, PruneBy::Group)?;
+        let (bytes_removed_from_group, mut pruned_from_group) = self.prune_group(&mut group, msg_byte_size, PruneBy::Group)?;
 
         bytes_removed += bytes_removed_from_group;
-        msgs_removed.append(&mut msgs_removed_from_group);
+        pruned.append(&mut pruned_from_group);
 
         // prune store
-        let (bytes_removed_from_groups, mut groups_removed_from_store, mut msgs_removed_from_groups) = self.prune_store(Some(&mut group), priority, msg_byte_size)?;
+        let (bytes_removed_from_groups, mut groups_removed_from_store, mut pruned_from_groups) = self.prune_store(Some(&mut group), priority, msg_byte_size)?;
         bytes_removed += bytes_removed_from_groups;
-        msgs_removed.append(&mut msgs_removed_from_groups);
+        pruned.append(&mut pruned_from_groups);
         groups_removed.append(&mut groups_removed_from_store);
+        let msgs_removed = pruned.iter().map(|pruned_msg| pruned_msg.uuid.clone()).collect();
 
         // insert msg
         self.insert_msg(group, uuid.clone(), priority, msg_byte_size);
+
+        // a msg that sorts behind a cursor has not been given to that consumer group yet
+        let consumer_groups_behind = self.consumer_groups.iter_mut()
+            .filter_map(|(name, consumer_group)| match consumer_group.add_late(uuid.clone()) {
+                true => Some(name.clone()),
+                false => None
+            })
+            .collect();
         
-        Ok(AddResult{ uuid, bytes_removed, msgs_removed, groups_removed })
+        Ok(AddResult{ uuid, bytes_removed, msgs_removed, groups_removed, consumer_groups_behind, pruned })
+    }
+
+    /// Adds the msgs of another store, e.g. msgs exported from another node
+    ///
+    /// Uuids include the node id of the store that gave them, so msgs from different nodes
+    /// are kept apart even when their priority, timestamp and sequence are the same. Msgs
+    /// the store already holds are skipped, so the same msgs can be merged more than once.
+    /// Merged msgs are pruned like any other msg, a merged msg that is pruned by a later one
+    /// is left out of the result.
+    ///
+    /// # Errors
+    /// An error will be returned if the uuid manager encounters an error, the msgs merged
+    /// before the error are kept.
+    ///
+    /// # Example
+    /// ```
+    /// use msg_store::Store;
+    ///
+    /// let mut first = Store::new(Some(1), None).unwrap();
+    /// let mut second = Store::new(Some(2), None).unwrap();
+    /// let uuid = first.add(1, "my message".len() as u64).unwrap().uuid;
+    /// second.add(1, "my message".len() as u64).unwrap();
+    /// let merge_result = second.merge(vec![(uuid, "my message".len() as u64)]).unwrap();
+    /// assert_eq!(1, merge_result.inserted.len());
+    /// assert_eq!(2, second.id_to_group_map.len());
+    ///
+    /// ```
+    pub fn merge(&mut self, msgs: Vec<(Arc<Uuid>, u64)>) -> Result<MergeResult, StoreError> {
+        let mut inserted = BTreeSet::new();
+        let mut duplicates = vec![];
+        let mut refused = vec![];
+        let mut msgs_removed = vec![];
+        let mut consumer_groups_behind = BTreeSet::new();
+        for (uuid, msg_byte_size) in msgs {
+            if self.id_to_group_map.contains_key(&uuid) || inserted.contains(&uuid) {
+                duplicates.push(uuid);
+                continue;
+            }
+            let add_result = match self.add_with_uuid(uuid.clone(), msg_byte_size) {
+                Ok(add_result) => add_result,
+                Err(error) => match error.err_ty {
+                    StoreErrorTy::ExceedesStoreMax |
+                    StoreErrorTy::ExceedesGroupMax |
+                    StoreErrorTy::LacksPriority => {
+                        refused.push(uuid);
+                        continue;
+                    },
+                    _ => return Err(error)
+                }
+            };
+            for uuid_removed in add_result.msgs_removed {
+                if !inserted.remove(&uuid_removed) {
+                    msgs_removed.push(uuid_removed);
+                }
+            }
+            consumer_groups_behind.extend(add_result.consumer_groups_behind);
+            inserted.insert(add_result.uuid);
+        }
+        Ok(MergeResult {
+            inserted: inserted.into_iter().collect(),
+            duplicates,
+            refused,
+            msgs_removed,
+            consumer_groups_behind: consumer_groups_behind.into_iter().collect()
+        })
     }
     
+    /// Takes back an add, e.g. when the msg could not be saved to the database
+    ///
+    /// The added msg is removed and the msgs it pruned are put back as they were.
+    ///
+    /// # Example
+    /// ```
+    /// use msg_store::{Store, StoreDefaults, DEFAULT_NODE_ID};
+    ///
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
+    /// store.update_store_defaults(&StoreDefaults{ max_byte_size: Some(3) }).unwrap();
+    /// let uuid = store.add(1, "foo".len() as u64).unwrap().uuid;
+    /// let add_result = store.add(1, "bar".len() as u64).unwrap();
+    /// assert_eq!(vec![uuid.clone()], add_result.msgs_removed);
+    ///
+    /// store.undo_add(add_result).unwrap();
+    /// assert_eq!(vec![uuid], store.id_to_group_map.keys().cloned().collect::<Vec<_>>());
+    /// assert_eq!(3, store.byte_size);
+    ///
+    /// ```
+    pub fn undo_add(&mut self, add_result: AddResult) -> Result<(), StoreError> {
+        self.del(add_result.uuid)?;
+        for pruned_msg in add_result.pruned {
+            let priority = pruned_msg.uuid.priority;
+            let group = self.get_group(priority);
+            self.insert_msg(group, pruned_msg.uuid.clone(), priority, pruned_msg.byte_size);
+            for name in pruned_msg.consumer_groups_behind {
+                if let Some(consumer_group) = self.consumer_groups.get_mut(&name) {
+                    consumer_group.late.insert(pruned_msg.uuid.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Deletes a message from the store
     /// 
     /// A message will be removed from the store and disk once given the
@@ -486,7 +718,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// let uuid = store.add(1, "my message".len() as u64).unwrap().uuid;
     /// store.del(uuid).unwrap();
     /// 
@@ -520,6 +752,9 @@ impl Store {
             self.groups_map.remove(&priority);
         }
         self.id_to_group_map.remove(&uuid);
+        for consumer_group in self.consumer_groups.values_mut() {
+            consumer_group.late.remove(&uuid);
+        }
         Ok(())
     }
 
@@ -537,7 +772,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// store.add(1, "my message".len() as u64).unwrap();
     /// store.del_group(&1).unwrap();
     /// 
@@ -548,6 +783,9 @@ impl Store {
         if let Some(group) = self.groups_map.remove(priority) {
             for (uuid, _msg_byte_size) in group.msgs_map.iter() {
                 self.id_to_group_map.remove(uuid);
+                for consumer_group in self.consumer_groups.values_mut() {
+                    consumer_group.late.remove(uuid);
+                }
             }
             self.byte_size -= group.byte_size;            
         }        
@@ -568,7 +806,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// let uuid = store.add(1, "my message".len() as u64).unwrap().uuid;
     /// let my_message = store.get(Some(uuid), None, false).unwrap();
     /// assert!(my_message.is_some());
@@ -707,7 +945,7 @@ impl Store {
     /// # Example
     /// ```
     /// use msg_store::{Store, DEFAULT_NODE_ID};
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// let uuid1 = store.add(1, "my message".len() as u64).unwrap().uuid;
     /// let uuid2 = store.add(1, "my second message".len() as u64).unwrap().uuid;
     /// let uuid3 = store.add(1, "my thrid message".len() as u64).unwrap().uuid;
@@ -777,7 +1015,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store,GroupDefaults, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// store.add(1, "foo".len() as u64).unwrap();
     /// store.add(1, "bar".len() as u64).unwrap();
     /// assert_eq!(6, store.byte_size); // The store should contain 6 bytes of data, 3 for each message.
@@ -794,9 +1032,9 @@ impl Store {
         self.group_defaults.insert(priority, defaults.clone());
         if let Some(mut group) = self.groups_map.remove(&priority) {
             group.update_from_config(defaults.clone());
-            let (bytes_removed_from_group, mut msgs_removed_from_group) = self.prune_group(&mut group, 0, PruneBy::Group)?;
+            let (bytes_removed_from_group, pruned) = self.prune_group(&mut group, 0, PruneBy::Group)?;
             bytes_removed += bytes_removed_from_group;
-            msgs_removed.append(&mut msgs_removed_from_group);
+            msgs_removed.extend(pruned.into_iter().map(|pruned_msg| pruned_msg.uuid));
             self.groups_map.insert(priority, group);
         }
         Ok((bytes_removed, msgs_removed))
@@ -808,7 +1046,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store,GroupDefaults, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap();
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
     /// store.update_group_defaults(1, &GroupDefaults{ max_byte_size: Some(6) }).unwrap();
     /// store.add(1, "foo".len() as u64).unwrap();
     /// store.add(1, "bar".len() as u64).unwrap();
@@ -845,7 +1083,7 @@ impl Store {
     /// ```
     /// use msg_store::{Store, StoreDefaults, DEFAULT_NODE_ID};
     /// 
-    /// let mut store = Store::new(DEFAULT_NODE_ID).unwrap( );
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap( );
     /// store.add(1, "foo".len() as u64).unwrap();
     /// store.add(1, "bar".len() as u64).unwrap();
     /// assert_eq!(6, store.byte_size); // The store should contain 6 bytes of data, 3 for each message.
@@ -858,7 +1096,8 @@ impl Store {
     /// ```
     pub fn update_store_defaults(&mut self, defaults: &StoreDefaults) -> Result<(u64, Vec<u16>, Vec<Arc<Uuid>>), StoreError> {
         self.max_byte_size = defaults.max_byte_size;
-        self.prune_store(None, u16::MAX, 0)
+        let (bytes_removed, groups_removed, pruned) = self.prune_store(None, u16::MAX, 0)?;
+        Ok((bytes_removed, groups_removed, pruned.into_iter().map(|pruned_msg| pruned_msg.uuid).collect()))
     }
 
     pub fn uuid(&mut self, priority: u16) -> Result<Arc<Uuid>, StoreError> {
@@ -868,6 +1107,150 @@ impl Store {
         }
     }
 
+    /// Adds a consumer group that starts at the given cursors
+    /// 
+    /// Once a consumer group exists, msgs are only removed from the store when every consumer group
+    /// has acknowledged them, when they are pruned, or when they are deleted with the del method.
+    /// 
+    /// # Errors
+    /// An error is returned if a consumer group with the same name already exists.
+    /// 
+    /// # Example
+    /// ```
+    /// use msg_store::{Store, DEFAULT_NODE_ID};
+    /// 
+    /// let mut store = Store::new(DEFAULT_NODE_ID, None).unwrap();
+    /// store.add_consumer_group("emails", vec![]).unwrap();
+    /// store.add_consumer_group("audit", vec![]).unwrap();
+    /// let uuid = store.add(1, "my message".len() as u64).unwrap().uuid;
+    /// 
+    /// // the msg is kept until the audit group has acknowledged it too
+    /// assert!(store.ack("emails", uuid.clone()).unwrap().is_empty());
+    /// assert_eq!(None, store.get_for_consumer("emails", None).unwrap());
+    /// assert_eq!(Some(uuid.clone()), store.get_for_consumer("audit", None).unwrap());
+    /// assert_eq!(vec![uuid.clone()], store.ack("audit", uuid).unwrap());
+    /// 
+    /// ```
+    pub fn add_consumer_group(&mut self, name: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), StoreError> {
+        if self.consumer_groups.contains_key(name) {
+            return Err(store_error!(StoreErrorTy::ConsumerGroupExists));
+        }
+        for cursor in cursors.iter() {
+            self.resume_uuids_after(cursor);
+        }
+        let mut consumer_group = ConsumerGroup::from_cursors(cursors);
+        // late msgs that were removed since the cursors were saved
+        let id_to_group_map = &self.id_to_group_map;
+        consumer_group.late.retain(|uuid| id_to_group_map.contains_key(uuid));
+        self.consumer_groups.insert(name.to_string(), consumer_group);
+        Ok(())
+    }
+
+    fn resume_uuids_after(&mut self, uuid: &Uuid) {
+        if uuid.node_id == self.uuid_manager.node_id {
+            self.uuid_manager.resume_after(uuid);
+        }
+    }
+
+    /// Removes a consumer group
+    /// 
+    /// Msgs that have been acknowledged by every remaining consumer group are removed from the store and returned
+    /// so that they can be removed from the database.
+    pub fn del_consumer_group(&mut self, name: &str) -> Result<Vec<Arc<Uuid>>, StoreError> {
+        if self.consumer_groups.remove(name).is_none() {
+            return Ok(vec![]);
+        }
+        self.remove_acknowledged()
+    }
+
+    /// Gets the next msg a consumer group has not yet acknowledged, either store wide or within a priority
+    /// 
+    /// # Errors
+    /// An error is returned if the consumer group does not exist.
+    pub fn get_for_consumer(&self, name: &str, priority: Option<u16>) -> Result<Option<Arc<Uuid>>, StoreError> {
+        let consumer_group = match self.consumer_groups.get(name) {
+            Some(consumer_group) => Ok(consumer_group),
+            None => Err(store_error!(StoreErrorTy::ConsumerGroupNotFound))
+        }?;
+        let next_in_group = |priority: &u16, group: &Group| -> Option<Arc<Uuid>> {
+            // late msgs are older than any msg after the cursor, the oldest of them is next
+            if let Some(uuid) = consumer_group.late.iter().rev().find(|uuid| uuid.priority == *priority) {
+                return Some(uuid.clone());
+            }
+            // newer msgs are less than the cursor, the oldest of them is next
+            match consumer_group.cursors.get(priority) {
+                Some(cursor) => group.msgs_map.range::<Arc<Uuid>, _>((Unbounded, Excluded(cursor))).next_back(),
+                None => group.msgs_map.iter().next_back()
+            }.map(|(uuid, _msg_byte_size)| uuid.clone())
+        };
+        if let Some(priority) = priority {
+            match self.groups_map.get(&priority) {
+                Some(group) => Ok(next_in_group(&priority, group)),
+                None => Ok(None)
+            }
+        } else {
+            Ok(self.groups_map.iter().rev().find_map(|(priority, group)| next_in_group(priority, group)))
+        }
+    }
+
+    /// Acknowledges a msg and every older msg of the same priority for a consumer group
+    /// 
+    /// A late msg, one that was added behind the cursor, is acknowledged on its own.
+    /// 
+    /// Msgs that have now been acknowledged by every consumer group are removed from the store and returned
+    /// so that they can be removed from the database.
+    /// 
+    /// # Errors
+    /// An error is returned if the consumer group does not exist.
+    pub fn ack(&mut self, name: &str, uuid: Arc<Uuid>) -> Result<Vec<Arc<Uuid>>, StoreError> {
+        let priority = uuid.priority;
+        match self.consumer_groups.get_mut(name) {
+            Some(consumer_group) => consumer_group.ack(uuid),
+            None => return Err(store_error!(StoreErrorTy::ConsumerGroupNotFound))
+        };
+        self.remove_acknowledged_from_group(priority)
+    }
+
+    /// Removes every msg that has been acknowledged by all consumer groups
+    /// 
+    /// Nothing is removed when there are no consumer groups.
+    pub fn remove_acknowledged(&mut self) -> Result<Vec<Arc<Uuid>>, StoreError> {
+        let priorities: Vec<u16> = self.groups_map.keys().cloned().collect();
+        let mut msgs_removed = vec![];
+        for priority in priorities {
+            msgs_removed.append(&mut self.remove_acknowledged_from_group(priority)?);
+        }
+        Ok(msgs_removed)
+    }
+
+    fn remove_acknowledged_from_group(&mut self, priority: u16) -> Result<Vec<Arc<Uuid>>, StoreError> {
+        let mut cursors = vec![];
+        for consumer_group in self.consumer_groups.values() {
+            match consumer_group.cursors.get(&priority) {
+                Some(cursor) => cursors.push(cursor),
+                None => return Ok(vec![])
+            }
+        }
+        // the cursor furthest behind is the oldest, which is the greatest
+        let cursor = match cursors.into_iter().max() {
+            Some(cursor) => cursor.clone(),
+            None => return Ok(vec![])
+        };
+        let consumer_groups = &self.consumer_groups;
+        let msgs_removed: Vec<Arc<Uuid>> = match self.groups_map.get(&priority) {
+            Some(group) => group.msgs_map.range(cursor..)
+                .map(|(uuid, _msg_byte_size)| uuid)
+                .filter(|uuid| consumer_groups.values().all(|consumer_group| !consumer_group.late.contains(*uuid)))
+                .cloned()
+                .collect(),
+            None => return Ok(vec![])
+        };
+        for uuid in msgs_removed.iter() {
+            self.del(uuid.clone())?;
+        }
+        Ok(msgs_removed)
+    }
+
 }
 
 #[cfg(test)]
@@ -878,14 +1261,14 @@ mod tests {
 
         #[test]
         fn should_increase_store_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "1234567890".len() as u64).expect("Could not add msg");
             assert_eq!(store.byte_size, 10)
         }
 
         #[test]
         fn should_increase_group_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "1234567890".len() as u64).expect("Could not add msg");
             let group = store.groups_map.get(&1).expect("Could not find group");
             assert_eq!(group.byte_size, 10)
@@ -893,7 +1276,7 @@ mod tests {
 
         #[test]
         fn should_prune_store_byte_size_to_10_when_store_max_byte_size_exists() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(10);
             store.add(1, "1234567890".len() as u64).expect("Could not add first msg");
             store.add(1, "1234567890".len() as u64).expect("Could not second msg");
@@ -902,7 +1285,7 @@ mod tests {
 
         #[test]
         fn should_prune_store_byte_size_to_10_when_group_max_byte_size_exists() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "1234567890".len() as u64).expect("Could not add first msg");
             let mut group = store.groups_map.get_mut(&1).expect("Could not find group");
             group.max_byte_size = Some(10);
@@ -912,7 +1295,7 @@ mod tests {
 
         #[test]
         fn should_prune_group_byte_size_to_10_when_group_max_byte_size_exists() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "1234567890".len() as u64).expect("Could not add first msg");
             let mut group = store.groups_map.get_mut(&1).expect("Could not get mutable group");
             group.max_byte_size = Some(10);
@@ -923,7 +1306,7 @@ mod tests {
 
         #[test]
         fn should_prune_oldest_msg_in_a_group_when_exceeding_group_max_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let first_uuid = store.add(1, "1234567890".len() as u64).expect("Could not add first msg").uuid;
             let mut group = store.groups_map.get_mut(&1).expect("Could not get mutable group");
             group.max_byte_size = Some(10);
@@ -934,7 +1317,7 @@ mod tests {
 
         #[test]
         fn should_prune_oldest_msg_in_a_group_when_exceeding_store_max_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(10);
             let first_uuid = store.add(1, "1234567890".len() as u64).expect("Could not add first msg").uuid;
             let second_uuid = store.add(1, "1234567890".len() as u64).expect("Could not second msg").uuid;
@@ -944,7 +1327,7 @@ mod tests {
 
         #[test]
         fn should_prune_oldest_lowest_pri_msg_in_the_store_when_exceeding_store_max_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(20);
             let first_uuid = store.add(2, "1234567890".len() as u64).expect("Could not add first msg").uuid;
             let second_uuid = store.add(1, "1234567890".len() as u64).expect("Could not second msg").uuid;
@@ -957,7 +1340,7 @@ mod tests {
         #[test]
         fn should_return_add_result_with_pruned_msgs() {
             // from the same priority
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(3);
             let _first_uuid = store.add(1, "foo".len() as u64).expect("Could not add first msg").uuid;
             let add_result = store.add(1, "foo".len() as u64).expect("Could not second msg");
@@ -966,7 +1349,7 @@ mod tests {
             assert_eq!(1, add_result.groups_removed[0]);
 
             // from a lower priority
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(3);
             let _first_uuid = store.add(1, "foo".len() as u64).expect("Could not add first msg").uuid;
             let add_result = store.add(2, "foo".len() as u64).expect("Could not second msg");
@@ -977,7 +1360,7 @@ mod tests {
 
         #[test]
         fn should_return_msg_to_large_for_store_err() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(9);
             let result = store.add(2, "1234567890".len() as u64);
             assert!(result.is_err());
@@ -985,7 +1368,7 @@ mod tests {
 
         #[test]
         fn should_return_msg_to_large_for_group_err() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "1234567890".len() as u64).expect("Could not add first msg");
             let mut group = store.groups_map.get_mut(&1).expect("Could not get mutable group");
             group.max_byte_size = Some(10);
@@ -995,7 +1378,7 @@ mod tests {
 
         #[test]
         fn should_return_msg_lacks_priority_err() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(20);
             store.add(2, "1234567890".len() as u64).expect("Could not add first msg");
             store.add(2, "1234567890".len() as u64).expect("Could not second msg");
@@ -1005,7 +1388,7 @@ mod tests {
 
         #[test]
         fn should_create_group_with_defaults() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.group_defaults.insert(1, GroupDefaults { max_byte_size: Some(10) });
             store.add(1, "1234567890".len() as u64).expect("Could not add msg");
             let group = store.groups_map.get(&1).expect("Could not get group");
@@ -1014,7 +1397,7 @@ mod tests {
 
         #[test]
         fn should_reinsert_group_after_errors() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.max_byte_size = Some(10);
             store.add(2, "12345".len() as u64).expect("Could not add msg");
             let first_attempt = store.add(2, "12345678901".len() as u64);
@@ -1036,7 +1419,7 @@ mod tests {
 
         #[test]
         fn should_return_msg() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuid = store.add(1, "first message".len() as u64).unwrap().uuid;
             let stored_packet = store.get(Some(uuid.clone()), None, false).unwrap().expect("Msg not found");
             assert_eq!(uuid, stored_packet);
@@ -1044,7 +1427,7 @@ mod tests {
 
         #[test]
         fn should_return_oldest_msg() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let first_uuid = store.add(1, "first message".len() as u64).unwrap().uuid;
             store.add(1, "second message".len() as u64).unwrap();
             let stored_packet = store.get(None, None, false).unwrap().expect("Msg not found");
@@ -1053,7 +1436,7 @@ mod tests {
 
         #[test]
         fn should_return_youngest_msg() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let _first_uuid = store.add(1, "first message".len() as u64).unwrap().uuid;
             let second_uuid = store.add(1, "second message".len() as u64).unwrap().uuid;
             let stored_packet = store.get(None, None, true).unwrap().expect("Msg not found");
@@ -1062,7 +1445,7 @@ mod tests {
 
         #[test]
         fn should_return_highest_pri_msg() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "first message".len() as u64).unwrap();
             let second_msg = store.add(2, "second message".len() as u64).unwrap().uuid;
             let stored_packet = store.get(None, None, false).unwrap().expect("Msg not found");
@@ -1071,7 +1454,7 @@ mod tests {
 
         #[test]
         fn should_return_lowest_pri_msg() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let first_msg = store.add(1, "first message".len() as u64).unwrap().uuid;
             let _second_msg = store.add(2, "second message".len() as u64).unwrap().uuid;
             let stored_packet = store.get(None, None, true).unwrap().expect("Msg not found");
@@ -1080,7 +1463,7 @@ mod tests {
 
         #[test]
         fn should_return_oldest_msg_in_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let first_uuid = store.add(1, "first message".len() as u64).unwrap().uuid;
             let _second_uuid = store.add(2, "second message".len() as u64).unwrap().uuid;
             let _third_uuid = store.add(1, "third message".len() as u64).unwrap().uuid;
@@ -1090,7 +1473,7 @@ mod tests {
 
         #[test]
         fn should_return_youngest_msg_in_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let _first_uuid = store.add(1, "first message".len() as u64).unwrap().uuid;
             let _second_uuid = store.add(2, "second message".len() as u64).unwrap().uuid;
             let third_uuid = store.add(1, "third message".len() as u64).unwrap().uuid;
@@ -1105,7 +1488,7 @@ mod tests {
 
         #[test]
         fn should_return_n_msg_uuids() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuids = vec![
                 store.add(1, 10).unwrap().uuid, // 0 => 2
                 store.add(2, 10).unwrap().uuid, // 1 => 1
@@ -1133,7 +1516,7 @@ mod tests {
 
         #[test]
         fn should_return_9_messages_lt_4() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuids = vec![
                 store.add(1, 10).unwrap().uuid, // 0 => 1
                 store.add(2, 10).unwrap().uuid, // 1 => 0
@@ -1161,7 +1544,7 @@ mod tests {
 
         #[test]
         fn should_return_8_messages_lt_the_pri_2_message() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuids = vec![
                 store.add(1, 10).unwrap().uuid, // 0 => 0
                 store.add(2, 10).unwrap().uuid, // 1 => N/A
@@ -1193,7 +1576,7 @@ mod tests {
 
         #[test]
         fn should_return_2_message_data_points() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuid1 = store.add(1, "first message".len() as u64).unwrap().uuid;
             let uuid2 = store.add(1, "second message".len() as u64).unwrap().uuid;
             let set = store.get_metadata((0, 1), None);
@@ -1204,7 +1587,7 @@ mod tests {
 
         #[test]
         fn should_return_2_message_data_points_with_range_starting_at_2() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let _uuid1 = store.add(1, "first message".len() as u64).unwrap().uuid;
             let _uuid2 = store.add(1, "second message".len() as u64).unwrap().uuid;
             let _uuid3 = store.add(1, "third message".len() as u64).unwrap().uuid;
@@ -1219,7 +1602,7 @@ mod tests {
 
         #[test]
         fn should_decrease_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuid = store.add(1, "foo".len() as u64).unwrap().uuid;
             store.add(1, "bar".len() as u64).unwrap();
             let group = store.groups_map.get(&1).expect("Could get group ref");
@@ -1233,7 +1616,7 @@ mod tests {
 
         #[test]
         fn should_remove_empty_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             let uuid = store.add(1, "foo".len() as u64).unwrap().uuid;
             assert!(store.groups_map.get(&1).is_some());
             store.del(uuid).unwrap();
@@ -1247,7 +1630,7 @@ mod tests {
 
         #[test]
         fn should_decrease_byte_size() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             store.add(1, "bar".len() as u64).unwrap();
             let group = store.groups_map.get(&1).expect("Could get group ref");
@@ -1260,7 +1643,7 @@ mod tests {
 
         #[test]
         fn should_remove_empty_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             store.add(1, "bar".len() as u64).unwrap();
             let group = store.groups_map.get(&1).expect("Could get group ref");
@@ -1277,7 +1660,7 @@ mod tests {
 
         #[test]
         fn should_update_store_config() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.update_group_defaults(1, &GroupDefaults{ max_byte_size: Some(10) }).unwrap();
             let defaults = store.group_defaults.get(&1).expect("Could not find defaults");
             assert_eq!(Some(10), defaults.max_byte_size);
@@ -1285,7 +1668,7 @@ mod tests {
 
         #[test]
         fn should_update_existing_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.update_group_defaults(1, &GroupDefaults{ max_byte_size: Some(10) }).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             let group = store.groups_map.get(&1).expect("Could not find defaults");
@@ -1294,7 +1677,7 @@ mod tests {
 
         #[test]
         fn should_prune_group_after_update() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             store.add(1, "bar".len() as u64).unwrap();
             store.update_group_defaults(1, &GroupDefaults{ max_byte_size: Some(3) }).unwrap();            
@@ -1310,7 +1693,7 @@ mod tests {
 
         #[test]
         fn should_update_existing_group() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.update_group_defaults(1, &GroupDefaults{ max_byte_size: Some(10) }).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             let group = store.groups_map.get(&1).expect("Could not find defaults");
@@ -1327,14 +1710,14 @@ mod tests {
 
         #[test]
         fn should_update_store_config() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.update_store_defaults(&StoreDefaults{ max_byte_size: Some(10) }).unwrap();
             assert_eq!(Some(10), store.max_byte_size);
         }
 
         #[test]
         fn should_prune_store_after_update() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             store.add(1, "bar".len() as u64).unwrap();
             store.update_store_defaults(&StoreDefaults{ max_byte_size: Some(3) }).unwrap();            
@@ -1345,7 +1728,7 @@ mod tests {
 
         #[test]
         fn should_remove_empty_group_after_update() {
-            let mut store = Store::new(None).unwrap();
+            let mut store = Store::new(None, None).unwrap();
             store.add(1, "foo".len() as u64).unwrap();
             store.update_store_defaults(&StoreDefaults{ max_byte_size: Some(2) }).unwrap();
             assert_eq!(0, store.groups_map.len());
@@ -1354,23 +1737,305 @@ mod tests {
     }
 
     mod uuid {
-        use msg_store_uuid::Uuid;
+        use msg_store_uuid::{ManualClock, Uuid, UuidManager, UuidVersion};
         use crate::Store;
+        use std::cmp::Ordering;
         use std::sync::Arc;
 
         #[test]
         fn should_convert_a_str_to_uuid() {
-            let left = Arc::new(Uuid{ priority: 1, timestamp: 1636523479865480266, sequence: 1, node_id: 0 });
+            let left = Arc::new(Uuid{ priority: 1, timestamp: 1636523479865480266, sequence: 1, node_id: 0, version: UuidVersion::V1 });
             assert_eq!(left, Uuid::from_string("1-1636523479865480266-1-0").unwrap())
         }
 
         #[test]
         fn should_reflect_node_id() {
-            let mut store = Store::new(Some(10)).unwrap();
+            let mut store = Store::new(Some(10), None).unwrap();
             let uuid = store.uuid(1).unwrap();
             assert_eq!(10, uuid.node_id);            
         }
 
+        #[test]
+        fn should_convert_uuids_of_every_version_back_to_the_same_str() {
+            for id in ["1-1636523479-1-0", "v2-1-1636523479865480-1-0"] {
+                assert_eq!(id, Uuid::from_string(id).unwrap().to_string());
+            }
+            assert_eq!(UuidVersion::V2, Uuid::from_string("v2-1-1636523479865480-1-0").unwrap().version);
+            assert!(Uuid::from_string("v3-1-1636523479865480-1-0").is_err());
+            assert!(Uuid::from_string("v2-1-1636523479865480-1").is_err());
+        }
+
+        #[test]
+        fn should_order_uuids_of_different_versions_by_time() {
+            let old = Uuid::from_string("1-1636523479-5-0").unwrap();
+            let new = Uuid::from_string("v2-1-1636523479000001-1-0").unwrap();
+            // older msgs are greater
+            assert!(old > new);
+            assert!(Uuid::from_string("v2-1-1636523478999999-9-0").unwrap() > old);
+        }
+
+        #[test]
+        fn should_give_uuids_in_sub_second_order() {
+            let clock = Arc::new(ManualClock::new(1_636_523_479_000_000));
+            let mut store = Store::new(None, Some(clock.clone())).unwrap();
+            let first = store.uuid(1).unwrap();
+            clock.advance(1);
+            let second = store.uuid(1).unwrap();
+            assert_eq!(UuidVersion::V2, first.version);
+            assert_eq!(1_636_523_479_000_001, second.timestamp);
+            assert!(first > second);
+        }
+
+        #[test]
+        fn should_stay_monotonic_when_the_clock_goes_backwards() {
+            let clock = Arc::new(ManualClock::new(2_000));
+            let mut manager = UuidManager::new(Some(1), Some(clock.clone())).unwrap();
+            let uuid = manager.next(1).unwrap();
+            assert_eq!((2_000, 1), (uuid.timestamp, uuid.sequence));
+            clock.set(1_000);
+            let uuid = manager.next(1).unwrap();
+            assert_eq!((2_000, 2), (uuid.timestamp, uuid.sequence));
+            clock.set(2_001);
+            let uuid = manager.next(1).unwrap();
+            assert_eq!((2_001, 1), (uuid.timestamp, uuid.sequence));
+        }
+
+        #[test]
+        fn should_move_the_timestamp_on_when_the_sequence_overflows() {
+            let mut manager = UuidManager::new(Some(1), Some(Arc::new(ManualClock::new(2_000)))).unwrap();
+            manager.sequence = u32::MAX - 1;
+            manager.timestamp = 2_000;
+            assert_eq!(u32::MAX, manager.next(1).unwrap().sequence);
+            let uuid = manager.next(1).unwrap();
+            assert_eq!((2_001, 1), (uuid.timestamp, uuid.sequence));
+        }
+
+        #[test]
+        fn should_order_uuids_from_different_nodes() {
+            let first = Uuid::from_string("1-1636523479-1-1").unwrap();
+            let second = Uuid::from_string("1-1636523479-1-2").unwrap();
+            assert_ne!(first.cmp(&second), Ordering::Equal);
+            assert_eq!(first.cmp(&second), second.cmp(&first).reverse());
+            // the node id only breaks ties
+            assert!(Uuid::from_string("1-1636523479-2-1").unwrap() < second);
+            assert!(Uuid::from_string("2-1636523480-1-1").unwrap() > second);
+        }
+
+        #[test]
+        fn should_keep_msgs_from_different_nodes_with_the_same_uuid_fields() {
+            let mut store = Store::new(None, None).unwrap();
+            let first = Uuid::from_string("1-1636523479-1-1").unwrap();
+            let second = Uuid::from_string("1-1636523479-1-2").unwrap();
+            store.add_with_uuid(first.clone(), 3).unwrap();
+            store.add_with_uuid(second.clone(), 5).unwrap();
+            assert_eq!(2, store.id_to_group_map.len());
+            assert_eq!(8, store.byte_size);
+            assert_eq!(Some(&3), store.groups_map.get(&1).unwrap().msgs_map.get(&first));
+            assert_eq!(Some(&5), store.groups_map.get(&1).unwrap().msgs_map.get(&second));
+        }
+
+        #[test]
+        fn should_resume_after_uuids_given_before_a_restart() {
+            // the store is restarted within the same microsecond
+            let clock = Arc::new(ManualClock::new(5_000));
+            let mut store = Store::new(Some(3), Some(clock.clone())).unwrap();
+            store.uuid(1).unwrap();
+            let held = store.uuid(1).unwrap();
+            let mut store = Store::new(Some(3), Some(clock)).unwrap();
+            store.add_with_uuid(held.clone(), 3).unwrap();
+            let uuid = store.uuid(1).unwrap();
+            assert_ne!(held, uuid);
+            assert!(held > uuid);
+            assert_eq!((5_000, 3), (uuid.timestamp, uuid.sequence));
+        }
+
+        #[test]
+        fn should_resume_after_cursors_given_before_a_restart() {
+            let clock = Arc::new(ManualClock::new(5_000));
+            let cursor = Arc::new(Uuid { priority: 1, timestamp: 5_000, sequence: 7, node_id: 3, version: UuidVersion::V2 });
+            let mut store = Store::new(Some(3), Some(clock)).unwrap();
+            store.add_consumer_group("emails", vec![cursor.clone()]).unwrap();
+            assert!(cursor > store.uuid(1).unwrap());
+        }
+
+        #[test]
+        fn should_not_resume_after_uuids_of_other_nodes() {
+            let clock = Arc::new(ManualClock::new(5_000));
+            let held = Arc::new(Uuid { priority: 1, timestamp: 5_000, sequence: 7, node_id: 4, version: UuidVersion::V2 });
+            let mut store = Store::new(Some(3), Some(clock)).unwrap();
+            store.add_with_uuid(held.clone(), 3).unwrap();
+            assert_eq!(1, store.uuid(1).unwrap().sequence);
+        }
+
+        #[test]
+        fn should_resume_after_v1_uuids_in_microseconds() {
+            let mut manager = UuidManager::new(Some(1), Some(Arc::new(ManualClock::new(1_000)))).unwrap();
+            manager.resume_after(&Uuid::from_string("1-1636523479-4-1").unwrap());
+            assert_eq!((1_636_523_479_000_000, 4), (manager.timestamp, manager.sequence));
+            // older uuids change nothing
+            manager.resume_after(&Uuid::from_string("v2-1-1636523478999999-9-1").unwrap());
+            assert_eq!((1_636_523_479_000_000, 4), (manager.timestamp, manager.sequence));
+        }
+
+    }
+
+    mod merge {
+        use msg_store_uuid::Uuid;
+        use crate::{GroupDefaults, Store};
+        use std::sync::Arc;
+
+        #[test]
+        fn should_keep_msgs_from_every_node() {
+            let mut first = Store::new(Some(1), None).unwrap();
+            let mut second = Store::new(Some(2), None).unwrap();
+            let mut msgs = vec![];
+            for _ in 0..3 {
+                msgs.push((first.add(1, 3).unwrap().uuid, 3));
+                msgs.push((second.add(1, 3).unwrap().uuid, 3));
+            }
+            let mut merged = Store::new(Some(3), None).unwrap();
+            let merge_result = merged.merge(msgs).unwrap();
+            assert_eq!(6, merge_result.inserted.len());
+            assert_eq!(6, merged.id_to_group_map.len());
+            assert_eq!(18, merged.byte_size);
+        }
+
+        #[test]
+        fn should_skip_msgs_the_store_already_holds() {
+            let mut store = Store::new(Some(1), None).unwrap();
+            let held = store.add(1, 3).unwrap().uuid;
+            let other = Arc::new(Uuid { node_id: 2, ..*held });
+            let merge_result = store.merge(vec![(held.clone(), 3), (other.clone(), 3), (other.clone(), 3)]).unwrap();
+            assert_eq!(vec![other.clone()], merge_result.inserted);
+            assert_eq!(vec![held, other], merge_result.duplicates);
+            assert_eq!(6, store.byte_size);
+        }
+
+        #[test]
+        fn should_prune_and_refuse_merged_msgs() {
+            let mut store = Store::new(Some(1), None).unwrap();
+            store.update_group_defaults(1, &GroupDefaults { max_byte_size: Some(6) }).unwrap();
+            let held = store.add(1, 3).unwrap().uuid;
+            let first = Uuid::from_string("1-1636523479-1-2").unwrap();
+            let second = Uuid::from_string("1-1636523479-2-2").unwrap();
+            let third = Uuid::from_string("1-1636523479-3-2").unwrap();
+            let too_large = Uuid::from_string("1-1636523479-4-2").unwrap();
+            let msgs = vec![(first.clone(), 3), (second.clone(), 3), (third.clone(), 3), (too_large.clone(), 7)];
+            let merge_result = store.merge(msgs).unwrap();
+            // the held msg is pruned to make room for the second msg, the second for the third
+            assert_eq!(vec![third, first], merge_result.inserted);
+            assert_eq!(vec![too_large], merge_result.refused);
+            assert_eq!(vec![held], merge_result.msgs_removed);
+            assert_eq!(None, store.id_to_group_map.get(&second));
+        }
+
+    }
+
+    mod consumer_groups {
+        use crate::{ConsumerGroup, Store};
+        use msg_store_uuid::Uuid;
+
+        #[test]
+        fn should_get_msgs_in_order_for_each_consumer_group() {
+            let mut store = Store::new(None, None).unwrap();
+            store.add_consumer_group("first", vec![]).unwrap();
+            store.add_consumer_group("second", vec![]).unwrap();
+            let uuid_1 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            let uuid_2 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            let uuid_3 = store.add(2, "foo".len() as u64).unwrap().uuid;
+            assert_eq!(Some(uuid_3.clone()), store.get_for_consumer("first", None).unwrap());
+            store.ack("first", uuid_3.clone()).unwrap();
+            assert_eq!(Some(uuid_1.clone()), store.get_for_consumer("first", None).unwrap());
+            store.ack("first", uuid_1.clone()).unwrap();
+            assert_eq!(Some(uuid_2.clone()), store.get_for_consumer("first", None).unwrap());
+            store.ack("first", uuid_2.clone()).unwrap();
+            assert_eq!(None, store.get_for_consumer("first", None).unwrap());
+
+            // the second consumer group has not moved
+            assert_eq!(Some(uuid_3), store.get_for_consumer("second", None).unwrap());
+            assert_eq!(Some(uuid_1), store.get_for_consumer("second", Some(1)).unwrap());
+            assert_eq!(3, store.id_to_group_map.len());
+        }
+
+        #[test]
+        fn should_only_remove_msgs_acknowledged_by_every_consumer_group() {
+            let mut store = Store::new(None, None).unwrap();
+            store.add_consumer_group("first", vec![]).unwrap();
+            store.add_consumer_group("second", vec![]).unwrap();
+            let uuid_1 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            let uuid_2 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            assert!(store.ack("first", uuid_2.clone()).unwrap().is_empty());
+            assert_eq!(vec![uuid_1.clone()], store.ack("second", uuid_1.clone()).unwrap());
+            assert_eq!(None, store.id_to_group_map.get(&uuid_1));
+            assert_eq!(3, store.byte_size);
+
+            // acking an older msg does not move the cursor back
+            store.ack("first", uuid_1).unwrap();
+            assert_eq!(vec![uuid_2.clone()], store.ack("second", uuid_2.clone()).unwrap());
+            assert_eq!(0, store.byte_size);
+            assert_eq!(0, store.groups_map.len());
+        }
+
+        #[test]
+        fn should_remove_msgs_acknowledged_by_the_remaining_consumer_groups() {
+            let mut store = Store::new(None, None).unwrap();
+            store.add_consumer_group("first", vec![]).unwrap();
+            store.add_consumer_group("second", vec![]).unwrap();
+            let uuid = store.add(1, "foo".len() as u64).unwrap().uuid;
+            store.ack("first", uuid.clone()).unwrap();
+            assert_eq!(vec![uuid], store.del_consumer_group("second").unwrap());
+            assert!(store.del_consumer_group("second").unwrap().is_empty());
+        }
+
+        #[test]
+        fn should_resume_from_cursors() {
+            let mut store = Store::new(None, None).unwrap();
+            let uuid_1 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            let uuid_2 = store.add(1, "foo".len() as u64).unwrap().uuid;
+            store.add_consumer_group("first", vec![uuid_1.clone()]).unwrap();
+            assert_eq!(Some(uuid_2), store.get_for_consumer("first", None).unwrap());
+            assert_eq!(vec![uuid_1], store.remove_acknowledged().unwrap());
+        }
+
+        #[test]
+        fn should_return_errors_for_unknown_or_duplicate_consumer_groups() {
+            let mut store = Store::new(None, None).unwrap();
+            let uuid = store.add(1, "foo".len() as u64).unwrap().uuid;
+            assert!(store.get_for_consumer("first", None).is_err());
+            assert!(store.ack("first", uuid).is_err());
+            store.add_consumer_group("first", vec![]).unwrap();
+            assert!(store.add_consumer_group("first", vec![]).is_err());
+        }
+
+        #[test]
+        fn should_keep_msgs_merged_behind_a_cursor_until_they_are_acknowledged() {
+            let mut store = Store::new(Some(1), None).unwrap();
+            store.add_consumer_group("first", vec![]).unwrap();
+            store.add_consumer_group("second", vec![]).unwrap();
+            let uuid_1 = Uuid::from_string("1-100-0-1").unwrap();
+            let uuid_2 = Uuid::from_string("1-300-0-1").unwrap();
+            store.merge(vec![(uuid_1.clone(), 3), (uuid_2.clone(), 3)]).unwrap();
+            store.ack("first", uuid_2.clone()).unwrap();
+            assert_eq!(vec![uuid_1.clone()], store.ack("second", uuid_1).unwrap());
+
+            // a msg of another node that is older than the cursor of the first consumer group
+            let late = Uuid::from_string("1-200-0-2").unwrap();
+            let merge_result = store.merge(vec![(late.clone(), 3)]).unwrap();
+            assert_eq!(vec!["first".to_string()], merge_result.consumer_groups_behind);
+            assert_eq!(Some(late.clone()), store.get_for_consumer("first", None).unwrap());
+            assert!(store.ack("second", late.clone()).unwrap().is_empty());
+
+            // the late msg is kept in the saved cursors
+            let consumer_group = store.consumer_groups.get("first").unwrap();
+            assert_eq!(vec![uuid_2.clone(), late.clone()], consumer_group.to_cursors());
+            assert_eq!(consumer_group, &ConsumerGroup::from_cursors(consumer_group.to_cursors()));
+
+            // acknowledging the late msg does not acknowledge the msgs after the cursor
+            assert_eq!(vec![late.clone()], store.ack("first", late).unwrap());
+            assert_eq!(None, store.get_for_consumer("first", None).unwrap());
+            assert_eq!(Some(uuid_2), store.get_for_consumer("second", None).unwrap());
+        }
+
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,586 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use msg_store_uuid::{Clock, SystemClock, Uuid};
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
+use std::collections::BTreeSet;
+use std::fs::{copy, create_dir_all, write};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+macro_rules! checksum_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The bytes a checksummed value or file starts with
+///
+/// 0xff is never part of utf-8, so a text msg can not be mistaken for a checksummed one.
+pub const MAGIC: [u8; 8] = [0x00, 0xff, b'm', b's', b'g', b'c', 0x00, 0x01];
+
+/// The magic and the CRC32C of the rest of the value
+pub const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// How many msgs are checked at a time while the database is scrubbed
+const SCRUB_BATCH_SIZE: usize = 100;
+
+/// The length of the pieces a file is read in while its checksum is worked out
+const READ_LEN: usize = 64 * 1024;
+
+/// What is done with a msg once it is found to be corrupt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// The stored msg is copied to the directory before the msg is removed
+    Quarantine(PathBuf),
+    /// The msg is removed
+    Delete,
+    /// The msg is still served, along with a warning
+    Serve
+}
+impl CorruptionPolicy {
+    /// Gets the policy from its name, a quarantine keeps the msgs in the directory given
+    pub fn from_name(name: &str, quarantine_path: &Path) -> Option<CorruptionPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "quarantine" => Some(CorruptionPolicy::Quarantine(quarantine_path.to_path_buf())),
+            "delete" => Some(CorruptionPolicy::Delete),
+            "serve" => Some(CorruptionPolicy::Serve),
+            _ => None
+        }
+    }
+}
+
+/// The msgs that were found to be corrupt, shared by the database and the file storage
+///
+/// The msgs are kept until they are taken to be removed, or until they are deleted.
+#[derive(Debug, Clone)]
+pub struct Corruption {
+    policy: Arc<CorruptionPolicy>,
+    msgs: Arc<Mutex<BTreeSet<Arc<Uuid>>>>
+}
+impl Corruption {
+    pub fn new(policy: CorruptionPolicy) -> Corruption {
+        Corruption {
+            policy: Arc::new(policy),
+            msgs: Arc::new(Mutex::new(BTreeSet::new()))
+        }
+    }
+    pub fn policy(&self) -> &CorruptionPolicy {
+        &self.policy
+    }
+    fn msgs(&self) -> std::sync::MutexGuard<'_, BTreeSet<Arc<Uuid>>> {
+        self.msgs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Records a corrupt msg, returning false if it was already recorded
+    pub fn report(&self, uuid: Arc<Uuid>) -> bool {
+        self.msgs().insert(uuid)
+    }
+    pub fn is_corrupt(&self, uuid: &Uuid) -> bool {
+        self.msgs().contains(uuid)
+    }
+    /// Forgets a msg, e.g. once it is deleted
+    pub fn forget(&self, uuid: &Uuid) {
+        self.msgs().remove(uuid);
+    }
+    /// Takes the msgs that were recorded
+    pub fn take(&self) -> Vec<Arc<Uuid>> {
+        std::mem::take(&mut *self.msgs()).into_iter().collect()
+    }
+    /// Writes the stored msg to the quarantine, if msgs are quarantined
+    pub fn quarantine(&self, uuid: &Uuid, value: &[u8]) -> io::Result<()> {
+        if let CorruptionPolicy::Quarantine(quarantine_path) = &*self.policy {
+            create_dir_all(quarantine_path)?;
+            write(quarantine_path.join(uuid.to_string()), value)?;
+        }
+        Ok(())
+    }
+    /// Copies the file of a msg to the quarantine, if msgs are quarantined
+    pub fn quarantine_file(&self, uuid: &Uuid, file_path: &Path) -> io::Result<()> {
+        if let CorruptionPolicy::Quarantine(quarantine_path) = &*self.policy {
+            create_dir_all(quarantine_path)?;
+            copy(file_path, quarantine_path.join(uuid.to_string()))?;
+        }
+        Ok(())
+    }
+}
+impl Default for Corruption {
+    fn default() -> Corruption {
+        Corruption::new(CorruptionPolicy::Delete)
+    }
+}
+
+/// Whether a value or file matched its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    /// The checksum matches
+    Sealed,
+    /// There is no checksum, the value was written before checksums were kept
+    Unsealed,
+    /// The checksum does not match
+    Corrupt
+}
+
+/// Works out the CRC32C of a msg
+pub fn checksum(msg: &[u8]) -> u32 {
+    crc32c::crc32c(msg)
+}
+
+fn stored_checksum(header: &[u8]) -> Option<u32> {
+    if header.len() < HEADER_LEN || header[..MAGIC.len()] != MAGIC {
+        return None
+    }
+    let mut stored = [0u8; 4];
+    stored.copy_from_slice(&header[MAGIC.len()..HEADER_LEN]);
+    Some(u32::from_le_bytes(stored))
+}
+
+/// Puts the checksum of a msg in front of it
+pub fn seal(msg: &[u8]) -> Bytes {
+    let mut value = BytesMut::with_capacity(HEADER_LEN + msg.len());
+    value.put_slice(&MAGIC);
+    value.put_u32_le(checksum(msg));
+    value.put_slice(msg);
+    value.freeze()
+}
+
+/// Checks a value against its checksum
+///
+/// A value is only known to be checksummed by its magic, so a value whose magic is damaged is
+/// read as one that was written before checksums were kept.
+pub fn verify(value: &[u8]) -> Verified {
+    match stored_checksum(value) {
+        Some(stored) if stored == checksum(&value[HEADER_LEN..]) => Verified::Sealed,
+        Some(_) => Verified::Corrupt,
+        None => Verified::Unsealed
+    }
+}
+
+/// Writes the header of a checksummed file, the checksum is filled in by `seal_file`
+pub fn write_header<W: io::Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[0u8; 4])
+}
+
+fn checksum_from<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut crc = 0;
+    let mut buffer = vec![0u8; READ_LEN];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(crc)
+        }
+        crc = crc32c::crc32c_append(crc, &buffer[..read]);
+    }
+}
+
+/// Fills in the checksum of a file that starts with the header of `write_header`
+pub fn seal_file<F: Read + io::Write + Seek>(file: &mut F) -> io::Result<()> {
+    file.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+    let crc = checksum_from(file)?;
+    file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    file.write_all(&crc.to_le_bytes())?;
+    file.flush()
+}
+
+/// Checks a file against its checksum
+///
+/// The file is left at the start of the msg, after the header of a checksummed file.
+pub fn verify_file<F: Read + Seek>(file: &mut F) -> io::Result<Verified> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.by_ref().take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    let stored = match stored_checksum(&header) {
+        Some(stored) => stored,
+        None => {
+            file.seek(SeekFrom::Start(0))?;
+            return Ok(Verified::Unsealed)
+        }
+    };
+    let verified = match checksum_from(file)? == stored {
+        true => Verified::Sealed,
+        false => Verified::Corrupt
+    };
+    file.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+    Ok(verified)
+}
+
+/// Where a pass over every msg to check them is at
+struct Scrub {
+    interval: Duration,
+    /// When the last pass started, in microseconds since the unix epoch
+    started: Option<u64>,
+    /// Whether a pass is under way
+    in_pass: bool,
+    /// The last msg that was checked in this pass, the next batch is fetched after it
+    last: Option<Arc<Uuid>>
+}
+
+/// Checksums the msgs of any database
+///
+/// A CRC32C is put in front of each msg as it is added and checked as the msg is read. A
+/// corrupt msg is recorded and dealt with by the policy: it is either still read, or the read
+/// fails with `DatabaseErrorTy::CorruptMsg` after the msg is quarantined if msgs are
+/// quarantined. Msgs added before the database was wrapped are read as they are.
+///
+/// Every msg is checked each scrub interval by `maintain`, a batch at a time. The interval is
+/// measured by the clock that is given, or by the system clock.
+pub struct ChecksummedDb<D: Db> {
+    db: D,
+    corruption: Corruption,
+    /// The last msg that was checked, kept to read it in chunks
+    verified: Option<(Arc<Uuid>, Bytes)>,
+    scrub: Scrub,
+    clock: Arc<dyn Clock>
+}
+impl<D: Db> ChecksummedDb<D> {
+    pub fn new(db: D, corruption: Corruption, scrub_interval: Duration, clock: Option<Arc<dyn Clock>>) -> ChecksummedDb<D> {
+        let clock: Arc<dyn Clock> = match clock {
+            Some(clock) => clock,
+            None => Arc::new(SystemClock)
+        };
+        ChecksummedDb {
+            db,
+            corruption,
+            verified: None,
+            scrub: Scrub { interval: scrub_interval, started: None, in_pass: false, last: None },
+            clock
+        }
+    }
+    /// Records a corrupt msg, quarantining it the first time it is found
+    fn report(&mut self, uuid: Arc<Uuid>, value: &[u8]) -> Result<(), DatabaseError> {
+        if self.corruption.report(uuid.clone()) {
+            if let Err(error) = self.corruption.quarantine(&uuid, value) {
+                return Err(checksum_error!(DatabaseErrorTy::CouldNotGetMsg, format!("Could not quarantine the msg: {}", error)))
+            }
+        }
+        Ok(())
+    }
+    /// Checks a value, returning the msg it holds
+    fn contents(&mut self, uuid: Arc<Uuid>, value: Bytes) -> Result<Bytes, DatabaseError> {
+        match verify(&value) {
+            Verified::Sealed => Ok(value.slice(HEADER_LEN..)),
+            Verified::Unsealed => Ok(value),
+            Verified::Corrupt => {
+                self.report(uuid.clone(), &value)?;
+                match self.corruption.policy() {
+                    CorruptionPolicy::Serve => Ok(value.slice(HEADER_LEN..)),
+                    _ => Err(checksum_error!(DatabaseErrorTy::CorruptMsg, format!("The checksum of {} does not match", uuid.to_string())))
+                }
+            }
+        }
+    }
+    fn is_sealed(&mut self, uuid: Arc<Uuid>) -> Result<bool, DatabaseError> {
+        Ok(stored_checksum(&self.db.get_chunk(uuid, 0, HEADER_LEN)?).is_some())
+    }
+    /// Gets a checked msg, keeping it to be read in chunks
+    fn verified(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        if let Some((verified_uuid, msg)) = &self.verified {
+            if *verified_uuid == uuid {
+                return Ok(msg.clone())
+            }
+        }
+        let value = self.db.get(uuid.clone())?;
+        let msg = self.contents(uuid.clone(), value)?;
+        self.verified = Some((uuid, msg.clone()));
+        Ok(msg)
+    }
+    /// Checks the next batch of msgs, starting a new pass once the scrub interval has passed
+    fn scrub(&mut self) -> Result<bool, DatabaseError> {
+        if !self.scrub.in_pass {
+            let now = match self.clock.now_micros() {
+                Ok(now) => Ok(now),
+                Err(error) => Err(checksum_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            if let Some(started) = self.scrub.started {
+                if u128::from(now.saturating_sub(started)) < self.scrub.interval.as_micros() {
+                    return Ok(false)
+                }
+            }
+            self.scrub.started = Some(now);
+            self.scrub.in_pass = true;
+            self.scrub.last = None;
+        }
+        let fetched = match self.scrub.last.clone() {
+            Some(last) => self.db.fetch_after(last)?,
+            None => self.db.fetch()?
+        };
+        let uuids = fetched
+            .take(SCRUB_BATCH_SIZE)
+            .map(|fetched| fetched.map(|(uuid, _)| uuid))
+            .collect::<Result<Vec<Arc<Uuid>>, DatabaseError>>()?;
+        for uuid in uuids.iter() {
+            let value = match self.db.get(uuid.clone()) {
+                Ok(value) => value,
+                // the msg was deleted while the pass was under way
+                Err(DatabaseError { err_ty: DatabaseErrorTy::MsgNotFound, .. }) => continue,
+                Err(error) => return Err(error)
+            };
+            if verify(&value) == Verified::Corrupt {
+                self.report(uuid.clone(), &value)?;
+            }
+        }
+        self.scrub.in_pass = uuids.len() == SCRUB_BATCH_SIZE;
+        self.scrub.last = uuids.last().cloned();
+        Ok(self.scrub.in_pass)
+    }
+}
+
+impl<D: Db> Db for ChecksummedDb<D> {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        match self.verified.take() {
+            Some((verified_uuid, msg)) if verified_uuid == uuid => Ok(msg),
+            _ => {
+                let value = self.db.get(uuid.clone())?;
+                self.contents(uuid, value)
+            }
+        }
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        self.verified = None;
+        self.corruption.forget(&uuid);
+        self.db.add(uuid, seal(&msg), msg_byte_size)
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        self.verified = None;
+        self.corruption.forget(&uuid);
+        self.db.del(uuid)
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        // a msg that was not checksummed is read in chunks from the database
+        let cached = matches!(&self.verified, Some((verified_uuid, _)) if *verified_uuid == uuid);
+        if !cached && !self.is_sealed(uuid.clone())? {
+            return self.db.get_chunk(uuid, offset, max_len)
+        }
+        let msg = self.verified(uuid)?;
+        let start = match usize::try_from(offset) {
+            Ok(offset) => offset.min(msg.len()),
+            Err(_) => msg.len()
+        };
+        let end = start.saturating_add(max_len).min(msg.len());
+        // the msg is let go of once its last chunk is read
+        if end == msg.len() {
+            self.verified = None;
+        }
+        Ok(msg.slice(start..end))
+    }
+    fn stored_size(&mut self, msg: &Bytes) -> Result<u64, DatabaseError> {
+        self.db.stored_size(msg)
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        // the msg is checked before its length is given, so that a corrupt msg is found
+        // before it is streamed
+        let cached = matches!(&self.verified, Some((verified_uuid, _)) if *verified_uuid == uuid);
+        if !cached && !self.is_sealed(uuid.clone())? {
+            return self.db.msg_len(uuid)
+        }
+        Ok(self.verified(uuid)?.len() as u64)
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        self.verified = None;
+        let mut sealed = Batch::new();
+        for write in batch.writes {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => {
+                    self.corruption.forget(&uuid);
+                    sealed.add(uuid, seal(&msg), msg_byte_size);
+                },
+                Write::Del { uuid } => {
+                    self.corruption.forget(&uuid);
+                    sealed.del(uuid);
+                }
+            }
+        }
+        self.db.commit(sealed)
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch()
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch_after(after)
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        self.db.put_cursors(consumer_group, cursors)
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        self.db.del_cursors(consumer_group)
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        self.db.fetch_cursors()
+    }
+    fn maintain(&mut self) -> Result<bool, DatabaseError> {
+        let scrubbing = self.scrub()?;
+        Ok(self.db.maintain()? || scrubbing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::{Db, DatabaseErrorTy};
+    use msg_store_database_test_kit::check_db;
+    use msg_store_uuid::{ManualClock, Uuid};
+    use crate::{seal_file, verify_file, write_header, ChecksummedDb, Corruption, CorruptionPolicy, Verified, HEADER_LEN};
+    use std::fs::{read, remove_dir_all};
+    use std::io::{Cursor, Read, Write};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Flips a bit of the msg held in the database
+    fn corrupt(db: &mut MemDb, uuid: std::sync::Arc<Uuid>) {
+        let mut value = BytesMut::from(&db.get(uuid.clone()).unwrap()[..]);
+        let last = value.len() - 1;
+        value[last] ^= 1;
+        db.add(uuid, value.freeze(), 10).unwrap();
+    }
+
+    #[test]
+    fn should_checksum_msgs() {
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let legacy_uuid = Uuid::from_string("1-0-1-1").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        let mut mem_db = MemDb::new();
+        mem_db.add(legacy_uuid.clone(), Bytes::from_static(b"legacy"), 6).unwrap();
+        let corruption = Corruption::new(CorruptionPolicy::Delete);
+        let mut checksummed = ChecksummedDb::new(mem_db, corruption.clone(), Duration::from_secs(60), None);
+        checksummed.add(uuid.clone(), msg.clone(), 10).unwrap();
+        assert_eq!(msg.len() + HEADER_LEN, checksummed.db.get(uuid.clone()).unwrap().len());
+        assert_eq!(msg, checksummed.get(uuid.clone()).unwrap());
+        assert_eq!(msg.len() as u64, checksummed.msg_len(uuid.clone()).unwrap());
+        assert_eq!(Bytes::from_static(b"my me"), checksummed.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), checksummed.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"legacy"), checksummed.get(legacy_uuid.clone()).unwrap());
+        assert_eq!(Bytes::from_static(b"leg"), checksummed.get_chunk(legacy_uuid, 0, 3).unwrap());
+
+        corrupt(&mut checksummed.db, uuid.clone());
+        let error = checksummed.get(uuid.clone()).err().unwrap();
+        assert!(matches!(error.err_ty, DatabaseErrorTy::CorruptMsg));
+        assert!(checksummed.msg_len(uuid.clone()).is_err());
+        assert!(corruption.is_corrupt(&uuid));
+        assert_eq!(vec![uuid.clone()], corruption.take());
+        assert!(!corruption.is_corrupt(&uuid));
+    }
+
+    #[test]
+    fn should_follow_the_corruption_policy() {
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        // a corrupt msg can still be served
+        let corruption = Corruption::new(CorruptionPolicy::Serve);
+        let mut checksummed = ChecksummedDb::new(MemDb::new(), corruption.clone(), Duration::from_secs(60), None);
+        checksummed.add(uuid.clone(), msg.clone(), 10).unwrap();
+        corrupt(&mut checksummed.db, uuid.clone());
+        assert_eq!(Bytes::from_static(b"my messagd"), checksummed.get(uuid.clone()).unwrap());
+        assert!(corruption.is_corrupt(&uuid));
+        checksummed.del(uuid.clone()).unwrap();
+        assert!(!corruption.is_corrupt(&uuid));
+
+        // or copied to the quarantine
+        let quarantine_path = PathBuf::from_str("/tmp/msg-store-plugin-checksum-quarantine").unwrap();
+        if quarantine_path.exists() {
+            remove_dir_all(&quarantine_path).unwrap();
+        }
+        let corruption = Corruption::new(CorruptionPolicy::Quarantine(quarantine_path.clone()));
+        let mut checksummed = ChecksummedDb::new(MemDb::new(), corruption.clone(), Duration::from_secs(60), None);
+        checksummed.add(uuid.clone(), msg, 10).unwrap();
+        corrupt(&mut checksummed.db, uuid.clone());
+        let value = checksummed.db.get(uuid.clone()).unwrap();
+        assert!(checksummed.get(uuid.clone()).is_err());
+        assert_eq!(value.to_vec(), read(quarantine_path.join(uuid.to_string())).unwrap());
+        remove_dir_all(&quarantine_path).unwrap();
+    }
+
+    #[test]
+    fn should_scrub_msgs() {
+        let uuids = (0..250).map(|sequence| Uuid::from_string(&format!("1-0-1-{}", sequence)).unwrap()).collect::<Vec<_>>();
+        let corruption = Corruption::default();
+        let mut checksummed = ChecksummedDb::new(MemDb::new(), corruption.clone(), Duration::from_secs(0), None);
+        for uuid in uuids.iter() {
+            checksummed.add(uuid.clone(), Bytes::from(uuid.to_string()), 10).unwrap();
+        }
+        corrupt(&mut checksummed.db, uuids[3].clone());
+        corrupt(&mut checksummed.db, uuids[240].clone());
+        let mut steps = 1;
+        while checksummed.maintain().unwrap() {
+            steps += 1;
+        }
+        assert_eq!(3, steps);
+        let mut corrupt_uuids = corruption.take();
+        let mut expected = vec![uuids[3].clone(), uuids[240].clone()];
+        corrupt_uuids.sort();
+        expected.sort();
+        assert_eq!(expected, corrupt_uuids);
+        // the next pass waits for the scrub interval
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut waiting = ChecksummedDb::new(checksummed.db, corruption.clone(), Duration::from_secs(60), Some(clock.clone()));
+        while waiting.maintain().unwrap() {}
+        assert_eq!(2, corruption.take().len());
+        clock.advance(59_000_000);
+        assert!(!waiting.maintain().unwrap());
+        assert!(corruption.take().is_empty());
+        clock.advance(1_000_000);
+        while waiting.maintain().unwrap() {}
+        assert_eq!(2, corruption.take().len());
+    }
+
+    #[test]
+    fn should_resume_a_scrub_after_a_deleted_msg() {
+        let uuids = (0..150).map(|sequence| Uuid::from_string(&format!("1-0-1-{}", sequence)).unwrap()).collect::<Vec<_>>();
+        let corruption = Corruption::default();
+        let mut checksummed = ChecksummedDb::new(MemDb::new(), corruption.clone(), Duration::from_secs(60), None);
+        for uuid in uuids.iter() {
+            checksummed.add(uuid.clone(), Bytes::from(uuid.to_string()), 10).unwrap();
+        }
+        assert!(checksummed.maintain().unwrap());
+        let last = checksummed.scrub.last.clone().unwrap();
+        checksummed.del(last).unwrap();
+        let unchecked = checksummed.db.fetch_all().unwrap().into_iter()
+            .filter(|(uuid, _)| *uuid > checksummed.scrub.last.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(50, unchecked.len());
+        corrupt(&mut checksummed.db, unchecked[0].0.clone());
+        assert!(!checksummed.maintain().unwrap());
+        assert_eq!(vec![unchecked[0].0.clone()], corruption.take());
+    }
+
+    #[test]
+    fn should_checksum_files() {
+        let mut file = Cursor::new(vec![]);
+        write_header(&mut file).unwrap();
+        file.write_all(b"my message").unwrap();
+        seal_file(&mut file).unwrap();
+        assert_eq!(Verified::Sealed, verify_file(&mut file).unwrap());
+        let mut msg = String::new();
+        file.read_to_string(&mut msg).unwrap();
+        assert_eq!("my message", msg);
+        let mut value = file.into_inner();
+        let last = value.len() - 1;
+        value[last] ^= 1;
+        assert_eq!(Verified::Corrupt, verify_file(&mut Cursor::new(value)).unwrap());
+        let mut legacy = Cursor::new(b"my message".to_vec());
+        assert_eq!(Verified::Unsealed, verify_file(&mut legacy).unwrap());
+        let mut msg = String::new();
+        legacy.read_to_string(&mut msg).unwrap();
+        assert_eq!("my message", msg);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_db("checksum", |_| ChecksummedDb::new(MemDb::new(), Corruption::default(), Duration::from_secs(60), None));
+    }
+}
@@ -0,0 +1,532 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use msg_store_uuid::Uuid;
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+macro_rules! compressed_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The bytes an encoded value starts with
+///
+/// 0xff is never part of utf-8, so a text msg can not be mistaken for an encoded one.
+pub const MAGIC: [u8; 8] = [0x00, 0xff, b'm', b's', b'g', b'z', 0x00, 0x01];
+
+/// The magic, the algorithm and the length of the msg before it was compressed
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// Msgs shorter than this are not worth compressing
+pub const DEFAULT_THRESHOLD: usize = 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// The algorithm byte of the header
+const NONE: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Lz4,
+    Zstd
+}
+impl Algorithm {
+    /// Gets the algorithm by the name used in the config, i.e. lz4 or zstd
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "lz4" => Some(Algorithm::Lz4),
+            "zstd" => Some(Algorithm::Zstd),
+            _ => None
+        }
+    }
+    fn tag(&self) -> u8 {
+        match self {
+            Algorithm::Lz4 => LZ4,
+            Algorithm::Zstd => ZSTD
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// Msgs shorter than the threshold are kept as they are
+    pub threshold: usize
+}
+impl Compression {
+    pub fn new(algorithm: Algorithm) -> Compression {
+        Compression { algorithm, threshold: DEFAULT_THRESHOLD }
+    }
+}
+
+fn header(tag: u8, len: u64) -> BytesMut {
+    let mut header = BytesMut::with_capacity(HEADER_LEN);
+    header.put_slice(&MAGIC);
+    header.put_u8(tag);
+    header.put_u64_le(len);
+    header
+}
+
+/// Reads the algorithm and the length of the msg, None if the value was not encoded
+fn read_header(value: &[u8]) -> Option<(u8, u64)> {
+    if value.len() < HEADER_LEN || value[..MAGIC.len()] != MAGIC {
+        return None
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&value[MAGIC.len() + 1..HEADER_LEN]);
+    Some((value[MAGIC.len()], u64::from_le_bytes(len)))
+}
+
+/// Checks if a value starts with the header of an encoded msg
+pub fn is_encoded(value: &[u8]) -> bool {
+    read_header(value).is_some()
+}
+
+fn compress(msg: &[u8], algorithm: Algorithm) -> io::Result<Vec<u8>> {
+    match algorithm {
+        Algorithm::Lz4 => {
+            let mut encoder = FrameEncoder::new(vec![]);
+            io::Write::write_all(&mut encoder, msg)?;
+            Ok(encoder.finish()?)
+        },
+        Algorithm::Zstd => zstd::encode_all(msg, ZSTD_LEVEL)
+    }
+}
+
+/// Keeps a msg as it is, unless it could be mistaken for an encoded one
+fn uncompressed(msg: &Bytes) -> Bytes {
+    if !msg.starts_with(&MAGIC) {
+        return msg.clone()
+    }
+    let mut value = header(NONE, msg.len() as u64);
+    value.put_slice(msg);
+    value.freeze()
+}
+
+/// Compresses a msg that reaches the threshold
+///
+/// A msg that does not get shorter is kept as it is, so that it is read without being
+/// decompressed.
+pub fn encode(msg: &Bytes, compression: &Compression) -> Result<Bytes, DatabaseError> {
+    if msg.len() < compression.threshold {
+        return Ok(uncompressed(msg))
+    }
+    let compressed = match compress(msg, compression.algorithm) {
+        Ok(compressed) => Ok(compressed),
+        Err(error) => Err(compressed_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+    }?;
+    if compressed.len() + HEADER_LEN >= msg.len() {
+        return Ok(uncompressed(msg))
+    }
+    let mut value = header(compression.algorithm.tag(), msg.len() as u64);
+    value.put_slice(&compressed);
+    Ok(value.freeze())
+}
+
+/// Gets the msg back from a value, a value that was not encoded is returned as it is
+pub fn decode(value: Bytes) -> Result<Bytes, DatabaseError> {
+    let (tag, len) = match read_header(&value) {
+        Some(header) => header,
+        None => return Ok(value)
+    };
+    let payload = value.slice(HEADER_LEN..);
+    let mut msg = Vec::with_capacity(len.min(u32::MAX as u64) as usize);
+    let read = match tag {
+        NONE => return Ok(payload),
+        LZ4 => FrameDecoder::new(payload.as_ref()).read_to_end(&mut msg),
+        ZSTD => match zstd::Decoder::new(payload.as_ref()) {
+            Ok(mut decoder) => decoder.read_to_end(&mut msg),
+            Err(error) => Err(error)
+        },
+        tag => return Err(compressed_error!(DatabaseErrorTy::CouldNotGetMsg, format!("Unknown compression algorithm {}", tag)))
+    };
+    if let Err(error) = read {
+        return Err(compressed_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+    }
+    if msg.len() as u64 != len {
+        return Err(compressed_error!(DatabaseErrorTy::CouldNotGetMsg, "The msg does not have the length it was compressed with"))
+    }
+    Ok(Bytes::from(msg))
+}
+
+/// Wraps a reader of a value so that the msg is read back
+///
+/// Also returns the length of the msg if the value was encoded, a value that was not encoded
+/// is read as it is.
+pub fn decoder<R: Read + Send + 'static>(mut reader: R) -> io::Result<(Box<dyn Read + Send>, Option<u64>)> {
+    let mut prefix = Vec::with_capacity(HEADER_LEN);
+    reader.by_ref().take(HEADER_LEN as u64).read_to_end(&mut prefix)?;
+    let (tag, len) = match read_header(&prefix) {
+        Some(header) => header,
+        None => return Ok((Box::new(Cursor::new(prefix).chain(reader)), None))
+    };
+    let reader: Box<dyn Read + Send> = match tag {
+        NONE => Box::new(reader),
+        LZ4 => Box::new(FrameDecoder::new(reader)),
+        ZSTD => Box::new(zstd::Decoder::new(reader)?),
+        tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown compression algorithm {}", tag)))
+    };
+    Ok((reader, Some(len)))
+}
+
+enum Encoder<W: io::Write> {
+    Lz4(FrameEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>)
+}
+
+/// Compresses a msg as it is written, e.g. to a file
+///
+/// The length of the msg is not known until every chunk is written, so the header is written
+/// with a length of 0 that is filled in by `finish`. Every msg is compressed, whatever its
+/// length.
+pub struct StreamEncoder<W: io::Write + Seek> {
+    encoder: Encoder<W>,
+    start: u64,
+    len: u64
+}
+impl<W: io::Write + Seek> StreamEncoder<W> {
+    pub fn new(mut writer: W, algorithm: Algorithm) -> io::Result<StreamEncoder<W>> {
+        let start = writer.stream_position()?;
+        writer.write_all(&header(algorithm.tag(), 0))?;
+        let encoder = match algorithm {
+            Algorithm::Lz4 => Encoder::Lz4(FrameEncoder::new(writer)),
+            Algorithm::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, ZSTD_LEVEL)?)
+        };
+        Ok(StreamEncoder { encoder, start, len: 0 })
+    }
+    /// Finishes the compressed msg and writes its length into the header
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = match self.encoder {
+            Encoder::Lz4(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?
+        };
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(self.start + MAGIC.len() as u64 + 1))?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        writer.seek(SeekFrom::Start(end))?;
+        Ok(writer)
+    }
+}
+impl<W: io::Write + Seek> io::Write for StreamEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match &mut self.encoder {
+            Encoder::Lz4(encoder) => encoder.write(buf)?,
+            Encoder::Zstd(encoder) => encoder.write(buf)?
+        };
+        self.len += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Lz4(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush()
+        }
+    }
+}
+
+/// How a msg that is read in chunks is held in the database
+enum Chunked {
+    /// The msg is read from the database, after the header if it has one
+    Stored { header_len: u64 },
+    /// The msg was decompressed whole
+    Decompressed(Bytes)
+}
+
+/// Compresses the msgs of any database
+///
+/// Msgs are compressed as they are added and decompressed as they are read. Values that were
+/// added before the database was wrapped are not encoded and are read as they are. The
+/// stored size of a msg is the size it is compressed to, a msg whose stored size was asked
+/// for is not compressed again when it is added right after. The byte sizes given with the
+/// msgs are kept as they are.
+pub struct CompressedDb<D: Db> {
+    db: D,
+    /// None if msgs are only decompressed
+    compression: Option<Compression>,
+    /// The last msg that was read in chunks
+    chunked: Option<(Arc<Uuid>, Chunked)>,
+    /// The last msg whose stored size was asked for and what it was encoded to
+    sized: Option<(Bytes, Bytes)>
+}
+impl<D: Db> CompressedDb<D> {
+    pub fn new(db: D, compression: Compression) -> CompressedDb<D> {
+        CompressedDb { db, compression: Some(compression), chunked: None, sized: None }
+    }
+    /// Decompresses the msgs that were compressed before, without compressing the msgs added
+    pub fn decompressing(db: D) -> CompressedDb<D> {
+        CompressedDb { db, compression: None, chunked: None, sized: None }
+    }
+    /// Encodes a msg before it is added
+    ///
+    /// A msg is never trusted to be encoded by its bytes, a msg that only looks encoded is
+    /// escaped instead.
+    fn encoded(&mut self, msg: Bytes) -> Result<Bytes, DatabaseError> {
+        if let Some((sized_msg, encoded)) = self.sized.take() {
+            if sized_msg == msg {
+                return Ok(encoded)
+            }
+        }
+        match &self.compression {
+            Some(compression) => encode(&msg, compression),
+            None => Ok(uncompressed(&msg))
+        }
+    }
+}
+
+impl<D: Db> Db for CompressedDb<D> {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        decode(self.db.get(uuid)?)
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        let msg = self.encoded(msg)?;
+        self.db.add(uuid, msg, msg_byte_size)
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        self.db.del(uuid)
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        let chunked = match self.chunked.take() {
+            Some((chunked_uuid, chunked)) if offset != 0 && chunked_uuid == uuid => chunked,
+            // a msg that was not compressed is still read in chunks from the database
+            _ => match read_header(&self.db.get_chunk(uuid.clone(), 0, HEADER_LEN)?) {
+                Some((NONE, _)) => Chunked::Stored { header_len: HEADER_LEN as u64 },
+                Some(_) => Chunked::Decompressed(self.get(uuid.clone())?),
+                None => Chunked::Stored { header_len: 0 }
+            }
+        };
+        let chunk = match &chunked {
+            Chunked::Stored { header_len } => self.db.get_chunk(uuid.clone(), header_len + offset, max_len)?,
+            Chunked::Decompressed(msg) => {
+                let start = match usize::try_from(offset) {
+                    Ok(offset) => offset.min(msg.len()),
+                    Err(_) => msg.len()
+                };
+                let end = start.saturating_add(max_len).min(msg.len());
+                msg.slice(start..end)
+            }
+        };
+        // the msg is let go of once its last chunk is read
+        if !chunk.is_empty() && chunk.len() == max_len {
+            self.chunked = Some((uuid, chunked));
+        }
+        Ok(chunk)
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        match read_header(&self.db.get_chunk(uuid.clone(), 0, HEADER_LEN)?) {
+            Some((_, len)) => Ok(len),
+            None => self.db.msg_len(uuid)
+        }
+    }
+    fn stored_size(&mut self, msg: &Bytes) -> Result<u64, DatabaseError> {
+        let encoded = self.encoded(msg.clone())?;
+        let stored_size = encoded.len() as u64;
+        self.sized = Some((msg.clone(), encoded));
+        Ok(stored_size)
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        let mut encoded = Batch::new();
+        for write in batch.writes {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => encoded.add(uuid, self.encoded(msg)?, msg_byte_size),
+                Write::Del { uuid } => encoded.del(uuid)
+            }
+        }
+        self.db.commit(encoded)
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch()
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch_after(after)
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        self.db.put_cursors(consumer_group, cursors)
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        self.db.del_cursors(consumer_group)
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        self.db.fetch_cursors()
+    }
+    fn maintain(&mut self) -> Result<bool, DatabaseError> {
+        self.db.maintain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::{Batch, Db};
+    use msg_store_database_test_kit::check_db;
+    use msg_store_uuid::Uuid;
+    use crate::{decode, decoder, encode, is_encoded, Algorithm, CompressedDb, Compression, StreamEncoder, MAGIC};
+    use std::io::{Cursor, Read, Write};
+
+    fn json(len: usize) -> Bytes {
+        let mut msg = String::new();
+        while msg.len() < len {
+            msg.push_str("{\"name\":\"my message\",\"priority\":1},");
+        }
+        Bytes::from(msg)
+    }
+
+    #[test]
+    fn should_compress_msgs_that_reach_the_threshold() {
+        for algorithm in [Algorithm::Lz4, Algorithm::Zstd] {
+            let compression = Compression::new(algorithm);
+            let small = Bytes::from_static(b"my message");
+            assert_eq!(small, encode(&small, &compression).unwrap());
+            let large = json(10_000);
+            let encoded = encode(&large, &compression).unwrap();
+            assert!(is_encoded(&encoded));
+            assert!(encoded.len() < large.len());
+            assert_eq!(large, decode(encoded).unwrap());
+        }
+    }
+
+    #[test]
+    fn should_keep_msgs_that_look_encoded_apart() {
+        let compression = Compression::new(Algorithm::Zstd);
+        let mut msg = MAGIC.to_vec();
+        msg.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+        let msg = Bytes::from(msg);
+        let encoded = encode(&msg, &compression).unwrap();
+        assert_ne!(msg, encoded);
+        assert_eq!(msg, decode(encoded).unwrap());
+    }
+
+    #[test]
+    fn should_escape_added_msgs_that_start_with_the_magic() {
+        let mut msg = MAGIC.to_vec();
+        msg.extend_from_slice(&[2, 3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+        let msg = Bytes::from(msg);
+        for compression in [Some(Compression::new(Algorithm::Lz4)), None] {
+            let mut compressed = match compression {
+                Some(compression) => CompressedDb::new(MemDb::new(), compression),
+                None => CompressedDb::decompressing(MemDb::new())
+            };
+            let added = Uuid::from_string("1-0-1-0").unwrap();
+            let committed = Uuid::from_string("1-0-2-0").unwrap();
+            compressed.add(added.clone(), msg.clone(), msg.len() as u64).unwrap();
+            let mut batch = Batch::new();
+            batch.add(committed.clone(), msg.clone(), msg.len() as u64);
+            compressed.commit(batch).unwrap();
+            for uuid in [added, committed] {
+                assert_eq!(msg, compressed.get(uuid.clone()).unwrap());
+                assert_eq!(msg.len() as u64, compressed.msg_len(uuid.clone()).unwrap());
+                assert_eq!(msg.slice(..5), compressed.get_chunk(uuid.clone(), 0, 5).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn should_read_legacy_and_compressed_msgs() {
+        let legacy_uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let uuid = Uuid::from_string("1-0-1-1").unwrap();
+        let legacy_msg = json(5_000);
+        let msg = json(5_000);
+        let mut mem_db = MemDb::new();
+        mem_db.add(legacy_uuid.clone(), legacy_msg.clone(), 5_000).unwrap();
+        let mut compressed = CompressedDb::new(mem_db, Compression::new(Algorithm::Lz4));
+        compressed.add(uuid.clone(), msg.clone(), 5_000).unwrap();
+        assert_eq!(legacy_msg, compressed.get(legacy_uuid.clone()).unwrap());
+        assert_eq!(msg, compressed.get(uuid.clone()).unwrap());
+        assert_eq!(msg.len() as u64, compressed.msg_len(uuid.clone()).unwrap());
+        assert_eq!(legacy_msg.len() as u64, compressed.msg_len(legacy_uuid).unwrap());
+        // the byte size is kept as it was given
+        let fetched = compressed.fetch_all().unwrap();
+        assert_eq!(2, fetched.len());
+        assert!(fetched.iter().all(|(_, msg_byte_size)| *msg_byte_size == 5_000));
+        let mut chunks = vec![];
+        let mut offset = 0;
+        loop {
+            let chunk = compressed.get_chunk(uuid.clone(), offset, 1_000).unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            offset += chunk.len() as u64;
+            chunks.extend_from_slice(&chunk);
+        }
+        assert_eq!(msg, Bytes::from(chunks));
+    }
+
+    #[test]
+    fn should_report_the_size_msgs_are_stored_at() {
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let compression = Compression::new(Algorithm::Zstd);
+        let msg = json(5_000);
+        let encoded = encode(&msg, &compression).unwrap();
+        let mut compressed = CompressedDb::new(MemDb::new(), compression);
+        assert_eq!(encoded.len() as u64, compressed.stored_size(&msg).unwrap());
+        let mut batch = Batch::new();
+        batch.add(uuid.clone(), msg.clone(), 5_000);
+        compressed.commit(batch).unwrap();
+        assert_eq!(msg, compressed.get(uuid.clone()).unwrap());
+        assert_eq!(encoded, compressed.db.get(uuid).unwrap());
+    }
+
+    #[test]
+    fn should_read_compressed_msgs_once_compression_is_turned_off() {
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let later_uuid = Uuid::from_string("1-0-1-1").unwrap();
+        let msg = json(5_000);
+        let mut compressed = CompressedDb::new(MemDb::new(), Compression::new(Algorithm::Zstd));
+        compressed.add(uuid.clone(), msg.clone(), 5_000).unwrap();
+        let mut decompressing = CompressedDb::decompressing(compressed.db);
+        decompressing.add(later_uuid.clone(), msg.clone(), 5_000).unwrap();
+        assert_eq!(msg, decompressing.get(uuid).unwrap());
+        assert_eq!(msg, decompressing.db.get(later_uuid.clone()).unwrap());
+        assert_eq!(Bytes::from_static(b"{\"name\":"), decompressing.get_chunk(later_uuid, 0, 8).unwrap());
+    }
+
+    #[test]
+    fn should_stream_msgs() {
+        for algorithm in [Algorithm::Lz4, Algorithm::Zstd] {
+            let msg = json(100_000);
+            let mut encoder = StreamEncoder::new(Cursor::new(vec![]), algorithm).unwrap();
+            for chunk in msg.chunks(4_096) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let value = encoder.finish().unwrap().into_inner();
+            assert!(value.len() < msg.len());
+            let (mut reader, len) = decoder(Cursor::new(value)).unwrap();
+            assert_eq!(Some(msg.len() as u64), len);
+            let mut read = vec![];
+            reader.read_to_end(&mut read).unwrap();
+            assert_eq!(msg, Bytes::from(read));
+        }
+        let (mut reader, len) = decoder(Cursor::new(b"my message".to_vec())).unwrap();
+        assert_eq!(None, len);
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(b"my message".to_vec(), read);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        // every msg is compressed
+        check_db("compressed-lz4", |_| CompressedDb::new(MemDb::new(), Compression { algorithm: Algorithm::Lz4, threshold: 0 }));
+        check_db("compressed-zstd", |_| CompressedDb::new(MemDb::new(), Compression { algorithm: Algorithm::Zstd, threshold: 0 }));
+    }
+}
@@ -0,0 +1,683 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use msg_store_uuid::Uuid;
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+macro_rules! encrypted_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The bytes an encrypted value or file starts with
+///
+/// 0xff is never part of utf-8, so a text msg can not be mistaken for an encrypted one.
+pub const MAGIC: [u8; 8] = [0x00, 0xff, b'm', b's', b'g', b'e', 0x00, 0x01];
+
+/// The length of a key in bytes
+pub const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_ID_LEN: usize = 4;
+
+/// The magic, the id of the key and the nonce of an encrypted value
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+
+/// The length of the segments a file is encrypted in
+pub const SEGMENT_LEN: usize = 64 * 1024;
+
+/// The nonce of a segment is the prefix, the number of the segment and a flag set on the last
+/// segment, so that segments can not be reordered, dropped or added
+const NONCE_PREFIX_LEN: usize = 7;
+
+/// The magic, the id of the key and the nonce prefix of an encrypted file
+const STREAM_HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_PREFIX_LEN;
+
+/// How many msgs are re-encrypted at a time after a key is rotated
+const REENCRYPTION_BATCH_SIZE: usize = 100;
+
+/// The keys msgs are encrypted with
+///
+/// The last key encrypts new msgs, the others are kept to read the msgs that were encrypted
+/// before a key was rotated.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, ChaCha20Poly1305>,
+    active: u32
+}
+impl Keyring {
+    pub fn new(keys: Vec<(u32, [u8; KEY_LEN])>) -> Result<Keyring, DatabaseError> {
+        let active = match keys.last() {
+            Some((key_id, _)) => Ok(*key_id),
+            None => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, "There are no keys"))
+        }?;
+        let mut ciphers = BTreeMap::new();
+        for (key_id, key) in keys.iter() {
+            if ciphers.insert(*key_id, ChaCha20Poly1305::new(Key::from_slice(key))).is_some() {
+                return Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("The key id {} is used twice", key_id)))
+            }
+        }
+        Ok(Keyring { keys: ciphers, active })
+    }
+    /// Reads the keys from a key file
+    ///
+    /// Each line holds the id of a key and the key in base64, e.g. 2:<base64 key>. Empty lines
+    /// and lines starting with # are skipped.
+    pub fn open(path: &Path) -> Result<Keyring, DatabaseError> {
+        let contents = match read_to_string(path) {
+            Ok(contents) => Ok(contents),
+            Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        let mut keys = vec![];
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key_id, key) = match line.split_once(':') {
+                Some(key) => Ok(key),
+                None => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("Expected <key id>:<key> on line {}", line_number + 1)))
+            }?;
+            let key_id = match key_id.trim().parse::<u32>() {
+                Ok(key_id) => Ok(key_id),
+                Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("Invalid key id on line {}: {}", line_number + 1, error)))
+            }?;
+            let key = match base64::decode(key.trim()) {
+                Ok(key) => Ok(key),
+                Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("Invalid key on line {}: {}", line_number + 1, error)))
+            }?;
+            let key: [u8; KEY_LEN] = match key.try_into() {
+                Ok(key) => Ok(key),
+                Err(_) => Err(encrypted_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("The key on line {} is not {} bytes", line_number + 1, KEY_LEN)))
+            }?;
+            keys.push((key_id, key));
+        }
+        Keyring::new(keys)
+    }
+    /// Gets the id of the key new msgs are encrypted with
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+    fn cipher(&self, key_id: u32) -> io::Result<&ChaCha20Poly1305> {
+        match self.keys.get(&key_id) {
+            Some(cipher) => Ok(cipher),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown key id {}", key_id)))
+        }
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Reads the id of the key a value or file was encrypted with, None if it was not encrypted
+pub fn key_id(value: &[u8]) -> Option<u32> {
+    if value.len() < MAGIC.len() + KEY_ID_LEN || value[..MAGIC.len()] != MAGIC {
+        return None
+    }
+    let mut key_id = [0u8; KEY_ID_LEN];
+    key_id.copy_from_slice(&value[MAGIC.len()..MAGIC.len() + KEY_ID_LEN]);
+    Some(u32::from_le_bytes(key_id))
+}
+
+/// Encrypts a msg with the active key
+///
+/// The associated data, e.g. the uuid of the msg, must be given again to decrypt the msg, so a
+/// value can not be moved to another msg.
+pub fn encrypt(keyring: &Keyring, associated_data: &[u8], msg: &[u8]) -> Result<Bytes, DatabaseError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = match keyring.cipher(keyring.active) {
+        Ok(cipher) => Ok(cipher),
+        Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+    }?;
+    let encrypted = match cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg, aad: associated_data }) {
+        Ok(encrypted) => Ok(encrypted),
+        Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+    }?;
+    let mut value = BytesMut::with_capacity(HEADER_LEN + encrypted.len());
+    value.put_slice(&MAGIC);
+    value.put_u32_le(keyring.active);
+    value.put_slice(&nonce);
+    value.put_slice(&encrypted);
+    Ok(value.freeze())
+}
+
+/// Decrypts a value, a value that was not encrypted is returned as it is
+pub fn decrypt(keyring: &Keyring, associated_data: &[u8], value: Bytes) -> Result<Bytes, DatabaseError> {
+    let key_id = match key_id(&value) {
+        Some(key_id) => key_id,
+        None => return Ok(value)
+    };
+    if value.len() < HEADER_LEN + TAG_LEN {
+        return Err(encrypted_error!(DatabaseErrorTy::CouldNotGetMsg, "The encrypted msg is cut short"))
+    }
+    let cipher = match keyring.cipher(key_id) {
+        Ok(cipher) => Ok(cipher),
+        Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+    }?;
+    let nonce = Nonce::from_slice(&value[MAGIC.len() + KEY_ID_LEN..HEADER_LEN]);
+    match cipher.decrypt(nonce, Payload { msg: &value[HEADER_LEN..], aad: associated_data }) {
+        Ok(msg) => Ok(Bytes::from(msg)),
+        Err(error) => Err(encrypted_error!(DatabaseErrorTy::CouldNotGetMsg, format!("Could not decrypt the msg: {}", error)))
+    }
+}
+
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_LEN], segment: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&segment.to_be_bytes());
+    nonce[NONCE_LEN - 1] = last as u8;
+    nonce
+}
+
+/// Encrypts a msg as it is written, e.g. to a file
+///
+/// The msg is encrypted in segments of `SEGMENT_LEN` bytes. The first segment is only
+/// encrypted once the writer is finished, so a header written at the start of the msg can be
+/// filled in later by seeking back to it. Any other seek fails.
+pub struct StreamEncryptor<W: io::Write + Seek> {
+    writer: W,
+    cipher: ChaCha20Poly1305,
+    associated_data: Vec<u8>,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    /// Where the msg starts in the writer
+    start: u64,
+    /// The first segment, kept until the writer is finished
+    first: Vec<u8>,
+    /// The segment that is being written, once the first is full
+    segment: Vec<u8>,
+    /// The number of the segment that is being written
+    segment_number: u32,
+    /// The length of the msg and the position that is written to
+    len: u64,
+    position: u64
+}
+impl<W: io::Write + Seek> StreamEncryptor<W> {
+    pub fn new(mut writer: W, keyring: &Keyring, associated_data: &[u8]) -> io::Result<StreamEncryptor<W>> {
+        let start = writer.stream_position()?;
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&keyring.active.to_le_bytes())?;
+        writer.write_all(&prefix)?;
+        Ok(StreamEncryptor {
+            writer,
+            cipher: keyring.cipher(keyring.active)?.clone(),
+            associated_data: associated_data.to_vec(),
+            prefix,
+            start,
+            first: Vec::with_capacity(SEGMENT_LEN),
+            segment: vec![],
+            segment_number: 1,
+            len: 0,
+            position: 0
+        })
+    }
+    fn encrypt_segment(&self, segment_number: u32, last: bool, segment: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = segment_nonce(&self.prefix, segment_number, last);
+        match self.cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: segment, aad: &self.associated_data }) {
+            Ok(encrypted) => Ok(encrypted),
+            Err(error) => Err(invalid_data(error))
+        }
+    }
+    /// Encrypts the segments that are full, keeping the last one back as it may be the last
+    fn write_segments(&mut self) -> io::Result<()> {
+        while self.segment.len() > SEGMENT_LEN {
+            let encrypted = self.encrypt_segment(self.segment_number, false, &self.segment[..SEGMENT_LEN])?;
+            self.writer.write_all(&encrypted)?;
+            self.segment.drain(..SEGMENT_LEN);
+            self.segment_number = match self.segment_number.checked_add(1) {
+                Some(segment_number) => segment_number,
+                None => return Err(io::Error::other("The msg has too many segments"))
+            };
+        }
+        Ok(())
+    }
+    /// Encrypts the last segments and the first one, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.len <= SEGMENT_LEN as u64 {
+            let encrypted = self.encrypt_segment(0, true, &self.first)?;
+            self.writer.write_all(&encrypted)?;
+            return Ok(self.writer)
+        }
+        let last = self.encrypt_segment(self.segment_number, true, &self.segment)?;
+        self.writer.write_all(&last)?;
+        let first = self.encrypt_segment(0, false, &self.first)?;
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.start + STREAM_HEADER_LEN as u64))?;
+        self.writer.write_all(&first)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        Ok(self.writer)
+    }
+}
+impl<W: io::Write + Seek> io::Write for StreamEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position < self.len {
+            // only the first segment can be written to again
+            let end = self.position + buf.len() as u64;
+            if end > self.len || end > SEGMENT_LEN as u64 {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Only the first segment can be written to again"))
+            }
+            self.first[self.position as usize..end as usize].copy_from_slice(buf);
+            self.position = end;
+            return Ok(buf.len())
+        }
+        let mut rest = buf;
+        if self.first.len() < SEGMENT_LEN {
+            let taken = rest.len().min(SEGMENT_LEN - self.first.len());
+            self.first.extend_from_slice(&rest[..taken]);
+            rest = &rest[taken..];
+        }
+        if !rest.is_empty() {
+            if self.segment.is_empty() && self.segment_number == 1 {
+                // room is left for the first segment, it is written once the writer is finished
+                self.writer.write_all(&[0u8; SEGMENT_LEN + TAG_LEN])?;
+            }
+            self.segment.extend_from_slice(rest);
+            self.write_segments()?;
+        }
+        self.len += buf.len() as u64;
+        self.position = self.len;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+impl<W: io::Write + Seek> Seek for StreamEncryptor<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset)
+        };
+        match position {
+            Some(position) if position <= self.len => {
+                self.position = position;
+                Ok(position)
+            },
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Can not seek past the end of the msg"))
+        }
+    }
+}
+
+/// Decrypts a msg that was written by a `StreamEncryptor` as it is read
+struct StreamDecryptor<R: Read> {
+    reader: R,
+    cipher: ChaCha20Poly1305,
+    associated_data: Vec<u8>,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    segment_count: u64,
+    /// The length of the last encrypted segment
+    last_len: usize,
+    next_segment: u64,
+    segment: Cursor<Vec<u8>>
+}
+impl<R: Read> StreamDecryptor<R> {
+    fn read_segment(&mut self) -> io::Result<()> {
+        let last = self.next_segment + 1 == self.segment_count;
+        let mut encrypted = vec![0u8; if last { self.last_len } else { SEGMENT_LEN + TAG_LEN }];
+        self.reader.read_exact(&mut encrypted)?;
+        let segment_number = match u32::try_from(self.next_segment) {
+            Ok(segment_number) => Ok(segment_number),
+            Err(error) => Err(invalid_data(error))
+        }?;
+        let nonce = segment_nonce(&self.prefix, segment_number, last);
+        let segment = match self.cipher.decrypt(Nonce::from_slice(&nonce), Payload { msg: &encrypted, aad: &self.associated_data }) {
+            Ok(segment) => Ok(segment),
+            Err(error) => Err(invalid_data(format!("Could not decrypt the msg: {}", error)))
+        }?;
+        self.segment = Cursor::new(segment);
+        self.next_segment += 1;
+        Ok(())
+    }
+}
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.segment.read(buf)?;
+            if read > 0 || buf.is_empty() || self.next_segment == self.segment_count {
+                return Ok(read)
+            }
+            self.read_segment()?;
+        }
+    }
+}
+
+/// Wraps a reader of `len` bytes so that the msg is read back decrypted
+///
+/// Also returns the length of the msg. A msg that was not encrypted is read as it is, one that
+/// was needs the keyring.
+pub fn decryptor<R: Read + Send + 'static>(mut reader: R, len: u64, keyring: Option<&Keyring>, associated_data: &[u8]) -> io::Result<(Box<dyn Read + Send>, u64)> {
+    let mut header = Vec::with_capacity(STREAM_HEADER_LEN);
+    reader.by_ref().take(STREAM_HEADER_LEN as u64).read_to_end(&mut header)?;
+    let key_id = match key_id(&header) {
+        Some(key_id) => key_id,
+        None => return Ok((Box::new(Cursor::new(header).chain(reader)), len))
+    };
+    let keyring = match keyring {
+        Some(keyring) => Ok(keyring),
+        None => Err(invalid_data("The msg is encrypted but there are no keys"))
+    }?;
+    // every segment is full apart from the last one, which holds at least the tag
+    let encrypted_len = match len.checked_sub(STREAM_HEADER_LEN as u64) {
+        Some(encrypted_len) if encrypted_len >= TAG_LEN as u64 && header.len() == STREAM_HEADER_LEN => Ok(encrypted_len),
+        _ => Err(invalid_data("The encrypted msg is cut short"))
+    }?;
+    let full_segments = (encrypted_len - TAG_LEN as u64) / (SEGMENT_LEN + TAG_LEN) as u64;
+    let last_len = (encrypted_len - full_segments * (SEGMENT_LEN + TAG_LEN) as u64) as usize;
+    let mut prefix = [0u8; NONCE_PREFIX_LEN];
+    prefix.copy_from_slice(&header[MAGIC.len() + KEY_ID_LEN..]);
+    let decryptor = StreamDecryptor {
+        reader,
+        cipher: keyring.cipher(key_id)?.clone(),
+        associated_data: associated_data.to_vec(),
+        prefix,
+        segment_count: full_segments + 1,
+        last_len,
+        next_segment: 0,
+        segment: Cursor::new(vec![])
+    };
+    Ok((Box::new(decryptor), encrypted_len - (full_segments + 1) * TAG_LEN as u64))
+}
+
+/// Where a pass over every msg to re-encrypt them is at
+#[derive(Default)]
+struct Reencryption {
+    /// The last msg that was looked at, the next batch is fetched after it
+    last: Option<Arc<Uuid>>,
+    done: bool
+}
+
+/// Encrypts the msgs of any database
+///
+/// Msgs are encrypted with the active key of the keyring as they are added and decrypted as
+/// they are read. The uuid of a msg is bound to its value, so values can not be swapped. Msgs
+/// that were added before the database was wrapped are read as they are, until they are
+/// re-encrypted by `maintain` along with the msgs encrypted with a key that was rotated out.
+/// The byte sizes given with the msgs are kept as they are.
+pub struct EncryptedDb<D: Db> {
+    db: D,
+    keyring: Keyring,
+    /// The last msg that was decrypted to be read in chunks
+    chunked: Option<(Arc<Uuid>, Bytes)>,
+    reencryption: Reencryption
+}
+impl<D: Db> EncryptedDb<D> {
+    pub fn new(db: D, keyring: Keyring) -> EncryptedDb<D> {
+        EncryptedDb { db, keyring, chunked: None, reencryption: Reencryption::default() }
+    }
+    /// Re-encrypts the next batch of msgs that are not encrypted with the active key
+    ///
+    /// The msgs that are added during the pass are encrypted with the active key, so a single
+    /// pass is enough.
+    fn reencrypt(&mut self) -> Result<bool, DatabaseError> {
+        if self.reencryption.done {
+            return Ok(false)
+        }
+        let fetched = match self.reencryption.last.clone() {
+            Some(last) => self.db.fetch_after(last)?,
+            None => self.db.fetch()?
+        };
+        let msgs = fetched
+            .take(REENCRYPTION_BATCH_SIZE)
+            .collect::<Result<Vec<(Arc<Uuid>, u64)>, DatabaseError>>()?;
+        let mut batch = Batch::new();
+        for (uuid, msg_byte_size) in msgs.iter() {
+            let header = self.db.get_chunk(uuid.clone(), 0, HEADER_LEN)?;
+            if key_id(&header) == Some(self.keyring.active) {
+                continue;
+            }
+            let msg = self.get(uuid.clone())?;
+            batch.add(uuid.clone(), encrypt(&self.keyring, uuid.to_string().as_bytes(), &msg)?, *msg_byte_size);
+        }
+        if !batch.is_empty() {
+            self.chunked = None;
+            self.db.commit(batch)?;
+        }
+        self.reencryption.last = msgs.last().map(|(uuid, _)| uuid.clone());
+        self.reencryption.done = msgs.len() < REENCRYPTION_BATCH_SIZE;
+        Ok(!self.reencryption.done)
+    }
+}
+
+impl<D: Db> Db for EncryptedDb<D> {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        let value = self.db.get(uuid.clone())?;
+        decrypt(&self.keyring, uuid.to_string().as_bytes(), value)
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        let value = encrypt(&self.keyring, uuid.to_string().as_bytes(), &msg)?;
+        self.db.add(uuid, value, msg_byte_size)
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        self.db.del(uuid)
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        let msg = match self.chunked.take() {
+            Some((chunked_uuid, msg)) if offset != 0 && chunked_uuid == uuid => msg,
+            _ => {
+                // a msg that was not encrypted is read in chunks from the database
+                if key_id(&self.db.get_chunk(uuid.clone(), 0, HEADER_LEN)?).is_none() {
+                    return self.db.get_chunk(uuid, offset, max_len)
+                }
+                self.get(uuid.clone())?
+            }
+        };
+        let start = match usize::try_from(offset) {
+            Ok(offset) => offset.min(msg.len()),
+            Err(_) => msg.len()
+        };
+        let end = start.saturating_add(max_len).min(msg.len());
+        let chunk = msg.slice(start..end);
+        // the msg is let go of once its last chunk is read
+        if end < msg.len() {
+            self.chunked = Some((uuid, msg));
+        }
+        Ok(chunk)
+    }
+    fn stored_size(&mut self, msg: &Bytes) -> Result<u64, DatabaseError> {
+        // a msg is stored with its header before it and the tag after it
+        Ok(msg.len() as u64 + (HEADER_LEN + TAG_LEN) as u64)
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        let encrypted = key_id(&self.db.get_chunk(uuid.clone(), 0, HEADER_LEN)?).is_some();
+        let value_len = self.db.msg_len(uuid)?;
+        match encrypted {
+            true => Ok(value_len.saturating_sub((HEADER_LEN + TAG_LEN) as u64)),
+            false => Ok(value_len)
+        }
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        let mut encrypted = Batch::new();
+        for write in batch.writes {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => {
+                    let value = encrypt(&self.keyring, uuid.to_string().as_bytes(), &msg)?;
+                    encrypted.add(uuid, value, msg_byte_size);
+                },
+                Write::Del { uuid } => encrypted.del(uuid)
+            }
+        }
+        self.db.commit(encrypted)
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch()
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        self.db.fetch_after(after)
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        self.db.put_cursors(consumer_group, cursors)
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        self.db.del_cursors(consumer_group)
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        self.db.fetch_cursors()
+    }
+    fn maintain(&mut self) -> Result<bool, DatabaseError> {
+        let reencrypting = self.reencrypt()?;
+        Ok(self.db.maintain()? || reencrypting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::Db;
+    use msg_store_database_test_kit::check_db;
+    use msg_store_uuid::Uuid;
+    use crate::{decryptor, key_id, EncryptedDb, Keyring, StreamEncryptor, SEGMENT_LEN};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn keyring(key_ids: &[u32]) -> Keyring {
+        Keyring::new(key_ids.iter().map(|key_id| (*key_id, [*key_id as u8; 32])).collect()).unwrap()
+    }
+
+    #[test]
+    fn should_read_the_key_file() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-encrypted").unwrap();
+        if tmp_dir.exists() {
+            remove_dir_all(&tmp_dir).unwrap();
+        }
+        create_dir_all(&tmp_dir).unwrap();
+        let key_path = tmp_dir.join("keys");
+        write(&key_path, format!("# rotated out\n1:{}\n\n2:{}\n", base64::encode([1u8; 32]), base64::encode([2u8; 32]))).unwrap();
+        assert_eq!(2, Keyring::open(&key_path).unwrap().active_key_id());
+        write(&key_path, format!("1:{}\n", base64::encode([1u8; 16]))).unwrap();
+        assert!(Keyring::open(&key_path).is_err());
+        write(&key_path, "").unwrap();
+        assert!(Keyring::open(&key_path).is_err());
+        remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn should_encrypt_msgs() {
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let other_uuid = Uuid::from_string("1-0-1-1").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        let mut encrypted = EncryptedDb::new(MemDb::new(), keyring(&[1]));
+        encrypted.add(uuid.clone(), msg.clone(), 10).unwrap();
+        let value = encrypted.db.get(uuid.clone()).unwrap();
+        assert_eq!(Some(1), key_id(&value));
+        assert!(!value.windows(msg.len()).any(|window| window == msg));
+        assert_eq!(msg, encrypted.get(uuid.clone()).unwrap());
+        assert_eq!(msg.len() as u64, encrypted.msg_len(uuid.clone()).unwrap());
+        assert_eq!(Bytes::from_static(b"my me"), encrypted.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), encrypted.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert_eq!(vec![(uuid.clone(), 10)], encrypted.fetch_all().unwrap());
+        // a value can not be read as another msg
+        encrypted.db.add(other_uuid.clone(), value, 10).unwrap();
+        assert!(encrypted.get(other_uuid).is_err());
+        // nor without its key
+        let mut other_keys = EncryptedDb::new(encrypted.db, keyring(&[2]));
+        assert!(other_keys.get(uuid).is_err());
+    }
+
+    #[test]
+    fn should_reencrypt_msgs_once_the_key_is_rotated() {
+        let legacy_uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let uuids = (1..=250).map(|sequence| Uuid::from_string(&format!("1-0-1-{}", sequence)).unwrap()).collect::<Vec<_>>();
+        let mut mem_db = MemDb::new();
+        mem_db.add(legacy_uuid.clone(), Bytes::from_static(b"legacy"), 6).unwrap();
+        let mut encrypted = EncryptedDb::new(mem_db, keyring(&[1]));
+        for uuid in uuids.iter() {
+            encrypted.add(uuid.clone(), Bytes::from(uuid.to_string()), 10).unwrap();
+        }
+        let mut rotated = EncryptedDb::new(encrypted.db, keyring(&[1, 2]));
+        assert_eq!(Bytes::from_static(b"legacy"), rotated.get(legacy_uuid.clone()).unwrap());
+        let mut steps = 0;
+        while rotated.maintain().unwrap() {
+            steps += 1;
+        }
+        assert_eq!(2, steps);
+        assert!(!rotated.maintain().unwrap());
+        for uuid in uuids.iter().chain([&legacy_uuid]) {
+            assert_eq!(Some(2), key_id(&rotated.db.get(uuid.clone()).unwrap()));
+        }
+        assert_eq!(Bytes::from_static(b"legacy"), rotated.get(legacy_uuid.clone()).unwrap());
+        assert_eq!(Bytes::from(uuids[0].to_string()), rotated.get(uuids[0].clone()).unwrap());
+        assert_eq!(6, rotated.fetch_all().unwrap().into_iter().find(|(uuid, _)| *uuid == legacy_uuid).unwrap().1);
+        // the old key is no longer needed
+        let mut rotated_out = EncryptedDb::new(rotated.db, keyring(&[2]));
+        assert_eq!(Bytes::from_static(b"legacy"), rotated_out.get(legacy_uuid).unwrap());
+    }
+
+    #[test]
+    fn should_stream_msgs() {
+        let keyring = keyring(&[1]);
+        for len in [0, 10, SEGMENT_LEN, SEGMENT_LEN + 1, 3 * SEGMENT_LEN] {
+            let msg = (0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+            let mut encryptor = StreamEncryptor::new(Cursor::new(vec![]), &keyring, b"1-0-1-0").unwrap();
+            for chunk in msg.chunks(1000) {
+                encryptor.write_all(chunk).unwrap();
+            }
+            let value = encryptor.finish().unwrap().into_inner();
+            let (mut reader, msg_len) = decryptor(Cursor::new(value.clone()), value.len() as u64, Some(&keyring), b"1-0-1-0").unwrap();
+            assert_eq!(len as u64, msg_len);
+            let mut read = vec![];
+            reader.read_to_end(&mut read).unwrap();
+            assert_eq!(msg, read);
+            // a cut short msg is not read
+            if len > 0 {
+                let cut = value[..value.len() - 1].to_vec();
+                let (mut reader, _) = decryptor(Cursor::new(cut.clone()), cut.len() as u64, Some(&keyring), b"1-0-1-0").unwrap();
+                assert!(reader.read_to_end(&mut vec![]).is_err());
+            }
+        }
+        // a header at the start of the msg can be filled in later
+        let mut encryptor = StreamEncryptor::new(Cursor::new(vec![]), &keyring, b"").unwrap();
+        encryptor.write_all(&[0u8; 4]).unwrap();
+        encryptor.write_all(&vec![1u8; 2 * SEGMENT_LEN]).unwrap();
+        let end = encryptor.stream_position().unwrap();
+        encryptor.seek(SeekFrom::Start(0)).unwrap();
+        encryptor.write_all(b"head").unwrap();
+        encryptor.seek(SeekFrom::Start(end)).unwrap();
+        let value = encryptor.finish().unwrap().into_inner();
+        let (mut reader, _) = decryptor(Cursor::new(value.clone()), value.len() as u64, Some(&keyring), b"").unwrap();
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(b"head", &read[..4]);
+        assert_eq!(2 * SEGMENT_LEN + 4, read.len());
+        // a msg that was not encrypted is read as it is
+        let (mut reader, msg_len) = decryptor(Cursor::new(b"my message".to_vec()), 10, None, b"").unwrap();
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!((b"my message".to_vec(), 10), (read, msg_len));
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_db("encrypted", |_| EncryptedDb::new(MemDb::new(), keyring(&[1])));
+    }
+}
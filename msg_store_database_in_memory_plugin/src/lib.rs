@@ -1,7 +1,8 @@
 use bytes::Bytes;
 use msg_store_uuid::Uuid;
-use msg_store_database_plugin::{Db, DatabaseError, DatabaseErrorTy};
+use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
 use std::collections::BTreeMap;
+use std::ops::Bound;
 use std::sync::Arc;
 
 macro_rules! memdb_error {
@@ -25,13 +26,15 @@ macro_rules! memdb_error {
 
 pub struct MemDb {
     msgs: BTreeMap<Arc<Uuid>, Bytes>,
-    byte_size_data: BTreeMap<Arc<Uuid>, u64>
+    byte_size_data: BTreeMap<Arc<Uuid>, u64>,
+    cursors: BTreeMap<String, Vec<Arc<Uuid>>>
 }
 impl MemDb {
     pub fn new() -> MemDb {
         MemDb {
             msgs: BTreeMap::new(),
-            byte_size_data: BTreeMap::new()
+            byte_size_data: BTreeMap::new(),
+            cursors: BTreeMap::new()
         }
     }
 }
@@ -52,11 +55,42 @@ impl Db for MemDb {
         self.byte_size_data.remove(&uuid);
         Ok(())
     }
-    fn fetch(&mut self) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError> {
-        let mut export = vec![];
-        for (uuid, byte_size) in self.byte_size_data.iter() {
-            export.push((uuid.clone(), *byte_size));
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        // nothing can fail part way through
+        for write in batch.writes {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => self.add(uuid, msg, msg_byte_size)?,
+                Write::Del { uuid } => self.del(uuid)?
+            }
         }
-        Ok(export)
+        Ok(())
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        Ok(Box::new(self.byte_size_data.iter().map(|(uuid, byte_size)| Ok((uuid.clone(), *byte_size)))))
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        Ok(Box::new(self.byte_size_data.range((Bound::Excluded(after), Bound::Unbounded)).map(|(uuid, byte_size)| Ok((uuid.clone(), *byte_size)))))
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        self.cursors.insert(consumer_group.to_string(), cursors);
+        Ok(())
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        self.cursors.remove(consumer_group);
+        Ok(())
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        Ok(self.cursors.iter().map(|(name, cursors)| (name.clone(), cursors.clone())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use msg_store_database_test_kit::check_db;
+    use crate::MemDb;
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_db("memory", |_| MemDb::new());
     }
 }
@@ -1,10 +1,11 @@
 use bincode::{serialize, deserialize};
 use bytes::Bytes;
 use msg_store_uuid::Uuid;
-pub use msg_store_database_plugin::{Db, DatabaseError, DatabaseErrorTy};
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Log, Write};
 use db_key::Key;
+use leveldb::batch::{Batch as LeveldbBatch, Writebatch};
 use leveldb::database::Database;
-use leveldb::iterator::Iterable;
+use leveldb::iterator::{Iterable, LevelDBIterator};
 use leveldb::kv::KV;
 use leveldb::options::{
     Options,
@@ -12,10 +13,14 @@ use leveldb::options::{
     WriteOptions
 };
 use serde::{Serialize, Deserialize};
-use std::fs::create_dir_all;
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, remove_file, File};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// The file that is kept while the database has batches that were committed since it was opened
+pub const DIRTY_FILE: &str = "dirty";
+
 #[derive(Deserialize, Serialize)]
 pub struct Id(Vec<u8>);
 
@@ -49,9 +54,21 @@ macro_rules! leveldb_error {
     };
 }
 
+/// Msgs and their byte sizes are kept in separate databases
+///
+/// The byte sizes tell which msgs are held, so a batch is committed to them in one leveldb
+/// write batch. Msgs that are added are written and synced before, so that a committed byte
+/// size never outlives its msg, and msgs that are deleted are removed after. Msgs left
+/// without a byte size by a crash in between are removed on open, the dirty file that is
+/// kept until the database is closed tells whether there was a crash.
 pub struct Leveldb {
     pub msgs: Database<Id>,
-    pub data: Database<Id>
+    pub data: Database<Id>,
+    pub cursors: Database<Id>,
+    dirty_path: PathBuf,
+    dirty: bool,
+    /// The msg that is being read in chunks, so that it is only read from leveldb once
+    chunked: Option<(Arc<Uuid>, Bytes)>
 }
 
 impl Leveldb {
@@ -71,12 +88,19 @@ impl Leveldb {
         msg_data_path.push("msg_data");
         let msg_data_path = msg_data_path.as_path();
 
+        let mut cursors_path = dir.to_path_buf();
+        cursors_path.push("cursors");
+        let cursors_path = cursors_path.as_path();
+
         let mut msgs_options = Options::new();
         msgs_options.create_if_missing = true;
 
         let mut msg_data_options = Options::new();
         msg_data_options.create_if_missing = true;
 
+        let mut cursors_options = Options::new();
+        cursors_options.create_if_missing = true;
+
         let msgs = match Database::open(msgs_path, msgs_options) {
             Ok(msgs) => Ok(msgs),
             Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
@@ -85,26 +109,90 @@ impl Leveldb {
             Ok(data) => Ok(data),
             Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error)) 
         }?;
+        let cursors = match Database::open(cursors_path, cursors_options) {
+            Ok(cursors) => Ok(cursors),
+            Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
         
-        Ok(Leveldb {
+        let dirty_path = dir.join(DIRTY_FILE);
+        let mut leveldb = Leveldb {
             msgs,
-            data
-        })
+            data,
+            cursors,
+            dirty: dirty_path.exists(),
+            dirty_path,
+            chunked: None
+        };
+        if leveldb.dirty {
+            leveldb.rm_orphaned_msgs()?;
+        }
+        Ok(leveldb)
     }
+
+    /// Keeps the dirty file from the first commit until the database is closed
+    fn set_dirty(&mut self) -> Result<(), DatabaseError> {
+        if self.dirty {
+            return Ok(())
+        }
+        if let Err(error) = File::create(&self.dirty_path).and_then(|file| file.sync_all()) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Removes the msgs of batches that were not committed
+    fn rm_orphaned_msgs(&mut self) -> Result<(), DatabaseError> {
+        let mut orphans = Writebatch::new();
+        let mut orphan_count = 0;
+        for id in self.msgs.keys_iter(ReadOptions::new()) {
+            let byte_size = match self.data.get(ReadOptions::new(), Id(id.0.clone())) {
+                Ok(byte_size) => Ok(byte_size),
+                Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }?;
+            if byte_size.is_none() {
+                orphans.delete(id);
+                orphan_count += 1;
+            }
+        }
+        if orphan_count == 0 {
+            return Ok(())
+        }
+        if let Err(error) = self.msgs.write(WriteOptions::new(), &orphans) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        };
+        Ok(())
+    }
+}
+
+fn parse_msg_data((id, data): (Id, Vec<u8>)) -> Result<(Arc<Uuid>, u64), DatabaseError> {
+    let data = match std::str::from_utf8(&data) {
+        Ok(data) => Ok(data),
+        Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    let data = match data.parse::<u64>() {
+        Ok(data) => Ok(data),
+        Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    let uuid = match std::str::from_utf8(&id.0) {
+        Ok(uuid) => Ok(uuid),
+        Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    let uuid = match Uuid::from_string(uuid) {
+        Ok(uuid) => Ok(uuid),
+        Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    Ok((uuid, data))
 }
 
 impl Db for Leveldb {
     fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
-        let uuid_bytes = uuid.to_string().as_bytes().to_vec();
-        let byte_size_str = msg_byte_size.to_string();
-        let byte_size = byte_size_str.as_bytes();
-        if let Err(error) = self.data.put(WriteOptions::new(), Id(uuid_bytes.clone()), byte_size) {
-            return Err(leveldb_error!(DatabaseErrorTy::CouldNotAddMsg, error))
-        };
-        if let Err(error) = self.msgs.put(WriteOptions::new(), Id(uuid_bytes), &msg) {
-            return Err(leveldb_error!(DatabaseErrorTy::CouldNotAddMsg, error))
-        };
-        Ok(())
+        let mut batch = Batch::new();
+        batch.add(uuid, msg, msg_byte_size);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+        }
     }
     fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
         let uuid_bytes = uuid.to_string().as_bytes().to_vec();
@@ -118,35 +206,197 @@ impl Db for Leveldb {
         }
     }
     fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
-        let uuid_bytes = uuid.to_string().as_bytes().to_vec();
-        if let Err(error) = self.msgs.delete(WriteOptions::new(), Id(uuid_bytes.clone())) {
-            return Err(leveldb_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        let mut batch = Batch::new();
+        batch.del(uuid);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        }
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        let msg = match &self.chunked {
+            Some((chunked_uuid, msg)) if offset != 0 && *chunked_uuid == uuid => msg.clone(),
+            _ => self.get(uuid.clone())?
         };
-        if let Err(error) = self.data.delete(WriteOptions::new(), Id(uuid_bytes)) {
-            return Err(leveldb_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        let start = match usize::try_from(offset) {
+            Ok(offset) => offset.min(msg.len()),
+            Err(_) => msg.len()
+        };
+        let end = start.saturating_add(max_len).min(msg.len());
+        // the msg is let go of once its last chunk is read
+        self.chunked = match end < msg.len() {
+            true => Some((uuid, msg.clone())),
+            false => None
+        };
+        Ok(msg.slice(start..end))
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        self.chunked = None;
+        self.set_dirty()?;
+        let mut added_msgs = Writebatch::new();
+        let mut byte_sizes = Writebatch::new();
+        // a msg that is deleted and then added again is kept
+        let mut deleted = BTreeSet::new();
+        for write in batch.writes.iter() {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => {
+                    let uuid_bytes = uuid.to_string().as_bytes().to_vec();
+                    added_msgs.put(Id(uuid_bytes.clone()), msg);
+                    byte_sizes.put(Id(uuid_bytes.clone()), msg_byte_size.to_string().as_bytes());
+                    deleted.remove(&uuid_bytes);
+                },
+                Write::Del { uuid } => {
+                    let uuid_bytes = uuid.to_string().as_bytes().to_vec();
+                    byte_sizes.delete(Id(uuid_bytes.clone()));
+                    deleted.insert(uuid_bytes);
+                }
+            }
+        }
+        let mut deleted_msgs = Writebatch::new();
+        for uuid_bytes in deleted {
+            deleted_msgs.delete(Id(uuid_bytes));
+        }
+        // the added msgs have to be on disk before the byte sizes that commit them
+        let mut write_options = WriteOptions::new();
+        write_options.sync = true;
+        if let Err(error) = self.msgs.write(write_options, &added_msgs) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        };
+        // the batch is committed once the byte sizes are written
+        let mut write_options = WriteOptions::new();
+        write_options.sync = true;
+        if let Err(error) = self.data.write(write_options, &byte_sizes) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        };
+        if let Err(error) = self.msgs.write(WriteOptions::new(), &deleted_msgs) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
         };
         Ok(())
     }
-    fn fetch(&mut self) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError> {
-        self.data.iter(ReadOptions::new()).map(|(id, data)| {
-            let data = match String::from_utf8(data) {
-                Ok(data) => Ok(data),
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        Ok(Box::new(self.data.iter(ReadOptions::new()).map(parse_msg_data)))
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        let after = after.to_string().as_bytes().to_vec();
+        // the iterator starts at the first key that is not before `after`
+        let iter = self.data.iter(ReadOptions::new());
+        iter.seek(&Id(after.clone()));
+        Ok(Box::new(iter.skip_while(move |(id, _)| id.0 == after).map(parse_msg_data)))
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        // cursors are stored as a comma separated list of uuids
+        let cursors_str = cursors.iter().map(|uuid| uuid.to_string()).collect::<Vec<String>>().join(",");
+        if let Err(error) = self.cursors.put(WriteOptions::new(), Id(consumer_group.as_bytes().to_vec()), cursors_str.as_bytes()) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotSaveCursors, error))
+        };
+        Ok(())
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        if let Err(error) = self.cursors.delete(WriteOptions::new(), Id(consumer_group.as_bytes().to_vec())) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotDeleteCursors, error))
+        };
+        Ok(())
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        self.cursors.iter(ReadOptions::new()).map(|(id, data)| {
+            let consumer_group = match String::from_utf8(id.0) {
+                Ok(consumer_group) => Ok(consumer_group),
                 Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
             }?;
-            let data = match data.parse::<u64>() {
+            let data = match String::from_utf8(data) {
                 Ok(data) => Ok(data),
                 Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
             }?;
-            let uuid = match String::from_utf8(id.0) {
-                Ok(uuid) => Ok(uuid),
-                Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
-            }?;
-            let uuid = match Uuid::from_string(&uuid) {
-                Ok(uuid) => Ok(uuid),
-                Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
-            }?;
-            Ok((uuid, data))
-        }).collect::<Result<Vec<(Arc<Uuid>, u64)>, DatabaseError>>()
+            let cursors = data.split(',').filter(|uuid| !uuid.is_empty()).map(|uuid| {
+                match Uuid::from_string(uuid) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }
+            }).collect::<Result<Vec<Arc<Uuid>>, DatabaseError>>()?;
+            Ok((consumer_group, cursors))
+        }).collect::<Result<Cursors, DatabaseError>>()
+    }
+}
+
+impl Drop for Leveldb {
+    fn drop(&mut self) {
+        if !self.dirty {
+            return
+        }
+        // the deleted msgs are synced before the dirty file is removed, the file is left when
+        // they can not be so that they are removed on open
+        let mut write_options = WriteOptions::new();
+        write_options.sync = true;
+        if self.msgs.write(write_options, &Writebatch::new()).is_ok() {
+            let _ = remove_file(&self.dirty_path);
+        }
+    }
+}
+
+/// A log of entries held in a single leveldb instance
+/// 
+/// Sequence numbers are stored big endian so that leveldb keeps the entries in order.
+pub struct LeveldbLog {
+    pub entries: Database<Id>
+}
+
+impl LeveldbLog {
+    pub fn new(dir: &Path) -> Result<LeveldbLog, DatabaseError> {
+        if !dir.exists() {
+            if let Err(err) = create_dir_all(dir) {
+                return Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, err))
+            }
+        }
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        let entries = match Database::open(dir, options) {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        Ok(LeveldbLog {
+            entries
+        })
+    }
+    fn to_seq(id: Id) -> Result<u64, DatabaseError> {
+        match <[u8; 8]>::try_from(id.0.as_slice()) {
+            Ok(seq) => Ok(u64::from_be_bytes(seq)),
+            Err(error) => Err(leveldb_error!(DatabaseErrorTy::CouldNotReadLog, error))
+        }
+    }
+}
+
+impl Log for LeveldbLog {
+    fn append(&mut self, seq: u64, entry: Bytes) -> Result<(), DatabaseError> {
+        if let Err(error) = self.entries.put(WriteOptions::new(), Id(seq.to_be_bytes().to_vec()), &entry) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotAppendEntry, error))
+        };
+        Ok(())
+    }
+    fn read(&mut self, from_seq: u64, limit: usize) -> Result<Vec<(u64, Bytes)>, DatabaseError> {
+        let from = Id(from_seq.to_be_bytes().to_vec());
+        self.entries.iter(ReadOptions::new()).from(&from).take(limit).map(|(id, entry)| {
+            Ok((Self::to_seq(id)?, Bytes::from(entry)))
+        }).collect::<Result<Vec<(u64, Bytes)>, DatabaseError>>()
+    }
+    fn last_seq(&mut self) -> Result<Option<u64>, DatabaseError> {
+        // the last method of the iterator does not check whether the log is empty
+        match self.entries.keys_iter(ReadOptions::new()).reverse().next() {
+            Some(id) => Ok(Some(Self::to_seq(id)?)),
+            None => Ok(None)
+        }
+    }
+    fn truncate_before(&mut self, seq: u64) -> Result<(), DatabaseError> {
+        let mut truncated = Writebatch::new();
+        for id in self.entries.keys_iter(ReadOptions::new()) {
+            if Self::to_seq(Id(id.0.clone()))? >= seq {
+                break;
+            }
+            truncated.delete(id);
+        }
+        if let Err(error) = self.entries.write(WriteOptions::new(), &truncated) {
+            return Err(leveldb_error!(DatabaseErrorTy::CouldNotTruncateLog, error))
+        };
+        Ok(())
     }
 }
 
@@ -155,8 +405,12 @@ mod tests {
     use bytes::Bytes;
     use msg_store_uuid::Uuid;
     use msg_store_database_plugin::Db;
-    use crate::Leveldb;
-    use std::fs::{create_dir_all, remove_dir_all};
+    use crate::{Id, Leveldb, LeveldbLog, DIRTY_FILE};
+    use leveldb::kv::KV;
+    use leveldb::options::WriteOptions;
+    use msg_store_database_plugin::{Batch, Log};
+    use msg_store_database_test_kit::check_persistent_db;
+    use std::fs::{create_dir_all, remove_dir_all, File};
     use std::path::{Path, PathBuf};
     use std::str::FromStr;
 
@@ -195,7 +449,7 @@ mod tests {
         let mut level = Leveldb::new(&tmp_dir).unwrap();
         
         // fetch messages
-        let msgs = level.fetch().unwrap();
+        let msgs = level.fetch_all().unwrap();
         assert_eq!(1, msgs.len());
         let (received_uuid, received_msg_byte_size) = &msgs[0];
         assert_eq!(uuid, received_uuid.clone());
@@ -211,8 +465,122 @@ mod tests {
         let get_msg_result = level.get(uuid);
         assert!(get_msg_result.is_err());
 
+        // save cursors
+        let cursors = vec![Uuid::from_string("1-0-1-0").unwrap(), Uuid::from_string("2-0-1-0").unwrap()];
+        level.put_cursors("consumers", cursors.clone()).unwrap();
+        level.put_cursors("idle", vec![]).unwrap();
+        drop(level);
+        let mut level = Leveldb::new(&tmp_dir).unwrap();
+        let mut saved_cursors = level.fetch_cursors().unwrap();
+        saved_cursors.sort();
+        assert_eq!(vec![("consumers".to_string(), cursors), ("idle".to_string(), vec![])], saved_cursors);
+
+        // delete cursors
+        level.del_cursors("consumers").unwrap();
+        assert_eq!(1, level.fetch_cursors().unwrap().len());
+
         
         // assert_eq!(2 + 2, 4);
         dir_teardown(&tmp_dir);
     }
+
+    #[test]
+    fn should_read_msgs_in_chunks() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-leveldb-chunks").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let mut level = Leveldb::new(&tmp_dir).unwrap();
+        let mut chunks = vec![Bytes::from_static(b"my "), Bytes::from_static(b"message")].into_iter();
+        level.add_chunks(uuid.clone(), &mut chunks, 10).unwrap();
+        assert_eq!(Bytes::from_static(b"my me"), level.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), level.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert!(level.get_chunk(uuid.clone(), 10, 5).unwrap().is_empty());
+        // a msg that is removed between chunks is not read from what was kept of it
+        level.get_chunk(uuid.clone(), 0, 5).unwrap();
+        level.del(uuid.clone()).unwrap();
+        assert!(level.get_chunk(uuid, 5, 5).is_err());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_commit_batches() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-leveldb-batch").unwrap();
+        dir_setup(&tmp_dir);
+        let first = Uuid::from_string("1-0-1-0").unwrap();
+        let second = Uuid::from_string("1-0-2-0").unwrap();
+        let third = Uuid::from_string("1-0-3-0").unwrap();
+        let mut level = Leveldb::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        batch.add(first.clone(), Bytes::from_static(b"first"), 5);
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        level.commit(batch).unwrap();
+        let mut batch = Batch::new();
+        batch.del(first.clone());
+        batch.del(second.clone());
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        batch.add(third.clone(), Bytes::from_static(b"third"), 5);
+        level.commit(batch).unwrap();
+        assert_eq!(vec![(second.clone(), 6), (third.clone(), 5)], level.fetch_all().unwrap());
+        assert!(level.get(first.clone()).is_err());
+        assert_eq!(Bytes::from_static(b"second"), level.get(second).unwrap());
+
+        // a database that was closed leaves no dirty file
+        drop(level);
+        assert!(!tmp_dir.join(DIRTY_FILE).exists());
+
+        // a msg written by a batch that was not committed is removed on open after a crash,
+        // which leaves the dirty file
+        let level = Leveldb::new(&tmp_dir).unwrap();
+        level.msgs.put(WriteOptions::new(), Id(first.to_string().as_bytes().to_vec()), b"first").unwrap();
+        drop(level);
+        File::create(tmp_dir.join(DIRTY_FILE)).unwrap();
+        let mut level = Leveldb::new(&tmp_dir).unwrap();
+        assert!(level.get(first).is_err());
+        assert_eq!(Bytes::from_static(b"third"), level.get(third).unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_log_entries_in_order() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-leveldb-log").unwrap();
+        dir_setup(&tmp_dir);
+        {
+            let mut log = LeveldbLog::new(&tmp_dir).unwrap();
+            assert_eq!(None, log.last_seq().unwrap());
+            for seq in 1..=300 {
+                log.append(seq, Bytes::from(seq.to_string())).unwrap();
+            }
+        }
+        let mut log = LeveldbLog::new(&tmp_dir).unwrap();
+        assert_eq!(Some(300), log.last_seq().unwrap());
+        let entries = log.read(255, 3).unwrap();
+        assert_eq!(vec![
+            (255, Bytes::from("255")),
+            (256, Bytes::from("256")),
+            (257, Bytes::from("257"))
+        ], entries);
+        assert!(log.read(301, 10).unwrap().is_empty());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_truncate_log_entries() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-leveldb-log-truncate").unwrap();
+        dir_setup(&tmp_dir);
+        let mut log = LeveldbLog::new(&tmp_dir).unwrap();
+        for seq in 1..=10 {
+            log.append(seq, Bytes::from(seq.to_string())).unwrap();
+        }
+        log.truncate_before(8).unwrap();
+        assert_eq!(vec![8, 9, 10], log.read(0, 10).unwrap().iter().map(|(seq, _)| *seq).collect::<Vec<u64>>());
+        assert_eq!(Some(10), log.last_seq().unwrap());
+        log.truncate_before(1).unwrap();
+        assert_eq!(3, log.read(0, 10).unwrap().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_persistent_db("leveldb", |path| Leveldb::new(path).unwrap());
+    }
 }
@@ -1,7 +1,14 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::stream::{self, Stream};
 use msg_store_uuid::Uuid;
+use std::collections::VecDeque;
 use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::thread;
 
 #[derive(Debug)]
 pub enum DatabaseErrorTy {
@@ -9,8 +16,17 @@ pub enum DatabaseErrorTy {
     CouldNotAddMsg,
     CouldNotGetMsg,
     CouldNotDeleteMsg,
+    CouldNotCommitBatch,
     CouldNotFetchData,
-    MsgNotFound
+    CouldNotSaveCursors,
+    CouldNotDeleteCursors,
+    CouldNotAppendEntry,
+    CouldNotReadLog,
+    CouldNotTruncateLog,
+    CouldNotCompact,
+    DatabaseClosed,
+    MsgNotFound,
+    CorruptMsg
 }
 impl Display for DatabaseErrorTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -19,8 +35,17 @@ impl Display for DatabaseErrorTy {
             Self::CouldNotAddMsg |
             Self::CouldNotGetMsg |
             Self::CouldNotDeleteMsg |
+            Self::CouldNotCommitBatch |
             Self::CouldNotFetchData |
-            Self::MsgNotFound => write!(f, "{}", self)
+            Self::CouldNotSaveCursors |
+            Self::CouldNotDeleteCursors |
+            Self::CouldNotAppendEntry |
+            Self::CouldNotReadLog |
+            Self::CouldNotTruncateLog |
+            Self::CouldNotCompact |
+            Self::DatabaseClosed |
+            Self::MsgNotFound |
+            Self::CorruptMsg => write!(f, "{:#?}", self)
         }
     }
 }
@@ -42,9 +67,502 @@ impl Display for DatabaseError {
     }   
 }
 
+macro_rules! database_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The size of the chunks a msg is read in when it is streamed from the database
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The uuids and byte sizes of the msgs held in a database, read as they are iterated over
+pub type Fetched<'a> = Box<dyn Iterator<Item = Result<(Arc<Uuid>, u64), DatabaseError>> + 'a>;
+
+/// The cursors of each consumer group
+pub type Cursors = Vec<(String, Vec<Arc<Uuid>>)>;
+
+/// A write that is part of a batch
+#[derive(Debug, Clone, PartialEq)]
+pub enum Write {
+    Add { uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64 },
+    Del { uuid: Arc<Uuid> }
+}
+
+/// Adds and deletes of msgs that are committed together
+///
+/// The writes are applied in the order they were made, either all of them are kept or
+/// none of them are.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Batch {
+    pub writes: Vec<Write>
+}
+impl Batch {
+    pub fn new() -> Batch {
+        Batch { writes: vec![] }
+    }
+    pub fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) {
+        self.writes.push(Write::Add { uuid, msg, msg_byte_size });
+    }
+    pub fn del(&mut self, uuid: Arc<Uuid>) {
+        self.writes.push(Write::Del { uuid });
+    }
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
 pub trait Db: Send + Sync {
     fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError>;
     fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError>;
     fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError>;
-    fn fetch(&mut self) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError>;
+    /// Gets up to `max_len` bytes of a msg, starting at `offset`
+    ///
+    /// An empty chunk is returned once the end of the msg is reached. By default the whole msg
+    /// is read and sliced, a backend that keeps msgs in pieces can read only the pieces needed.
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        let msg = self.get(uuid)?;
+        let start = match usize::try_from(offset) {
+            Ok(offset) => offset.min(msg.len()),
+            Err(_) => msg.len()
+        };
+        let end = start.saturating_add(max_len).min(msg.len());
+        Ok(msg.slice(start..end))
+    }
+    /// Gets the length of a msg as it is read back
+    ///
+    /// By default the whole msg is read, a backend that knows the length without reading the
+    /// msg can get it directly.
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        Ok(self.get(uuid)?.len() as u64)
+    }
+    /// Gets the size a msg would be stored at, e.g. once it is compressed
+    ///
+    /// By default a msg is stored as it is given.
+    fn stored_size(&mut self, msg: &Bytes) -> Result<u64, DatabaseError> {
+        Ok(msg.len() as u64)
+    }
+    /// Adds a msg that is given in chunks
+    ///
+    /// By default the chunks are joined and the msg is added whole.
+    fn add_chunks(&mut self, uuid: Arc<Uuid>, chunks: &mut dyn Iterator<Item = Bytes>, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        let mut msg = BytesMut::new();
+        for chunk in chunks {
+            msg.extend_from_slice(&chunk);
+        }
+        self.add(uuid, msg.freeze(), msg_byte_size)
+    }
+    /// Applies every write of the batch at once
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError>;
+    /// Iterates over the uuid and byte size of every msg, without reading them all up front
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError>;
+    /// Iterates like `fetch` over the msgs that come after `after`, whether or not it is held
+    ///
+    /// Used to resume a walk over every msg. By default the msgs are fetched and skipped up
+    /// to and including `after`, which finds nothing once `after` was deleted, a backend that
+    /// reads its msgs in order can start right after it.
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        let mut found = false;
+        Ok(Box::new(self.fetch()?.skip_while(move |fetched| match fetched {
+            Ok((uuid, _)) => {
+                let skip = !found;
+                found = found || *uuid == after;
+                skip
+            },
+            Err(_) => false
+        })))
+    }
+    /// Gets the uuid and byte size of every msg at once
+    fn fetch_all(&mut self) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError> {
+        self.fetch()?.collect()
+    }
+    /// Saves the cursors of a consumer group, replacing any that were saved before
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError>;
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError>;
+    /// Gets every consumer group and its cursors
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError>;
+    /// Does a step of the work a database does in the background, e.g. re-encrypting msgs
+    ///
+    /// Each step is kept short so that it can run between other operations. Returns true
+    /// while there is more to do. By default there is nothing to do.
+    fn maintain(&mut self) -> Result<bool, DatabaseError> {
+        Ok(false)
+    }
+}
+
+impl Db for Box<dyn Db> {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        self.as_mut().get(uuid)
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        self.as_mut().add(uuid, msg, msg_byte_size)
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        self.as_mut().del(uuid)
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        self.as_mut().get_chunk(uuid, offset, max_len)
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        self.as_mut().msg_len(uuid)
+    }
+    fn stored_size(&mut self, msg: &Bytes) -> Result<u64, DatabaseError> {
+        self.as_mut().stored_size(msg)
+    }
+    fn add_chunks(&mut self, uuid: Arc<Uuid>, chunks: &mut dyn Iterator<Item = Bytes>, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        self.as_mut().add_chunks(uuid, chunks, msg_byte_size)
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        self.as_mut().commit(batch)
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        self.as_mut().fetch()
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        self.as_mut().fetch_after(after)
+    }
+    fn fetch_all(&mut self) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError> {
+        self.as_mut().fetch_all()
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        self.as_mut().put_cursors(consumer_group, cursors)
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        self.as_mut().del_cursors(consumer_group)
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        self.as_mut().fetch_cursors()
+    }
+    fn maintain(&mut self) -> Result<bool, DatabaseError> {
+        self.as_mut().maintain()
+    }
+}
+
+/// Copies every msg and the cursors of every consumer group from one database to another
+///
+/// Msgs are committed `batch_size` at a time, so a copy that is interrupted leaves whole
+/// batches behind. Returns the number of msgs copied.
+pub fn copy_all(from: &mut dyn Db, to: &mut dyn Db, batch_size: usize) -> Result<u64, DatabaseError> {
+    let msgs = from.fetch_all()?;
+    let mut batch = Batch::new();
+    for (uuid, msg_byte_size) in msgs.iter() {
+        let msg = from.get(uuid.clone())?;
+        batch.add(uuid.clone(), msg, *msg_byte_size);
+        if batch.writes.len() >= batch_size {
+            to.commit(batch)?;
+            batch = Batch::new();
+        }
+    }
+    if !batch.is_empty() {
+        to.commit(batch)?;
+    }
+    for (consumer_group, cursors) in from.fetch_cursors()? {
+        to.put_cursors(&consumer_group, cursors)?;
+    }
+    Ok(msgs.len() as u64)
+}
+
+/// An append-only log of entries ordered by their sequence number
+pub trait Log: Send + Sync {
+    fn append(&mut self, seq: u64, entry: Bytes) -> Result<(), DatabaseError>;
+    /// Gets up to `limit` entries, starting with the first entry at or after `from_seq`
+    fn read(&mut self, from_seq: u64, limit: usize) -> Result<Vec<(u64, Bytes)>, DatabaseError>;
+    /// Gets the sequence number of the last entry, if there is one
+    fn last_seq(&mut self) -> Result<Option<u64>, DatabaseError>;
+    /// Removes every entry before `seq`
+    fn truncate_before(&mut self, seq: u64) -> Result<(), DatabaseError>;
+}
+
+/// The future of an operation of an `AsyncDb`
+pub type DbFuture<T> = Pin<Box<dyn Future<Output = Result<T, DatabaseError>> + Send>>;
+
+/// The chunks of a msg that is read from an `AsyncDb`
+pub type DbStream = Pin<Box<dyn Stream<Item = Result<Bytes, DatabaseError>> + Send>>;
+
+/// A database that is used without blocking the caller
+///
+/// Operations are applied in the order their methods are called, not the order their futures
+/// are awaited. An operation can be started while the store is locked and awaited once the
+/// lock is released. A step of `maintain` is the exception, it may be applied after operations
+/// that were started later.
+pub trait AsyncDb: Send + Sync {
+    fn get(&self, uuid: Arc<Uuid>) -> DbFuture<Bytes>;
+    fn add(&self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> DbFuture<()>;
+    fn del(&self, uuid: Arc<Uuid>) -> DbFuture<()>;
+    /// Gets up to `max_len` bytes of a msg, starting at `offset`
+    fn get_chunk(&self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> DbFuture<Bytes>;
+    /// Gets the length of a msg as it is read back
+    fn msg_len(&self, uuid: Arc<Uuid>) -> DbFuture<u64>;
+    /// Reads a msg in chunks of up to `chunk_size` bytes
+    ///
+    /// Each chunk is read once the stream is polled for it, so a msg that is removed while it
+    /// is read ends the stream with a MsgNotFound error.
+    fn get_chunks(&self, uuid: Arc<Uuid>, chunk_size: usize) -> DbStream;
+    /// Gets the size a msg would be stored at
+    fn stored_size(&self, msg: Bytes) -> DbFuture<u64>;
+    /// Adds a msg that is given in chunks
+    fn add_chunks(&self, uuid: Arc<Uuid>, chunks: Vec<Bytes>, msg_byte_size: u64) -> DbFuture<()>;
+    /// Applies every write of the batch at once
+    fn commit(&self, batch: Batch) -> DbFuture<()>;
+    fn fetch(&self) -> DbFuture<Vec<(Arc<Uuid>, u64)>>;
+    /// Saves the cursors of a consumer group, replacing any that were saved before
+    fn put_cursors(&self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> DbFuture<()>;
+    fn del_cursors(&self, consumer_group: &str) -> DbFuture<()>;
+    /// Gets every consumer group and its cursors
+    fn fetch_cursors(&self) -> DbFuture<Cursors>;
+    /// Does a step of the work a database does in the background, true while there is more
+    fn maintain(&self) -> DbFuture<bool>;
+}
+
+type Task = Box<dyn FnOnce(&mut dyn Db) + Send>;
+
+/// The most operations that run ahead of a step of maintenance, so that a busy database is
+/// still maintained
+const MAX_OPERATIONS_BEFORE_MAINTENANCE: usize = 100;
+
+/// What is queued on the thread of a `BlockingDb`
+enum Job {
+    Operation(Task),
+    /// A step of maintenance, which waits until no operation is queued
+    Maintenance(Task)
+}
+
+/// Runs a `Db` on a thread of its own so that its I/O does not block the async runtime
+///
+/// Operations are queued and run one after another on that one thread, so they keep the order
+/// they were started in and a slow operation holds up the operations queued after it. A msg
+/// read with `get_chunks` is read an operation per chunk, so other operations run between its
+/// chunks. The steps of `maintain` are kept apart and a step only runs once no operation is
+/// queued, or once a hundred operations have run ahead of it, so the upkeep of the database,
+/// e.g. a scrub, holds up requests for a single step at a time. The thread stops once the `BlockingDb` is dropped, dropping the database.
+///
+/// # Example
+/// ```
+/// use bytes::Bytes;
+/// use futures::executor::block_on;
+/// use msg_store_database_plugin::{AsyncDb, BlockingDb};
+/// # use msg_store_database_plugin::{Cursors, Db, DatabaseError, DatabaseErrorTy};
+/// # use msg_store_uuid::Uuid;
+/// # use std::collections::BTreeMap;
+/// # use std::sync::Arc;
+/// # #[derive(Default)]
+/// # struct Msgs(BTreeMap<Arc<Uuid>, Bytes>);
+/// # impl Db for Msgs {
+/// #     fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+/// #         self.0.get(&uuid).cloned().ok_or(DatabaseError { err_ty: DatabaseErrorTy::MsgNotFound, file: file!(), line: line!(), msg: None })
+/// #     }
+/// #     fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, _: u64) -> Result<(), DatabaseError> { self.0.insert(uuid, msg); Ok(()) }
+/// #     fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> { self.0.remove(&uuid); Ok(()) }
+/// #     fn commit(&mut self, _: msg_store_database_plugin::Batch) -> Result<(), DatabaseError> { Ok(()) }
+/// #     fn fetch(&mut self) -> Result<msg_store_database_plugin::Fetched<'_>, DatabaseError> { Ok(Box::new(std::iter::empty())) }
+/// #     fn put_cursors(&mut self, _: &str, _: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> { Ok(()) }
+/// #     fn del_cursors(&mut self, _: &str) -> Result<(), DatabaseError> { Ok(()) }
+/// #     fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> { Ok(vec![]) }
+/// # }
+///
+/// let db = BlockingDb::new(Box::new(Msgs::default()));
+/// let uuid = Uuid::from_string("1-0-1-0").unwrap();
+/// // the add is queued before the get, so the get finds the msg
+/// let added = db.add(uuid.clone(), Bytes::from("my message"), 10);
+/// let msg = db.get(uuid);
+/// block_on(added).unwrap();
+/// assert_eq!(Bytes::from("my message"), block_on(msg).unwrap());
+///
+/// ```
+pub struct BlockingDb {
+    sender: Sender<Job>
+}
+impl BlockingDb {
+    pub fn new(mut db: Box<dyn Db>) -> BlockingDb {
+        let (sender, receiver) = channel::<Job>();
+        thread::spawn(move || {
+            let mut maintenance: VecDeque<Task> = VecDeque::new();
+            // the operations that ran while a step of maintenance was waiting
+            let mut passed = 0;
+            loop {
+                let job = if maintenance.is_empty() {
+                    match receiver.recv() {
+                        Ok(job) => Some(job),
+                        Err(_) => break
+                    }
+                } else if passed >= MAX_OPERATIONS_BEFORE_MAINTENANCE {
+                    None
+                } else {
+                    match receiver.try_recv() {
+                        Ok(job) => Some(job),
+                        Err(TryRecvError::Empty) => None,
+                        Err(TryRecvError::Disconnected) => break
+                    }
+                };
+                match job {
+                    Some(Job::Operation(operation)) => {
+                        operation(db.as_mut());
+                        if !maintenance.is_empty() {
+                            passed += 1;
+                        }
+                    },
+                    Some(Job::Maintenance(step)) => maintenance.push_back(step),
+                    None => if let Some(step) = maintenance.pop_front() {
+                        step(db.as_mut());
+                        passed = 0;
+                    }
+                }
+            }
+        });
+        BlockingDb { sender }
+    }
+    fn run<T, F>(&self, operation: F) -> DbFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Db) -> Result<T, DatabaseError> + Send + 'static
+    {
+        queue(&self.sender, Job::Operation, operation)
+    }
+}
+
+/// Queues an operation or a step of maintenance on the thread of a `BlockingDb`
+fn queue<T, F>(sender: &Sender<Job>, job: fn(Task) -> Job, operation: F) -> DbFuture<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut dyn Db) -> Result<T, DatabaseError> + Send + 'static
+{
+    let (result_sender, result_receiver) = oneshot::channel();
+    let task: Task = Box::new(move |db| {
+        // the caller may have stopped waiting for the result
+        let _ = result_sender.send(operation(db));
+    });
+    let queued = sender.send(job(task));
+    Box::pin(async move {
+        if queued.is_err() {
+            return Err(database_error!(DatabaseErrorTy::DatabaseClosed));
+        }
+        match result_receiver.await {
+            Ok(result) => result,
+            Err(error) => Err(database_error!(DatabaseErrorTy::DatabaseClosed, error))
+        }
+    })
+}
+impl AsyncDb for BlockingDb {
+    fn get(&self, uuid: Arc<Uuid>) -> DbFuture<Bytes> {
+        self.run(move |db| db.get(uuid))
+    }
+    fn add(&self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> DbFuture<()> {
+        self.run(move |db| db.add(uuid, msg, msg_byte_size))
+    }
+    fn del(&self, uuid: Arc<Uuid>) -> DbFuture<()> {
+        self.run(move |db| db.del(uuid))
+    }
+    fn get_chunk(&self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> DbFuture<Bytes> {
+        self.run(move |db| db.get_chunk(uuid, offset, max_len))
+    }
+    fn msg_len(&self, uuid: Arc<Uuid>) -> DbFuture<u64> {
+        self.run(move |db| db.msg_len(uuid))
+    }
+    fn get_chunks(&self, uuid: Arc<Uuid>, chunk_size: usize) -> DbStream {
+        let sender = self.sender.clone();
+        // the offset of the next chunk, none once the stream has failed
+        Box::pin(stream::unfold(Some(0), move |offset: Option<u64>| {
+            let sender = sender.clone();
+            let uuid = uuid.clone();
+            async move {
+                let offset = offset?;
+                match queue(&sender, Job::Operation, move |db| db.get_chunk(uuid, offset, chunk_size)).await {
+                    Ok(chunk) if chunk.is_empty() => None,
+                    Ok(chunk) => {
+                        let next_offset = offset + chunk.len() as u64;
+                        Some((Ok(chunk), Some(next_offset)))
+                    },
+                    Err(error) => Some((Err(error), None))
+                }
+            }
+        }))
+    }
+    fn stored_size(&self, msg: Bytes) -> DbFuture<u64> {
+        self.run(move |db| db.stored_size(&msg))
+    }
+    fn add_chunks(&self, uuid: Arc<Uuid>, chunks: Vec<Bytes>, msg_byte_size: u64) -> DbFuture<()> {
+        self.run(move |db| db.add_chunks(uuid, &mut chunks.into_iter(), msg_byte_size))
+    }
+    fn commit(&self, batch: Batch) -> DbFuture<()> {
+        self.run(move |db| db.commit(batch))
+    }
+    fn fetch(&self) -> DbFuture<Vec<(Arc<Uuid>, u64)>> {
+        self.run(|db| db.fetch_all())
+    }
+    fn put_cursors(&self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> DbFuture<()> {
+        let consumer_group = consumer_group.to_string();
+        self.run(move |db| db.put_cursors(&consumer_group, cursors))
+    }
+    fn del_cursors(&self, consumer_group: &str) -> DbFuture<()> {
+        let consumer_group = consumer_group.to_string();
+        self.run(move |db| db.del_cursors(&consumer_group))
+    }
+    fn fetch_cursors(&self) -> DbFuture<Cursors> {
+        self.run(|db| db.fetch_cursors())
+    }
+    fn maintain(&self) -> DbFuture<bool> {
+        queue(&self.sender, Job::Maintenance, |db| db.maintain())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use msg_store_uuid::Uuid;
+    use crate::{AsyncDb, Batch, BlockingDb, Cursors, Db, DatabaseError, Fetched};
+    use std::sync::{Arc, Mutex};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    /// Records the operations it runs, taking a while to add a msg
+    struct Recorder(Arc<Mutex<Vec<&'static str>>>);
+    impl Db for Recorder {
+        fn get(&mut self, _: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+            self.0.lock().unwrap().push("get");
+            Ok(Bytes::new())
+        }
+        fn add(&mut self, _: Arc<Uuid>, _: Bytes, _: u64) -> Result<(), DatabaseError> {
+            sleep(Duration::from_millis(100));
+            self.0.lock().unwrap().push("add");
+            Ok(())
+        }
+        fn del(&mut self, _: Arc<Uuid>) -> Result<(), DatabaseError> { Ok(()) }
+        fn commit(&mut self, _: Batch) -> Result<(), DatabaseError> { Ok(()) }
+        fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> { Ok(Box::new(std::iter::empty())) }
+        fn put_cursors(&mut self, _: &str, _: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> { Ok(()) }
+        fn del_cursors(&mut self, _: &str) -> Result<(), DatabaseError> { Ok(()) }
+        fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> { Ok(vec![]) }
+        fn maintain(&mut self) -> Result<bool, DatabaseError> {
+            self.0.lock().unwrap().push("maintain");
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn should_maintain_once_no_operation_is_queued() {
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let db = BlockingDb::new(Box::new(Recorder(recorded.clone())));
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let added = db.add(uuid.clone(), Bytes::from("my message"), 10);
+        let maintained = db.maintain();
+        let got = db.get(uuid);
+        block_on(maintained).unwrap();
+        block_on(added).unwrap();
+        block_on(got).unwrap();
+        assert_eq!(vec!["add", "get", "maintain"], *recorded.lock().unwrap());
+    }
 }
@@ -0,0 +1,593 @@
+use bytes::Bytes;
+use msg_store_uuid::Uuid;
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Log, Write};
+use redb::{Database, ReadableTable, TableDefinition};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::create_dir_all;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+macro_rules! redb_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The name of the database file within the directory of the database
+pub const DATABASE_FILE: &str = "msgs.redb";
+
+/// The name of the log file within the directory of a log
+pub const LOG_FILE: &str = "log.redb";
+
+/// The number of msgs read at a time while the msgs are fetched
+const FETCH_PAGE_SIZE: usize = 10_000;
+
+/// Msgs are kept apart from their sizes so that fetching the sizes does not read the msgs
+const MSGS: TableDefinition<&str, &[u8]> = TableDefinition::new("msgs");
+const MSG_DATA: TableDefinition<&str, u64> = TableDefinition::new("msg_data");
+const CURSORS: TableDefinition<&str, &str> = TableDefinition::new("cursors");
+const ENTRIES: TableDefinition<u64, &[u8]> = TableDefinition::new("entries");
+
+/// Any error of redb, boxed as some of them are large
+struct RedbError(Box<redb::Error>);
+impl<E: Into<redb::Error>> From<E> for RedbError {
+    fn from(error: E) -> RedbError {
+        RedbError(Box::new(error.into()))
+    }
+}
+impl Display for RedbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A database held in a single redb file
+///
+/// Every batch is committed in one write transaction that is synced to disk before the commit
+/// returns, so either all of its writes are kept or none of them are.
+pub struct Redb {
+    database: Database
+}
+
+impl Redb {
+    /// Opens the database in the directory, creating it if it does not exist
+    pub fn new(dir: &Path) -> Result<Redb, DatabaseError> {
+        if !dir.exists() {
+            if let Err(error) = create_dir_all(dir) {
+                return Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }
+        }
+        let mut path = dir.to_path_buf();
+        path.push(DATABASE_FILE);
+        let database = match Database::create(&path) {
+            Ok(database) => Ok(database),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        if let Err(error) = create_tables(&database) {
+            return Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }
+        Ok(Redb { database })
+    }
+
+    fn read_msg(&self, uuid: &str, offset: u64, max_len: Option<usize>) -> Result<Option<Bytes>, RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(MSGS)?;
+        let msg = match table.get(uuid)? {
+            Some(msg) => msg,
+            None => return Ok(None)
+        };
+        let msg = msg.value();
+        // only the requested bytes are copied out of the database
+        let start = match usize::try_from(offset) {
+            Ok(offset) => offset.min(msg.len()),
+            Err(_) => msg.len()
+        };
+        let end = match max_len {
+            Some(max_len) => start.saturating_add(max_len).min(msg.len()),
+            None => msg.len()
+        };
+        Ok(Some(Bytes::copy_from_slice(&msg[start..end])))
+    }
+
+    fn read_msg_len(&self, uuid: &str) -> Result<Option<u64>, RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(MSGS)?;
+        let msg_len = table.get(uuid)?.map(|msg| msg.value().len() as u64);
+        Ok(msg_len)
+    }
+
+    fn write_batch(&self, batch: &Batch) -> Result<(), RedbError> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut msgs = transaction.open_table(MSGS)?;
+            let mut msg_data = transaction.open_table(MSG_DATA)?;
+            for write in batch.writes.iter() {
+                match write {
+                    Write::Add { uuid, msg, msg_byte_size } => {
+                        let uuid = uuid.to_string();
+                        msgs.insert(uuid.as_str(), msg.as_ref())?;
+                        msg_data.insert(uuid.as_str(), *msg_byte_size)?;
+                    },
+                    Write::Del { uuid } => {
+                        let uuid = uuid.to_string();
+                        msgs.remove(uuid.as_str())?;
+                        msg_data.remove(uuid.as_str())?;
+                    }
+                }
+            }
+        }
+        // the transaction is aborted when it is dropped without being committed
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn write_cursors(&self, consumer_group: &str, cursors: Option<&str>) -> Result<(), RedbError> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut table = transaction.open_table(CURSORS)?;
+            match cursors {
+                Some(cursors) => { table.insert(consumer_group, cursors)?; },
+                None => { table.remove(consumer_group)?; }
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn read_cursors(&self) -> Result<Vec<(String, String)>, RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(CURSORS)?;
+        let mut cursors = vec![];
+        for entry in table.iter()? {
+            let (consumer_group, data) = entry?;
+            cursors.push((consumer_group.value().to_string(), data.value().to_string()));
+        }
+        Ok(cursors)
+    }
+}
+
+/// Creates the tables that do not exist yet, so that readers can always open them
+fn create_tables(database: &Database) -> Result<(), RedbError> {
+    let transaction = database.begin_write()?;
+    transaction.open_table(MSGS)?;
+    transaction.open_table(MSG_DATA)?;
+    transaction.open_table(CURSORS)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn parse_msg_data((uuid, byte_size): (String, u64)) -> Result<(Arc<Uuid>, u64), DatabaseError> {
+    match Uuid::from_string(&uuid) {
+        Ok(uuid) => Ok((uuid, byte_size)),
+        Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }
+}
+
+/// Reads the byte sizes of the msgs a page at a time, in the order of their uuids
+struct FetchPages<'a> {
+    database: &'a Database,
+    after: Option<String>,
+    page: VecDeque<(String, u64)>,
+    done: bool
+}
+impl FetchPages<'_> {
+    fn read_page(&mut self) -> Result<(), RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(MSG_DATA)?;
+        let start = match &self.after {
+            Some(after) => Bound::Excluded(after.as_str()),
+            None => Bound::Unbounded
+        };
+        for entry in table.range::<&str>((start, Bound::Unbounded))?.take(FETCH_PAGE_SIZE) {
+            let (uuid, byte_size) = entry?;
+            self.page.push_back((uuid.value().to_string(), byte_size.value()));
+        }
+        Ok(())
+    }
+    fn next_page(&mut self) -> Result<(), DatabaseError> {
+        if let Err(error) = self.read_page() {
+            return Err(redb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }
+        if self.page.len() < FETCH_PAGE_SIZE {
+            self.done = true;
+        }
+        if let Some((uuid, _)) = self.page.back() {
+            self.after = Some(uuid.clone());
+        }
+        Ok(())
+    }
+}
+impl Iterator for FetchPages<'_> {
+    type Item = Result<(Arc<Uuid>, u64), DatabaseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            if let Err(error) = self.next_page() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        self.page.pop_front().map(parse_msg_data)
+    }
+}
+
+impl Db for Redb {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        match self.read_msg(&uuid.to_string(), 0, None) {
+            Ok(Some(msg)) => Ok(msg),
+            Ok(None) => Err(redb_error!(DatabaseErrorTy::MsgNotFound)),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.add(uuid, msg, msg_byte_size);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+        }
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.del(uuid);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        }
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        match self.read_msg(&uuid.to_string(), offset, Some(max_len)) {
+            Ok(Some(chunk)) => Ok(chunk),
+            Ok(None) => Err(redb_error!(DatabaseErrorTy::MsgNotFound)),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        match self.read_msg_len(&uuid.to_string()) {
+            Ok(Some(msg_len)) => Ok(msg_len),
+            Ok(None) => Err(redb_error!(DatabaseErrorTy::MsgNotFound)),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        match self.write_batch(&batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        }
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        Ok(Box::new(FetchPages {
+            database: &self.database,
+            after: None,
+            page: VecDeque::new(),
+            done: false
+        }))
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        Ok(Box::new(FetchPages {
+            database: &self.database,
+            after: Some(after.to_string()),
+            page: VecDeque::new(),
+            done: false
+        }))
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        // cursors are stored as a comma separated list of uuids
+        let cursors_str = cursors.iter().map(|uuid| uuid.to_string()).collect::<Vec<String>>().join(",");
+        match self.write_cursors(consumer_group, Some(&cursors_str)) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotSaveCursors, error))
+        }
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        match self.write_cursors(consumer_group, None) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotDeleteCursors, error))
+        }
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        let saved_cursors = match self.read_cursors() {
+            Ok(saved_cursors) => Ok(saved_cursors),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        saved_cursors.into_iter().map(|(consumer_group, data)| {
+            let cursors = data.split(',').filter(|uuid| !uuid.is_empty()).map(|uuid| {
+                match Uuid::from_string(uuid) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }
+            }).collect::<Result<Vec<Arc<Uuid>>, DatabaseError>>()?;
+            Ok((consumer_group, cursors))
+        }).collect()
+    }
+}
+
+/// A log of entries held in a single redb file
+///
+/// Every entry is appended in a write transaction of its own that is synced to disk before the
+/// append returns.
+pub struct RedbLog {
+    database: Database
+}
+
+impl RedbLog {
+    /// Opens the log in the directory, creating it if it does not exist
+    pub fn new(dir: &Path) -> Result<RedbLog, DatabaseError> {
+        if !dir.exists() {
+            if let Err(error) = create_dir_all(dir) {
+                return Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }
+        }
+        let database = match Database::create(dir.join(LOG_FILE)) {
+            Ok(database) => Ok(database),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        if let Err(error) = create_log_table(&database) {
+            return Err(redb_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }
+        Ok(RedbLog { database })
+    }
+
+    fn write_entry(&self, seq: u64, entry: &[u8]) -> Result<(), RedbError> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut entries = transaction.open_table(ENTRIES)?;
+            entries.insert(seq, entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn read_entries(&self, from_seq: u64, limit: usize) -> Result<Vec<(u64, Bytes)>, RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(ENTRIES)?;
+        let mut entries = vec![];
+        for entry in table.range(from_seq..)?.take(limit) {
+            let (seq, entry) = entry?;
+            entries.push((seq.value(), Bytes::copy_from_slice(entry.value())));
+        }
+        Ok(entries)
+    }
+
+    fn read_last_seq(&self) -> Result<Option<u64>, RedbError> {
+        let transaction = self.database.begin_read()?;
+        let table = transaction.open_table(ENTRIES)?;
+        let last_seq = table.last()?.map(|(seq, _)| seq.value());
+        Ok(last_seq)
+    }
+
+    fn remove_entries_before(&self, seq: u64) -> Result<(), RedbError> {
+        let transaction = self.database.begin_write()?;
+        {
+            let mut entries = transaction.open_table(ENTRIES)?;
+            entries.retain_in(..seq, |_, _| false)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Creates the table of a log if it does not exist yet, so that readers can always open it
+fn create_log_table(database: &Database) -> Result<(), RedbError> {
+    let transaction = database.begin_write()?;
+    transaction.open_table(ENTRIES)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+impl Log for RedbLog {
+    fn append(&mut self, seq: u64, entry: Bytes) -> Result<(), DatabaseError> {
+        match self.write_entry(seq, &entry) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotAppendEntry, error))
+        }
+    }
+    fn read(&mut self, from_seq: u64, limit: usize) -> Result<Vec<(u64, Bytes)>, DatabaseError> {
+        match self.read_entries(from_seq, limit) {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotReadLog, error))
+        }
+    }
+    fn last_seq(&mut self) -> Result<Option<u64>, DatabaseError> {
+        match self.read_last_seq() {
+            Ok(last_seq) => Ok(last_seq),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotReadLog, error))
+        }
+    }
+    fn truncate_before(&mut self, seq: u64) -> Result<(), DatabaseError> {
+        match self.remove_entries_before(seq) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(redb_error!(DatabaseErrorTy::CouldNotTruncateLog, error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use msg_store_uuid::Uuid;
+    use msg_store_database_plugin::{copy_all, Batch, Db, Log};
+    use msg_store_database_test_kit::check_persistent_db;
+    use crate::{Redb, RedbLog};
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    fn dir_setup(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+        create_dir_all(tmp_dir).unwrap();
+    }
+
+    fn dir_teardown(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        {
+            let mut redb = Redb::new(&tmp_dir).unwrap();
+            redb.add(uuid.clone(), msg.clone(), 10).unwrap();
+        }
+        let mut redb = Redb::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(uuid.clone(), 10)], redb.fetch_all().unwrap());
+        assert_eq!(msg, redb.get(uuid.clone()).unwrap());
+        redb.del(uuid.clone()).unwrap();
+        assert!(redb.get(uuid).is_err());
+        assert!(redb.fetch_all().unwrap().is_empty());
+
+        let cursors = vec![Uuid::from_string("1-0-1-0").unwrap(), Uuid::from_string("2-0-1-0").unwrap()];
+        redb.put_cursors("consumers", cursors.clone()).unwrap();
+        redb.put_cursors("idle", vec![]).unwrap();
+        drop(redb);
+        let mut redb = Redb::new(&tmp_dir).unwrap();
+        let mut saved_cursors = redb.fetch_cursors().unwrap();
+        saved_cursors.sort();
+        assert_eq!(vec![("consumers".to_string(), cursors), ("idle".to_string(), vec![])], saved_cursors);
+        redb.del_cursors("consumers").unwrap();
+        assert_eq!(1, redb.fetch_cursors().unwrap().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_commit_batches() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-batch").unwrap();
+        dir_setup(&tmp_dir);
+        let first = Uuid::from_string("1-0-1-0").unwrap();
+        let second = Uuid::from_string("1-0-2-0").unwrap();
+        let third = Uuid::from_string("1-0-3-0").unwrap();
+        let mut redb = Redb::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        batch.add(first.clone(), Bytes::from_static(b"first"), 5);
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        redb.commit(batch).unwrap();
+        let mut batch = Batch::new();
+        batch.del(first.clone());
+        batch.del(second.clone());
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        batch.add(third.clone(), Bytes::from_static(b"third"), 5);
+        redb.commit(batch).unwrap();
+        assert!(redb.get(first).is_err());
+        assert_eq!(Bytes::from_static(b"second"), redb.get(second.clone()).unwrap());
+        assert_eq!(vec![(second, 6), (third, 5)], redb.fetch_all().unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_fetch_msgs_a_page_at_a_time() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-fetch").unwrap();
+        dir_setup(&tmp_dir);
+        let mut redb = Redb::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        for sequence in 1..=25_001 {
+            batch.add(Uuid::from_string(&format!("1-0-{}-0", sequence)).unwrap(), Bytes::from_static(b"msg"), 3);
+        }
+        redb.commit(batch).unwrap();
+        let msgs = redb.fetch_all().unwrap();
+        assert_eq!(25_001, msgs.len());
+        assert_eq!(25_001, msgs.iter().map(|(uuid, _)| uuid.sequence).collect::<std::collections::BTreeSet<u32>>().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_msgs_in_chunks() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-chunks").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let mut redb = Redb::new(&tmp_dir).unwrap();
+        redb.add(uuid.clone(), Bytes::from_static(b"my message"), 10).unwrap();
+        assert_eq!(Bytes::from_static(b"my me"), redb.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), redb.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert!(redb.get_chunk(uuid.clone(), 10, 5).unwrap().is_empty());
+        redb.del(uuid.clone()).unwrap();
+        assert!(redb.get_chunk(uuid, 0, 5).is_err());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_copy_msgs_and_cursors_from_another_database() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-copy").unwrap();
+        dir_setup(&tmp_dir);
+        let mut from = Redb::new(&tmp_dir.join("from")).unwrap();
+        let mut batch = Batch::new();
+        for sequence in 1..=5 {
+            batch.add(Uuid::from_string(&format!("1-0-{}-0", sequence)).unwrap(), Bytes::from(format!("msg {}", sequence)), 5);
+        }
+        from.commit(batch).unwrap();
+        let cursors = vec![Uuid::from_string("1-0-2-0").unwrap()];
+        from.put_cursors("consumers", cursors.clone()).unwrap();
+
+        let mut to = Redb::new(&tmp_dir.join("to")).unwrap();
+        assert_eq!(5, copy_all(&mut from, &mut to, 2).unwrap());
+        assert_eq!(from.fetch_all().unwrap(), to.fetch_all().unwrap());
+        let uuid = Uuid::from_string("1-0-3-0").unwrap();
+        assert_eq!(Bytes::from_static(b"msg 3"), to.get(uuid).unwrap());
+        assert_eq!(vec![("consumers".to_string(), cursors)], to.fetch_cursors().unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_log_entries_in_order() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-log").unwrap();
+        dir_setup(&tmp_dir);
+        {
+            let mut log = RedbLog::new(&tmp_dir).unwrap();
+            assert_eq!(None, log.last_seq().unwrap());
+            for seq in 1..=300 {
+                log.append(seq, Bytes::from(seq.to_string())).unwrap();
+            }
+        }
+        let mut log = RedbLog::new(&tmp_dir).unwrap();
+        assert_eq!(Some(300), log.last_seq().unwrap());
+        let entries = log.read(255, 3).unwrap();
+        assert_eq!(vec![
+            (255, Bytes::from("255")),
+            (256, Bytes::from("256")),
+            (257, Bytes::from("257"))
+        ], entries);
+        assert!(log.read(301, 10).unwrap().is_empty());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_truncate_log_entries() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-redb-log-truncate").unwrap();
+        dir_setup(&tmp_dir);
+        let mut log = RedbLog::new(&tmp_dir).unwrap();
+        for seq in 1..=10 {
+            log.append(seq, Bytes::from(seq.to_string())).unwrap();
+        }
+        log.truncate_before(8).unwrap();
+        assert_eq!(vec![8, 9, 10], log.read(0, 10).unwrap().iter().map(|(seq, _)| *seq).collect::<Vec<u64>>());
+        assert_eq!(Some(10), log.last_seq().unwrap());
+        log.truncate_before(1).unwrap();
+        assert_eq!(3, log.read(0, 10).unwrap().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_persistent_db("redb", |path| Redb::new(path).unwrap());
+    }
+}
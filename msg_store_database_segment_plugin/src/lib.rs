@@ -0,0 +1,1086 @@
+use bincode::{deserialize, serialize};
+use bytes::Bytes;
+use crc32fast::hash;
+use msg_store_uuid::Uuid;
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write as IoWrite};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+macro_rules! segment_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The size a segment grows to before the next segment of its log is started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often the compactor looks for segments to rewrite
+pub const COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+
+const SEGMENT_EXTENSION: &str = "seg";
+const COMPACTING_EXTENSION: &str = "compacting";
+const COMMITS_FILE: &str = "commits";
+const CURSORS_FILE: &str = "cursors";
+
+/// The commits file is rewritten once it grows past this size
+const MAX_COMMITS_FILE_SIZE: u64 = 1024 * 1024;
+
+/// The checksum and length that come before the body of a record
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// The batch number, kind, value and uuid length that start the body of a record
+const RECORD_PREFIX_SIZE: usize = 19;
+
+/// An entry of the commits file, a checksum and a batch number
+const COMMIT_SIZE: usize = 12;
+
+const ADD: u8 = 0;
+const DEL: u8 = 1;
+
+/// A record read back from a segment
+///
+/// The value is the byte size of the msg for an add and the segment of the deleted msg for a
+/// delete.
+struct Record {
+    batch: u64,
+    kind: u8,
+    value: u64,
+    uuid: String,
+    body: Vec<u8>
+}
+impl Record {
+    fn len(&self) -> u64 {
+        RECORD_HEADER_SIZE + self.body.len() as u64
+    }
+    fn msg_offset(&self) -> u64 {
+        RECORD_HEADER_SIZE + (RECORD_PREFIX_SIZE + self.uuid.len()) as u64
+    }
+}
+
+fn encode_record(batch: u64, kind: u8, value: u64, uuid: &str, msg: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(RECORD_PREFIX_SIZE + uuid.len() + msg.len());
+    body.extend_from_slice(&batch.to_le_bytes());
+    body.push(kind);
+    body.extend_from_slice(&value.to_le_bytes());
+    body.extend_from_slice(&(uuid.len() as u16).to_le_bytes());
+    body.extend_from_slice(uuid.as_bytes());
+    body.extend_from_slice(msg);
+    frame_record(&body)
+}
+
+fn frame_record(body: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + body.len());
+    record.extend_from_slice(&hash(body).to_le_bytes());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+/// Reads the next record of a segment
+///
+/// Returns none at the end of the segment or at a record that was not written whole, which
+/// can only be the tail of the segment.
+fn read_record(reader: &mut impl Read, remaining: u64) -> Option<Record> {
+    if remaining < RECORD_HEADER_SIZE {
+        return None
+    }
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    reader.read_exact(&mut header).ok()?;
+    let checksum = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let body_len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if body_len < RECORD_PREFIX_SIZE || body_len as u64 > remaining - RECORD_HEADER_SIZE {
+        return None
+    }
+    let mut body = vec![0u8; body_len];
+    reader.read_exact(&mut body).ok()?;
+    if hash(&body) != checksum {
+        return None
+    }
+    let mut batch = [0u8; 8];
+    batch.copy_from_slice(&body[0..8]);
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&body[9..17]);
+    let uuid_len = u16::from_le_bytes([body[17], body[18]]) as usize;
+    let uuid = std::str::from_utf8(body.get(RECORD_PREFIX_SIZE..RECORD_PREFIX_SIZE + uuid_len)?).ok()?.to_string();
+    Some(Record {
+        batch: u64::from_le_bytes(batch),
+        kind: body[8],
+        value: u64::from_le_bytes(value),
+        uuid,
+        body
+    })
+}
+
+fn encode_commit(batch: u64) -> [u8; COMMIT_SIZE] {
+    let batch = batch.to_le_bytes();
+    let mut commit = [0u8; COMMIT_SIZE];
+    commit[0..4].copy_from_slice(&hash(&batch).to_le_bytes());
+    commit[4..12].copy_from_slice(&batch);
+    commit
+}
+
+fn segment_path(dir: &Path, priority: u16, segment: u64) -> PathBuf {
+    dir.join(priority.to_string()).join(format!("{:020}.{}", segment, SEGMENT_EXTENSION))
+}
+
+fn open_segment(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+}
+
+/// Makes the creation, removal or renaming of the files of a directory durable
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Writes a file whole, replacing the file it is renamed over only once it is on disk
+fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    rename(&tmp_path, path)?;
+    match path.parent() {
+        Some(dir) => sync_dir(dir),
+        None => Ok(())
+    }
+}
+
+/// Where the record of a msg is kept
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    segment: u64,
+    offset: u64,
+    record_len: u64,
+    msg_offset: u64,
+    msg_len: u64,
+    byte_size: u64
+}
+
+struct Segment {
+    file: File,
+    len: u64,
+    /// The bytes of the records that are no longer needed
+    dead_bytes: u64,
+    live_msgs: u64,
+    /// The earlier segments holding msgs that the deletes of this segment remove, and how many
+    targets: BTreeMap<u64, u64>
+}
+impl Segment {
+    fn new(file: File, len: u64) -> Segment {
+        Segment { file, len, dead_bytes: 0, live_msgs: 0, targets: BTreeMap::new() }
+    }
+}
+
+/// A write of a batch that is applied to the index once the batch is committed
+enum Staged {
+    Add { uuid: Arc<Uuid>, location: Location },
+    Del { uuid: Arc<Uuid>, segment: u64, target: u64, record_len: u64 }
+}
+
+/// The logs of every priority and the index of the msgs they hold
+struct Segments {
+    dir: PathBuf,
+    segment_size: u64,
+    logs: BTreeMap<u16, BTreeMap<u64, Segment>>,
+    index: BTreeMap<Arc<Uuid>, Location>,
+    commits: File,
+    commits_len: u64,
+    last_batch: u64,
+    cursors: BTreeMap<String, Vec<Arc<Uuid>>>,
+    replayed: bool
+}
+
+impl Segments {
+    fn open(dir: &Path, segment_size: u64) -> Result<Segments, DatabaseError> {
+        if let Err(error) = create_dir_all(dir) {
+            return Err(segment_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }
+        let commits = match OpenOptions::new().read(true).append(true).create(true).open(dir.join(COMMITS_FILE)) {
+            Ok(commits) => Ok(commits),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        let cursors_path = dir.join(CURSORS_FILE);
+        let mut cursors = BTreeMap::new();
+        if cursors_path.exists() {
+            let mut data = vec![];
+            if let Err(error) = File::open(&cursors_path).and_then(|mut file| file.read_to_end(&mut data)) {
+                return Err(segment_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }
+            let saved_cursors: Vec<(String, Vec<String>)> = match deserialize(&data) {
+                Ok(saved_cursors) => Ok(saved_cursors),
+                Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }?;
+            for (consumer_group, uuids) in saved_cursors {
+                let uuids = uuids.iter().map(|uuid| match Uuid::from_string(uuid) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+                }).collect::<Result<Vec<Arc<Uuid>>, DatabaseError>>()?;
+                cursors.insert(consumer_group, uuids);
+            }
+        }
+        Ok(Segments {
+            dir: dir.to_path_buf(),
+            segment_size,
+            logs: BTreeMap::new(),
+            index: BTreeMap::new(),
+            commits,
+            commits_len: 0,
+            last_batch: 0,
+            cursors,
+            replayed: false
+        })
+    }
+
+    /// Rebuilds the index by reading every segment from the start
+    ///
+    /// Records that were written after the last committed batch, or that were not written
+    /// whole, are cut off the end of their segment.
+    fn replay(&mut self) -> Result<(), DatabaseError> {
+        self.replay_commits()?;
+        self.logs.clear();
+        self.index.clear();
+        let entries = match read_dir(&self.dir) {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let mut priorities = BTreeSet::new();
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => Ok(entry),
+                Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            if let Some(priority) = entry.file_name().to_str().and_then(|name| name.parse::<u16>().ok()) {
+                if entry.path().is_dir() {
+                    priorities.insert(priority);
+                }
+            }
+        }
+        for priority in priorities {
+            self.replay_log(priority)?;
+            self.drop_dead_segments(priority)?;
+        }
+        self.replayed = true;
+        Ok(())
+    }
+
+    fn replay_commits(&mut self) -> Result<(), DatabaseError> {
+        let mut data = vec![];
+        let read = self.commits.seek(SeekFrom::Start(0)).and_then(|_| self.commits.read_to_end(&mut data));
+        if let Err(error) = read {
+            return Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }
+        self.last_batch = 0;
+        self.commits_len = 0;
+        for commit in data.chunks_exact(COMMIT_SIZE) {
+            let mut batch = [0u8; 8];
+            batch.copy_from_slice(&commit[4..12]);
+            if hash(&batch).to_le_bytes() != commit[0..4] {
+                break
+            }
+            self.last_batch = u64::from_le_bytes(batch);
+            self.commits_len += COMMIT_SIZE as u64;
+        }
+        if self.commits_len < data.len() as u64 {
+            if let Err(error) = self.commits.set_len(self.commits_len).and_then(|_| self.commits.sync_all()) {
+                return Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }
+        }
+        Ok(())
+    }
+
+    fn replay_log(&mut self, priority: u16) -> Result<(), DatabaseError> {
+        let log_dir = self.dir.join(priority.to_string());
+        let entries = match read_dir(&log_dir) {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let mut segment_ids = BTreeSet::new();
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => Ok(entry.path()),
+                Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if extension == Some(COMPACTING_EXTENSION) {
+                // left behind by a compaction that did not finish, the segment is unchanged
+                if let Err(error) = remove_file(&path) {
+                    return Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }
+            } else if extension == Some(SEGMENT_EXTENSION) {
+                if let Some(segment_id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) {
+                    segment_ids.insert(segment_id);
+                }
+            }
+        }
+        for segment_id in segment_ids {
+            let file = match open_segment(&segment_path(&self.dir, priority, segment_id)) {
+                Ok(file) => Ok(file),
+                Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            let file_len = match file.metadata() {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            let mut reader = BufReader::new(&file);
+            let mut records = vec![];
+            let mut offset = 0;
+            while let Some(record) = read_record(&mut reader, file_len - offset) {
+                if record.batch > self.last_batch {
+                    break
+                }
+                let record_len = record.len();
+                records.push((offset, record));
+                offset += record_len;
+            }
+            drop(reader);
+            if offset < file_len {
+                if let Err(error) = file.set_len(offset).and_then(|_| file.sync_all()) {
+                    return Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }
+            }
+            self.logs.entry(priority).or_default().insert(segment_id, Segment::new(file, offset));
+            for (offset, record) in records {
+                let uuid = match Uuid::from_string(&record.uuid) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }?;
+                if record.kind == ADD {
+                    self.apply_add(uuid, Location {
+                        segment: segment_id,
+                        offset,
+                        record_len: record.len(),
+                        msg_offset: offset + record.msg_offset(),
+                        msg_len: record.len() - record.msg_offset(),
+                        byte_size: record.value
+                    });
+                } else {
+                    self.apply_del(&uuid, segment_id, record.value, record.len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_add(&mut self, uuid: Arc<Uuid>, location: Location) {
+        let priority = uuid.priority;
+        if let Some(old_location) = self.index.insert(uuid, location) {
+            self.mark_dead(priority, &old_location);
+        }
+        if let Some(segment) = self.logs.get_mut(&priority).and_then(|log| log.get_mut(&location.segment)) {
+            segment.live_msgs += 1;
+        }
+    }
+
+    fn apply_del(&mut self, uuid: &Arc<Uuid>, segment_id: u64, target: u64, record_len: u64) {
+        if let Some(old_location) = self.index.remove(uuid) {
+            self.mark_dead(uuid.priority, &old_location);
+        }
+        let log = match self.logs.get_mut(&uuid.priority) {
+            Some(log) => log,
+            None => return
+        };
+        // the delete is only needed while the segment of the msg it removes is kept
+        let needed = target != segment_id && log.contains_key(&target);
+        if let Some(segment) = log.get_mut(&segment_id) {
+            if needed {
+                *segment.targets.entry(target).or_insert(0) += 1;
+            } else {
+                segment.dead_bytes += record_len;
+            }
+        }
+    }
+
+    fn mark_dead(&mut self, priority: u16, location: &Location) {
+        if let Some(segment) = self.logs.get_mut(&priority).and_then(|log| log.get_mut(&location.segment)) {
+            segment.live_msgs = segment.live_msgs.saturating_sub(1);
+            segment.dead_bytes += location.record_len;
+        }
+    }
+
+    /// Removes the segments that no longer hold a msg or a delete that is needed
+    ///
+    /// The last segment of a log is kept, as it is the one being written to.
+    fn drop_dead_segments(&mut self, priority: u16) -> Result<(), DatabaseError> {
+        loop {
+            let log = match self.logs.get_mut(&priority) {
+                Some(log) => log,
+                None => return Ok(())
+            };
+            let active_id = match log.keys().next_back() {
+                Some(active_id) => *active_id,
+                None => return Ok(())
+            };
+            let dead_id = log.iter()
+                .find(|(segment_id, segment)| **segment_id != active_id && segment.live_msgs == 0 && segment.targets.is_empty())
+                .map(|(segment_id, _)| *segment_id);
+            let dead_id = match dead_id {
+                Some(dead_id) => dead_id,
+                None => return Ok(())
+            };
+            log.remove(&dead_id);
+            for segment in log.values_mut() {
+                segment.targets.remove(&dead_id);
+            }
+            let path = segment_path(&self.dir, priority, dead_id);
+            if let Err(error) = remove_file(&path) {
+                return Err(segment_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+            }
+        }
+    }
+
+    /// Appends a record to the last segment of a log, starting a new segment when it is full
+    ///
+    /// The length each touched segment had before the batch is kept so the batch can be undone.
+    fn append(&mut self, priority: u16, record: &[u8], undo: &mut BTreeMap<(u16, u64), Option<u64>>) -> std::io::Result<(u64, u64)> {
+        let log = self.logs.entry(priority).or_default();
+        let next_id = match log.iter().next_back() {
+            Some((segment_id, segment)) if segment.len >= self.segment_size => Some(segment_id + 1),
+            Some(_) => None,
+            None => Some(0)
+        };
+        if let Some(next_id) = next_id {
+            let log_dir = self.dir.join(priority.to_string());
+            create_dir_all(&log_dir)?;
+            let file = open_segment(&segment_path(&self.dir, priority, next_id))?;
+            sync_dir(&log_dir)?;
+            log.insert(next_id, Segment::new(file, 0));
+            undo.insert((priority, next_id), None);
+        }
+        let (segment_id, segment) = match log.iter_mut().next_back() {
+            Some(last) => last,
+            None => return Err(std::io::Error::other("The log has no segments"))
+        };
+        undo.entry((priority, *segment_id)).or_insert(Some(segment.len));
+        segment.file.seek(SeekFrom::Start(segment.len))?;
+        segment.file.write_all(record)?;
+        let offset = segment.len;
+        segment.len += record.len() as u64;
+        Ok((*segment_id, offset))
+    }
+
+    /// Writes every record of a batch and then its commit, syncing both to disk
+    fn write_batch(&mut self, batch: &Batch, undo: &mut BTreeMap<(u16, u64), Option<u64>>) -> std::io::Result<Vec<Staged>> {
+        let batch_number = self.last_batch + 1;
+        let mut staged = vec![];
+        // the segment each msg of the batch is in so far, none once it is deleted
+        let mut written: BTreeMap<Arc<Uuid>, Option<u64>> = BTreeMap::new();
+        for write in batch.writes.iter() {
+            match write {
+                Write::Add { uuid, msg, msg_byte_size } => {
+                    let uuid_str = uuid.to_string();
+                    if msg.len() > u32::MAX as usize - RECORD_PREFIX_SIZE - uuid_str.len() {
+                        return Err(std::io::Error::other("The msg is too large for a segment"))
+                    }
+                    let record = encode_record(batch_number, ADD, *msg_byte_size, &uuid_str, msg);
+                    let (segment, offset) = self.append(uuid.priority, &record, undo)?;
+                    let msg_offset = offset + RECORD_HEADER_SIZE + (RECORD_PREFIX_SIZE + uuid_str.len()) as u64;
+                    let location = Location {
+                        segment,
+                        offset,
+                        record_len: record.len() as u64,
+                        msg_offset,
+                        msg_len: msg.len() as u64,
+                        byte_size: *msg_byte_size
+                    };
+                    written.insert(uuid.clone(), Some(segment));
+                    staged.push(Staged::Add { uuid: uuid.clone(), location });
+                },
+                Write::Del { uuid } => {
+                    let target = match written.get(uuid) {
+                        Some(target) => *target,
+                        None => self.index.get(uuid).map(|location| location.segment)
+                    };
+                    // there is nothing to delete
+                    let target = match target {
+                        Some(target) => target,
+                        None => continue
+                    };
+                    let record = encode_record(batch_number, DEL, target, &uuid.to_string(), &[]);
+                    let (segment, _) = self.append(uuid.priority, &record, undo)?;
+                    written.insert(uuid.clone(), None);
+                    staged.push(Staged::Del { uuid: uuid.clone(), segment, target, record_len: record.len() as u64 });
+                }
+            }
+        }
+        for (priority, segment_id) in undo.keys() {
+            if let Some(segment) = self.logs.get(priority).and_then(|log| log.get(segment_id)) {
+                segment.file.sync_data()?;
+            }
+        }
+        // the batch is kept once its commit is on disk
+        self.commits.write_all(&encode_commit(batch_number))?;
+        self.commits.sync_data()?;
+        self.commits_len += COMMIT_SIZE as u64;
+        self.last_batch = batch_number;
+        Ok(staged)
+    }
+
+    /// Cuts the records of a batch that was not committed off the end of their segments
+    fn undo(&mut self, undo: BTreeMap<(u16, u64), Option<u64>>) {
+        for ((priority, segment_id), len) in undo {
+            let log = match self.logs.get_mut(&priority) {
+                Some(log) => log,
+                None => continue
+            };
+            match len {
+                Some(len) => {
+                    if let Some(segment) = log.get_mut(&segment_id) {
+                        // a record left behind is cut off when the segments are replayed
+                        let _ = segment.file.set_len(len);
+                        segment.len = len;
+                    }
+                },
+                None => {
+                    log.remove(&segment_id);
+                    let _ = remove_file(segment_path(&self.dir, priority, segment_id));
+                }
+            }
+        }
+    }
+
+    fn commit(&mut self, batch: &Batch) -> Result<(), DatabaseError> {
+        let mut undo = BTreeMap::new();
+        let staged = match self.write_batch(batch, &mut undo) {
+            Ok(staged) => staged,
+            Err(error) => {
+                self.undo(undo);
+                return Err(segment_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+            }
+        };
+        let mut priorities = BTreeSet::new();
+        for write in staged {
+            match write {
+                Staged::Add { uuid, location } => self.apply_add(uuid, location),
+                Staged::Del { uuid, segment, target, record_len } => {
+                    priorities.insert(uuid.priority);
+                    self.apply_del(&uuid, segment, target, record_len);
+                }
+            }
+        }
+        for priority in priorities {
+            self.drop_dead_segments(priority)?;
+        }
+        Ok(())
+    }
+
+    fn read_msg(&mut self, uuid: &Arc<Uuid>, offset: u64, max_len: Option<usize>) -> Result<Bytes, DatabaseError> {
+        let location = match self.index.get(uuid) {
+            Some(location) => *location,
+            None => return Err(segment_error!(DatabaseErrorTy::MsgNotFound))
+        };
+        let segment = match self.logs.get_mut(&uuid.priority).and_then(|log| log.get_mut(&location.segment)) {
+            Some(segment) => Ok(segment),
+            None => Err(segment_error!(DatabaseErrorTy::CouldNotGetMsg, "The segment of the msg is missing"))
+        }?;
+        let start = offset.min(location.msg_len);
+        let end = match max_len {
+            Some(max_len) => start.saturating_add(max_len as u64).min(location.msg_len),
+            None => location.msg_len
+        };
+        let mut msg = vec![0u8; (end - start) as usize];
+        let read = segment.file.seek(SeekFrom::Start(location.msg_offset + start)).and_then(|_| segment.file.read_exact(&mut msg));
+        if let Err(error) = read {
+            return Err(segment_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }
+        Ok(Bytes::from(msg))
+    }
+
+    /// Rewrites the full segments that are mostly made of records that are no longer needed
+    ///
+    /// Returns the number of segments rewritten.
+    fn compact(&mut self) -> Result<u64, DatabaseError> {
+        let mut candidates = vec![];
+        for (priority, log) in self.logs.iter() {
+            let active_id = log.keys().next_back().copied();
+            for (segment_id, segment) in log.iter() {
+                if Some(*segment_id) != active_id && segment.dead_bytes * 2 > segment.len {
+                    candidates.push((*priority, *segment_id));
+                }
+            }
+        }
+        for (priority, segment_id) in candidates.iter() {
+            if let Err(error) = self.compact_segment(*priority, *segment_id) {
+                return Err(segment_error!(DatabaseErrorTy::CouldNotCompact, error))
+            }
+        }
+        if self.commits_len > MAX_COMMITS_FILE_SIZE {
+            if let Err(error) = self.compact_commits() {
+                return Err(segment_error!(DatabaseErrorTy::CouldNotCompact, error))
+            }
+        }
+        Ok(candidates.len() as u64)
+    }
+
+    fn compact_segment(&mut self, priority: u16, segment_id: u64) -> std::io::Result<()> {
+        let path = segment_path(&self.dir, priority, segment_id);
+        let compacting_path = path.with_extension(COMPACTING_EXTENSION);
+        let (segment_len, kept_segments) = match self.logs.get(&priority) {
+            Some(log) => match log.get(&segment_id) {
+                Some(segment) => (segment.len, log.keys().copied().collect::<BTreeSet<u64>>()),
+                None => return Ok(())
+            },
+            None => return Ok(())
+        };
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut writer = BufWriter::new(File::create(&compacting_path)?);
+        let mut moved = vec![];
+        let mut targets = BTreeMap::new();
+        let mut offset = 0;
+        let mut new_len = 0;
+        while let Some(record) = read_record(&mut reader, segment_len - offset) {
+            let keep = match record.kind {
+                ADD => match Uuid::from_string(&record.uuid) {
+                    Ok(uuid) => match self.index.get(&uuid) {
+                        Some(location) if location.segment == segment_id && location.offset == offset => {
+                            moved.push((uuid, new_len, new_len + record.msg_offset()));
+                            true
+                        },
+                        _ => false
+                    },
+                    Err(_) => false
+                },
+                DEL => {
+                    let needed = record.value != segment_id && kept_segments.contains(&record.value);
+                    if needed {
+                        *targets.entry(record.value).or_insert(0) += 1;
+                    }
+                    needed
+                },
+                _ => false
+            };
+            if keep {
+                writer.write_all(&frame_record(&record.body))?;
+                new_len += record.len();
+            }
+            offset += record.len();
+        }
+        let file = match writer.into_inner() {
+            Ok(file) => file,
+            Err(error) => return Err(error.into_error())
+        };
+        file.sync_all()?;
+        drop(file);
+        rename(&compacting_path, &path)?;
+        if let Some(log_dir) = path.parent() {
+            sync_dir(log_dir)?;
+        }
+        let file = open_segment(&path)?;
+        if let Some(segment) = self.logs.get_mut(&priority).and_then(|log| log.get_mut(&segment_id)) {
+            segment.file = file;
+            segment.len = new_len;
+            segment.dead_bytes = 0;
+            segment.targets = targets;
+        }
+        for (uuid, new_offset, new_msg_offset) in moved {
+            if let Some(location) = self.index.get_mut(&uuid) {
+                location.offset = new_offset;
+                location.msg_offset = new_msg_offset;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the commits file with one that only holds the last commit
+    fn compact_commits(&mut self) -> std::io::Result<()> {
+        let path = self.dir.join(COMMITS_FILE);
+        replace_file(&path, &encode_commit(self.last_batch))?;
+        self.commits = OpenOptions::new().read(true).append(true).open(&path)?;
+        self.commits_len = COMMIT_SIZE as u64;
+        Ok(())
+    }
+
+    fn save_cursors(&self) -> Result<(), String> {
+        let cursors = self.cursors.iter()
+            .map(|(consumer_group, uuids)| (consumer_group.clone(), uuids.iter().map(|uuid| uuid.to_string()).collect::<Vec<String>>()))
+            .collect::<Vec<(String, Vec<String>)>>();
+        let data = match serialize(&cursors) {
+            Ok(data) => Ok(data),
+            Err(error) => Err(error.to_string())
+        }?;
+        match replace_file(&self.dir.join(CURSORS_FILE), &data) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(error.to_string())
+        }
+    }
+}
+
+/// A database of append-only logs, one for each priority, made for queues
+///
+/// Msgs are appended to the last segment of the log of their priority and a delete appends a
+/// record that removes the msg. A segment is removed whole once none of its msgs are left,
+/// and a background thread rewrites the segments where most msgs were deleted out of order.
+/// The segments are replayed once, when the database is first used, to rebuild the index
+/// of the msgs, which is kept up to date by every commit after that.
+///
+/// A batch is kept once its commit is written to the commits file, the records of a batch
+/// that was not committed are cut off when the segments are replayed.
+pub struct SegmentDb {
+    segments: Arc<Mutex<Segments>>,
+    compactor: Option<(Sender<()>, JoinHandle<()>)>
+}
+
+impl SegmentDb {
+    /// Opens the database in the directory, creating it if it does not exist
+    pub fn new(dir: &Path) -> Result<SegmentDb, DatabaseError> {
+        SegmentDb::with_segment_size(dir, DEFAULT_SEGMENT_SIZE, COMPACTION_INTERVAL)
+    }
+
+    /// Opens the database with segments of the given size, compacting them at the given interval
+    pub fn with_segment_size(dir: &Path, segment_size: u64, compaction_interval: Duration) -> Result<SegmentDb, DatabaseError> {
+        let segments = Arc::new(Mutex::new(Segments::open(dir, segment_size)?));
+        let (stop_sender, stop_receiver) = channel::<()>();
+        let compacted_segments = segments.clone();
+        let compactor = thread::spawn(move || loop {
+            match stop_receiver.recv_timeout(compaction_interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Ok(mut segments) = compacted_segments.lock() {
+                        if segments.replayed {
+                            // a segment is left as it was when it can not be rewritten
+                            let _ = segments.compact();
+                        }
+                    }
+                },
+                _ => break
+            }
+        });
+        Ok(SegmentDb {
+            segments,
+            compactor: Some((stop_sender, compactor))
+        })
+    }
+
+    /// Rewrites the segments that are mostly made of deleted msgs, returning how many were
+    pub fn compact(&mut self) -> Result<u64, DatabaseError> {
+        let mut segments = self.replayed()?;
+        segments.compact()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Segments>, DatabaseError> {
+        match self.segments.lock() {
+            Ok(segments) => Ok(segments),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::DatabaseClosed, error))
+        }
+    }
+
+    /// Locks the segments, replaying them first if they were not yet
+    fn replayed(&self) -> Result<MutexGuard<'_, Segments>, DatabaseError> {
+        let mut segments = self.lock()?;
+        if !segments.replayed {
+            segments.replay()?;
+        }
+        Ok(segments)
+    }
+}
+
+impl Drop for SegmentDb {
+    fn drop(&mut self) {
+        if let Some((stop_sender, compactor)) = self.compactor.take() {
+            let _ = stop_sender.send(());
+            let _ = compactor.join();
+        }
+    }
+}
+
+impl Db for SegmentDb {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        let mut segments = self.replayed()?;
+        segments.read_msg(&uuid, 0, None)
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.add(uuid, msg, msg_byte_size);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+        }
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.del(uuid);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        }
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        let mut segments = self.replayed()?;
+        segments.read_msg(&uuid, offset, Some(max_len))
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        let segments = self.replayed()?;
+        match segments.index.get(&uuid) {
+            Some(location) => Ok(location.msg_len),
+            None => Err(segment_error!(DatabaseErrorTy::MsgNotFound))
+        }
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        let mut segments = self.replayed()?;
+        segments.commit(&batch)
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        let segments = self.replayed()?;
+        let msgs = segments.index.iter()
+            .map(|(uuid, location)| Ok((uuid.clone(), location.byte_size)))
+            .collect::<Vec<Result<(Arc<Uuid>, u64), DatabaseError>>>();
+        Ok(Box::new(msgs.into_iter()))
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        let segments = self.replayed()?;
+        let msgs = segments.index.range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(uuid, location)| Ok((uuid.clone(), location.byte_size)))
+            .collect::<Vec<Result<(Arc<Uuid>, u64), DatabaseError>>>();
+        Ok(Box::new(msgs.into_iter()))
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        let mut segments = self.lock()?;
+        segments.cursors.insert(consumer_group.to_string(), cursors);
+        match segments.save_cursors() {
+            Ok(()) => Ok(()),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotSaveCursors, error))
+        }
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        let mut segments = self.lock()?;
+        segments.cursors.remove(consumer_group);
+        match segments.save_cursors() {
+            Ok(()) => Ok(()),
+            Err(error) => Err(segment_error!(DatabaseErrorTy::CouldNotDeleteCursors, error))
+        }
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        let segments = self.lock()?;
+        Ok(segments.cursors.iter().map(|(consumer_group, cursors)| (consumer_group.clone(), cursors.clone())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use msg_store_uuid::Uuid;
+    use msg_store_database_plugin::{Batch, Db};
+    use msg_store_database_test_kit::check_persistent_db;
+    use crate::{SegmentDb, COMMITS_FILE, COMMIT_SIZE, COMPACTION_INTERVAL};
+    use std::fs::{create_dir_all, read_dir, remove_dir_all, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn dir_setup(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+        create_dir_all(tmp_dir).unwrap();
+    }
+
+    fn dir_teardown(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+    }
+
+    fn segment_count(tmp_dir: &Path, priority: u16) -> usize {
+        read_dir(tmp_dir.join(priority.to_string())).unwrap().count()
+    }
+
+    fn msg_uuid(sequence: u32) -> std::sync::Arc<Uuid> {
+        Uuid::from_string(&format!("1-0-{}-0", sequence)).unwrap()
+    }
+
+    #[test]
+    fn it_works() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        {
+            let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+            segment_db.add(uuid.clone(), msg.clone(), 10).unwrap();
+        }
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(uuid.clone(), 10)], segment_db.fetch_all().unwrap());
+        assert_eq!(msg, segment_db.get(uuid.clone()).unwrap());
+        segment_db.del(uuid.clone()).unwrap();
+        assert!(segment_db.get(uuid).is_err());
+        assert!(segment_db.fetch_all().unwrap().is_empty());
+
+        let cursors = vec![Uuid::from_string("1-0-1-0").unwrap(), Uuid::from_string("2-0-1-0").unwrap()];
+        segment_db.put_cursors("consumers", cursors.clone()).unwrap();
+        segment_db.put_cursors("idle", vec![]).unwrap();
+        drop(segment_db);
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        let saved_cursors = segment_db.fetch_cursors().unwrap();
+        assert_eq!(vec![("consumers".to_string(), cursors), ("idle".to_string(), vec![])], saved_cursors);
+        segment_db.del_cursors("consumers").unwrap();
+        assert_eq!(1, segment_db.fetch_cursors().unwrap().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_commit_batches() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-batch").unwrap();
+        dir_setup(&tmp_dir);
+        let first = Uuid::from_string("1-0-1-0").unwrap();
+        let second = Uuid::from_string("2-0-2-0").unwrap();
+        let third = Uuid::from_string("1-0-3-0").unwrap();
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        batch.add(first.clone(), Bytes::from_static(b"first"), 5);
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        segment_db.commit(batch).unwrap();
+        let mut batch = Batch::new();
+        batch.del(first.clone());
+        batch.del(second.clone());
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        batch.add(third.clone(), Bytes::from_static(b"third"), 5);
+        segment_db.commit(batch).unwrap();
+        assert!(segment_db.get(first).is_err());
+        assert_eq!(Bytes::from_static(b"second"), segment_db.get(second.clone()).unwrap());
+        assert_eq!(vec![(third.clone(), 5), (second.clone(), 6)], segment_db.fetch_all().unwrap());
+        drop(segment_db);
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(third, 5), (second, 6)], segment_db.fetch_all().unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_drop_segments_once_their_msgs_are_deleted() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-drop").unwrap();
+        dir_setup(&tmp_dir);
+        // every msg fills a segment of its own
+        let mut segment_db = SegmentDb::with_segment_size(&tmp_dir, 1, COMPACTION_INTERVAL).unwrap();
+        for sequence in 1..=5 {
+            segment_db.add(msg_uuid(sequence), Bytes::from_static(b"msg"), 3).unwrap();
+        }
+        assert_eq!(5, segment_count(&tmp_dir, 1));
+        for sequence in 1..=3 {
+            segment_db.del(msg_uuid(sequence)).unwrap();
+        }
+        // the segments of the deleted msgs are gone, the deletes are kept in the last segment
+        assert_eq!(3, segment_count(&tmp_dir, 1));
+        drop(segment_db);
+        let mut segment_db = SegmentDb::with_segment_size(&tmp_dir, 1, COMPACTION_INTERVAL).unwrap();
+        assert_eq!(vec![(msg_uuid(5), 3), (msg_uuid(4), 3)], segment_db.fetch_all().unwrap());
+        segment_db.add(msg_uuid(6), Bytes::from_static(b"msg"), 3).unwrap();
+        segment_db.del(msg_uuid(4)).unwrap();
+        segment_db.del(msg_uuid(5)).unwrap();
+        assert_eq!(vec![(msg_uuid(6), 3)], segment_db.fetch_all().unwrap());
+        assert_eq!(Bytes::from_static(b"msg"), segment_db.get(msg_uuid(6)).unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_compact_partly_deleted_segments() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-compact").unwrap();
+        dir_setup(&tmp_dir);
+        let mut segment_db = SegmentDb::with_segment_size(&tmp_dir, 512, COMPACTION_INTERVAL).unwrap();
+        for sequence in 1..=20 {
+            segment_db.add(msg_uuid(sequence), Bytes::from(format!("msg {:040}", sequence)), 44).unwrap();
+        }
+        let first_segment = tmp_dir.join("1").join(format!("{:020}.seg", 0));
+        let len = first_segment.metadata().unwrap().len();
+        // delete most of the msgs of the first segment, out of order
+        for sequence in (2..=6).rev() {
+            segment_db.del(msg_uuid(sequence)).unwrap();
+        }
+        assert_eq!(1, segment_db.compact().unwrap());
+        assert!(first_segment.metadata().unwrap().len() < len);
+        assert_eq!(0, segment_db.compact().unwrap());
+        assert_eq!(Bytes::from(format!("msg {:040}", 1)), segment_db.get(msg_uuid(1)).unwrap());
+        assert_eq!(Bytes::from(format!("msg {:040}", 7)), segment_db.get(msg_uuid(7)).unwrap());
+        drop(segment_db);
+        let mut segment_db = SegmentDb::with_segment_size(&tmp_dir, 512, COMPACTION_INTERVAL).unwrap();
+        let msgs = segment_db.fetch_all().unwrap();
+        assert_eq!(15, msgs.len());
+        assert!(segment_db.get(msg_uuid(3)).is_err());
+        assert_eq!(Bytes::from(format!("msg {:040}", 20)), segment_db.get(msg_uuid(20)).unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_compact_segments_in_the_background() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-compactor").unwrap();
+        dir_setup(&tmp_dir);
+        let mut segment_db = SegmentDb::with_segment_size(&tmp_dir, 512, Duration::from_millis(10)).unwrap();
+        for sequence in 1..=20 {
+            segment_db.add(msg_uuid(sequence), Bytes::from(format!("msg {:040}", sequence)), 44).unwrap();
+        }
+        let first_segment = tmp_dir.join("1").join(format!("{:020}.seg", 0));
+        let len = first_segment.metadata().unwrap().len();
+        for sequence in 2..=6 {
+            segment_db.del(msg_uuid(sequence)).unwrap();
+        }
+        sleep(Duration::from_millis(200));
+        assert!(first_segment.metadata().unwrap().len() < len);
+        assert_eq!(Bytes::from(format!("msg {:040}", 7)), segment_db.get(msg_uuid(7)).unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_drop_batches_that_were_not_committed() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-recover").unwrap();
+        dir_setup(&tmp_dir);
+        {
+            let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+            segment_db.add(msg_uuid(1), Bytes::from_static(b"first"), 5).unwrap();
+            segment_db.add(msg_uuid(2), Bytes::from_static(b"second"), 6).unwrap();
+        }
+        // the commit of the second batch never made it to disk
+        let commits = OpenOptions::new().write(true).open(tmp_dir.join(COMMITS_FILE)).unwrap();
+        commits.set_len(COMMIT_SIZE as u64).unwrap();
+        // and the segment ends with a record that was not written whole
+        let mut segment = OpenOptions::new().append(true).open(tmp_dir.join("1").join(format!("{:020}.seg", 0))).unwrap();
+        segment.write_all(b"torn").unwrap();
+        drop(segment);
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(msg_uuid(1), 5)], segment_db.fetch_all().unwrap());
+        segment_db.add(msg_uuid(3), Bytes::from_static(b"third"), 5).unwrap();
+        drop(segment_db);
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(msg_uuid(3), 5), (msg_uuid(1), 5)], segment_db.fetch_all().unwrap());
+        assert_eq!(Bytes::from_static(b"third"), segment_db.get(msg_uuid(3)).unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_msgs_in_chunks() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-segment-chunks").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let mut segment_db = SegmentDb::new(&tmp_dir).unwrap();
+        segment_db.add(uuid.clone(), Bytes::from_static(b"my message"), 10).unwrap();
+        assert_eq!(Bytes::from_static(b"my me"), segment_db.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), segment_db.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert!(segment_db.get_chunk(uuid.clone(), 10, 5).unwrap().is_empty());
+        segment_db.del(uuid.clone()).unwrap();
+        assert!(segment_db.get_chunk(uuid, 0, 5).is_err());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_persistent_db("segment", |path| SegmentDb::new(path).unwrap());
+    }
+}
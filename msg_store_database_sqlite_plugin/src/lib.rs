@@ -0,0 +1,539 @@
+use bytes::Bytes;
+use msg_store_uuid::Uuid;
+pub use msg_store_database_plugin::{Batch, Cursors, Db, DatabaseError, DatabaseErrorTy, Fetched, Write};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::VecDeque;
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+macro_rules! sqlite_error {
+    ($err_ty:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        DatabaseError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The version of the schema, kept in the metadata table
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The name of the database file within the directory of the database
+pub const DATABASE_FILE: &str = "msgs.sqlite3";
+
+/// The number of msgs read at a time while the msgs are fetched
+const FETCH_PAGE_SIZE: i64 = 10_000;
+
+/// Msgs are kept apart from their sizes so that fetching the sizes does not read the msgs
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS msgs (
+        uuid TEXT PRIMARY KEY NOT NULL,
+        msg BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS msg_data (
+        uuid TEXT PRIMARY KEY NOT NULL,
+        priority INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        sequence INTEGER NOT NULL,
+        node_id INTEGER NOT NULL,
+        byte_size INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS msg_data_priority_timestamp ON msg_data (priority, timestamp);
+    CREATE TABLE IF NOT EXISTS cursors (
+        consumer_group TEXT PRIMARY KEY NOT NULL,
+        cursors TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );
+";
+
+/// The msgs within a range of priorities and timestamps
+///
+/// Every bound is inclusive and is left open when it is not given. Timestamps are in
+/// microseconds, whatever the version of the uuid.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MsgQuery {
+    pub min_priority: Option<u16>,
+    pub max_priority: Option<u16>,
+    pub from_timestamp: Option<u64>,
+    pub to_timestamp: Option<u64>
+}
+
+/// A database held in a single SQLite file in WAL mode
+///
+/// Every batch is committed in one transaction, so either all of its writes are kept or none
+/// of them are.
+pub struct Sqlite {
+    connection: Mutex<Connection>
+}
+
+impl Sqlite {
+    /// Opens the database in the directory, creating it if it does not exist
+    pub fn new(dir: &Path) -> Result<Sqlite, DatabaseError> {
+        if !dir.exists() {
+            if let Err(error) = create_dir_all(dir) {
+                return Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+            }
+        }
+        let mut path = dir.to_path_buf();
+        path.push(DATABASE_FILE);
+        let connection = match Connection::open(&path) {
+            Ok(connection) => Ok(connection),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        // readers are not blocked by the writer and a commit only appends to the log
+        let journal_mode = match connection.query_row("PRAGMA journal_mode = WAL", [], |row| row.get::<_, String>(0)) {
+            Ok(journal_mode) => Ok(journal_mode),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            return Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("Could not use WAL mode, the journal mode is {}", journal_mode)))
+        }
+        if let Err(error) = connection.execute_batch(SCHEMA) {
+            return Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }
+        let schema_version = match connection.query_row("SELECT value FROM metadata WHERE key = 'schema_version'", [], |row| row.get::<_, String>(0)).optional() {
+            Ok(schema_version) => Ok(schema_version),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+        }?;
+        match schema_version {
+            Some(schema_version) => {
+                if schema_version != SCHEMA_VERSION.to_string() {
+                    return Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, format!("Unsupported schema version {}", schema_version)))
+                }
+            },
+            None => {
+                if let Err(error) = connection.execute("INSERT INTO metadata (key, value) VALUES ('schema_version', ?1)", params![SCHEMA_VERSION.to_string()]) {
+                    return Err(sqlite_error!(DatabaseErrorTy::CouldNotOpenDatabase, error))
+                }
+            }
+        };
+        Ok(Sqlite {
+            connection: Mutex::new(connection)
+        })
+    }
+
+    fn connection(&mut self) -> Result<&mut Connection, DatabaseError> {
+        match self.connection.get_mut() {
+            Ok(connection) => Ok(connection),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::DatabaseClosed, error))
+        }
+    }
+
+    /// Gets the uuid and byte size of the msgs within the query, highest priority then oldest first
+    pub fn query(&mut self, query: &MsgQuery) -> Result<Vec<(Arc<Uuid>, u64)>, DatabaseError> {
+        let from_timestamp = match query.from_timestamp.map(i64::try_from).transpose() {
+            Ok(from_timestamp) => Ok(from_timestamp),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let to_timestamp = match query.to_timestamp.map(i64::try_from).transpose() {
+            Ok(to_timestamp) => Ok(to_timestamp),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let connection = self.connection()?;
+        let mut statement = match connection.prepare(
+            "SELECT uuid, byte_size FROM msg_data
+            WHERE (?1 IS NULL OR priority >= ?1) AND (?2 IS NULL OR priority <= ?2)
+            AND (?3 IS NULL OR timestamp >= ?3) AND (?4 IS NULL OR timestamp <= ?4)
+            ORDER BY priority DESC, timestamp ASC, sequence ASC, node_id ASC"
+        ) {
+            Ok(statement) => Ok(statement),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let rows = match statement.query_map(params![query.min_priority, query.max_priority, from_timestamp, to_timestamp], to_msg_data) {
+            Ok(rows) => Ok(rows),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        rows.map(|row| match row {
+            Ok(row) => parse_msg_data(row),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }).collect()
+    }
+}
+
+fn to_msg_data(row: &rusqlite::Row) -> rusqlite::Result<(String, i64)> {
+    Ok((row.get(0)?, row.get(1)?))
+}
+
+fn parse_msg_data((uuid, byte_size): (String, i64)) -> Result<(Arc<Uuid>, u64), DatabaseError> {
+    let uuid = match Uuid::from_string(&uuid) {
+        Ok(uuid) => Ok(uuid),
+        Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    let byte_size = match u64::try_from(byte_size) {
+        Ok(byte_size) => Ok(byte_size),
+        Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+    }?;
+    Ok((uuid, byte_size))
+}
+
+/// Reads the byte sizes of the msgs a page at a time, in the order of their uuids
+struct FetchPages<'a> {
+    connection: &'a Connection,
+    after: Option<String>,
+    page: VecDeque<(String, i64)>,
+    done: bool
+}
+impl FetchPages<'_> {
+    fn next_page(&mut self) -> Result<(), DatabaseError> {
+        let mut statement = match self.connection.prepare_cached(
+            "SELECT uuid, byte_size FROM msg_data WHERE ?1 IS NULL OR uuid > ?1 ORDER BY uuid LIMIT ?2"
+        ) {
+            Ok(statement) => Ok(statement),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let rows = match statement.query_map(params![self.after, FETCH_PAGE_SIZE], to_msg_data) {
+            Ok(rows) => Ok(rows),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        for row in rows {
+            match row {
+                Ok(row) => self.page.push_back(row),
+                Err(error) => return Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }
+        }
+        if (self.page.len() as i64) < FETCH_PAGE_SIZE {
+            self.done = true;
+        }
+        if let Some((uuid, _)) = self.page.back() {
+            self.after = Some(uuid.clone());
+        }
+        Ok(())
+    }
+}
+impl Iterator for FetchPages<'_> {
+    type Item = Result<(Arc<Uuid>, u64), DatabaseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            if let Err(error) = self.next_page() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        self.page.pop_front().map(parse_msg_data)
+    }
+}
+
+fn to_i64(value: u128) -> Result<i64, DatabaseError> {
+    match i64::try_from(value) {
+        Ok(value) => Ok(value),
+        Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+    }
+}
+
+impl Db for Sqlite {
+    fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+        let connection = self.connection()?;
+        let msg_option = match connection.query_row("SELECT msg FROM msgs WHERE uuid = ?1", params![uuid.to_string()], |row| row.get::<_, Vec<u8>>(0)).optional() {
+            Ok(msg_option) => Ok(msg_option),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }?;
+        match msg_option {
+            Some(msg) => Ok(Bytes::from(msg)),
+            None => Err(sqlite_error!(DatabaseErrorTy::MsgNotFound))
+        }
+    }
+    fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.add(uuid, msg, msg_byte_size);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotAddMsg, error))
+        }
+    }
+    fn del(&mut self, uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+        let mut batch = Batch::new();
+        batch.del(uuid);
+        match self.commit(batch) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotDeleteMsg, error))
+        }
+    }
+    fn get_chunk(&mut self, uuid: Arc<Uuid>, offset: u64, max_len: usize) -> Result<Bytes, DatabaseError> {
+        // substr counts bytes from 1 when given a blob
+        let start = to_i64(offset as u128 + 1)?;
+        let max_len = to_i64(max_len as u128)?;
+        let connection = self.connection()?;
+        // the substr of an empty msg is null
+        let chunk_option = match connection.query_row("SELECT substr(msg, ?2, ?3) FROM msgs WHERE uuid = ?1", params![uuid.to_string(), start, max_len], |row| row.get::<_, Option<Vec<u8>>>(0)).optional() {
+            Ok(chunk_option) => Ok(chunk_option),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }?;
+        match chunk_option {
+            Some(chunk) => Ok(Bytes::from(chunk.unwrap_or_default())),
+            None => Err(sqlite_error!(DatabaseErrorTy::MsgNotFound))
+        }
+    }
+    fn msg_len(&mut self, uuid: Arc<Uuid>) -> Result<u64, DatabaseError> {
+        let connection = self.connection()?;
+        let len_option = match connection.query_row("SELECT length(msg) FROM msgs WHERE uuid = ?1", params![uuid.to_string()], |row| row.get::<_, i64>(0)).optional() {
+            Ok(len_option) => Ok(len_option),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotGetMsg, error))
+        }?;
+        match len_option {
+            Some(len) => Ok(len as u64),
+            None => Err(sqlite_error!(DatabaseErrorTy::MsgNotFound))
+        }
+    }
+    fn commit(&mut self, batch: Batch) -> Result<(), DatabaseError> {
+        let connection = self.connection()?;
+        let transaction = match connection.transaction() {
+            Ok(transaction) => Ok(transaction),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        }?;
+        for write in batch.writes.iter() {
+            let result = match write {
+                Write::Add { uuid, msg, msg_byte_size } => {
+                    let uuid_str = uuid.to_string();
+                    let timestamp = to_i64(uuid.timestamp_micros())?;
+                    let byte_size = to_i64(*msg_byte_size as u128)?;
+                    transaction.execute(
+                        "INSERT OR REPLACE INTO msgs (uuid, msg) VALUES (?1, ?2)",
+                        params![uuid_str, msg.as_ref()]
+                    ).and_then(|_| transaction.execute(
+                        "INSERT OR REPLACE INTO msg_data (uuid, priority, timestamp, sequence, node_id, byte_size) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![uuid_str, uuid.priority, timestamp, uuid.sequence, uuid.node_id, byte_size]
+                    ))
+                },
+                Write::Del { uuid } => {
+                    let uuid_str = uuid.to_string();
+                    transaction.execute("DELETE FROM msgs WHERE uuid = ?1", params![uuid_str])
+                        .and_then(|_| transaction.execute("DELETE FROM msg_data WHERE uuid = ?1", params![uuid_str]))
+                }
+            };
+            // the transaction is rolled back when it is dropped
+            if let Err(error) = result {
+                return Err(sqlite_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+            }
+        }
+        if let Err(error) = transaction.commit() {
+            return Err(sqlite_error!(DatabaseErrorTy::CouldNotCommitBatch, error))
+        }
+        Ok(())
+    }
+    fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+        let connection = self.connection()?;
+        Ok(Box::new(FetchPages {
+            connection,
+            after: None,
+            page: VecDeque::new(),
+            done: false
+        }))
+    }
+    fn fetch_after(&mut self, after: Arc<Uuid>) -> Result<Fetched<'_>, DatabaseError> {
+        let connection = self.connection()?;
+        Ok(Box::new(FetchPages {
+            connection,
+            after: Some(after.to_string()),
+            page: VecDeque::new(),
+            done: false
+        }))
+    }
+    fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+        // cursors are stored as a comma separated list of uuids
+        let cursors_str = cursors.iter().map(|uuid| uuid.to_string()).collect::<Vec<String>>().join(",");
+        let connection = self.connection()?;
+        if let Err(error) = connection.execute("INSERT OR REPLACE INTO cursors (consumer_group, cursors) VALUES (?1, ?2)", params![consumer_group, cursors_str]) {
+            return Err(sqlite_error!(DatabaseErrorTy::CouldNotSaveCursors, error))
+        }
+        Ok(())
+    }
+    fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+        let connection = self.connection()?;
+        if let Err(error) = connection.execute("DELETE FROM cursors WHERE consumer_group = ?1", params![consumer_group]) {
+            return Err(sqlite_error!(DatabaseErrorTy::CouldNotDeleteCursors, error))
+        }
+        Ok(())
+    }
+    fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+        let connection = self.connection()?;
+        let mut statement = match connection.prepare("SELECT consumer_group, cursors FROM cursors") {
+            Ok(statement) => Ok(statement),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        let rows = match statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))) {
+            Ok(rows) => Ok(rows),
+            Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+        }?;
+        rows.map(|row| {
+            let (consumer_group, data) = match row {
+                Ok(row) => Ok(row),
+                Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+            }?;
+            let cursors = data.split(',').filter(|uuid| !uuid.is_empty()).map(|uuid| {
+                match Uuid::from_string(uuid) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(sqlite_error!(DatabaseErrorTy::CouldNotFetchData, error))
+                }
+            }).collect::<Result<Vec<Arc<Uuid>>, DatabaseError>>()?;
+            Ok((consumer_group, cursors))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use msg_store_uuid::Uuid;
+    use msg_store_database_plugin::{Batch, Db};
+    use msg_store_database_test_kit::check_persistent_db;
+    use crate::{MsgQuery, Sqlite};
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    fn dir_setup(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+        create_dir_all(tmp_dir).unwrap();
+    }
+
+    fn dir_teardown(tmp_dir: &Path) {
+        if tmp_dir.exists() {
+            remove_dir_all(tmp_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let msg = Bytes::from_static(b"my message");
+        {
+            let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+            sqlite.add(uuid.clone(), msg.clone(), 10).unwrap();
+        }
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        assert_eq!(vec![(uuid.clone(), 10)], sqlite.fetch_all().unwrap());
+        assert_eq!(msg, sqlite.get(uuid.clone()).unwrap());
+        sqlite.del(uuid.clone()).unwrap();
+        assert!(sqlite.get(uuid).is_err());
+        assert!(sqlite.fetch_all().unwrap().is_empty());
+
+        let cursors = vec![Uuid::from_string("1-0-1-0").unwrap(), Uuid::from_string("2-0-1-0").unwrap()];
+        sqlite.put_cursors("consumers", cursors.clone()).unwrap();
+        sqlite.put_cursors("idle", vec![]).unwrap();
+        drop(sqlite);
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        let mut saved_cursors = sqlite.fetch_cursors().unwrap();
+        saved_cursors.sort();
+        assert_eq!(vec![("consumers".to_string(), cursors), ("idle".to_string(), vec![])], saved_cursors);
+        sqlite.del_cursors("consumers").unwrap();
+        assert_eq!(1, sqlite.fetch_cursors().unwrap().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_commit_batches() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite-batch").unwrap();
+        dir_setup(&tmp_dir);
+        let first = Uuid::from_string("1-0-1-0").unwrap();
+        let second = Uuid::from_string("1-0-2-0").unwrap();
+        let third = Uuid::from_string("1-0-3-0").unwrap();
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        batch.add(first.clone(), Bytes::from_static(b"first"), 5);
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        sqlite.commit(batch).unwrap();
+        let mut batch = Batch::new();
+        batch.del(first.clone());
+        batch.del(second.clone());
+        batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+        batch.add(third.clone(), Bytes::from_static(b"third"), 5);
+        sqlite.commit(batch).unwrap();
+        assert!(sqlite.get(first).is_err());
+        assert_eq!(Bytes::from_static(b"second"), sqlite.get(second.clone()).unwrap());
+        assert_eq!(vec![(second, 6), (third, 5)], sqlite.fetch_all().unwrap());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_fetch_msgs_a_page_at_a_time() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite-fetch").unwrap();
+        dir_setup(&tmp_dir);
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        let mut batch = Batch::new();
+        for sequence in 1..=25_001 {
+            batch.add(Uuid::from_string(&format!("1-0-{}-0", sequence)).unwrap(), Bytes::from_static(b"msg"), 3);
+        }
+        sqlite.commit(batch).unwrap();
+        let msgs = sqlite.fetch_all().unwrap();
+        assert_eq!(25_001, msgs.len());
+        assert_eq!(25_001, msgs.iter().map(|(uuid, _)| uuid.sequence).collect::<std::collections::BTreeSet<u32>>().len());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_query_by_priority_and_timestamp() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite-query").unwrap();
+        dir_setup(&tmp_dir);
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        let old_low = Uuid::from_string("1-10-1-0").unwrap();
+        let new_low = Uuid::from_string("v2-1-20000000-1-0").unwrap();
+        let old_high = Uuid::from_string("3-10-1-0").unwrap();
+        let new_high = Uuid::from_string("v2-3-30000000-1-0").unwrap();
+        let mut batch = Batch::new();
+        for uuid in [&new_high, &old_low, &new_low, &old_high] {
+            batch.add(uuid.clone(), Bytes::from_static(b"msg"), 3);
+        }
+        sqlite.commit(batch).unwrap();
+
+        let uuids = |msgs: Vec<(std::sync::Arc<Uuid>, u64)>| msgs.into_iter().map(|(uuid, _)| uuid).collect::<Vec<_>>();
+        // highest priority then oldest first
+        assert_eq!(vec![old_high.clone(), new_high.clone(), old_low.clone(), new_low.clone()], uuids(sqlite.query(&MsgQuery::default()).unwrap()));
+        let query = MsgQuery { min_priority: Some(2), ..Default::default() };
+        assert_eq!(vec![old_high.clone(), new_high.clone()], uuids(sqlite.query(&query).unwrap()));
+        // v1 timestamps are compared in microseconds
+        let query = MsgQuery { from_timestamp: Some(15_000_000), to_timestamp: Some(25_000_000), ..Default::default() };
+        assert_eq!(vec![new_low.clone()], uuids(sqlite.query(&query).unwrap()));
+        let query = MsgQuery { max_priority: Some(1), to_timestamp: Some(10_000_000), ..Default::default() };
+        assert_eq!(vec![old_low], uuids(sqlite.query(&query).unwrap()));
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_empty_msgs_in_chunks() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite-empty-chunks").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        sqlite.add(uuid.clone(), Bytes::new(), 0).unwrap();
+        assert!(sqlite.get_chunk(uuid, 0, 5).unwrap().is_empty());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_read_msgs_in_chunks() {
+        let tmp_dir = PathBuf::from_str("/tmp/msg-store-plugin-sqlite-chunks").unwrap();
+        dir_setup(&tmp_dir);
+        let uuid = Uuid::from_string("1-0-1-0").unwrap();
+        let mut sqlite = Sqlite::new(&tmp_dir).unwrap();
+        sqlite.add(uuid.clone(), Bytes::from_static(b"my message"), 10).unwrap();
+        assert_eq!(Bytes::from_static(b"my me"), sqlite.get_chunk(uuid.clone(), 0, 5).unwrap());
+        assert_eq!(Bytes::from_static(b"ssage"), sqlite.get_chunk(uuid.clone(), 5, 5).unwrap());
+        assert!(sqlite.get_chunk(uuid.clone(), 10, 5).unwrap().is_empty());
+        sqlite.del(uuid.clone()).unwrap();
+        assert!(sqlite.get_chunk(uuid, 0, 5).is_err());
+        dir_teardown(&tmp_dir);
+    }
+
+    #[test]
+    fn should_pass_the_conformance_suite() {
+        check_persistent_db("sqlite", |path| Sqlite::new(path).unwrap());
+    }
+}
@@ -0,0 +1,337 @@
+//! A conformance test suite for `Db` implementations
+//!
+//! Every database is expected to behave the same way:
+//! * a msg that is missing fails to be read with `DatabaseErrorTy::MsgNotFound`
+//! * deleting a msg that is missing succeeds, as does deleting the cursors of a consumer
+//!   group that has none
+//! * adding a msg with a uuid that is already held replaces the msg and its byte size
+//! * the writes of a batch are applied in the order they were made
+//! * `fetch` returns every msg once, in no particular order
+//! * `fetch_after` returns the msgs `fetch` returns after a msg, even once it was deleted
+//! * the cursors of a consumer group are returned in the order they were saved
+//! * a msg is read back whole or in chunks, whatever its size
+//!
+//! A database that keeps its msgs on disk must also find them again once it is reopened.
+use bytes::{Bytes, BytesMut};
+use msg_store_database_plugin::{Batch, Db, DatabaseError, DatabaseErrorTy, CHUNK_SIZE};
+use msg_store_uuid::Uuid;
+use std::fmt::Debug;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A check of the suite, given the database to check
+type Check<D> = fn(&mut D);
+
+/// Runs the suite against the database `open` opens
+///
+/// Each check is given a database of its own, opened in an empty directory under /tmp that
+/// is named after the database and the check. The directory is removed once the check
+/// passes.
+pub fn check_db<D: Db, F: Fn(&Path) -> D>(name: &str, open: F) {
+    let checks: Vec<(&str, Check<D>)> = vec![
+        ("get-added-msgs", should_get_added_msgs),
+        ("missing-msgs", should_not_find_missing_msgs),
+        ("deleted-msgs", should_not_find_deleted_msgs),
+        ("overwritten-msgs", should_replace_overwritten_msgs),
+        ("batches", should_apply_batches_in_order),
+        ("fetch", should_fetch_every_msg_once),
+        ("fetch-after", should_fetch_the_msgs_after_a_msg),
+        ("large-msgs", should_read_large_msgs),
+        ("empty-msgs", should_read_empty_msgs),
+        ("cursors", should_save_cursors_in_order)
+    ];
+    for (check, run) in checks {
+        let dir = dir_setup(name, check);
+        println!("{}: {}", name, check);
+        let mut db = open(&dir);
+        run(&mut db);
+        // the database is closed before its directory is removed
+        drop(db);
+        dir_teardown(&dir);
+    }
+}
+
+/// Runs the suite and then checks that the msgs and cursors are kept once the database is
+/// reopened
+pub fn check_persistent_db<D: Db, F: Fn(&Path) -> D>(name: &str, open: F) {
+    check_db(name, &open);
+    let dir = dir_setup(name, "reopen");
+    println!("{}: reopen", name);
+    should_keep_msgs_once_reopened(&dir, &open);
+    dir_teardown(&dir);
+}
+
+fn dir_setup(name: &str, check: &str) -> PathBuf {
+    let dir = PathBuf::from(format!("/tmp/msg-store-test-kit-{}-{}", name, check));
+    if dir.exists() {
+        remove_dir_all(&dir).unwrap();
+    }
+    create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn dir_teardown(dir: &Path) {
+    if dir.exists() {
+        remove_dir_all(dir).unwrap();
+    }
+}
+
+/// Gets a uuid, the uuids of a priority are not in the same order as their strings once
+/// the sequence reaches 10
+fn msg_uuid(priority: u16, sequence: u32) -> Arc<Uuid> {
+    Uuid::from_string(&format!("{}-0-{}-0", priority, sequence)).unwrap()
+}
+
+/// Gets a msg of `len` bytes that does not repeat every few bytes
+fn large_msg(len: usize) -> Bytes {
+    (0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>().into()
+}
+
+fn assert_not_found<T: Debug>(result: Result<T, DatabaseError>) {
+    match result {
+        Err(DatabaseError { err_ty: DatabaseErrorTy::MsgNotFound, .. }) => {},
+        result => panic!("Expected MsgNotFound, got {:?}", result)
+    }
+}
+
+/// Gets every msg, sorted by uuid as the order of `fetch` is not part of the contract
+fn fetch_sorted<D: Db>(db: &mut D) -> Vec<(Arc<Uuid>, u64)> {
+    let mut msgs = db.fetch().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    msgs.sort();
+    msgs
+}
+
+/// Checks that the database holds exactly the msgs expected, with their byte sizes
+fn assert_fetched<D: Db>(db: &mut D, mut expected: Vec<(Arc<Uuid>, u64)>) {
+    expected.sort();
+    assert_eq!(expected, fetch_sorted(db));
+}
+
+/// Reads a msg a chunk at a time until an empty chunk is read
+fn read_in_chunks<D: Db>(db: &mut D, uuid: &Arc<Uuid>, chunk_size: usize) -> Bytes {
+    let mut msg = BytesMut::new();
+    loop {
+        let chunk = db.get_chunk(uuid.clone(), msg.len() as u64, chunk_size).unwrap();
+        if chunk.is_empty() {
+            return msg.freeze()
+        }
+        assert!(chunk.len() <= chunk_size);
+        msg.extend_from_slice(&chunk);
+    }
+}
+
+fn should_get_added_msgs<D: Db>(db: &mut D) {
+    let first = msg_uuid(1, 1);
+    let second = msg_uuid(2, 1);
+    // the byte size is kept as it is given, it need not be the length of the msg
+    db.add(first.clone(), Bytes::from_static(b"first"), 5).unwrap();
+    db.add(second.clone(), Bytes::from_static(b"second"), 100).unwrap();
+    assert_eq!(Bytes::from_static(b"first"), db.get(first.clone()).unwrap());
+    assert_eq!(Bytes::from_static(b"second"), db.get(second.clone()).unwrap());
+    assert_eq!(6, db.msg_len(second.clone()).unwrap());
+    assert_eq!(Bytes::from_static(b"seco"), db.get_chunk(second.clone(), 0, 4).unwrap());
+    assert_eq!(Bytes::from_static(b"nd"), db.get_chunk(second.clone(), 4, 4).unwrap());
+    assert_fetched(db, vec![(first, 5), (second, 100)]);
+}
+
+fn should_not_find_missing_msgs<D: Db>(db: &mut D) {
+    let uuid = msg_uuid(1, 1);
+    assert!(fetch_sorted(db).is_empty());
+    assert_not_found(db.get(uuid.clone()));
+    assert_not_found(db.get_chunk(uuid.clone(), 0, CHUNK_SIZE));
+    assert_not_found(db.msg_len(uuid.clone()));
+    db.del(uuid.clone()).unwrap();
+    let mut batch = Batch::new();
+    batch.del(uuid);
+    db.commit(batch).unwrap();
+    assert!(fetch_sorted(db).is_empty());
+}
+
+fn should_not_find_deleted_msgs<D: Db>(db: &mut D) {
+    let kept = msg_uuid(1, 1);
+    let deleted = msg_uuid(1, 2);
+    db.add(kept.clone(), Bytes::from_static(b"kept"), 4).unwrap();
+    db.add(deleted.clone(), Bytes::from_static(b"deleted"), 7).unwrap();
+    db.del(deleted.clone()).unwrap();
+    assert_not_found(db.get(deleted.clone()));
+    assert_not_found(db.get_chunk(deleted.clone(), 0, CHUNK_SIZE));
+    assert_not_found(db.msg_len(deleted.clone()));
+    // a msg can be deleted again
+    db.del(deleted.clone()).unwrap();
+    assert_fetched(db, vec![(kept.clone(), 4)]);
+    // a msg that is read in chunks is not read from what was kept of it once it is deleted
+    db.get_chunk(kept.clone(), 0, 2).unwrap();
+    db.del(kept.clone()).unwrap();
+    assert_not_found(db.get_chunk(kept, 2, 2));
+    assert!(fetch_sorted(db).is_empty());
+}
+
+fn should_replace_overwritten_msgs<D: Db>(db: &mut D) {
+    let uuid = msg_uuid(1, 1);
+    db.add(uuid.clone(), Bytes::from_static(b"first"), 5).unwrap();
+    db.add(uuid.clone(), Bytes::from_static(b"second"), 6).unwrap();
+    assert_eq!(Bytes::from_static(b"second"), db.get(uuid.clone()).unwrap());
+    assert_fetched(db, vec![(uuid.clone(), 6)]);
+    // nothing is left of a larger msg once it is replaced by a smaller one
+    let large = large_msg(CHUNK_SIZE * 2 + 1);
+    db.add(uuid.clone(), large.clone(), large.len() as u64).unwrap();
+    assert_eq!(large, db.get(uuid.clone()).unwrap());
+    // a msg that is read in chunks is not read from what was kept of it once it is replaced
+    assert_eq!(large.slice(0..2), db.get_chunk(uuid.clone(), 0, 2).unwrap());
+    db.add(uuid.clone(), Bytes::from_static(b"third"), 5).unwrap();
+    assert_eq!(Bytes::from_static(b"ird"), db.get_chunk(uuid.clone(), 2, CHUNK_SIZE).unwrap());
+    assert_eq!(Bytes::from_static(b"third"), db.get(uuid.clone()).unwrap());
+    assert_eq!(5, db.msg_len(uuid.clone()).unwrap());
+    assert_eq!(Bytes::from_static(b"third"), read_in_chunks(db, &uuid, 2));
+    assert_fetched(db, vec![(uuid, 5)]);
+}
+
+fn should_apply_batches_in_order<D: Db>(db: &mut D) {
+    let first = msg_uuid(1, 1);
+    let second = msg_uuid(1, 2);
+    let third = msg_uuid(1, 3);
+    let fourth = msg_uuid(1, 4);
+    let mut batch = Batch::new();
+    batch.add(first.clone(), Bytes::from_static(b"first"), 5);
+    batch.add(second.clone(), Bytes::from_static(b"second"), 6);
+    db.commit(batch).unwrap();
+    let mut batch = Batch::new();
+    // a msg that is deleted and then added again is kept
+    batch.del(first.clone());
+    batch.add(first.clone(), Bytes::from_static(b"first again"), 11);
+    // a msg that is added and then deleted is not
+    batch.add(third.clone(), Bytes::from_static(b"third"), 5);
+    batch.del(third.clone());
+    // the last add of a msg is kept
+    batch.add(fourth.clone(), Bytes::from_static(b"fourth"), 6);
+    batch.add(fourth.clone(), Bytes::from_static(b"fourth again"), 12);
+    batch.del(second.clone());
+    db.commit(batch).unwrap();
+    db.commit(Batch::new()).unwrap();
+    assert_eq!(Bytes::from_static(b"first again"), db.get(first.clone()).unwrap());
+    assert_not_found(db.get(second));
+    assert_not_found(db.get(third));
+    assert_eq!(Bytes::from_static(b"fourth again"), db.get(fourth.clone()).unwrap());
+    assert_fetched(db, vec![(first, 11), (fourth, 12)]);
+}
+
+fn should_fetch_every_msg_once<D: Db>(db: &mut D) {
+    // the msgs are added out of order, across priorities
+    let mut uuids = vec![];
+    for (priority, sequence) in [(2, 10), (1, 2), (2, 1), (1, 10), (3, 100), (1, 1)] {
+        let uuid = msg_uuid(priority, sequence);
+        db.add(uuid.clone(), Bytes::from(uuid.to_string()), sequence as u64).unwrap();
+        uuids.push((uuid, sequence as u64));
+    }
+    assert_fetched(db, uuids.clone());
+    assert_eq!(uuids.len(), db.fetch_all().unwrap().len());
+    for (uuid, _) in uuids {
+        assert_eq!(Bytes::from(uuid.to_string()), db.get(uuid).unwrap());
+    }
+}
+
+fn should_fetch_the_msgs_after_a_msg<D: Db>(db: &mut D) {
+    for (priority, sequence) in [(2, 10), (1, 2), (2, 1), (1, 10), (3, 100), (1, 1)] {
+        db.add(msg_uuid(priority, sequence), Bytes::from("msg"), sequence as u64).unwrap();
+    }
+    let fetched = db.fetch_all().unwrap();
+    for (position, (uuid, _)) in fetched.iter().enumerate() {
+        let after = db.fetch_after(uuid.clone()).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(fetched[position + 1..].to_vec(), after);
+    }
+    // a walk over the msgs is resumed after a msg that was deleted in the meantime
+    let (deleted, _) = fetched[2].clone();
+    db.del(deleted.clone()).unwrap();
+    let after = db.fetch_after(deleted).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(fetched[3..].to_vec(), after);
+}
+
+fn should_read_large_msgs<D: Db>(db: &mut D) {
+    let uuid = msg_uuid(1, 1);
+    let msg = large_msg(CHUNK_SIZE * 3 + 7);
+    db.add(uuid.clone(), msg.clone(), msg.len() as u64).unwrap();
+    assert_eq!(msg, db.get(uuid.clone()).unwrap());
+    assert_eq!(msg.len() as u64, db.msg_len(uuid.clone()).unwrap());
+    assert_eq!(msg, read_in_chunks(db, &uuid, CHUNK_SIZE));
+    assert_eq!(msg, read_in_chunks(db, &uuid, 1000));
+    // a chunk past the end of the msg is empty
+    assert!(db.get_chunk(uuid.clone(), msg.len() as u64 + 1, CHUNK_SIZE).unwrap().is_empty());
+    // a msg that is added in chunks is read back whole
+    let chunked = msg_uuid(1, 2);
+    let mut chunks = msg.chunks(CHUNK_SIZE).map(Bytes::copy_from_slice).collect::<Vec<Bytes>>().into_iter();
+    db.add_chunks(chunked.clone(), &mut chunks, msg.len() as u64).unwrap();
+    assert_eq!(msg, db.get(chunked.clone()).unwrap());
+    assert_fetched(db, vec![(uuid, msg.len() as u64), (chunked, msg.len() as u64)]);
+}
+
+fn should_read_empty_msgs<D: Db>(db: &mut D) {
+    let uuid = msg_uuid(1, 1);
+    db.add(uuid.clone(), Bytes::new(), 0).unwrap();
+    assert!(db.get(uuid.clone()).unwrap().is_empty());
+    assert_eq!(0, db.msg_len(uuid.clone()).unwrap());
+    assert!(db.get_chunk(uuid.clone(), 0, CHUNK_SIZE).unwrap().is_empty());
+    assert_fetched(db, vec![(uuid, 0)]);
+}
+
+fn should_save_cursors_in_order<D: Db>(db: &mut D) {
+    assert!(db.fetch_cursors().unwrap().is_empty());
+    db.del_cursors("missing").unwrap();
+    // the cursors are not sorted
+    let cursors = vec![msg_uuid(2, 10), msg_uuid(1, 2), msg_uuid(1, 10)];
+    db.put_cursors("consumers", cursors.clone()).unwrap();
+    db.put_cursors("idle", vec![]).unwrap();
+    db.put_cursors("replaced", vec![msg_uuid(1, 1)]).unwrap();
+    db.put_cursors("replaced", vec![msg_uuid(1, 3)]).unwrap();
+    let mut saved_cursors = db.fetch_cursors().unwrap();
+    saved_cursors.sort();
+    assert_eq!(vec![
+        ("consumers".to_string(), cursors.clone()),
+        ("idle".to_string(), vec![]),
+        ("replaced".to_string(), vec![msg_uuid(1, 3)])
+    ], saved_cursors);
+    db.del_cursors("replaced").unwrap();
+    db.del_cursors("idle").unwrap();
+    assert_eq!(vec![("consumers".to_string(), cursors)], db.fetch_cursors().unwrap());
+}
+
+fn should_keep_msgs_once_reopened<D: Db, F: Fn(&Path) -> D>(dir: &Path, open: &F) {
+    let first = msg_uuid(1, 1);
+    let second = msg_uuid(1, 2);
+    let third = msg_uuid(2, 10);
+    let fourth = msg_uuid(2, 2);
+    let large = large_msg(CHUNK_SIZE * 2 + 3);
+    let cursors = vec![third.clone(), first.clone()];
+    {
+        let mut db = open(dir);
+        db.add(first.clone(), Bytes::from_static(b"first"), 5).unwrap();
+        db.add(second.clone(), Bytes::from_static(b"second"), 6).unwrap();
+        db.add(third.clone(), large.clone(), large.len() as u64).unwrap();
+        let mut batch = Batch::new();
+        batch.del(second.clone());
+        batch.add(fourth.clone(), Bytes::from_static(b"fourth"), 6);
+        db.commit(batch).unwrap();
+        db.add(first.clone(), Bytes::from_static(b"first again"), 11).unwrap();
+        db.put_cursors("consumers", cursors.clone()).unwrap();
+        db.put_cursors("deleted", vec![second.clone()]).unwrap();
+        db.del_cursors("deleted").unwrap();
+    }
+    {
+        let mut db = open(dir);
+        assert_fetched(&mut db, vec![(first.clone(), 11), (fourth.clone(), 6), (third.clone(), large.len() as u64)]);
+        assert_eq!(Bytes::from_static(b"first again"), db.get(first.clone()).unwrap());
+        assert_not_found(db.get(second.clone()));
+        assert_eq!(large, db.get(third.clone()).unwrap());
+        assert_eq!(large, read_in_chunks(&mut db, &third, CHUNK_SIZE));
+        assert_eq!(Bytes::from_static(b"fourth"), db.get(fourth.clone()).unwrap());
+        assert_eq!(vec![("consumers".to_string(), cursors.clone())], db.fetch_cursors().unwrap());
+        // the database can still be written to once it is reopened
+        db.del(fourth.clone()).unwrap();
+        db.add(second.clone(), Bytes::from_static(b"second again"), 12).unwrap();
+    }
+    let mut db = open(dir);
+    assert_fetched(&mut db, vec![(first, 11), (second.clone(), 12), (third, large.len() as u64)]);
+    assert_eq!(Bytes::from_static(b"second again"), db.get(second).unwrap());
+    assert_not_found(db.get(fourth));
+    assert_eq!(vec![("consumers".to_string(), cursors)], db.fetch_cursors().unwrap());
+}
@@ -0,0 +1,70 @@
+use crate::changes::{ChangeBatch, ChangeFeed, ChangeFeedError, MAX_READ};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_CHANGES_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_CHANGES_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Gets the changes starting with `from_seq`, at most MAX_READ unless a smaller limit is given
+pub async fn handle(
+    changes_mutex: &Mutex<ChangeFeed>,
+    from_seq: u64,
+    limit_option: Option<usize>
+) -> Result<ChangeBatch, ApiError> {
+    let mut changes = match changes_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    match changes.read(from_seq, limit_option.unwrap_or(MAX_READ)) {
+        Ok(batch) => Ok(batch),
+        Err(err) => Err(api_error!(ErrTy::ChangeFeedError(err)))
+    }
+}
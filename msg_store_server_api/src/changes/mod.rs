@@ -0,0 +1,381 @@
+//! An ordered feed of every mutation of the store
+//!
+//! Every change is given a global sequence number that increases by one with each change.
+//! The most recent changes are held in a bounded ring in memory. When a log is configured,
+//! every change is also appended to it, so the feed survives restarts and changes that have
+//! left the ring can still be read. The log retains a bounded number of the most recent
+//! changes, the older entries are truncated as new changes are recorded.
+pub mod get;
+
+use bytes::Bytes;
+use msg_store_database_plugin::{DatabaseError, Log};
+use msg_store_uuid::Uuid;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::sync::Arc;
+
+/// The number of changes that are held in memory by default
+pub const DEFAULT_CAPACITY: usize = 10_000;
+/// The most changes that are returned by a single read
+pub const MAX_READ: usize = 1_000;
+/// The number of changes that are retained by the log by default
+pub const DEFAULT_LOG_RETENTION: u64 = 1_000_000;
+
+#[derive(Debug)]
+pub enum ChangeFeedErrorTy {
+    DatabaseError(DatabaseError),
+    CouldNotParseEntry,
+    CouldNotSerializeEntry
+}
+impl Display for ChangeFeedErrorTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::CouldNotParseEntry |
+            Self::CouldNotSerializeEntry => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ChangeFeedError {
+    pub err_ty: ChangeFeedErrorTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ChangeFeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "CHANGE_FEED_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "CHANGE_FEED_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! change_feed_error {
+    ($err_ty:expr) => {
+        ChangeFeedError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ChangeFeedError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+pub(crate) fn serialize_uuid<S: Serializer>(uuid: &Arc<Uuid>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&uuid.to_string())
+}
+
+pub(crate) fn deserialize_uuid<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<Uuid>, D::Error> {
+    let uuid = String::deserialize(deserializer)?;
+    match Uuid::from_string(&uuid) {
+        Ok(uuid) => Ok(uuid),
+        Err(error) => Err(serde::de::Error::custom(error))
+    }
+}
+
+/// A mutation of the store
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Change {
+    /// A msg was added
+    Insert {
+        #[serde(serialize_with = "serialize_uuid", deserialize_with = "deserialize_uuid")]
+        uuid: Arc<Uuid>,
+        #[serde(rename = "byteSize")]
+        byte_size: u64
+    },
+    /// A msg was deleted on request or acknowledged by every consumer group
+    Delete {
+        #[serde(serialize_with = "serialize_uuid", deserialize_with = "deserialize_uuid")]
+        uuid: Arc<Uuid>
+    },
+    /// A msg was removed to make room for other msgs
+    Prune {
+        #[serde(serialize_with = "serialize_uuid", deserialize_with = "deserialize_uuid")]
+        uuid: Arc<Uuid>
+    },
+    /// The defaults of a priority group were set, or removed when max_byte_size is None
+    GroupDefaults {
+        priority: u16,
+        #[serde(rename = "maxByteSize")]
+        max_byte_size: Option<u64>
+    },
+    /// The store defaults were set
+    StoreDefaults {
+        #[serde(rename = "maxByteSize")]
+        max_byte_size: Option<u64>
+    }
+}
+
+/// A change and its sequence number
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChangeEntry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub change: Change
+}
+
+/// The changes returned by a read
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeBatch {
+    /// The oldest sequence number that can still be read, changes before it have been dropped
+    pub first_seq: u64,
+    /// The sequence number to read from next
+    pub next_seq: u64,
+    pub changes: Vec<ChangeEntry>
+}
+
+/// Records changes and reads them back by sequence number
+pub struct ChangeFeed {
+    next_seq: u64,
+    capacity: usize,
+    entries: VecDeque<ChangeEntry>,
+    log: Option<Box<dyn Log>>,
+    /// The number of changes the log retains
+    log_retention: u64,
+    /// The sequence number of the oldest entry of the log
+    log_first_seq: u64
+}
+impl ChangeFeed {
+    /// Creates a feed that is only held in memory and starts at sequence number 1
+    pub fn new(capacity: usize) -> ChangeFeed {
+        ChangeFeed {
+            next_seq: 1,
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            log: None,
+            log_retention: 0,
+            log_first_seq: 1
+        }
+    }
+    /// Creates a feed that is appended to the log, continuing after the last entry of the log
+    ///
+    /// The log retains at least the `log_retention` most recent changes, which must be one or
+    /// more so that the last sequence number survives restarts.
+    pub fn with_log(capacity: usize, mut log: Box<dyn Log>, log_retention: u64) -> Result<ChangeFeed, ChangeFeedError> {
+        let last_seq = match log.last_seq() {
+            Ok(last_seq) => Ok(last_seq),
+            Err(error) => Err(change_feed_error!(ChangeFeedErrorTy::DatabaseError(error)))
+        }?;
+        let mut change_feed = ChangeFeed::new(capacity);
+        change_feed.next_seq = last_seq.unwrap_or(0) + 1;
+        change_feed.log = Some(log);
+        change_feed.log_retention = log_retention.max(1);
+        change_feed.log_first_seq = change_feed.first_seq()?;
+        Ok(change_feed)
+    }
+    /// The sequence number the next change will get
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+    /// The oldest sequence number that can still be read
+    pub fn first_seq(&mut self) -> Result<u64, ChangeFeedError> {
+        if self.log.is_some() {
+            let entries = self.read_log(0, 1)?;
+            return Ok(entries.first().map_or(self.next_seq, |entry| entry.seq));
+        }
+        Ok(self.entries.front().map_or(self.next_seq, |entry| entry.seq))
+    }
+    /// Records a change and returns its sequence number
+    pub fn record(&mut self, change: Change) -> Result<u64, ChangeFeedError> {
+        let entry = ChangeEntry {
+            seq: self.next_seq,
+            change
+        };
+        if let Some(log) = self.log.as_mut() {
+            let bytes = match serde_json::to_vec(&entry) {
+                Ok(bytes) => Ok(bytes),
+                Err(error) => Err(change_feed_error!(ChangeFeedErrorTy::CouldNotSerializeEntry, error))
+            }?;
+            if let Err(error) = log.append(entry.seq, Bytes::from(bytes)) {
+                return Err(change_feed_error!(ChangeFeedErrorTy::DatabaseError(error)));
+            }
+            self.truncate_log(entry.seq)?;
+        }
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+        self.next_seq += 1;
+        Ok(self.next_seq - 1)
+    }
+    /// Records every change in order
+    pub fn record_all(&mut self, changes: Vec<Change>) -> Result<(), ChangeFeedError> {
+        for change in changes {
+            self.record(change)?;
+        }
+        Ok(())
+    }
+    /// Truncates the log to the retained changes once it holds a tenth more than it retains
+    ///
+    /// Truncating in steps keeps the log from being rewritten with every change.
+    fn truncate_log(&mut self, last_seq: u64) -> Result<(), ChangeFeedError> {
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return Ok(())
+        };
+        let slack = (self.log_retention / 10).max(1);
+        if last_seq + 1 - self.log_first_seq <= self.log_retention + slack {
+            return Ok(());
+        }
+        let first_seq = last_seq + 1 - self.log_retention;
+        if let Err(error) = log.truncate_before(first_seq) {
+            return Err(change_feed_error!(ChangeFeedErrorTy::DatabaseError(error)));
+        }
+        self.log_first_seq = first_seq;
+        Ok(())
+    }
+    fn read_log(&mut self, from_seq: u64, limit: usize) -> Result<Vec<ChangeEntry>, ChangeFeedError> {
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return Ok(vec![])
+        };
+        let entries = match log.read(from_seq, limit) {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(change_feed_error!(ChangeFeedErrorTy::DatabaseError(error)))
+        }?;
+        entries.iter().map(|(_seq, bytes)| {
+            match serde_json::from_slice(bytes) {
+                Ok(entry) => Ok(entry),
+                Err(error) => Err(change_feed_error!(ChangeFeedErrorTy::CouldNotParseEntry, error))
+            }
+        }).collect::<Result<Vec<ChangeEntry>, ChangeFeedError>>()
+    }
+    /// Reads up to `limit` changes, starting with the change numbered `from_seq`
+    ///
+    /// The changes come from memory when they are still in the ring, otherwise from the log.
+    /// If `from_seq` is older than the first sequence number, the read starts at the first
+    /// sequence number instead, which the caller can detect by comparing the two.
+    pub fn read(&mut self, from_seq: u64, limit: usize) -> Result<ChangeBatch, ChangeFeedError> {
+        let limit = limit.min(MAX_READ);
+        let in_ring = match self.entries.front() {
+            Some(entry) => from_seq >= entry.seq,
+            None => from_seq >= self.next_seq
+        };
+        let changes = if in_ring || self.log.is_none() {
+            let skip = match self.entries.front() {
+                Some(entry) if from_seq > entry.seq => (from_seq - entry.seq) as usize,
+                _ => 0
+            };
+            self.entries.iter().skip(skip).take(limit).cloned().collect()
+        } else {
+            self.read_log(from_seq, limit)?
+        };
+        let first_seq = self.first_seq()?;
+        let next_seq = match changes.last() {
+            Some(entry) => entry.seq + 1,
+            None => from_seq.max(first_seq).min(self.next_seq)
+        };
+        Ok(ChangeBatch {
+            first_seq,
+            next_seq,
+            changes
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, ChangeEntry, ChangeFeed, DEFAULT_LOG_RETENTION};
+    use msg_store_database_redb_plugin::RedbLog;
+    use msg_store_uuid::Uuid;
+    use tempdir::TempDir;
+
+    #[test]
+    fn should_drop_the_oldest_changes_from_the_ring() {
+        let mut change_feed = ChangeFeed::new(3);
+        for sequence in 0..5 {
+            let uuid = Uuid::from_string(&format!("1-1-{}-0", sequence)).unwrap();
+            change_feed.record(Change::Insert { uuid, byte_size: 1 }).unwrap();
+        }
+        let batch = change_feed.read(1, 10).unwrap();
+        assert_eq!(3, batch.first_seq);
+        assert_eq!(6, batch.next_seq);
+        assert_eq!(vec![3, 4, 5], batch.changes.iter().map(|entry| entry.seq).collect::<Vec<u64>>());
+
+        let batch = change_feed.read(4, 1).unwrap();
+        assert_eq!(vec![4], batch.changes.iter().map(|entry| entry.seq).collect::<Vec<u64>>());
+        assert_eq!(5, batch.next_seq);
+
+        let batch = change_feed.read(6, 10).unwrap();
+        assert!(batch.changes.is_empty());
+        assert_eq!(6, batch.next_seq);
+    }
+
+    #[test]
+    fn should_read_changes_from_the_log_after_a_restart() {
+        let tmp_dir = TempDir::new("should_read_changes_from_the_log_after_a_restart").unwrap();
+        let uuid = Uuid::from_string("1-1-1-0").unwrap();
+        {
+            let log = RedbLog::new(tmp_dir.path()).unwrap();
+            let mut change_feed = ChangeFeed::with_log(1, Box::new(log), DEFAULT_LOG_RETENTION).unwrap();
+            change_feed.record(Change::Insert { uuid: uuid.clone(), byte_size: 3 }).unwrap();
+            change_feed.record(Change::GroupDefaults { priority: 1, max_byte_size: Some(10) }).unwrap();
+            change_feed.record(Change::Delete { uuid: uuid.clone() }).unwrap();
+        }
+        let log = RedbLog::new(tmp_dir.path()).unwrap();
+        let mut change_feed = ChangeFeed::with_log(1, Box::new(log), DEFAULT_LOG_RETENTION).unwrap();
+        assert_eq!(4, change_feed.next_seq());
+        let batch = change_feed.read(0, 10).unwrap();
+        assert_eq!(1, batch.first_seq);
+        assert_eq!(4, batch.next_seq);
+        assert_eq!(vec![
+            ChangeEntry { seq: 1, change: Change::Insert { uuid: uuid.clone(), byte_size: 3 } },
+            ChangeEntry { seq: 2, change: Change::GroupDefaults { priority: 1, max_byte_size: Some(10) } },
+            ChangeEntry { seq: 3, change: Change::Delete { uuid } }
+        ], batch.changes);
+    }
+
+    #[test]
+    fn should_truncate_the_log_to_the_retained_changes() {
+        let tmp_dir = TempDir::new("should_truncate_the_log_to_the_retained_changes").unwrap();
+        {
+            let log = RedbLog::new(tmp_dir.path()).unwrap();
+            let mut change_feed = ChangeFeed::with_log(0, Box::new(log), 10).unwrap();
+            for sequence in 1..=11 {
+                let uuid = Uuid::from_string(&format!("1-1-{}-0", sequence)).unwrap();
+                change_feed.record(Change::Insert { uuid, byte_size: 1 }).unwrap();
+            }
+            // the log is let to grow by a tenth before it is truncated
+            assert_eq!(1, change_feed.read(0, 100).unwrap().first_seq);
+            let uuid = Uuid::from_string("1-1-12-0").unwrap();
+            change_feed.record(Change::Insert { uuid, byte_size: 1 }).unwrap();
+            assert_eq!(3, change_feed.read(0, 100).unwrap().first_seq);
+        }
+        let log = RedbLog::new(tmp_dir.path()).unwrap();
+        let mut change_feed = ChangeFeed::with_log(0, Box::new(log), 10).unwrap();
+        assert_eq!(13, change_feed.next_seq());
+        let batch = change_feed.read(0, 100).unwrap();
+        assert_eq!(3, batch.first_seq);
+        assert_eq!((3..=12).collect::<Vec<u64>>(), batch.changes.iter().map(|entry| entry.seq).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn should_serialize_changes_as_flat_json() {
+        let entry = ChangeEntry {
+            seq: 7,
+            change: Change::Insert { uuid: Uuid::from_string("1-2-3-0").unwrap(), byte_size: 3 }
+        };
+        assert_eq!(
+            r#"{"seq":7,"type":"insert","uuid":"1-2-3-0","byteSize":3}"#,
+            serde_json::to_string(&entry).unwrap()
+        );
+    }
+}
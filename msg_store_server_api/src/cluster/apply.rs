@@ -0,0 +1,179 @@
+use crate::Database;
+use crate::changes::ChangeFeed;
+use crate::cluster::Command;
+use crate::config::StoreConfig;
+use crate::file_storage::FileStorage;
+use crate::group_defaults;
+use crate::msg::add::{Chunky, MsgError};
+use crate::notify::Notifier;
+use crate::replication::apply::{self, ErrTy as ReplicaErrTy};
+use crate::stats::Stats;
+use crate::store;
+use bytes::Bytes;
+use msg_store::{Store, StoreErrorTy};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ApplyError(apply::ApiError),
+    RmGroupDefaultsError(group_defaults::rm::ApiError),
+    SetGroupDefaultsError(group_defaults::set::ApiError),
+    SetStoreDefaultsError(store::set::ApiError)
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ApplyError(err) => write!(f, "({})", err),
+            Self::RmGroupDefaultsError(err) => write!(f, "({})", err),
+            Self::SetGroupDefaultsError(err) => write!(f, "({})", err),
+            Self::SetStoreDefaultsError(err) => write!(f, "({})", err)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "APPLY_COMMAND_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "APPLY_COMMAND_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Commands only add msgs that are held in the database, no file is ever read
+struct NoFile;
+impl futures::Stream for NoFile {
+    type Item = Result<Bytes, &'static str>;
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(None)
+    }
+}
+impl Chunky for NoFile { }
+
+/// Applies a committed command to the store
+///
+/// Every node applies the same commands in the same order, so a msg the store refuses is
+/// refused by every node. The refusal is returned so it can be reported to the client that
+/// proposed the command.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    notifier_mutex: &Mutex<Notifier>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    configuration_mutex: &Mutex<StoreConfig>,
+    configuration_path_option: &Option<PathBuf>,
+    command: Command
+) -> Result<Option<MsgError>, ApiError> {
+    match command {
+        Command::Add { uuid, msg } => {
+            let byte_size = msg.len() as u64;
+            let result = apply::insert(
+                store_mutex,
+                database,
+                file_storage_option,
+                stats_mutex,
+                notifier_mutex,
+                changes_mutex,
+                uuid,
+                byte_size,
+                msg,
+                None::<NoFile>).await;
+            match result {
+                Ok(()) => Ok(None),
+                Err(err) => match &err.err_ty {
+                    ReplicaErrTy::StoreError(store_error) => match store_error.err_ty {
+                        StoreErrorTy::ExceedesStoreMax => Ok(Some(MsgError::MsgExceedesStoreMax)),
+                        StoreErrorTy::ExceedesGroupMax => Ok(Some(MsgError::MsgExceedesGroupMax)),
+                        StoreErrorTy::LacksPriority => Ok(Some(MsgError::MsgLacksPriority)),
+                        _ => Err(api_error!(ErrTy::ApplyError(err)))
+                    },
+                    _ => Err(api_error!(ErrTy::ApplyError(err)))
+                }
+            }
+        },
+        Command::Delete { uuid } => {
+            match apply::remove(store_mutex, database, file_storage_option, stats_mutex, changes_mutex, uuid, false).await {
+                Ok(()) => Ok(None),
+                Err(err) => Err(api_error!(ErrTy::ApplyError(err)))
+            }
+        },
+        Command::GroupDefaults { priority, max_byte_size: Some(max_byte_size) } => {
+            let result = group_defaults::set::handle(
+                store_mutex,
+                database,
+                file_storage_option,
+                stats_mutex,
+                changes_mutex,
+                configuration_mutex,
+                configuration_path_option,
+                priority,
+                Some(max_byte_size)).await;
+            match result {
+                Ok(()) => Ok(None),
+                Err(err) => Err(api_error!(ErrTy::SetGroupDefaultsError(err)))
+            }
+        },
+        Command::GroupDefaults { priority, max_byte_size: None } => {
+            let result = group_defaults::rm::handle(
+                store_mutex,
+                changes_mutex,
+                configuration_mutex,
+                configuration_path_option,
+                priority).await;
+            match result {
+                Ok(()) => Ok(None),
+                Err(err) => Err(api_error!(ErrTy::RmGroupDefaultsError(err)))
+            }
+        },
+        Command::StoreDefaults { max_byte_size } => {
+            let result = store::set::handle(
+                store_mutex,
+                database,
+                file_storage_option,
+                stats_mutex,
+                changes_mutex,
+                configuration_mutex,
+                configuration_path_option,
+                max_byte_size).await;
+            match result {
+                Ok(()) => Ok(None),
+                Err(err) => Err(api_error!(ErrTy::SetStoreDefaultsError(err)))
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::cluster::Cluster;
+use crate::cluster::raft::{NodeId, Role};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_CLUSTER_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_CLUSTER_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Member {
+    pub node_id: NodeId,
+    pub address: String
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterStats {
+    pub node_id: NodeId,
+    /// follower, candidate or leader
+    pub role: String,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub leader_address: Option<String>,
+    pub commit_index: u64,
+    pub last_index: u64,
+    pub members: Vec<Member>
+}
+
+pub async fn handle(cluster_mutex: &Mutex<Cluster>) -> Result<ClusterStats, ApiError> {
+    let cluster = match cluster_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    let role = match cluster.raft.role {
+        Role::Follower => "follower",
+        Role::Candidate => "candidate",
+        Role::Leader => "leader"
+    };
+    Ok(ClusterStats {
+        node_id: cluster.raft.id,
+        role: role.to_string(),
+        term: cluster.raft.term,
+        leader: cluster.raft.leader,
+        leader_address: cluster.leader_address(),
+        commit_index: cluster.raft.commit_index,
+        last_index: cluster.raft.last_index(),
+        members: cluster.members.iter().map(|(node_id, address)| Member { node_id: *node_id, address: address.clone() }).collect()
+    })
+}
@@ -0,0 +1,258 @@
+//! Replicates the mutations of the store across a cluster of nodes through a raft log
+//!
+//! Adds, deletes, and changes to the group and store defaults are proposed to the leader as
+//! commands. Once a command is committed, every node applies it to its store, in the same
+//! order, so every node holds the same msgs under the same uuids.
+pub mod apply;
+pub mod get;
+pub mod raft;
+pub mod snapshot;
+pub mod storage;
+
+use bytes::Bytes;
+use crate::changes::{deserialize_uuid, serialize_uuid};
+use crate::msg::add::MsgError;
+use futures::channel::oneshot;
+use msg_store_uuid::Uuid;
+use raft::{Entry, NodeId, Raft};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use storage::{Storage, StorageError};
+
+/// A mutation of the store held in the raft log
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Command {
+    /// Adds a msg under the uuid given by the leader
+    Add {
+        #[serde(serialize_with = "serialize_uuid", deserialize_with = "deserialize_uuid")]
+        uuid: Arc<Uuid>,
+        #[serde(serialize_with = "serialize_msg", deserialize_with = "deserialize_msg")]
+        msg: Bytes
+    },
+    Delete {
+        #[serde(serialize_with = "serialize_uuid", deserialize_with = "deserialize_uuid")]
+        uuid: Arc<Uuid>
+    },
+    /// Sets the defaults of a priority group, None removes them
+    GroupDefaults {
+        priority: u16,
+        #[serde(rename = "maxByteSize")]
+        max_byte_size: Option<u64>
+    },
+    StoreDefaults {
+        #[serde(rename = "maxByteSize")]
+        max_byte_size: Option<u64>
+    }
+}
+
+/// The result of a proposed command
+#[derive(Debug)]
+pub enum Outcome {
+    /// The command was committed and applied, with the refusal of the store if any
+    Applied(Option<MsgError>),
+    /// A new leader replaced the entry before it was committed, the command was not applied
+    Lost
+}
+
+/// The raft node of this server and the clients waiting on their proposals
+pub struct Cluster {
+    pub raft: Raft<Command>,
+    /// The address of every member by node id, including this node
+    pub members: BTreeMap<NodeId, String>,
+    /// The term and sender of each proposal, by the index of its entry
+    waiters: BTreeMap<u64, (u64, oneshot::Sender<Outcome>)>
+}
+impl Cluster {
+    /// Creates the node from what it saved to its storage before it was stopped
+    pub fn new(id: NodeId, members: BTreeMap<NodeId, String>, storage: Box<dyn Storage<Command>>) -> Result<Cluster, StorageError> {
+        let peers = members.keys().filter(|member| **member != id).cloned().collect();
+        Ok(Cluster {
+            raft: Raft::new(id, peers, storage)?,
+            members,
+            waiters: BTreeMap::new()
+        })
+    }
+    pub fn leader_address(&self) -> Option<String> {
+        match self.raft.leader {
+            Some(leader) => self.members.get(&leader).cloned(),
+            None => None
+        }
+    }
+    /// Proposes a command, the receiver resolves once the entry of the command is applied
+    ///
+    /// Returns the address of the leader, if known, when this node is not the leader.
+    pub fn propose(&mut self, command: Command) -> Result<Result<oneshot::Receiver<Outcome>, Option<String>>, StorageError> {
+        match self.raft.propose(command)? {
+            Ok((term, index)) => {
+                let (sender, receiver) = oneshot::channel();
+                self.waiters.insert(index, (term, sender));
+                Ok(Ok(receiver))
+            },
+            Err(_leader) => Ok(Err(self.leader_address()))
+        }
+    }
+    /// Resolves the proposal waiting on an entry once it is applied
+    pub fn resolve(&mut self, entry: &Entry<Command>, refusal: Option<MsgError>) {
+        if let Some((term, sender)) = self.waiters.remove(&entry.index) {
+            let outcome = if term == entry.term {
+                Outcome::Applied(refusal)
+            } else {
+                Outcome::Lost
+            };
+            // the client may have stopped waiting
+            let _ = sender.send(outcome);
+        }
+    }
+}
+
+fn serialize_msg<S: Serializer>(msg: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(msg))
+}
+
+fn deserialize_msg<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    match base64::decode(encoded) {
+        Ok(msg) => Ok(Bytes::from(msg)),
+        Err(err) => Err(D::Error::custom(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::cluster::{Cluster, Command, Outcome};
+    use crate::cluster::apply::handle;
+    use crate::cluster::raft::{Envelope, Message, Entry};
+    use crate::cluster::snapshot;
+    use crate::cluster::storage::MemStorage;
+    use crate::config::StoreConfig;
+    use crate::msg::add::MsgError;
+    use crate::notify::Notifier;
+    use crate::stats::Stats;
+    use crate::Database;
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use msg_store::Store;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::BlockingDb;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn should_send_commands_as_json() {
+        let uuid = msg_store_uuid::Uuid::from_string("1-1640000000-1-2").unwrap();
+        let envelope = Envelope {
+            from: 2,
+            to: 1,
+            message: Message::AppendEntries {
+                term: 3,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries: vec![Entry { term: 3, index: 1, command: Some(Command::Add { uuid, msg: Bytes::from_static(b"\x00hello?") }) }],
+                leader_commit: 0
+            }
+        };
+        let json = serde_json::to_string(&envelope).unwrap();
+        assert!(json.contains(r#""command":{"type":"add","uuid":"1-1640000000-1-2","msg":"AGhlbGxvPw=="}"#));
+        assert_eq!(envelope, serde_json::from_str::<Envelope<Command>>(&json).unwrap());
+    }
+
+    #[test]
+    fn should_apply_commands_to_the_store() {
+        let store_mx = Mutex::new(Store::new(Some(1), None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let config_mx = Mutex::new(StoreConfig::new());
+        let apply = |command: Command| block_on(handle(&store_mx, &database, &None, &stats_mx, &notifier_mx, &changes_mx, &config_mx, &None, command));
+        let first = msg_store_uuid::Uuid::from_string("1-1640000000-1-2").unwrap();
+        let second = msg_store_uuid::Uuid::from_string("1-1640000000-2-2").unwrap();
+        apply(Command::GroupDefaults { priority: 1, max_byte_size: Some(8) }).unwrap();
+        apply(Command::Add { uuid: first.clone(), msg: Bytes::from_static(b"hello") }).unwrap();
+        // pruning is part of applying, so it happens the same way on every node
+        apply(Command::Add { uuid: second.clone(), msg: Bytes::from_static(b"world") }).unwrap();
+        let refused = apply(Command::Add { uuid: msg_store_uuid::Uuid::from_string("1-1640000000-3-2").unwrap(), msg: Bytes::from_static(b"too long!") });
+        assert!(matches!(refused, Ok(Some(MsgError::MsgExceedesGroupMax))));
+        {
+            let store = store_mx.lock().unwrap();
+            assert_eq!(Some(&1), store.id_to_group_map.get(&second));
+            assert_eq!(None, store.id_to_group_map.get(&first));
+            assert_eq!(Some(8), store.group_defaults.get(&1).unwrap().max_byte_size);
+        }
+        assert_eq!(Some(Bytes::from_static(b"world")), block_on(database.get(second.clone())).ok());
+        apply(Command::Delete { uuid: second.clone() }).unwrap();
+        apply(Command::StoreDefaults { max_byte_size: Some(100) }).unwrap();
+        let store = store_mx.lock().unwrap();
+        assert_eq!(0, store.id_to_group_map.len());
+        assert_eq!(Some(100), store.max_byte_size);
+    }
+
+    #[test]
+    fn should_resolve_proposals_once_applied() {
+        let members = BTreeMap::from([(1, "127.0.0.1:8080".to_string())]);
+        let mut cluster = Cluster::new(1, members, Box::new(MemStorage::new())).unwrap();
+        // a single node elects itself
+        while !cluster.raft.is_leader() {
+            cluster.raft.tick().unwrap();
+        }
+        assert_eq!(Some("127.0.0.1:8080".to_string()), cluster.leader_address());
+        let mut receiver = cluster.propose(Command::StoreDefaults { max_byte_size: Some(100) }).unwrap().unwrap();
+        let committed = cluster.raft.take_committed();
+        assert_eq!(2, committed.len());
+        assert!(committed[0].command.is_none());
+        assert!(matches!(receiver.try_recv(), Ok(None)));
+        for entry in committed.iter() {
+            cluster.resolve(entry, None);
+        }
+        assert!(matches!(receiver.try_recv(), Ok(Some(Outcome::Applied(None)))));
+        // an entry of a later term was committed in place of the proposal
+        let mut receiver = cluster.propose(Command::StoreDefaults { max_byte_size: None }).unwrap().unwrap();
+        let mut entry = cluster.raft.take_committed().remove(0);
+        entry.term += 1;
+        cluster.resolve(&entry, None);
+        assert!(matches!(receiver.try_recv(), Ok(Some(Outcome::Lost))));
+    }
+
+    #[test]
+    fn should_install_a_snapshot_of_the_store() {
+        let stores = || (Mutex::new(Store::new(Some(1), None).unwrap()), Box::new(BlockingDb::new(Box::new(MemDb::new()))) as Database);
+        let (taken_store_mx, taken_database) = stores();
+        let (store_mx, database) = stores();
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let config_mx = Mutex::new(StoreConfig::new());
+        let first = msg_store_uuid::Uuid::from_string("1-1640000000-1-2").unwrap();
+        let second = msg_store_uuid::Uuid::from_string("2-1640000000-2-2").unwrap();
+        for command in [
+            Command::StoreDefaults { max_byte_size: Some(100) },
+            Command::GroupDefaults { priority: 1, max_byte_size: Some(8) },
+            Command::Add { uuid: first.clone(), msg: Bytes::from_static(b"hello") },
+            Command::Add { uuid: second.clone(), msg: Bytes::from_static(b"world!") }
+        ] {
+            block_on(handle(&taken_store_mx, &taken_database, &None, &stats_mx, &notifier_mx, &changes_mx, &config_mx, &None, command)).unwrap();
+        }
+        // the store that installs the snapshot holds state the snapshot does not
+        let stale = msg_store_uuid::Uuid::from_string("3-1640000000-3-2").unwrap();
+        for command in [
+            Command::GroupDefaults { priority: 3, max_byte_size: Some(4) },
+            Command::Add { uuid: stale.clone(), msg: Bytes::from_static(b"old") }
+        ] {
+            block_on(handle(&store_mx, &database, &None, &stats_mx, &notifier_mx, &changes_mx, &config_mx, &None, command)).unwrap();
+        }
+        let data = block_on(snapshot::take(&taken_store_mx, &taken_database)).unwrap();
+        block_on(snapshot::install(&store_mx, &database, &None, &stats_mx, &notifier_mx, &changes_mx, &config_mx, &None, &data)).unwrap();
+        {
+            let store = store_mx.lock().unwrap();
+            assert_eq!(Some(100), store.max_byte_size);
+            assert_eq!(vec![1], store.group_defaults.keys().cloned().collect::<Vec<u16>>());
+            assert_eq!(vec![first.clone(), second.clone()], store.id_to_group_map.keys().cloned().collect::<Vec<Arc<msg_store_uuid::Uuid>>>());
+        }
+        assert_eq!(Some(Bytes::from_static(b"world!")), block_on(database.get(second)).ok());
+        assert!(block_on(database.get(stale)).is_err());
+    }
+}
@@ -0,0 +1,713 @@
+//! The Raft consensus algorithm, without any io
+//!
+//! A node is driven by calling tick at a fixed interval and step with every message it
+//! receives. The messages to send and the entries that were committed are taken from the
+//! node afterwards, so the transport and the state machine can be anything, e.g. http
+//! requests between processes or a vec of messages between nodes in a test. The term, the
+//! vote and the log are saved through a storage before a message is answered.
+//!
+//! Once enough entries are applied, the state machine hands the node a snapshot of its state
+//! and the entries the snapshot holds are dropped. A follower that is behind the snapshot is
+//! sent the snapshot in place of the entries.
+use bytes::Bytes;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use super::{deserialize_msg, serialize_msg};
+use super::storage::{HardState, Storage, StorageError, Stored};
+
+pub type NodeId = u16;
+
+/// The ticks without a heartbeat before a follower starts an election, randomized up to double
+pub const ELECTION_TICKS: u32 = 10;
+/// The ticks between the heartbeats of a leader
+pub const HEARTBEAT_TICKS: u32 = 2;
+/// The most entries sent in one message
+pub const MAX_ENTRIES: usize = 64;
+/// The applied entries held in the log before they are compacted into a snapshot
+pub const SNAPSHOT_ENTRIES: u64 = 1_000;
+
+/// An entry of the replicated log, a leader appends an entry without a command when it is elected
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Entry<C> {
+    pub term: u64,
+    pub index: u64,
+    pub command: Option<C>
+}
+
+/// The state of the state machine once every entry up to and including index is applied
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub index: u64,
+    pub term: u64,
+    #[serde(serialize_with = "serialize_msg", deserialize_with = "deserialize_msg")]
+    pub data: Bytes
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Message<C> {
+    RequestVote {
+        term: u64,
+        #[serde(rename = "lastLogIndex")]
+        last_log_index: u64,
+        #[serde(rename = "lastLogTerm")]
+        last_log_term: u64
+    },
+    Vote {
+        term: u64,
+        granted: bool
+    },
+    AppendEntries {
+        term: u64,
+        #[serde(rename = "prevLogIndex")]
+        prev_log_index: u64,
+        #[serde(rename = "prevLogTerm")]
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        #[serde(rename = "leaderCommit")]
+        leader_commit: u64
+    },
+    AppendResponse {
+        term: u64,
+        success: bool,
+        /// The last index known to match the leader on success, a hint where to retry from otherwise
+        #[serde(rename = "matchIndex")]
+        match_index: u64
+    },
+    /// Sent in place of entries that were compacted, answered with an AppendResponse
+    InstallSnapshot {
+        term: u64,
+        snapshot: Snapshot
+    }
+}
+impl<C> Message<C> {
+    pub fn term(&self) -> u64 {
+        match self {
+            Self::RequestVote { term, .. } |
+            Self::Vote { term, .. } |
+            Self::AppendEntries { term, .. } |
+            Self::AppendResponse { term, .. } |
+            Self::InstallSnapshot { term, .. } => *term
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Envelope<C> {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub message: Message<C>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader
+}
+
+pub struct Raft<C> {
+    pub id: NodeId,
+    /// Every other member of the cluster
+    pub peers: Vec<NodeId>,
+    pub term: u64,
+    pub role: Role,
+    /// The leader of the current term, if known
+    pub leader: Option<NodeId>,
+    pub commit_index: u64,
+    voted_for: Option<NodeId>,
+    /// The entry with index i is held at i - snapshot.index - 1
+    log: Vec<Entry<C>>,
+    /// The latest snapshot, it takes the place of the entries up to and including its index
+    snapshot: Snapshot,
+    /// A snapshot the state machine is to be reset to before the committed entries are applied
+    pending_snapshot: Option<Snapshot>,
+    last_applied: u64,
+    votes: BTreeSet<NodeId>,
+    next_index: BTreeMap<NodeId, u64>,
+    match_index: BTreeMap<NodeId, u64>,
+    election_elapsed: u32,
+    election_timeout: u32,
+    heartbeat_elapsed: u32,
+    outbox: Vec<Envelope<C>>,
+    storage: Box<dyn Storage<C>>
+}
+
+impl<C: Clone> Raft<C> {
+    /// Creates a node from what it saved before it was stopped
+    ///
+    /// A node with a snapshot hands it to the state machine before any entry is applied.
+    pub fn new(id: NodeId, peers: Vec<NodeId>, mut storage: Box<dyn Storage<C>>) -> Result<Raft<C>, StorageError> {
+        let Stored { state, snapshot, entries } = storage.load()?;
+        let pending_snapshot = if snapshot.index > 0 {
+            Some(snapshot.clone())
+        } else {
+            None
+        };
+        Ok(Raft {
+            id,
+            peers,
+            term: state.term,
+            role: Role::Follower,
+            leader: None,
+            commit_index: snapshot.index,
+            voted_for: state.voted_for,
+            log: entries,
+            last_applied: snapshot.index,
+            snapshot,
+            pending_snapshot,
+            votes: BTreeSet::new(),
+            next_index: BTreeMap::new(),
+            match_index: BTreeMap::new(),
+            election_elapsed: 0,
+            election_timeout: random_election_timeout(),
+            heartbeat_elapsed: 0,
+            outbox: vec![],
+            storage
+        })
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index + self.log.len() as u64
+    }
+
+    /// The index of the latest snapshot
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot.index
+    }
+
+    fn last_term(&self) -> u64 {
+        self.term_at(self.last_index())
+    }
+
+    /// The term of the entry at index, 0 for the index before the first entry and for
+    /// entries that were compacted
+    fn term_at(&self, index: u64) -> u64 {
+        if index == self.snapshot.index {
+            return self.snapshot.term;
+        }
+        if index < self.snapshot.index {
+            return 0;
+        }
+        match self.log.get((index - self.snapshot.index) as usize - 1) {
+            Some(entry) => entry.term,
+            None => 0
+        }
+    }
+
+    fn save_state(&mut self) -> Result<(), StorageError> {
+        let state = HardState { term: self.term, voted_for: self.voted_for };
+        self.storage.save_state(&state)
+    }
+
+    fn quorum(&self) -> usize {
+        let members = self.peers.len() + 1;
+        members / 2 + 1
+    }
+
+    fn send(&mut self, to: NodeId, message: Message<C>) {
+        self.outbox.push(Envelope { from: self.id, to, message });
+    }
+
+    /// Advances the clock by one tick, starting an election or sending heartbeats when due
+    pub fn tick(&mut self) -> Result<(), StorageError> {
+        if self.is_leader() {
+            self.heartbeat_elapsed += 1;
+            if self.heartbeat_elapsed >= HEARTBEAT_TICKS {
+                self.heartbeat_elapsed = 0;
+                self.broadcast_append();
+            }
+        } else {
+            self.election_elapsed += 1;
+            if self.election_elapsed >= self.election_timeout {
+                self.campaign()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) -> Result<(), StorageError> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.save_state()?;
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.election_elapsed = 0;
+        self.election_timeout = random_election_timeout();
+        Ok(())
+    }
+
+    fn campaign(&mut self) -> Result<(), StorageError> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(self.id);
+        self.save_state()?;
+        self.votes = BTreeSet::new();
+        self.votes.insert(self.id);
+        self.election_elapsed = 0;
+        self.election_timeout = random_election_timeout();
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+        let (term, last_log_index, last_log_term) = (self.term, self.last_index(), self.last_term());
+        for peer in self.peers.clone() {
+            self.send(peer, Message::RequestVote { term, last_log_index, last_log_term });
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<(), StorageError> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.heartbeat_elapsed = 0;
+        let next_index = self.last_index() + 1;
+        for peer in self.peers.iter() {
+            self.next_index.insert(*peer, next_index);
+            self.match_index.insert(*peer, 0);
+        }
+        // entries of earlier terms are only committed along with an entry of the current term
+        self.append(None)?;
+        self.broadcast_append();
+        Ok(())
+    }
+
+    fn append(&mut self, command: Option<C>) -> Result<u64, StorageError> {
+        let index = self.last_index() + 1;
+        let entry = Entry { term: self.term, index, command };
+        self.storage.save_entries(index, std::slice::from_ref(&entry))?;
+        self.log.push(entry);
+        self.maybe_commit();
+        Ok(index)
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers.clone() {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, peer: NodeId) {
+        let next_index = *self.next_index.get(&peer).unwrap_or(&1);
+        if next_index <= self.snapshot.index {
+            let (term, snapshot) = (self.term, self.snapshot.clone());
+            return self.send(peer, Message::InstallSnapshot { term, snapshot });
+        }
+        let prev_log_index = next_index - 1;
+        let prev_log_term = self.term_at(prev_log_index);
+        let entries = self.log
+            .iter()
+            .skip((prev_log_index - self.snapshot.index) as usize)
+            .take(MAX_ENTRIES)
+            .cloned()
+            .collect::<Vec<Entry<C>>>();
+        let (term, leader_commit) = (self.term, self.commit_index);
+        self.send(peer, Message::AppendEntries { term, prev_log_index, prev_log_term, entries, leader_commit });
+    }
+
+    /// Commits the highest entry of the current term held by a quorum
+    fn maybe_commit(&mut self) {
+        let mut indexes = self.match_index.values().cloned().collect::<Vec<u64>>();
+        indexes.push(self.last_index());
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        let index = indexes[self.quorum() - 1];
+        if index > self.commit_index && self.term_at(index) == self.term {
+            self.commit_index = index;
+        }
+    }
+
+    /// Appends a command to the log if this node is the leader
+    ///
+    /// Returns the term and index of the entry, the command is applied once the entry is
+    /// committed. If another entry is committed at that index, the command was lost to a new
+    /// leader. Returns the leader, if known, when this node is not the leader.
+    pub fn propose(&mut self, command: C) -> Result<Result<(u64, u64), Option<NodeId>>, StorageError> {
+        if !self.is_leader() {
+            return Ok(Err(self.leader));
+        }
+        let index = self.append(Some(command))?;
+        self.broadcast_append();
+        Ok(Ok((self.term, index)))
+    }
+
+    /// Handles a message from another node
+    ///
+    /// The node is not to be used after an error, what it holds may not match its storage.
+    pub fn step(&mut self, envelope: Envelope<C>) -> Result<(), StorageError> {
+        let Envelope { from, message, .. } = envelope;
+        if message.term() > self.term {
+            let leader = match message {
+                Message::AppendEntries { .. } | Message::InstallSnapshot { .. } => Some(from),
+                _ => None
+            };
+            self.become_follower(message.term(), leader)?;
+        }
+        match message {
+            Message::RequestVote { term, last_log_index, last_log_term } => {
+                let up_to_date = (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let granted = term == self.term
+                    && up_to_date
+                    && (self.voted_for.is_none() || self.voted_for == Some(from));
+                if granted {
+                    self.voted_for = Some(from);
+                    self.save_state()?;
+                    self.election_elapsed = 0;
+                }
+                let term = self.term;
+                self.send(from, Message::Vote { term, granted });
+            },
+            Message::Vote { term, granted } => {
+                if self.role == Role::Candidate && term == self.term && granted {
+                    self.votes.insert(from);
+                    if self.votes.len() >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+            },
+            Message::AppendEntries { term, prev_log_index, prev_log_term, entries, leader_commit } => {
+                if term < self.term {
+                    let term = self.term;
+                    self.send(from, Message::AppendResponse { term, success: false, match_index: 0 });
+                    return Ok(());
+                }
+                self.become_follower(term, Some(from))?;
+                let match_index = prev_log_index + entries.len() as u64;
+                // the entries up to the snapshot are committed, so they match the leader's
+                let (prev_log_index, prev_log_term) = if prev_log_index < self.snapshot.index {
+                    (self.snapshot.index, self.snapshot.term)
+                } else {
+                    (prev_log_index, prev_log_term)
+                };
+                if prev_log_index > self.last_index() || self.term_at(prev_log_index) != prev_log_term {
+                    let hint = prev_log_index.saturating_sub(1).min(self.last_index());
+                    self.send(from, Message::AppendResponse { term, success: false, match_index: hint });
+                    return Ok(());
+                }
+                let mut appended = vec![];
+                for entry in entries {
+                    let held = entry.index <= prev_log_index
+                        || (entry.index <= self.last_index() && self.term_at(entry.index) == entry.term);
+                    if appended.is_empty() && held {
+                        continue;
+                    }
+                    appended.push(entry);
+                }
+                // a conflicting entry was never committed, it is replaced by the leader's
+                if let Some(first) = appended.first() {
+                    self.storage.save_entries(first.index, &appended)?;
+                    self.log.truncate((first.index - self.snapshot.index) as usize - 1);
+                    self.log.append(&mut appended);
+                }
+                if leader_commit > self.commit_index {
+                    self.commit_index = leader_commit.min(match_index).max(self.commit_index);
+                }
+                self.send(from, Message::AppendResponse { term, success: true, match_index });
+            },
+            Message::InstallSnapshot { term, snapshot } => {
+                if term < self.term {
+                    let term = self.term;
+                    self.send(from, Message::AppendResponse { term, success: false, match_index: 0 });
+                    return Ok(());
+                }
+                self.become_follower(term, Some(from))?;
+                let match_index = snapshot.index;
+                if snapshot.index > self.commit_index {
+                    self.install(snapshot)?;
+                }
+                self.send(from, Message::AppendResponse { term, success: true, match_index });
+            },
+            Message::AppendResponse { term, success, match_index } => {
+                if !self.is_leader() || term != self.term {
+                    return Ok(());
+                }
+                if success {
+                    if match_index > *self.match_index.get(&from).unwrap_or(&0) {
+                        self.match_index.insert(from, match_index);
+                        self.maybe_commit();
+                    }
+                    self.next_index.insert(from, match_index + 1);
+                    if match_index < self.last_index() {
+                        self.send_append(from);
+                    }
+                } else {
+                    let next_index = *self.next_index.get(&from).unwrap_or(&1);
+                    self.next_index.insert(from, (match_index + 1).min(next_index.saturating_sub(1)).max(1));
+                    self.send_append(from);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the messages waiting to be sent
+    pub fn take_messages(&mut self) -> Vec<Envelope<C>> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Takes the entries that were committed since the last call, in order
+    ///
+    /// The snapshot taken by take_snapshot, if any, is installed before these are applied.
+    pub fn take_committed(&mut self) -> Vec<Entry<C>> {
+        let from = (self.last_applied - self.snapshot.index) as usize;
+        let to = (self.commit_index - self.snapshot.index) as usize;
+        let committed = self.log[from..to].to_vec();
+        self.last_applied = self.commit_index;
+        committed
+    }
+
+    /// Takes the snapshot the state machine is to be reset to, when the node was given one
+    pub fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.pending_snapshot.take()
+    }
+
+    /// Whether enough entries were applied since the last snapshot to take a new one
+    pub fn should_compact(&self) -> bool {
+        self.last_applied - self.snapshot.index >= SNAPSHOT_ENTRIES
+    }
+
+    /// Replaces the entries up to and including index with the state they were applied to
+    ///
+    /// The entry at index has to be applied by the state machine, data is its state right after.
+    pub fn compact(&mut self, index: u64, data: Bytes) -> Result<(), StorageError> {
+        if index <= self.snapshot.index || index > self.last_applied {
+            return Ok(());
+        }
+        let snapshot = Snapshot { index, term: self.term_at(index), data };
+        self.storage.save_snapshot(&snapshot)?;
+        self.log.drain(..(index - self.snapshot.index) as usize);
+        self.snapshot = snapshot;
+        Ok(())
+    }
+
+    /// Takes the place of the log with a snapshot of the leader
+    ///
+    /// The entries after the snapshot are kept if the log holds the last entry of the snapshot.
+    fn install(&mut self, snapshot: Snapshot) -> Result<(), StorageError> {
+        let matches = snapshot.index <= self.last_index() && self.term_at(snapshot.index) == snapshot.term;
+        self.storage.save_snapshot(&snapshot)?;
+        if matches {
+            self.log.drain(..(snapshot.index - self.snapshot.index) as usize);
+        } else {
+            self.storage.save_entries(snapshot.index + 1, &[])?;
+            self.log.clear();
+        }
+        self.commit_index = snapshot.index;
+        self.last_applied = snapshot.index;
+        self.pending_snapshot = Some(snapshot.clone());
+        self.snapshot = snapshot;
+        Ok(())
+    }
+}
+
+fn random_election_timeout() -> u32 {
+    rand::thread_rng().gen_range(ELECTION_TICKS..ELECTION_TICKS * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Envelope, Message, NodeId, Raft, ELECTION_TICKS, HEARTBEAT_TICKS};
+    use crate::cluster::storage::MemStorage;
+    use bytes::Bytes;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// Nodes that exchange messages in memory, messages to or from a down node are dropped
+    ///
+    /// The state machine of a node is the list of the commands it applied.
+    struct Network {
+        nodes: BTreeMap<NodeId, Raft<String>>,
+        storages: BTreeMap<NodeId, MemStorage<String>>,
+        down: BTreeSet<NodeId>,
+        applied: BTreeMap<NodeId, Vec<String>>
+    }
+    impl Network {
+        fn new(size: u16) -> Network {
+            let ids = (1..=size).collect::<Vec<NodeId>>();
+            let storages = ids.iter().map(|id| (*id, MemStorage::new())).collect::<BTreeMap<NodeId, MemStorage<String>>>();
+            let mut network = Network { nodes: BTreeMap::new(), storages, down: BTreeSet::new(), applied: BTreeMap::new() };
+            for id in ids {
+                network.start(id);
+            }
+            network
+        }
+        /// Starts a node from its storage, its state machine starts empty
+        fn start(&mut self, id: NodeId) {
+            let peers = self.storages.keys().filter(|peer| **peer != id).cloned().collect();
+            let storage = Box::new(self.storages[&id].clone());
+            self.nodes.insert(id, Raft::new(id, peers, storage).unwrap());
+            self.applied.insert(id, vec![]);
+        }
+        fn node(&mut self, id: NodeId) -> &mut Raft<String> {
+            self.nodes.get_mut(&id).unwrap()
+        }
+        fn propose(&mut self, id: NodeId, command: &str) {
+            self.node(id).propose(command.to_string()).unwrap().unwrap();
+        }
+        /// Compacts the log of a node up to the last entry it applied
+        fn compact(&mut self, id: NodeId) {
+            let data = Bytes::from(serde_json::to_vec(&self.applied[&id]).unwrap());
+            let node = self.node(id);
+            let index = node.last_applied;
+            node.compact(index, data).unwrap();
+        }
+        fn deliver(&mut self) {
+            loop {
+                let mut envelopes: Vec<Envelope<String>> = vec![];
+                for node in self.nodes.values_mut() {
+                    envelopes.append(&mut node.take_messages());
+                }
+                if envelopes.is_empty() {
+                    break;
+                }
+                for envelope in envelopes {
+                    if self.down.contains(&envelope.from) || self.down.contains(&envelope.to) {
+                        continue;
+                    }
+                    self.nodes.get_mut(&envelope.to).unwrap().step(envelope).unwrap();
+                }
+            }
+            for (id, node) in self.nodes.iter_mut() {
+                let applied = self.applied.entry(*id).or_default();
+                if let Some(snapshot) = node.take_snapshot() {
+                    *applied = serde_json::from_slice(&snapshot.data).unwrap();
+                }
+                applied.extend(node.take_committed().into_iter().filter_map(|entry: Entry<String>| entry.command));
+            }
+        }
+        fn run(&mut self, ticks: u32) {
+            for _ in 0..ticks {
+                for (id, node) in self.nodes.iter_mut() {
+                    if !self.down.contains(id) {
+                        node.tick().unwrap();
+                    }
+                }
+                self.deliver();
+            }
+        }
+        fn leaders(&self) -> Vec<NodeId> {
+            self.nodes.values()
+                .filter(|node| node.is_leader() && !self.down.contains(&node.id))
+                .map(|node| node.id)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn should_elect_one_leader() {
+        let mut network = Network::new(3);
+        network.run(ELECTION_TICKS * 4);
+        let leaders = network.leaders();
+        assert_eq!(1, leaders.len());
+        let leader = leaders[0];
+        assert!(network.nodes.values().all(|node| node.leader == Some(leader)));
+    }
+
+    #[test]
+    fn should_apply_commands_in_the_same_order_on_every_node() {
+        let mut network = Network::new(5);
+        network.run(ELECTION_TICKS * 4);
+        let leader = network.leaders()[0];
+        for command in ["a", "b", "c"] {
+            network.propose(leader, command);
+        }
+        // followers learn that the entries were committed with a later heartbeat
+        network.run(HEARTBEAT_TICKS * 2);
+        for applied in network.applied.values() {
+            assert_eq!(vec!["a", "b", "c"], *applied);
+        }
+        let follower = *network.nodes.keys().find(|id| **id != leader).unwrap();
+        assert_eq!(Err(Some(leader)), network.node(follower).propose("d".to_string()).unwrap());
+    }
+
+    #[test]
+    fn should_fail_over_and_replace_uncommitted_entries() {
+        let mut network = Network::new(3);
+        network.run(ELECTION_TICKS * 4);
+        let old_leader = network.leaders()[0];
+        network.propose(old_leader, "committed");
+        network.run(HEARTBEAT_TICKS * 2);
+        // the old leader is cut off, its entry can not be committed
+        network.down.insert(old_leader);
+        network.propose(old_leader, "lost");
+        network.run(ELECTION_TICKS * 4);
+        let leaders = network.leaders();
+        assert_eq!(1, leaders.len());
+        let new_leader = leaders[0];
+        assert_ne!(old_leader, new_leader);
+        network.propose(new_leader, "after");
+        network.run(HEARTBEAT_TICKS * 2);
+        // the old leader rejoins as a follower and catches up
+        network.down.remove(&old_leader);
+        network.run(ELECTION_TICKS);
+        assert_eq!(vec![new_leader], network.leaders());
+        for applied in network.applied.values() {
+            assert_eq!(vec!["committed", "after"], *applied);
+        }
+    }
+
+    #[test]
+    fn should_keep_its_vote_through_a_restart() {
+        let mut network = Network::new(3);
+        let vote = |from: NodeId| Envelope { from, to: 1, message: Message::RequestVote { term: 1, last_log_index: 0, last_log_term: 0 } };
+        network.node(1).step(vote(2)).unwrap();
+        assert_eq!(vec![Message::Vote { term: 1, granted: true }], network.node(1).take_messages().into_iter().map(|envelope| envelope.message).collect::<Vec<Message<String>>>());
+        // the node restarts in the same term and is asked again by the other candidate
+        network.start(1);
+        assert_eq!(1, network.node(1).term);
+        network.node(1).step(vote(3)).unwrap();
+        assert_eq!(vec![Message::Vote { term: 1, granted: false }], network.node(1).take_messages().into_iter().map(|envelope| envelope.message).collect::<Vec<Message<String>>>());
+        network.node(1).step(vote(2)).unwrap();
+        assert_eq!(vec![Message::Vote { term: 1, granted: true }], network.node(1).take_messages().into_iter().map(|envelope| envelope.message).collect::<Vec<Message<String>>>());
+    }
+
+    #[test]
+    fn should_keep_its_log_through_a_restart() {
+        let mut network = Network::new(3);
+        network.run(ELECTION_TICKS * 4);
+        let leader = network.leaders()[0];
+        network.propose(leader, "a");
+        network.propose(leader, "b");
+        network.run(HEARTBEAT_TICKS * 2);
+        let follower = *network.nodes.keys().find(|id| **id != leader).unwrap();
+        let (term, last_index) = (network.node(follower).term, network.node(follower).last_index());
+        network.start(follower);
+        assert_eq!((term, last_index), (network.node(follower).term, network.node(follower).last_index()));
+        // the restarted node applies its log again once the leader tells it what is committed
+        network.run(HEARTBEAT_TICKS * 2);
+        assert_eq!(vec![leader], network.leaders());
+        assert_eq!(term, network.node(follower).term);
+        assert_eq!(vec!["a", "b"], network.applied[&follower]);
+    }
+
+    #[test]
+    fn should_send_a_snapshot_to_a_follower_that_is_behind() {
+        let mut network = Network::new(3);
+        network.run(ELECTION_TICKS * 4);
+        let leader = network.leaders()[0];
+        let follower = *network.nodes.keys().find(|id| **id != leader).unwrap();
+        network.down.insert(follower);
+        network.propose(leader, "a");
+        network.propose(leader, "b");
+        network.run(HEARTBEAT_TICKS * 2);
+        network.compact(leader);
+        let snapshot_index = network.node(leader).snapshot_index();
+        assert_eq!(network.node(leader).last_index(), snapshot_index);
+        network.propose(leader, "c");
+        network.down.remove(&follower);
+        network.run(HEARTBEAT_TICKS * 2);
+        assert_eq!(snapshot_index, network.node(follower).snapshot_index());
+        for applied in network.applied.values() {
+            assert_eq!(vec!["a", "b", "c"], *applied);
+        }
+        // a node that restarts rebuilds its state from its snapshot and the entries after it
+        network.start(follower);
+        network.run(HEARTBEAT_TICKS * 2);
+        assert_eq!(vec!["a", "b", "c"], network.applied[&follower]);
+    }
+}
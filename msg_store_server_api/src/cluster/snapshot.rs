@@ -0,0 +1,156 @@
+use bytes::Bytes;
+use crate::Database;
+use crate::changes::ChangeFeed;
+use crate::cluster::Command;
+use crate::cluster::apply::{self, handle as apply_command};
+use crate::config::StoreConfig;
+use crate::file_storage::FileStorage;
+use crate::notify::Notifier;
+use crate::replication::apply::{self as replica_apply, reset};
+use crate::stats::Stats;
+use msg_store::Store;
+use msg_store_database_plugin::DatabaseError;
+use msg_store_uuid::Uuid;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ApplyError(apply::ApiError),
+    DatabaseError(DatabaseError),
+    ResetError(replica_apply::ApiError),
+    CouldNotParseSnapshot,
+    CouldNotSerializeSnapshot,
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ApplyError(err) => write!(f, "({})", err),
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::ResetError(err) => write!(f, "({})", err),
+            Self::CouldNotParseSnapshot |
+            Self::CouldNotSerializeSnapshot |
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "CLUSTER_SNAPSHOT_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "CLUSTER_SNAPSHOT_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Takes the state of the store as the commands that rebuild it from an empty store
+///
+/// The store defaults come first, then the group defaults and then every msg in order.
+pub async fn take(store_mutex: &Mutex<Store>, database: &Database) -> Result<Bytes, ApiError> {
+    let (mut commands, uuids) = {
+        let store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut commands = vec![Command::StoreDefaults { max_byte_size: store.max_byte_size }];
+        for (priority, defaults) in store.group_defaults.iter() {
+            if let Some(max_byte_size) = defaults.max_byte_size {
+                commands.push(Command::GroupDefaults { priority: *priority, max_byte_size: Some(max_byte_size) });
+            }
+        }
+        let mut uuids = store.id_to_group_map.keys().cloned().collect::<Vec<Arc<Uuid>>>();
+        uuids.sort();
+        (commands, uuids)
+    };
+    for uuid in uuids {
+        let msg = match database.get(uuid.clone()).await {
+            Ok(msg) => Ok(msg),
+            Err(err) => Err(api_error!(ErrTy::DatabaseError(err)))
+        }?;
+        commands.push(Command::Add { uuid, msg });
+    }
+    match serde_json::to_vec(&commands) {
+        Ok(data) => Ok(Bytes::from(data)),
+        Err(err) => Err(api_error!(ErrTy::CouldNotSerializeSnapshot, err))
+    }
+}
+
+/// Resets the store to the state of a snapshot
+#[allow(clippy::too_many_arguments)]
+pub async fn install(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    notifier_mutex: &Mutex<Notifier>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    configuration_mutex: &Mutex<StoreConfig>,
+    configuration_path_option: &Option<PathBuf>,
+    data: &[u8]
+) -> Result<(), ApiError> {
+    let commands = match serde_json::from_slice::<Vec<Command>>(data) {
+        Ok(commands) => Ok(commands),
+        Err(err) => Err(api_error!(ErrTy::CouldNotParseSnapshot, err))
+    }?;
+    if let Err(err) = reset(store_mutex, database, file_storage_option, changes_mutex).await {
+        return Err(api_error!(ErrTy::ResetError(err)));
+    }
+    // group defaults that are not part of the snapshot were removed after it was taken
+    let kept = commands.iter().filter_map(|command| match command {
+        Command::GroupDefaults { priority, .. } => Some(*priority),
+        _ => None
+    }).collect::<BTreeSet<u16>>();
+    let removed = match store_mutex.lock() {
+        Ok(store) => Ok(store.group_defaults.keys().filter(|priority| !kept.contains(priority)).cloned().collect::<Vec<u16>>()),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    let removals = removed.into_iter().map(|priority| Command::GroupDefaults { priority, max_byte_size: None });
+    for command in removals.chain(commands) {
+        let result = apply_command(
+            store_mutex,
+            database,
+            file_storage_option,
+            stats_mutex,
+            notifier_mutex,
+            changes_mutex,
+            configuration_mutex,
+            configuration_path_option,
+            command).await;
+        if let Err(err) = result {
+            return Err(api_error!(ErrTy::ApplyError(err)));
+        }
+    }
+    Ok(())
+}
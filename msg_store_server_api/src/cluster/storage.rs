@@ -0,0 +1,371 @@
+//! Where a raft node keeps its term, its vote, its log and its latest snapshot
+//!
+//! A node saves through its storage before it answers a message, so a node that restarts
+//! never takes back a vote or an entry it has acknowledged.
+use bytes::Bytes;
+use crate::cluster::raft::{Entry, NodeId, Snapshot};
+use msg_store_database_plugin::{DatabaseError, Log};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+/// The most entries read from a log at a time while loading
+const READ_LIMIT: usize = 1_000;
+
+#[derive(Debug)]
+pub enum StorageErrorTy {
+    DatabaseError(DatabaseError),
+    CouldNotParseRecord,
+    CouldNotSerializeRecord,
+    LockingError,
+    /// An entry before the last index is missing from the log
+    MissingEntry
+}
+impl Display for StorageErrorTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::CouldNotParseRecord |
+            Self::CouldNotSerializeRecord |
+            Self::LockingError |
+            Self::MissingEntry => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StorageError {
+    pub err_ty: StorageErrorTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "RAFT_STORAGE_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "RAFT_STORAGE_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! storage_error {
+    ($err_ty:expr) => {
+        StorageError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        StorageError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// The term of a node and its vote in that term
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>
+}
+
+/// What a node finds in its storage when it starts
+#[derive(Debug, Clone)]
+pub struct Stored<C> {
+    pub state: HardState,
+    pub snapshot: Snapshot,
+    /// The entries after the snapshot, in order
+    pub entries: Vec<Entry<C>>
+}
+impl<C> Default for Stored<C> {
+    fn default() -> Stored<C> {
+        Stored {
+            state: HardState::default(),
+            snapshot: Snapshot::default(),
+            entries: vec![]
+        }
+    }
+}
+
+pub trait Storage<C>: Send {
+    fn load(&mut self) -> Result<Stored<C>, StorageError>;
+    fn save_state(&mut self, state: &HardState) -> Result<(), StorageError>;
+    /// Replaces the entries from index `from` on with `entries`
+    fn save_entries(&mut self, from: u64, entries: &[Entry<C>]) -> Result<(), StorageError>;
+    /// Saves a snapshot, the entries up to and including its index are dropped
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), StorageError>;
+}
+
+/// Keeps everything in memory, clones share the same storage so a node can be restarted from it
+#[derive(Clone)]
+pub struct MemStorage<C> {
+    stored: Arc<Mutex<Stored<C>>>
+}
+impl<C> MemStorage<C> {
+    pub fn new() -> MemStorage<C> {
+        MemStorage { stored: Arc::new(Mutex::new(Stored::default())) }
+    }
+}
+impl<C> Default for MemStorage<C> {
+    fn default() -> MemStorage<C> {
+        MemStorage::new()
+    }
+}
+impl<C: Clone + Send> Storage<C> for MemStorage<C> {
+    fn load(&mut self) -> Result<Stored<C>, StorageError> {
+        match self.stored.lock() {
+            Ok(stored) => Ok(stored.clone()),
+            Err(err) => Err(storage_error!(StorageErrorTy::LockingError, err))
+        }
+    }
+    fn save_state(&mut self, state: &HardState) -> Result<(), StorageError> {
+        match self.stored.lock() {
+            Ok(mut stored) => {
+                stored.state = state.clone();
+                Ok(())
+            },
+            Err(err) => Err(storage_error!(StorageErrorTy::LockingError, err))
+        }
+    }
+    fn save_entries(&mut self, from: u64, entries: &[Entry<C>]) -> Result<(), StorageError> {
+        let mut stored = match self.stored.lock() {
+            Ok(stored) => Ok(stored),
+            Err(err) => Err(storage_error!(StorageErrorTy::LockingError, err))
+        }?;
+        let kept = from.saturating_sub(stored.snapshot.index + 1) as usize;
+        stored.entries.truncate(kept);
+        stored.entries.extend_from_slice(entries);
+        Ok(())
+    }
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        let mut stored = match self.stored.lock() {
+            Ok(stored) => Ok(stored),
+            Err(err) => Err(storage_error!(StorageErrorTy::LockingError, err))
+        }?;
+        stored.entries.retain(|entry| entry.index > snapshot.index);
+        stored.snapshot = snapshot.clone();
+        Ok(())
+    }
+}
+
+/// The hard state along with the last index of the log, entries after it are left over
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct StateRecord {
+    #[serde(flatten)]
+    state: HardState,
+    last_index: u64
+}
+
+/// Keeps the log, the hard state and the snapshots in logs of their own
+///
+/// Entries are held under their index. Entries that are replaced are overwritten, the last
+/// index saved with the hard state tells where the log ends. The hard state and the snapshots
+/// are appended as new records, each truncating the records before it.
+pub struct LogStorage<C> {
+    entries: Box<dyn Log>,
+    states: Box<dyn Log>,
+    snapshots: Box<dyn Log>,
+    record: StateRecord,
+    record_seq: u64,
+    _command: std::marker::PhantomData<fn() -> C>
+}
+impl<C> LogStorage<C> {
+    pub fn new(entries: Box<dyn Log>, states: Box<dyn Log>, snapshots: Box<dyn Log>) -> LogStorage<C> {
+        LogStorage {
+            entries,
+            states,
+            snapshots,
+            record: StateRecord::default(),
+            record_seq: 0,
+            _command: std::marker::PhantomData
+        }
+    }
+    fn save_record(&mut self, record: StateRecord) -> Result<(), StorageError> {
+        let bytes = match serde_json::to_vec(&record) {
+            Ok(bytes) => Ok(bytes),
+            Err(err) => Err(storage_error!(StorageErrorTy::CouldNotSerializeRecord, err))
+        }?;
+        let seq = self.record_seq + 1;
+        if let Err(err) = self.states.append(seq, Bytes::from(bytes)) {
+            return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+        }
+        if let Err(err) = self.states.truncate_before(seq) {
+            return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+        }
+        self.record = record;
+        self.record_seq = seq;
+        Ok(())
+    }
+    fn save_last_index(&mut self, last_index: u64) -> Result<(), StorageError> {
+        let record = StateRecord { state: self.record.state.clone(), last_index };
+        self.save_record(record)
+    }
+}
+impl<C: Serialize + DeserializeOwned> Storage<C> for LogStorage<C> {
+    fn load(&mut self) -> Result<Stored<C>, StorageError> {
+        let mut stored = Stored::default();
+        match last_record(self.states.as_mut())? {
+            Some((seq, record)) => {
+                self.record = record;
+                self.record_seq = seq;
+            },
+            None => {
+                self.record = StateRecord::default();
+                self.record_seq = 0;
+            }
+        }
+        if let Some((_index, snapshot)) = last_record::<Snapshot>(self.snapshots.as_mut())? {
+            stored.snapshot = snapshot;
+        }
+        let (mut next_index, last_index) = (stored.snapshot.index + 1, self.record.last_index);
+        while next_index <= last_index {
+            let read = match self.entries.read(next_index, READ_LIMIT) {
+                Ok(read) => Ok(read),
+                Err(err) => Err(storage_error!(StorageErrorTy::DatabaseError(err)))
+            }?;
+            if read.is_empty() {
+                return Err(storage_error!(StorageErrorTy::MissingEntry, format!("entry {}", next_index)));
+            }
+            for (index, bytes) in read {
+                if next_index > last_index {
+                    break;
+                }
+                if index != next_index {
+                    return Err(storage_error!(StorageErrorTy::MissingEntry, format!("entry {}", next_index)));
+                }
+                stored.entries.push(parse(&bytes)?);
+                next_index += 1;
+            }
+        }
+        stored.state = self.record.state.clone();
+        Ok(stored)
+    }
+    fn save_state(&mut self, state: &HardState) -> Result<(), StorageError> {
+        let record = StateRecord { state: state.clone(), last_index: self.record.last_index };
+        self.save_record(record)
+    }
+    fn save_entries(&mut self, from: u64, entries: &[Entry<C>]) -> Result<(), StorageError> {
+        // the log is cut short before entries are overwritten, so a log that is read after a
+        // crash never mixes the entries that are replaced with the entries replacing them
+        if from <= self.record.last_index {
+            self.save_last_index(from - 1)?;
+        }
+        for entry in entries {
+            let bytes = match serde_json::to_vec(entry) {
+                Ok(bytes) => Ok(bytes),
+                Err(err) => Err(storage_error!(StorageErrorTy::CouldNotSerializeRecord, err))
+            }?;
+            if let Err(err) = self.entries.append(entry.index, Bytes::from(bytes)) {
+                return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+            }
+        }
+        let last_index = from - 1 + entries.len() as u64;
+        if last_index != self.record.last_index {
+            self.save_last_index(last_index)?;
+        }
+        Ok(())
+    }
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        let bytes = match serde_json::to_vec(snapshot) {
+            Ok(bytes) => Ok(bytes),
+            Err(err) => Err(storage_error!(StorageErrorTy::CouldNotSerializeRecord, err))
+        }?;
+        if let Err(err) = self.snapshots.append(snapshot.index, Bytes::from(bytes)) {
+            return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+        }
+        if let Err(err) = self.snapshots.truncate_before(snapshot.index) {
+            return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+        }
+        if self.record.last_index < snapshot.index {
+            self.save_last_index(snapshot.index)?;
+        }
+        if let Err(err) = self.entries.truncate_before(snapshot.index + 1) {
+            return Err(storage_error!(StorageErrorTy::DatabaseError(err)));
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StorageError> {
+    match serde_json::from_slice(bytes) {
+        Ok(record) => Ok(record),
+        Err(err) => Err(storage_error!(StorageErrorTy::CouldNotParseRecord, err))
+    }
+}
+
+fn last_record<T: DeserializeOwned>(log: &mut dyn Log) -> Result<Option<(u64, T)>, StorageError> {
+    let last_seq = match log.last_seq() {
+        Ok(last_seq) => Ok(last_seq),
+        Err(err) => Err(storage_error!(StorageErrorTy::DatabaseError(err)))
+    }?;
+    let last_seq = match last_seq {
+        Some(last_seq) => last_seq,
+        None => return Ok(None)
+    };
+    let read = match log.read(last_seq, 1) {
+        Ok(read) => Ok(read),
+        Err(err) => Err(storage_error!(StorageErrorTy::DatabaseError(err)))
+    }?;
+    match read.first() {
+        Some((seq, bytes)) => Ok(Some((*seq, parse(bytes)?))),
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::cluster::raft::{Entry, Snapshot};
+    use crate::cluster::storage::{HardState, LogStorage, Storage};
+    use msg_store_database_redb_plugin::RedbLog;
+    use tempdir::TempDir;
+
+    fn open(tmp_dir: &TempDir) -> LogStorage<String> {
+        let log = |name: &str| -> Box<RedbLog> {
+            let path = tmp_dir.path().join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            Box::new(RedbLog::new(&path).unwrap())
+        };
+        LogStorage::new(log("entries"), log("states"), log("snapshots"))
+    }
+
+    fn entry(term: u64, index: u64) -> Entry<String> {
+        Entry { term, index, command: Some(format!("{}-{}", term, index)) }
+    }
+
+    #[test]
+    fn should_load_what_was_saved() {
+        let tmp_dir = TempDir::new("should_load_what_was_saved").unwrap();
+        {
+            let mut storage = open(&tmp_dir);
+            storage.load().unwrap();
+            storage.save_state(&HardState { term: 2, voted_for: Some(3) }).unwrap();
+            storage.save_entries(1, &[entry(1, 1), entry(1, 2), entry(1, 3), entry(2, 4)]).unwrap();
+            // a new leader replaces the entries from index 3 on
+            storage.save_entries(3, &[entry(2, 3)]).unwrap();
+            storage.save_state(&HardState { term: 3, voted_for: None }).unwrap();
+        }
+        let mut storage = open(&tmp_dir);
+        let stored = storage.load().unwrap();
+        assert_eq!(HardState { term: 3, voted_for: None }, stored.state);
+        assert_eq!(vec![entry(1, 1), entry(1, 2), entry(2, 3)], stored.entries);
+        storage.save_snapshot(&Snapshot { index: 2, term: 1, data: Bytes::from_static(b"state") }).unwrap();
+        storage.save_entries(4, &[entry(3, 4)]).unwrap();
+        drop(storage);
+        let stored = open(&tmp_dir).load().unwrap();
+        assert_eq!(Snapshot { index: 2, term: 1, data: Bytes::from_static(b"state") }, stored.snapshot);
+        assert_eq!(vec![entry(2, 3), entry(3, 4)], stored.entries);
+        assert_eq!(HardState { term: 3, voted_for: None }, stored.state);
+    }
+}
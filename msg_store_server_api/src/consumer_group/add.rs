@@ -0,0 +1,85 @@
+use msg_store::{Store, StoreError, StoreErrorTy};
+use msg_store_database_plugin::DatabaseError;
+use crate::Database;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    DatabaseError(DatabaseError),
+    StoreError(StoreError),
+    ConsumerGroupExists,
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::StoreError(err) => write!(f, "({})", err),
+            Self::ConsumerGroupExists |
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "ADD_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "ADD_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Adds a consumer group that starts with every msg currently in the store
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    name: &str
+) -> Result<(), ApiError> {
+    let saved = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        if let Err(err) = store.add_consumer_group(name, vec![]) {
+            if let StoreErrorTy::ConsumerGroupExists = err.err_ty {
+                return Err(api_error!(ErrTy::ConsumerGroupExists));
+            }
+            return Err(api_error!(ErrTy::StoreError(err)));
+        }
+        database.put_cursors(name, vec![])
+    };
+    if let Err(err) = saved.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    Ok(())
+}
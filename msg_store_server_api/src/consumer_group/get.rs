@@ -0,0 +1,85 @@
+use msg_store::Store;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerGroup {
+    pub name: String,
+    /// The last msg acknowledged in each priority
+    pub cursors: Vec<String>
+}
+
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    name_option: Option<&str>
+) -> Result<Vec<ConsumerGroup>, ApiError> {
+    let store = match store_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    let consumer_groups = store
+        .consumer_groups
+        .iter()
+        .filter(|(name, _consumer_group)| name_option.is_none() || name_option == Some(name.as_str()))
+        .map(|(name, consumer_group)| ConsumerGroup {
+            name: name.clone(),
+            cursors: consumer_group
+                .to_cursors()
+                .iter()
+                .map(|uuid| uuid.to_string())
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<ConsumerGroup>>();
+    Ok(consumer_groups)
+}
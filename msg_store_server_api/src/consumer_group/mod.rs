@@ -0,0 +1,120 @@
+pub mod ack;
+pub mod add;
+pub mod get;
+pub mod next;
+pub mod rm;
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::fake_payload;
+    use crate::file_storage::FileStorage;
+    use crate::msg::add::handle as add_handle;
+    use crate::msg::frame::WireFormat;
+    use crate::msg::tests::FakePayload;
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::Notifier;
+    use crate::stats::Stats;
+    use futures::executor::block_on;
+    use msg_store::Store;
+    use crate::Database;
+    use msg_store_database_plugin::BlockingDb;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use std::sync::Mutex;
+    use super::ack::handle as ack_handle;
+    use super::add::{handle as add_group_handle, ErrTy as AddErrTy};
+    use super::get::handle as get_group_handle;
+    use super::next::{handle as next_handle, ErrTy as NextErrTy};
+    use super::rm::handle as rm_group_handle;
+    use tempdir::TempDir;
+
+    #[test]
+    fn should_deliver_every_msg_to_each_consumer_group() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_deliver_every_msg_to_each_consumer_group").unwrap();
+        let file_storage_op = Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap()));
+
+        block_on(add_group_handle(&store_mx, &database, "emails")).unwrap();
+        block_on(add_group_handle(&store_mx, &database, "audit")).unwrap();
+        let add_result = block_on(add_group_handle(&store_mx, &database, "audit"));
+        assert!(matches!(add_result.err().unwrap().err_ty, AddErrTy::ConsumerGroupExists));
+
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1?Hello, world"))).unwrap();
+
+        // both consumer groups get the msg until they acknowledge it
+        for name in ["emails", "audit"] {
+            let msg = block_on(next_handle(&store_mx, &database, &file_storage_op, name, None, WireFormat::QueryString)).unwrap().unwrap().b();
+            assert_eq!(uuid, msg.uuid);
+        }
+        block_on(ack_handle(&store_mx, &database, &file_storage_op, &stats_mx, &changes_mx, "emails", uuid.clone())).unwrap();
+        assert!(block_on(next_handle(&store_mx, &database, &file_storage_op, "emails", None, WireFormat::QueryString)).unwrap().is_none());
+        assert!(block_on(next_handle(&store_mx, &database, &file_storage_op, "audit", None, WireFormat::QueryString)).unwrap().is_some());
+        {
+            assert_eq!(1, block_on(database.fetch()).unwrap().len());
+            let mut cursors = block_on(database.fetch_cursors()).unwrap();
+            cursors.sort();
+            assert_eq!(vec![("audit".to_string(), vec![]), ("emails".to_string(), vec![uuid.clone()])], cursors);
+        }
+
+        // the msg is removed once every consumer group has acknowledged it
+        block_on(ack_handle(&store_mx, &database, &file_storage_op, &stats_mx, &changes_mx, "audit", uuid.clone())).unwrap();
+        {
+            let store = store_mx.lock().unwrap();
+            let stats = stats_mx.lock().unwrap();
+            assert_eq!(0, store.byte_size);
+            assert_eq!(0, block_on(database.fetch()).unwrap().len());
+            assert_eq!(1, stats.deleted);
+        }
+
+        let groups = block_on(get_group_handle(&store_mx, Some("audit"))).unwrap();
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![uuid.to_string()], groups[0].cursors);
+
+        let next_result = block_on(next_handle(&store_mx, &database, &file_storage_op, "unknown", None, WireFormat::QueryString));
+        assert!(matches!(next_result.err().unwrap().err_ty, NextErrTy::ConsumerGroupNotFound));
+    }
+
+    #[test]
+    fn should_remove_msgs_acknowledged_by_the_remaining_consumer_groups() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let file_storage_op = None;
+
+        block_on(add_group_handle(&store_mx, &database, "emails")).unwrap();
+        block_on(add_group_handle(&store_mx, &database, "audit")).unwrap();
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1?Hello, world"))).unwrap();
+        block_on(ack_handle(&store_mx, &database, &file_storage_op, &stats_mx, &changes_mx, "emails", uuid)).unwrap();
+        block_on(rm_group_handle(&store_mx, &database, &file_storage_op, &stats_mx, &changes_mx, "audit")).unwrap();
+
+        let store = store_mx.lock().unwrap();
+        assert_eq!(0, store.byte_size);
+        assert_eq!(0, block_on(database.fetch()).unwrap().len());
+        assert_eq!(1, block_on(database.fetch_cursors()).unwrap().len());
+    }
+
+}
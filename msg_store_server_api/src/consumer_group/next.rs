@@ -0,0 +1,93 @@
+use crate::{Database, Either};
+use crate::file_storage::FileStorage;
+use crate::msg::frame::WireFormat;
+use crate::msg::get::{handle as get_msg, GetError, ReturnBody, StoredMsg};
+use msg_store::{Store, StoreError, StoreErrorTy};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    GetError(GetError),
+    StoreError(StoreError),
+    ConsumerGroupNotFound,
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GetError(err) => write!(f, "({})", err),
+            Self::StoreError(err) => write!(f, "({})", err),
+            Self::ConsumerGroupNotFound |
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_NEXT_MSG_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_NEXT_MSG_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Gets the next msg a consumer group has not acknowledged yet
+/// 
+/// The msg is not acknowledged by getting it, so the same msg is returned until it is.
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    name: &str,
+    priority_option: Option<u16>,
+    format: WireFormat
+) -> Result<Option<Either<ReturnBody, StoredMsg>>, ApiError> {
+    let uuid = {
+        let store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        match store.get_for_consumer(name, priority_option) {
+            Ok(Some(uuid)) => Ok(uuid),
+            Ok(None) => return Ok(None),
+            Err(err) => match err.err_ty {
+                StoreErrorTy::ConsumerGroupNotFound => Err(api_error!(ErrTy::ConsumerGroupNotFound)),
+                _ => Err(api_error!(ErrTy::StoreError(err)))
+            }
+        }
+    }?;
+    match get_msg(store_mutex, database, file_storage_option, Some(uuid), None, false, format).await {
+        Ok(msg_option) => Ok(msg_option),
+        Err(err) => Err(api_error!(ErrTy::GetError(err)))
+    }
+}
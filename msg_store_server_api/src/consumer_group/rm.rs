@@ -0,0 +1,124 @@
+use msg_store::{Store, StoreError};
+use msg_store_database_plugin::{Batch, DatabaseError};
+use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
+use crate::file_storage::{rm_from_file_storage, FileStorage, FileStorageError};
+use crate::stats::Stats;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
+    DatabaseError(DatabaseError),
+    FileStorageError(FileStorageError),
+    StoreError(StoreError),
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::FileStorageError(err) => write!(f, "({})", err),
+            Self::StoreError(err) => write!(f, "({})", err),
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "REMOVE_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "REMOVE_CONSUMER_GROUP_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Removes a consumer group and the msgs that every remaining consumer group has acknowledged
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    name: &str
+) -> Result<(), ApiError> {
+    let (msgs_removed, deleted, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut stats = match stats_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let msgs_removed = match store.del_consumer_group(name) {
+            Ok(msgs_removed) => Ok(msgs_removed),
+            Err(err) => Err(api_error!(ErrTy::StoreError(err)))
+        }?;
+        let deleted = database.del_cursors(name);
+        let mut batch = Batch::new();
+        for uuid in msgs_removed.iter() {
+            batch.del(uuid.clone());
+        }
+        let committed = database.commit(batch);
+        stats.deleted += msgs_removed.len() as u64;
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let recorded_changes = msgs_removed.iter().map(|uuid| Change::Delete { uuid: uuid.clone() }).collect();
+        if let Err(err) = changes.record_all(recorded_changes) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)));
+        }
+        (msgs_removed, deleted, committed)
+    };
+    if let Err(err) = deleted.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    if let Err(err) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    if let Some(file_storage_mutex) = &file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for uuid in msgs_removed.iter() {
+            if let Err(err) = rm_from_file_storage(&mut file_storage, uuid) {
+                return Err(api_error!(ErrTy::FileStorageError(err)))
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::file_storage::{
     FileStorage,
     FileStorageError,
@@ -8,7 +9,7 @@ use crate::file_storage::{
 };
 use crate::stats::Stats;
 use msg_store::{Store, StoreError};
-use msg_store_database_leveldb_plugin::{Db, Leveldb, DatabaseError};
+use msg_store_database_redb_plugin::{Db, Redb, DatabaseError};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::fmt::Display;
@@ -17,26 +18,26 @@ use std::sync::Mutex;
 
 #[derive(Debug)]
 pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
     CouldNotAddFileToBackup(DatabaseError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
     StoreError(StoreError),
     CouldNotCopyFile,
     CouldNotCreateDirectory,
-    CouldNotReinsertFileAfterError,
     CouldNotRemoveFileAfterError,
     LockError
 }
 impl Display for ErrTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::StoreError(err) => write!(f, "({})", err),
             Self::CouldNotAddFileToBackup(err) => write!(f, "({})", err),
             Self::CouldNotCopyFile |
             Self::CouldNotCreateDirectory |
-            Self::CouldNotReinsertFileAfterError |
             Self::CouldNotRemoveFileAfterError |
             Self::LockError => write!(f, "{:#?}", self)
         }
@@ -118,9 +119,10 @@ pub struct StoredPacket {
 
 pub async fn handle(
     store_mutex: &Mutex<Store>,
-    database_mutex: &Mutex<Database>,
+    database: &Database,
     file_storage_option: &Option<Mutex<FileStorage>>,
     stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
     export_directory: &Path
 ) -> Result<(), ApiError> {
 
@@ -139,34 +141,29 @@ pub async fn handle(
 
         create_export_directory(&export_dir_path)?;
 
-        // get the leveldb path
-        let mut leveldb_path = export_dir_path.to_path_buf();
-        leveldb_path.push("leveldb");
+        // get the redb path
+        let mut redb_path = export_dir_path.to_path_buf();
+        redb_path.push("redb");
 
-        // open the leveldb instance
-        let mut leveldb_backup = match Leveldb::new(&leveldb_path) {
-            Ok(leveldb) => Ok(leveldb),
+        // open the redb instance
+        let mut redb_backup = match Redb::new(&redb_path) {
+            Ok(redb) => Ok(redb),
             Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
         }?;
 
-        if let Some(file_storage_mutex) = file_storage_option {
-
-            // create file storage directory
-            let file_storage_export_directory = match create_directory(&export_dir_path) {
-                Ok(directory) => Ok(directory),
-                Err(error) => Err(api_error!(ErrTy::FileStorageError(error)))
-            }?;
+        // create file storage directory
+        let file_storage_export_directory = match file_storage_option {
+            Some(_) => match create_directory(&export_dir_path) {
+                Ok(directory) => Some(directory),
+                Err(error) => return Err(api_error!(ErrTy::FileStorageError(error)))
+            },
+            None => None
+        };
 
-            for _ in 0..max_count {
-                let mut store = match store_mutex.lock() {
-                    Ok(gaurd) => Ok(gaurd),
-                    Err(err) => Err(api_error!(ErrTy::LockError, err))
-                }?;
-                let mut database = match database_mutex.lock() {
-                    Ok(gaurd) => Ok(gaurd),
-                    Err(err) => Err(api_error!(ErrTy::LockError, err))
-                }?;
-                let mut file_storage = match file_storage_mutex.lock() {
+        // the store is only locked to pick a msg and to remove it once it has been exported
+        for _ in 0..max_count {
+            let (uuid, msg) = {
+                let store = match store_mutex.lock() {
                     Ok(gaurd) => Ok(gaurd),
                     Err(err) => Err(api_error!(ErrTy::LockError, err))
                 }?;
@@ -174,90 +171,91 @@ pub async fn handle(
                     Ok(uuid) => Ok(uuid),
                     Err(error) => Err(api_error!(ErrTy::StoreError(error)))
                 }?;
-                let uuid = match uuid {
-                    Some(uuid) => uuid,
+                match uuid {
+                    Some(uuid) => (uuid.clone(), database.get(uuid)),
                     None => { break }
-                };
-                let msg = match database.get(uuid.clone()) {
-                    Ok(msg) => Ok(msg),
-                    Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
-                }?;
-                let msg_byte_size = msg.len() as u64;
-
-                let src_file_path = get_file_path_from_id(&file_storage.path, &uuid);
-                let dest_file_path = get_file_path_from_id(&file_storage_export_directory, &uuid);
-                if let Err(error) = copy(&src_file_path, &dest_file_path) {
+                }
+            };
+            let msg = match msg.await {
+                Ok(msg) => Ok(msg),
+                Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
+            }?;
+            let msg_byte_size = msg.len() as u64;
+
+            let file_paths = match (file_storage_option, &file_storage_export_directory) {
+                (Some(file_storage_mutex), Some(file_storage_export_directory)) => {
+                    let file_storage = match file_storage_mutex.lock() {
+                        Ok(gaurd) => Ok(gaurd),
+                        Err(err) => Err(api_error!(ErrTy::LockError, err))
+                    }?;
+                    if file_storage.index.contains(&uuid) {
+                        let src_file_path = get_file_path_from_id(&file_storage.path, &uuid);
+                        let dest_file_path = get_file_path_from_id(file_storage_export_directory, &uuid);
+                        Some((src_file_path, dest_file_path))
+                    } else {
+                        None
+                    }
+                },
+                _ => None
+            };
+            if let Some((src_file_path, dest_file_path)) = &file_paths {
+                if let Err(error) = copy(src_file_path, dest_file_path) {
                     return Err(api_error!(ErrTy::CouldNotCopyFile, error));
                 };
-                // remove the file from the index
-                if let Err(error) = rm_from_file_storage(&mut file_storage, &uuid) {
-                    return Err(api_error!(ErrTy::FileStorageError(error)));
-                }
+            }
 
-                // add the data to the leveldb backup
-                // if it errors then copy the destination file back to the source
-                // dont exit until on error handling has finished
-                if let Err(error) = leveldb_backup.add(uuid.clone(), msg, msg_byte_size) {
-                    if let Err(error) = copy(&dest_file_path, &src_file_path) {
-                        return Err(api_error!(ErrTy::CouldNotReinsertFileAfterError, error));
-                    };
+            // add the data to the redb backup
+            // if it errors then remove the copy of the file
+            // dont exit until on error handling has finished
+            if let Err(error) = redb_backup.add(uuid.clone(), msg, msg_byte_size) {
+                if let Some((_, dest_file_path)) = file_paths {
                     if let Err(error) = remove_file(dest_file_path) {
                         return Err(api_error!(ErrTy::CouldNotRemoveFileAfterError, error));
                     }
                     return Err(api_error!(ErrTy::CouldNotAddFileToBackup(error)));
                 }
-
-                if let Err(err) = store.del(uuid.clone()) {
-                    return Err(api_error!(ErrTy::StoreError(err)));
-                }
-                if let Err(err) = database.del(uuid.clone()) {
-                    return Err(api_error!(ErrTy::DatabaseError(err)))
-                }
-
-                // update deleted count
-                deleted_count += 1;    
+                return Err(api_error!(ErrTy::DatabaseError(error)));
             }
-        } else {
-            for _ in 0..max_count {
+
+            let deleted = {
                 let mut store = match store_mutex.lock() {
                     Ok(gaurd) => Ok(gaurd),
                     Err(err) => Err(api_error!(ErrTy::LockError, err))
                 }?;
-                let mut database = match database_mutex.lock() {
-                    Ok(gaurd) => Ok(gaurd),
-                    Err(err) => Err(api_error!(ErrTy::LockError, err))
-                }?;
-                let uuid = match store.get(None, None, false) {
-                    Ok(uuid) => Ok(uuid),
-                    Err(error) => Err(api_error!(ErrTy::StoreError(error)))
-                }?;
-                let uuid = match uuid {
-                    Some(uuid) => uuid,
-                    None => { break }
-                };
-                let msg = match database.get(uuid.clone()) {
-                    Ok(msg) => Ok(msg),
-                    Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
-                }?;                
-                let msg_byte_size = msg.len() as u64;
-
-                // add the data to the leveldb backup
-                // if it errors then copy the destination file back to the source
-                // dont exit until on error handling has finished
-                if let Err(error) = leveldb_backup.add(uuid.clone(), msg, msg_byte_size) {
-                    return Err(api_error!(ErrTy::DatabaseError(error)));
+                // the msg may have been removed while it was exported
+                if !store.id_to_group_map.contains_key(&uuid) {
+                    continue;
                 }
-
                 if let Err(err) = store.del(uuid.clone()) {
                     return Err(api_error!(ErrTy::StoreError(err)));
                 }
-                if let Err(err) = database.del(uuid.clone()) {
-                    return Err(api_error!(ErrTy::DatabaseError(err)))
+                let deleted = database.del(uuid.clone());
+                match changes_mutex.lock() {
+                    Ok(mut changes) => if let Err(err) = changes.record(Change::Delete { uuid: uuid.clone() }) {
+                        return Err(api_error!(ErrTy::ChangeFeedError(err)))
+                    },
+                    Err(err) => return Err(api_error!(ErrTy::LockError, err))
+                };
+                deleted
+            };
+            if let Err(err) = deleted.await {
+                return Err(api_error!(ErrTy::DatabaseError(err)))
+            }
+            if file_paths.is_some() {
+                if let Some(file_storage_mutex) = file_storage_option {
+                    let mut file_storage = match file_storage_mutex.lock() {
+                        Ok(gaurd) => Ok(gaurd),
+                        Err(err) => Err(api_error!(ErrTy::LockError, err))
+                    }?;
+                    // remove the file from the index
+                    if let Err(error) = rm_from_file_storage(&mut file_storage, &uuid) {
+                        return Err(api_error!(ErrTy::FileStorageError(error)));
+                    }
                 }
-
-                // update deleted count
-                deleted_count += 1;    
             }
+
+            // update deleted count
+            deleted_count += 1;    
         }
         deleted_count
     };
@@ -276,17 +274,21 @@ pub async fn handle(
 mod tests {
     use bytes::Bytes;
     use crate::fake_payload;
-    use crate::file_storage::FileStorage;
+    use crate::file_storage::{get_buffer, FileStorage};
     use crate::msg::add::handle as add_handle;
+    use crate::msg::frame::WireFormat;
     use crate::msg::tests::FakePayload;
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::Notifier;
     use crate::stats::Stats;
     use msg_store::Store;
-    use msg_store_database_plugin::Db;
-    use msg_store_database_leveldb_plugin::Leveldb;
+    use crate::Database;
+    use msg_store_database_plugin::{BlockingDb, Db};
+    use msg_store_database_redb_plugin::Redb;
     use futures::executor::block_on;
     use rand::prelude::random;
     use std::convert::AsRef;
-    use std::fs::{read_to_string, remove_dir_all};
+    use std::fs::remove_dir_all;
     use std::ops::Drop;
     use std::path::{Path, PathBuf};
     use std::sync::Mutex;
@@ -327,29 +329,31 @@ mod tests {
         
         let tmp_dir = TempDir::new("should_export_msgs").unwrap();
         let tmp_export_dir = LazyTempDir::new("should_export_msgs_export");
-        let level_db_path = {
-            let mut level_db_path = tmp_dir.path().to_path_buf();
-            level_db_path.push("leveldb");
-            level_db_path
+        let redb_path = {
+            let mut redb_path = tmp_dir.path().to_path_buf();
+            redb_path.push("redb");
+            redb_path
         };
         let file_storage_path = {
             let mut file_storage_path = tmp_dir.path().to_path_buf();
             file_storage_path.push("file-storage");
             file_storage_path
         };
-        let exported_level_db_path = {
-            let mut level_db_path = tmp_export_dir.path().to_path_buf();
-            level_db_path.push("leveldb");
-            level_db_path
+        let exported_redb_path = {
+            let mut redb_path = tmp_export_dir.path().to_path_buf();
+            redb_path.push("redb");
+            redb_path
         };
         let exported_file_storage_path = {
             let mut file_storage_path = tmp_export_dir.path().to_path_buf();
             file_storage_path.push("file-storage");
             file_storage_path
         };
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(Leveldb::new(&level_db_path).unwrap()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(Redb::new(&redb_path).unwrap())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         
         
         let file_storage_op = Some(Mutex::new(FileStorage::new(&file_storage_path).unwrap()));
@@ -359,17 +363,18 @@ mod tests {
         let msg_len = msg.len() as u64;
         let payload_str = format!("priority=1&saveToFile=true&bytesizeOverride={}&fileName=my-file?{}", msg_len, msg);
         let payload = fake_payload!(payload_str);
-        let uuid = block_on(add_handle(&store_mx, &file_storage_op, &stats_mx, &database_mx, payload)).unwrap();
+        let uuid = block_on(add_handle(&store_mx, &file_storage_op, &stats_mx, &database, &notifier_mx, &changes_mx, false, WireFormat::QueryString, payload)).unwrap();
         
         let msg_headers = {
-            database_mx.lock().unwrap().get(uuid.clone()).unwrap()
+            block_on(database.get(uuid.clone())).unwrap()
         };
 
         block_on(handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &file_storage_op, 
-            &stats_mx, 
+            &stats_mx,
+            &changes_mx,
             tmp_export_dir.path())).unwrap();
 
         // make assertions
@@ -380,8 +385,7 @@ mod tests {
             assert!(store.id_to_group_map.len() == 0);
             
             // the database should be empty
-            let mut database = database_mx.lock().unwrap();
-            assert!(database.fetch().unwrap().len() == 0);
+            assert!(block_on(database.fetch()).unwrap().len() == 0);
 
             // the stats object should have deleted 1
             let stats = stats_mx.lock().unwrap();
@@ -404,14 +408,17 @@ mod tests {
             assert!(file_path.exists());
 
             // there should be a msg in the database
-            let mut database = Leveldb::new(&exported_level_db_path).unwrap();
-            assert!(database.fetch().unwrap().len() == 1);
+            let mut backup = Redb::new(&exported_redb_path).unwrap();
+            assert!(backup.fetch_all().unwrap().len() == 1);
 
             // the headers should match
-            assert!(database.get(uuid.clone()).unwrap() == msg_headers);
+            assert!(backup.get(uuid.clone()).unwrap() == msg_headers);
 
             // the file contents should match
-            assert!(read_to_string(file_path).unwrap() == msg);
+            let mut contents = String::new();
+            let exported_file_storage = FileStorage::new(&exported_file_storage_path).unwrap();
+            get_buffer(&exported_file_storage, &uuid).unwrap().0.read_to_string(&mut contents).unwrap();
+            assert!(contents == msg);
 
         }
 
@@ -422,38 +429,41 @@ mod tests {
         
         let tmp_dir = TempDir::new("should_export_msgs").unwrap();
         let tmp_export_dir = LazyTempDir::new("should_export_msgs_export");
-        let level_db_path = {
-            let mut level_db_path = tmp_dir.path().to_path_buf();
-            level_db_path.push("leveldb");
-            level_db_path
+        let redb_path = {
+            let mut redb_path = tmp_dir.path().to_path_buf();
+            redb_path.push("redb");
+            redb_path
         };
 
-        let exported_level_db_path = {
-            let mut level_db_path = tmp_export_dir.path().to_path_buf();
-            level_db_path.push("leveldb");
-            level_db_path
+        let exported_redb_path = {
+            let mut redb_path = tmp_export_dir.path().to_path_buf();
+            redb_path.push("redb");
+            redb_path
         };
 
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(Leveldb::new(&level_db_path).unwrap()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(Redb::new(&redb_path).unwrap())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         
         
         // add a message to the store and database using the add msg api
         let msg = "Hello, world";
         let payload_str = format!("priority=1?{}", msg);
         let payload = fake_payload!(payload_str);
-        let uuid = block_on(add_handle(&store_mx, &None, &stats_mx, &database_mx, payload)).unwrap();
+        let uuid = block_on(add_handle(&store_mx, &None, &stats_mx, &database, &notifier_mx, &changes_mx, false, WireFormat::QueryString, payload)).unwrap();
         
         let inserted_msg = {
-            database_mx.lock().unwrap().get(uuid.clone()).unwrap()
+            block_on(database.get(uuid.clone())).unwrap()
         };
 
         block_on(handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &None, 
-            &stats_mx, 
+            &stats_mx,
+            &changes_mx,
             tmp_export_dir.path())).unwrap();
 
         // make assertions
@@ -464,20 +474,19 @@ mod tests {
             assert!(store.id_to_group_map.len() == 0);
             
             // the database should be empty
-            let mut database = database_mx.lock().unwrap();
-            assert!(database.fetch().unwrap().len() == 0);
+            assert!(block_on(database.fetch()).unwrap().len() == 0);
 
             // the stats object should have deleted 1
             let stats = stats_mx.lock().unwrap();
             assert!(stats.deleted == 1);
 
             // there should be a msg in the database
-            let mut database = Leveldb::new(&exported_level_db_path).unwrap();
-            assert!(database.fetch().unwrap().len() == 1);
+            let mut backup = Redb::new(&exported_redb_path).unwrap();
+            assert!(backup.fetch_all().unwrap().len() == 1);
 
             // the msg should match
-            assert!(database.get(uuid.clone()).unwrap() == inserted_msg);
-            assert!(database.get(uuid.clone()).unwrap() == msg);
+            assert!(backup.get(uuid.clone()).unwrap() == inserted_msg);
+            assert!(backup.get(uuid.clone()).unwrap() == msg);
 
         }
 
@@ -1,4 +1,7 @@
 use crate::msg::add::Chunky;
+use msg_store_database_checksum_plugin::{seal_file, verify_file, write_header, Corruption, CorruptionPolicy, Verified, HEADER_LEN};
+use msg_store_database_compressed_plugin::{decoder, Compression, StreamEncoder};
+use msg_store_database_encrypted_plugin::{decryptor, key_id, Keyring, StreamEncryptor};
 use msg_store_uuid::Uuid;
 use futures::StreamExt;
 use std::collections::BTreeSet;
@@ -7,10 +10,16 @@ use std::fs::{
     create_dir_all,
     read_dir,
     remove_file,
+    rename,
     File,
+    OpenOptions
 };
 use std::io::{
     BufReader,
+    BufWriter,
+    Read,
+    Seek,
+    SeekFrom,
     Write
 };
 use std::path::{Path, PathBuf};
@@ -27,6 +36,7 @@ pub enum FileStorageErrorTy {
     CouldNotOpenFile,
     CouldNotParseChunk,
     CouldNotWriteToFIle,
+    CorruptMsg,
     DirectoryDoesNotExist,
     PathIsNotADirectory
 }
@@ -42,6 +52,7 @@ impl Display for FileStorageErrorTy {
             Self::CouldNotOpenFile |
             Self::CouldNotParseChunk |
             Self::CouldNotWriteToFIle |
+            Self::CorruptMsg |
             Self::DirectoryDoesNotExist |
             Self::PathIsNotADirectory => write!(f, "{:#?}", self)
         }
@@ -86,9 +97,18 @@ macro_rules! fs_error {
     };
 }
 
+/// Reads the contents of a msg that is held in a file
+pub type FileReader = Box<dyn Read + Send>;
+
 pub struct FileStorage {
     pub index: BTreeSet<Arc<Uuid>>,
-    pub path: PathBuf
+    pub path: PathBuf,
+    /// Compresses the files that are written, files that were not compressed are still read
+    pub compression: Option<Compression>,
+    /// Encrypts the files that are written, files that were not encrypted are still read
+    pub keyring: Option<Keyring>,
+    /// Records the files that do not match their checksum
+    pub corruption: Corruption
 }
 impl FileStorage {
     pub fn new(storage_path: &Path) -> Result<FileStorage, FileStorageError> {
@@ -99,7 +119,10 @@ impl FileStorage {
         }
         Ok(FileStorage {
             index: BTreeSet::new(),
-            path: storage_path.to_path_buf()
+            path: storage_path.to_path_buf(),
+            compression: None,
+            keyring: None,
+            corruption: Corruption::default()
         })
     }
 }
@@ -171,11 +194,22 @@ pub fn read_file_storage_direcotory(file_storage_path: &Path) -> Result<Vec<Arc<
     Ok(uuids)
 }
 
-pub fn get_buffer(file_storage_path: &Path, uuid: &Uuid) -> Result<(BufReader<File>, u64), FileStorageError> {
-    let uuid_string = uuid.to_string();
-    let mut file_path = file_storage_path.to_path_buf();
-    file_path.push(uuid_string);
-    let file = match File::open(file_path) {
+/// The file of a msg, checked against its checksum
+struct VerifiedFile {
+    /// The file, at the start of the msg
+    file: File,
+    /// Where the msg starts in the file and its length
+    start: u64,
+    len: u64,
+    corrupt: bool
+}
+
+/// Opens the file of a msg and checks it against its checksum
+///
+/// A corrupt file is recorded, and copied to the quarantine if msgs are quarantined.
+fn open_verified(file_storage: &FileStorage, uuid: &Uuid) -> Result<VerifiedFile, FileStorageError> {
+    let file_path = get_file_path_from_id(&file_storage.path, uuid);
+    let mut file = match File::open(&file_path) {
         Ok(file) => Ok(file),
         Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
     }?;
@@ -183,18 +217,133 @@ pub fn get_buffer(file_storage_path: &Path, uuid: &Uuid) -> Result<(BufReader<Fi
         Ok(metadata) => Ok(metadata),
         Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotReadMetadata, error))
     }?;
-    let file_size = metadata.len();
-    let buffer = BufReader::new(file);
-    return Ok((buffer, file_size));
+    let verified = match verify_file(&mut file) {
+        Ok(verified) => Ok(verified),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }?;
+    let start = match verified {
+        Verified::Unsealed => 0,
+        Verified::Sealed | Verified::Corrupt => HEADER_LEN as u64
+    };
+    let corrupt = verified == Verified::Corrupt;
+    if corrupt && file_storage.corruption.report(Arc::new(*uuid)) {
+        if let Err(error) = file_storage.corruption.quarantine_file(uuid, &file_path) {
+            return Err(fs_error!(FileStorageErrorTy::CouldNotCreateFile, format!("Could not quarantine the file: {}", error)))
+        }
+    }
+    Ok(VerifiedFile { file, start, len: metadata.len() - start, corrupt })
+}
+
+fn corrupt_msg_error(uuid: &Uuid) -> FileStorageError {
+    fs_error!(FileStorageErrorTy::CorruptMsg, format!("The checksum of {} does not match", uuid.to_string()))
+}
+
+/// Opens the file of a msg, returning a reader of the msg and the length of the msg
+///
+/// The file is checked against its checksum first, a corrupt file is only read if corrupt
+/// msgs are served. An encrypted file is decrypted and a compressed file is decompressed as
+/// it is read.
+pub fn get_buffer(file_storage: &FileStorage, uuid: &Uuid) -> Result<(FileReader, u64), FileStorageError> {
+    let verified = open_verified(file_storage, uuid)?;
+    if verified.corrupt && *file_storage.corruption.policy() != CorruptionPolicy::Serve {
+        return Err(corrupt_msg_error(uuid))
+    }
+    let (buffer, decrypted_len) = match decryptor(BufReader::new(verified.file), verified.len, file_storage.keyring.as_ref(), uuid.to_string().as_bytes()) {
+        Ok(decrypted) => Ok(decrypted),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }?;
+    let (buffer, msg_len) = match decoder(buffer) {
+        Ok(decoded) => Ok(decoded),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }?;
+    Ok((buffer, msg_len.unwrap_or(decrypted_len)))
+}
+
+/// Checks the file of a msg against its checksum, recording it if it is corrupt
+///
+/// ## Returns
+/// Ok(true) if the file is corrupt
+/// Ok(false) if it is not, or if the file is no longer in the index
+pub fn scrub_file(file_storage: &FileStorage, uuid: &Uuid) -> Result<bool, FileStorageError> {
+    if !file_storage.index.contains(uuid) {
+        return Ok(false)
+    }
+    Ok(open_verified(file_storage, uuid)?.corrupt)
+}
+
+/// Creates a file that can be read back, to fill in its checksum
+fn create_file(file_path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).truncate(true).open(file_path)
 }
 
-pub async fn write_to_disk<T: Chunky>(file_storage_path: &Path, uuid: &Uuid, first_chunk: &[u8], mut payload: T) -> Result<(), FileStorageError> {
+/// Fills in the checksum of a file that was written, returning the size of the file
+fn seal(file: BufWriter<File>) -> std::io::Result<u64> {
+    let mut file = match file.into_inner() {
+        Ok(file) => file,
+        Err(error) => return Err(error.into_error())
+    };
+    seal_file(&mut file)?;
+    Ok(file.metadata()?.len())
+}
+
+/// The file a msg is written to
+///
+/// A msg is compressed before it is encrypted.
+enum FileWriter {
+    Plain(BufWriter<File>),
+    Compressed(Box<StreamEncoder<BufWriter<File>>>),
+    Encrypted(Box<StreamEncryptor<BufWriter<File>>>),
+    CompressedEncrypted(Box<StreamEncoder<StreamEncryptor<BufWriter<File>>>>)
+}
+impl FileWriter {
+    fn new(file: File, uuid: &Uuid, compression: &Option<Compression>, keyring: &Option<Keyring>) -> std::io::Result<FileWriter> {
+        let mut file = BufWriter::new(file);
+        write_header(&mut file)?;
+        match (compression, keyring) {
+            (Some(compression), Some(keyring)) => {
+                let encryptor = StreamEncryptor::new(file, keyring, uuid.to_string().as_bytes())?;
+                Ok(FileWriter::CompressedEncrypted(Box::new(StreamEncoder::new(encryptor, compression.algorithm)?)))
+            },
+            (Some(compression), None) => Ok(FileWriter::Compressed(Box::new(StreamEncoder::new(file, compression.algorithm)?))),
+            (None, Some(keyring)) => Ok(FileWriter::Encrypted(Box::new(StreamEncryptor::new(file, keyring, uuid.to_string().as_bytes())?))),
+            (None, None) => Ok(FileWriter::Plain(file))
+        }
+    }
+    fn write_all(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        match self {
+            FileWriter::Plain(file) => file.write_all(chunk),
+            FileWriter::Compressed(encoder) => encoder.write_all(chunk),
+            FileWriter::Encrypted(encryptor) => encryptor.write_all(chunk),
+            FileWriter::CompressedEncrypted(encoder) => encoder.write_all(chunk)
+        }
+    }
+    /// Flushes the file and fills in its checksum, returning the size of the file
+    fn finish(self) -> std::io::Result<u64> {
+        let file = match self {
+            FileWriter::Plain(file) => file,
+            FileWriter::Compressed(encoder) => encoder.finish()?,
+            FileWriter::Encrypted(encryptor) => encryptor.finish()?,
+            FileWriter::CompressedEncrypted(encoder) => encoder.finish()?.finish()?
+        };
+        seal(file)
+    }
+}
+
+/// Writes the msg to its file, compressing it if a compression is given and encrypting it if
+/// a keyring is given, the checksum of the file is kept in front of it
+///
+/// Returns the size of the file.
+pub async fn write_to_disk<T: Chunky>(file_storage_path: &Path, uuid: &Uuid, first_chunk: &[u8], mut payload: T, compression: &Option<Compression>, keyring: &Option<Keyring>) -> Result<u64, FileStorageError> {
     let file_path = get_file_path_from_id(file_storage_path, uuid);
-    let mut file = match File::create(file_path) {
+    let file = match create_file(&file_path) {
         Ok(file) => Ok(file),
         Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotCreateFile, error))
     }?;
-    if let Err(error) = file.write(first_chunk) {
+    let mut file = match FileWriter::new(file, uuid, compression, keyring) {
+        Ok(file) => Ok(file),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error))
+    }?;
+    if let Err(error) = file.write_all(first_chunk) {
         return Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error))
     };
     while let Some(chunk) = payload.next().await {
@@ -202,11 +351,14 @@ pub async fn write_to_disk<T: Chunky>(file_storage_path: &Path, uuid: &Uuid, fir
             Ok(chunk) => Ok(chunk),
             Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotGetChunkFromPayload, error))
         }?;
-        if let Err(error) = file.write(&chunk) {
+        if let Err(error) = file.write_all(&chunk) {
             return Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error));
         };
     };
-    Ok(())
+    match file.finish() {
+        Ok(file_size) => Ok(file_size),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error))
+    }
 }
 
 pub fn rm_from_disk(file_storage_path: &Path, uuid: &Uuid) -> Result<bool, FileStorageError> {
@@ -245,11 +397,77 @@ pub fn rm_from_file_storage(file_storage: &mut FileStorage, uuid: &Uuid) -> Resu
 }
 
 pub async fn add_to_file_storage<T: Chunky>(file_storage: &mut FileStorage, uuid: Arc<Uuid>, first_chunk: &[u8], payload: T) -> Result<(), FileStorageError> {
-    write_to_disk(&file_storage.path, &uuid, first_chunk, payload).await?;
+    write_to_disk(&file_storage.path, &uuid, first_chunk, payload, &file_storage.compression, &file_storage.keyring).await?;
     file_storage.index.insert(uuid.clone());
     Ok(())
 }
 
+/// Encrypts the file of a msg with the active key if it was not encrypted with it
+///
+/// The file is written next to the old one and then renamed over it, a compressed file stays
+/// compressed. Files that are no longer in the index are left alone, as are files when there
+/// is no keyring.
+///
+/// ## Returns
+/// Ok(true) if the file was re-encrypted
+/// Ok(false) if it did not need to be
+pub fn reencrypt_file(file_storage: &FileStorage, uuid: &Uuid) -> Result<bool, FileStorageError> {
+    let keyring = match &file_storage.keyring {
+        Some(keyring) => keyring,
+        None => return Ok(false)
+    };
+    if !file_storage.index.contains(uuid) {
+        return Ok(false)
+    }
+    let VerifiedFile { mut file, start, len, corrupt } = open_verified(file_storage, uuid)?;
+    // a new checksum would hide the damage
+    if corrupt {
+        return Err(corrupt_msg_error(uuid))
+    }
+    let mut header = Vec::with_capacity(12);
+    if let Err(error) = Read::by_ref(&mut file).take(12).read_to_end(&mut header) {
+        return Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }
+    if key_id(&header) == Some(keyring.active_key_id()) {
+        return Ok(false)
+    }
+    if let Err(error) = file.seek(SeekFrom::Start(start)) {
+        return Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }
+    let uuid_string = uuid.to_string();
+    let (mut reader, _) = match decryptor(BufReader::new(file), len, Some(keyring), uuid_string.as_bytes()) {
+        Ok(decrypted) => Ok(decrypted),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotOpenFile, error))
+    }?;
+    let file_path = get_file_path_from_id(&file_storage.path, uuid);
+    let mut tmp_path = file_storage.path.to_path_buf();
+    tmp_path.push(format!("{}.tmp", uuid_string));
+    let tmp_file = match create_file(&tmp_path) {
+        Ok(file) => Ok(file),
+        Err(error) => Err(fs_error!(FileStorageErrorTy::CouldNotCreateFile, error))
+    }?;
+    let written = write_reencrypted(&mut reader, tmp_file, keyring, uuid_string.as_bytes());
+    if let Err(error) = written {
+        let _ = remove_file(&tmp_path);
+        return Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error))
+    }
+    if let Err(error) = rename(&tmp_path, &file_path) {
+        let _ = remove_file(&tmp_path);
+        return Err(fs_error!(FileStorageErrorTy::CouldNotWriteToFIle, error))
+    }
+    Ok(true)
+}
+
+/// Writes a msg to a file encrypted with the active key, filling in the checksum of the file
+fn write_reencrypted(reader: &mut FileReader, file: File, keyring: &Keyring, associated_data: &[u8]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(file);
+    write_header(&mut file)?;
+    let mut encryptor = StreamEncryptor::new(file, keyring, associated_data)?;
+    std::io::copy(reader, &mut encryptor)?;
+    seal(encryptor.finish()?)?;
+    Ok(())
+}
+
 pub fn discover_files(file_storage: &mut FileStorage, uuids: Vec<Arc<Uuid>>) {
     for uuid in uuids {
         file_storage.index.insert(uuid);
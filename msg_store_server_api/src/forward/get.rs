@@ -0,0 +1,132 @@
+use crate::forward::Forwarder;
+use msg_store::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_FORWARD_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_FORWARD_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardGroupStats {
+    pub priority: u16,
+    /// The msgs waiting to be forwarded
+    pub backlog_count: usize,
+    pub backlog_byte_size: u64,
+    pub forwarded_count: u64,
+    pub forwarded_byte_size: u64,
+    /// The msgs the upstream rejected, which were removed without being forwarded
+    pub rejected_count: u64,
+    pub rejected_byte_size: u64
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardStats {
+    pub upstream: Option<String>,
+    /// Whether the last attempt to forward a msg succeeded
+    pub online: bool,
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Why the upstream rejected the last msg it rejected
+    pub last_rejection: Option<String>,
+    pub backlog_count: usize,
+    pub backlog_byte_size: u64,
+    /// Highest priority first, the order msgs are forwarded in
+    pub groups: Vec<ForwardGroupStats>
+}
+
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    forwarder_mutex: &Mutex<Forwarder>
+) -> Result<ForwardStats, ApiError> {
+    let store = match store_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    let forwarder = match forwarder_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    let priorities = store.groups_map.keys()
+        .chain(forwarder.forwarded.keys())
+        .chain(forwarder.rejected.keys())
+        .cloned()
+        .collect::<BTreeSet<u16>>();
+    let mut groups = vec![];
+    for priority in priorities {
+        let (backlog_count, backlog_byte_size) = match store.groups_map.get(&priority) {
+            Some(group) => (group.msgs_map.len(), group.byte_size),
+            None => (0, 0)
+        };
+        let forwarded = forwarder.forwarded.get(&priority).cloned().unwrap_or_default();
+        let rejected = forwarder.rejected.get(&priority).cloned().unwrap_or_default();
+        groups.push(ForwardGroupStats {
+            priority,
+            backlog_count,
+            backlog_byte_size,
+            forwarded_count: forwarded.count,
+            forwarded_byte_size: forwarded.byte_size,
+            rejected_count: rejected.count,
+            rejected_byte_size: rejected.byte_size
+        });
+    }
+    Ok(ForwardStats {
+        upstream: forwarder.upstream.clone(),
+        online: forwarder.failures == 0,
+        failures: forwarder.failures,
+        last_error: forwarder.last_error.clone(),
+        last_rejection: forwarder.last_rejection.clone(),
+        backlog_count: store.id_to_group_map.len(),
+        backlog_byte_size: store.byte_size,
+        groups: groups.into_iter().rev().collect()
+    })
+}
@@ -0,0 +1,158 @@
+//! Forwards msgs to an upstream msg-store, highest priority then oldest first
+//!
+//! A msg is only removed from the store once the upstream acknowledged it. While the
+//! upstream cannot be reached the forwarder retries with an exponential backoff. A msg the
+//! upstream can never add, e.g. one that exceeds its max byte size, is rejected and removed
+//! so that it does not hold back the msgs after it.
+pub mod get;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The msgs of a priority that were forwarded or rejected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Forwarded {
+    pub count: u64,
+    pub byte_size: u64
+}
+
+/// The progress of forwarding msgs to the upstream
+#[derive(Debug)]
+pub struct Forwarder {
+    /// The address of the upstream, None when forwarding is disabled
+    pub upstream: Option<String>,
+    /// The msgs forwarded since the server started, by priority
+    pub forwarded: BTreeMap<u16, Forwarded>,
+    /// The msgs the upstream rejected since the server started, by priority
+    pub rejected: BTreeMap<u16, Forwarded>,
+    /// The number of attempts that failed since the last msg was forwarded
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub last_rejection: Option<String>
+}
+
+impl Forwarder {
+    pub fn new(upstream: Option<String>) -> Forwarder {
+        Forwarder {
+            upstream,
+            forwarded: BTreeMap::new(),
+            rejected: BTreeMap::new(),
+            failures: 0,
+            last_error: None,
+            last_rejection: None
+        }
+    }
+    pub fn record_forwarded(&mut self, priority: u16, byte_size: u64) {
+        let forwarded = self.forwarded.entry(priority).or_default();
+        forwarded.count += 1;
+        forwarded.byte_size += byte_size;
+        self.failures = 0;
+        self.last_error = None;
+    }
+    /// Records a msg the upstream will never add, the upstream is online
+    pub fn record_rejected(&mut self, priority: u16, byte_size: u64, error: String) {
+        let rejected = self.rejected.entry(priority).or_default();
+        rejected.count += 1;
+        rejected.byte_size += byte_size;
+        self.failures = 0;
+        self.last_error = None;
+        self.last_rejection = Some(error);
+    }
+    pub fn record_failure(&mut self, error: String) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_error = Some(error);
+    }
+    /// The time to wait before the next attempt
+    ///
+    /// Doubles with each failure, starting at initial and never exceeding max.
+    pub fn backoff(&self, initial: Duration, max: Duration) -> Duration {
+        if self.failures == 0 {
+            return Duration::from_secs(0);
+        }
+        let factor = 2u32.saturating_pow(self.failures - 1);
+        initial.checked_mul(factor).unwrap_or(max).min(max)
+    }
+}
+
+/// Whether the upstream responded with a status that it will respond with to every attempt
+/// to add the msg
+///
+/// Other statuses, e.g. 403 by a replica that is yet to be promoted or 503 by a cluster
+/// without a leader, are retried.
+pub fn is_permanent_rejection(status: u16) -> bool {
+    matches!(status, 400 | 409 | 413 | 422)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forward::{get::handle, is_permanent_rejection, Forwarder};
+    use futures::executor::block_on;
+    use msg_store::Store;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[test]
+    fn should_double_the_backoff_until_max() {
+        let initial = Duration::from_secs(1);
+        let max = Duration::from_secs(10);
+        let mut forwarder = Forwarder::new(Some("127.0.0.1:8080".to_string()));
+        assert_eq!(Duration::from_secs(0), forwarder.backoff(initial, max));
+        let backoffs = (0..5).map(|_| {
+            forwarder.record_failure("offline".to_string());
+            forwarder.backoff(initial, max).as_secs()
+        }).collect::<Vec<u64>>();
+        assert_eq!(vec![1, 2, 4, 8, 10], backoffs);
+        forwarder.record_forwarded(1, 5);
+        assert_eq!(Duration::from_secs(0), forwarder.backoff(initial, max));
+        assert_eq!(None, forwarder.last_error);
+    }
+
+    #[test]
+    fn should_report_the_backlog_of_each_priority() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let forwarder_mx = Mutex::new(Forwarder::new(Some("127.0.0.1:8080".to_string())));
+        {
+            let mut store = store_mx.lock().unwrap();
+            store.add(1, 10).unwrap();
+            store.add(1, 5).unwrap();
+            store.add(3, 7).unwrap();
+        }
+        forwarder_mx.lock().unwrap().record_forwarded(2, 4);
+        let stats = block_on(handle(&store_mx, &forwarder_mx)).unwrap();
+        let groups = stats.groups.iter()
+            .map(|group| (group.priority, group.backlog_count, group.backlog_byte_size, group.forwarded_count, group.forwarded_byte_size))
+            .collect::<Vec<(u16, usize, u64, u64, u64)>>();
+        assert_eq!(vec![(3, 1, 7, 0, 0), (2, 0, 0, 1, 4), (1, 2, 15, 0, 0)], groups);
+        assert_eq!(3, stats.backlog_count);
+        assert_eq!(22, stats.backlog_byte_size);
+    }
+
+    #[test]
+    fn should_count_rejected_msgs_apart_from_failures() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let forwarder_mx = Mutex::new(Forwarder::new(Some("127.0.0.1:8080".to_string())));
+        {
+            let mut forwarder = forwarder_mx.lock().unwrap();
+            forwarder.record_failure("offline".to_string());
+            forwarder.record_rejected(4, 9, "400 MsgExceedesStoreMax".to_string());
+            forwarder.record_forwarded(2, 4);
+        }
+        let stats = block_on(handle(&store_mx, &forwarder_mx)).unwrap();
+        assert!(stats.online);
+        assert_eq!(Some("400 MsgExceedesStoreMax".to_string()), stats.last_rejection);
+        let groups = stats.groups.iter()
+            .map(|group| (group.priority, group.forwarded_count, group.rejected_count, group.rejected_byte_size))
+            .collect::<Vec<(u16, u64, u64, u64)>>();
+        assert_eq!(vec![(4, 0, 1, 9), (2, 1, 0, 0)], groups);
+    }
+
+    #[test]
+    fn should_only_reject_msgs_on_permanent_statuses() {
+        assert!(is_permanent_rejection(400));
+        assert!(is_permanent_rejection(409));
+        assert!(!is_permanent_rejection(403));
+        assert!(!is_permanent_rejection(404));
+        assert!(!is_permanent_rejection(500));
+        assert!(!is_permanent_rejection(503));
+    }
+}
@@ -1,7 +1,8 @@
 use msg_store::{Store, StoreError};
 use msg_store_uuid::Uuid;
-use msg_store_database_plugin::DatabaseError;
+use msg_store_database_plugin::{Batch, DatabaseError};
 use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::file_storage::{rm_from_file_storage, FileStorage, FileStorageError};
 use crate::stats::Stats;
 use std::fmt::Display;
@@ -9,6 +10,7 @@ use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
 pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
     StoreError(StoreError),
@@ -17,6 +19,7 @@ pub enum ErrTy {
 impl Display for ErrTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::StoreError(err) => write!(f, "({})", err),
@@ -64,24 +67,21 @@ macro_rules! api_error {
 
 pub async fn handle(
     store_mutex: &Mutex<Store>, 
-    database_mutex: &Mutex<Database>, 
+    database: &Database, 
     file_storage_option: &Option<Mutex<FileStorage>>,
     stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
     priority: u16
 ) -> Result<(), ApiError> {
-    let mut store = match store_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut db = match database_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut stats = match stats_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let list = {
+    let (list, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut stats = match stats_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
         // get list of messages to remove
         let list = if let Some(group) = store.groups_map.get(&priority) {
             group
@@ -92,27 +92,39 @@ pub async fn handle(
         } else {
             return Ok(());
         };
-        list
-    };
-    let mut deleted_count = 0;
-    for uuid in list.iter() {
-        if let Err(err) = store.del(uuid.clone()) {
-            return Err(api_error!(ErrTy::StoreError(err)));
+        let mut batch = Batch::new();
+        for uuid in list.iter() {
+            if let Err(err) = store.del(uuid.clone()) {
+                return Err(api_error!(ErrTy::StoreError(err)));
+            }
+            batch.del(uuid.clone());
         }
-        if let Err(err) = db.del(uuid.clone()) {
-            return Err(api_error!(ErrTy::DatabaseError(err)));
+        // the group is removed from the database at once
+        let committed = database.commit(batch);
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let recorded_changes = list.iter().map(|uuid| Change::Delete { uuid: uuid.clone() }).collect();
+        if let Err(err) = changes.record_all(recorded_changes) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)));
         }
-        if let Some(file_storage_mutex) = &file_storage_option {
-            let mut file_storage = match file_storage_mutex.lock() {
-                Ok(gaurd) => Ok(gaurd),
-                Err(err) => Err(api_error!(ErrTy::LockingError, err))
-            }?;
+        stats.deleted += list.len() as u64;
+        (list, committed)
+    };
+    if let Err(err) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    if let Some(file_storage_mutex) = &file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for uuid in list.iter() {
             if let Err(err) = rm_from_file_storage(&mut file_storage, uuid) {
                 return Err(api_error!(ErrTy::FileStorageError(err)))
             }
         }
-        deleted_count += 1;
     }
-    stats.deleted += deleted_count;
     Ok(())
 }
@@ -7,6 +7,8 @@ pub mod tests {
     
     use bytes::Bytes;
     use crate::{stats::Stats, fake_payload};
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::Notifier;
     use crate::config::StoreConfig;
     use crate::file_storage::FileStorage;
     use crate::group_defaults::set::handle as set_handle;
@@ -14,9 +16,11 @@ pub mod tests {
     use crate::group_defaults::rm::handle as rm_handle;
     use crate::msg::tests::FakePayload;
     use crate::msg::add::handle as add_handle;
+    use crate::msg::frame::WireFormat;
     use futures::executor::block_on;
     use msg_store::Store;
-    use msg_store_database_plugin::Db;
+    use crate::Database;
+    use msg_store_database_plugin::BlockingDb;
     use msg_store_database_in_memory_plugin::MemDb;
     use std::fs::read_to_string;
     use std::sync::Mutex;
@@ -25,9 +29,11 @@ pub mod tests {
     #[test]
     pub fn should_put_defaults_in_store() {
 
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(MemDb::new()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         let config_mx = Mutex::new(StoreConfig::new());
         let config_dir = TempDir::new("should_put_defaults_in_store-config-path").unwrap();
         let config_path = {
@@ -41,9 +47,10 @@ pub mod tests {
         // set group config
         block_on(set_handle(
             &store_mx,
-            &database_mx,
+            &database,
             &file_storage_op,
             &stats_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             1,
@@ -62,7 +69,11 @@ pub mod tests {
             &store_mx,
             &file_storage_op,
             &stats_mx,
-            &database_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             fake_payload
         )).unwrap();
 
@@ -89,9 +100,10 @@ pub mod tests {
         // should prune msg in priority group
         block_on(set_handle(
             &store_mx,
-            &database_mx,
+            &database,
             &file_storage_op,
             &stats_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             1,
@@ -102,8 +114,7 @@ pub mod tests {
             let store = store_mx.lock().unwrap();
             assert_eq!(store.group_defaults.get(&1).unwrap().max_byte_size.unwrap(), 3);
             assert_eq!(store.byte_size, 0);
-            let mut database = database_mx.lock().unwrap();
-            assert_eq!(database.fetch().unwrap().len(), 0);
+            assert_eq!(block_on(database.fetch()).unwrap().len(), 0);
             let stats = stats_mx.lock().unwrap();
             assert_eq!(stats.pruned, 1);
             let config_json: StoreConfig = serde_json::from_str(&read_to_string(&config_path.as_path()).unwrap()).unwrap();
@@ -112,6 +123,7 @@ pub mod tests {
 
         block_on(rm_handle(
             &store_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             1
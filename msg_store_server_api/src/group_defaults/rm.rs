@@ -1,3 +1,4 @@
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::config::{StoreConfig, GroupConfig, update_config};
 use msg_store::Store;
 use log::error;
@@ -9,6 +10,7 @@ use std::sync::Mutex;
 
 #[derive(Debug)]
 pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
     // DatabaseError(DatabaseError),
     // FileStorageError(FileStorageError),
     // StoreError(StoreError),
@@ -17,6 +19,7 @@ pub enum ErrTy {
 impl Display for ErrTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             // Self::DatabaseError(err) => write!(f, "({})", err),
             // Self::FileStorageError(err) => write!(f, "({})", err),
             // Self::StoreError(err) => write!(f, "({})", err),
@@ -69,6 +72,7 @@ pub struct Info {
 
 pub async fn handle(
     store_mutex: &Mutex<Store>,
+    changes_mutex: &Mutex<ChangeFeed>,
     configuration_mutex: &Mutex<StoreConfig>,
     configuration_path_option: &Option<PathBuf>, 
     priority: u16
@@ -84,6 +88,15 @@ pub async fn handle(
     {
         store.delete_group_defaults(priority);
     }
+    {
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        if let Err(err) = changes.record(Change::GroupDefaults { priority, max_byte_size: None }) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)))
+        }
+    }
     {
         let groups = match &config.groups {
             Some(groups) => groups,
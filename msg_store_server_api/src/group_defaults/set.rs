@@ -1,7 +1,8 @@
 use crate::config::{update_config, GroupConfig, StoreConfig, ConfigError};
 use msg_store::{GroupDefaults, Store, StoreError};
-use msg_store_database_plugin::DatabaseError;
+use msg_store_database_plugin::{Batch, DatabaseError};
 use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::file_storage::{rm_from_file_storage, FileStorage, FileStorageError};
 use crate::stats::Stats;
 use std::borrow::BorrowMut;
@@ -11,6 +12,7 @@ use std::sync::Mutex;
 
 #[derive(Debug)]
 pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
     ConfigError(ConfigError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
@@ -20,6 +22,7 @@ pub enum ErrTy {
 impl Display for ErrTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::ConfigError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
@@ -68,9 +71,10 @@ macro_rules! api_error {
 
 pub async fn handle(
     store_mutex: &Mutex<Store>,
-    database_mutex: &Mutex<Database>,
+    database: &Database,
     file_storage_option: &Option<Mutex<FileStorage>>,
     stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
     store_configuration_mutex: &Mutex<StoreConfig>,
     store_configuration_path_option: &Option<PathBuf>,
     priority: u16,
@@ -79,53 +83,60 @@ pub async fn handle(
     let defaults = GroupDefaults {
         max_byte_size: max_byte_size_option,
     };
-    let mut store = match store_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut db = match database_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut stats = match stats_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut config = match store_configuration_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let (pruned_count, msgs_removed) = {
-        match store.update_group_defaults(priority, &defaults) {
-            Ok((_bytes_removed, msgs_removed)) => Ok((msgs_removed.len() as u64, msgs_removed)),
-            Err(err) => Err(api_error!(ErrTy::StoreError(err)))
-        }
-    }?;
-    // remove messages
-    {
-        // remove from database
-        for uuid in msgs_removed.iter() {
-            if let Err(err) = db.del(uuid.clone()) {
-                return Err(api_error!(ErrTy::DatabaseError(err)))
+    let (msgs_removed, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut stats = match stats_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let (pruned_count, msgs_removed) = {
+            match store.update_group_defaults(priority, &defaults) {
+                Ok((_bytes_removed, msgs_removed)) => Ok((msgs_removed.len() as u64, msgs_removed)),
+                Err(err) => Err(api_error!(ErrTy::StoreError(err)))
             }
+        }?;
+        // remove from database in one batch
+        let mut batch = Batch::new();
+        for uuid in msgs_removed.iter() {
+            batch.del(uuid.clone());
         }
-        // remove from file_storage
-        if let Some(file_storage_mutex) = file_storage_option {
-            let mut file_storage = match file_storage_mutex.lock() {
-                Ok(gaurd) => Ok(gaurd),
-                Err(err) => Err(api_error!(ErrTy::LockingError, err))
-            }?;
-            for uuid in msgs_removed.iter() {
-                if let Err(err) = rm_from_file_storage(&mut file_storage, &uuid) {
-                    return Err(api_error!(ErrTy::FileStorageError(err)))
-                }
-            }        
+        let committed = database.commit(batch);
+        // update stats
+        stats.pruned += pruned_count;
+        // record changes
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut recorded_changes = vec![Change::GroupDefaults { priority, max_byte_size: max_byte_size_option }];
+        recorded_changes.extend(msgs_removed.iter().map(|uuid| Change::Prune { uuid: uuid.clone() }));
+        if let Err(err) = changes.record_all(recorded_changes) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)))
         }
+        (msgs_removed, committed)
+    };
+    if let Err(err) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)))
     }
-    // update stats
-    {
-        stats.pruned += pruned_count;
+    // remove from file_storage
+    if let Some(file_storage_mutex) = file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for uuid in msgs_removed.iter() {
+            if let Err(err) = rm_from_file_storage(&mut file_storage, &uuid) {
+                return Err(api_error!(ErrTy::FileStorageError(err)))
+            }
+        }        
     }
+    let mut config = match store_configuration_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
     // update config
     {
         let mk_group_config = || -> GroupConfig {
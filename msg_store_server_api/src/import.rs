@@ -0,0 +1,351 @@
+use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
+use crate::file_storage::{
+    FileStorage,
+    FileStorageError,
+    get_file_path_from_id,
+    read_file_storage_direcotory,
+    rm_from_disk,
+    rm_from_file_storage
+};
+use crate::notify::Notifier;
+use crate::stats::Stats;
+use msg_store::{Store, StoreError};
+#[cfg(feature = "leveldb")]
+use msg_store_database_leveldb_plugin::Leveldb;
+use msg_store_database_plugin::{Batch, Db, DbFuture, DatabaseError};
+use msg_store_database_redb_plugin::Redb;
+use msg_store_uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::fs::copy;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
+    DatabaseError(DatabaseError),
+    FileStorageError(FileStorageError),
+    StoreError(StoreError),
+    CouldNotCopyFile,
+    ExportNotFound,
+    FileStorageNotConfigured,
+    LockError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::FileStorageError(err) => write!(f, "({})", err),
+            Self::StoreError(err) => write!(f, "({})", err),
+            Self::CouldNotCopyFile |
+            Self::ExportNotFound |
+            Self::FileStorageNotConfigured |
+            Self::LockError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "IMPORT_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "IMPORT_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub inserted_count: usize,
+    /// The msgs the store already held
+    pub duplicate_count: usize,
+    /// The msgs that are too large for the store or their group
+    pub refused_count: usize,
+    pub pruned_count: usize
+}
+
+/// Opens the msgs of an export, exports made before they were kept in redb are read from leveldb
+#[allow(clippy::result_large_err)]
+fn open_export(import_directory: &Path) -> Result<Box<dyn Db>, ApiError> {
+    let redb_path = import_directory.join("redb");
+    if redb_path.exists() {
+        return match Redb::new(&redb_path) {
+            Ok(redb) => Ok(Box::new(redb)),
+            Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
+        }
+    }
+    #[cfg(feature = "leveldb")]
+    {
+        let leveldb_path = import_directory.join("leveldb");
+        if leveldb_path.exists() {
+            return match Leveldb::new(&leveldb_path) {
+                Ok(leveldb) => Ok(Box::new(leveldb)),
+                Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
+            }
+        }
+    }
+    Err(api_error!(ErrTy::ExportNotFound, redb_path.display()))
+}
+
+/// Adds the msgs of an export to the store, e.g. exports of several nodes
+///
+/// Msgs are kept by their uuids, msgs the store already holds are skipped so an export can
+/// be imported more than once. The export is left as it is.
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    notifier_mutex: &Mutex<Notifier>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    import_directory: &Path
+) -> Result<ImportResult, ApiError> {
+    let mut backup = open_export(import_directory)?;
+    let mut export_file_storage_path = import_directory.to_path_buf();
+    export_file_storage_path.push("file-storage");
+    let msgs = match backup.fetch_all() {
+        Ok(msgs) => Ok(msgs),
+        Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
+    }?;
+    let exported_files = if export_file_storage_path.exists() {
+        match read_file_storage_direcotory(&export_file_storage_path) {
+            Ok(uuids) => uuids.into_iter().collect::<BTreeSet<Arc<Uuid>>>(),
+            Err(error) => return Err(api_error!(ErrTy::FileStorageError(error)))
+        }
+    } else {
+        BTreeSet::new()
+    };
+    let file_storage_path = match file_storage_option {
+        Some(file_storage_mutex) => match file_storage_mutex.lock() {
+            Ok(file_storage) => Some(file_storage.path.clone()),
+            Err(err) => return Err(api_error!(ErrTy::LockError, err))
+        },
+        None if !exported_files.is_empty() => return Err(api_error!(ErrTy::FileStorageNotConfigured)),
+        None => None
+    };
+    let byte_sizes = msgs.iter().cloned().collect::<BTreeMap<Arc<Uuid>, u64>>();
+
+    // the msgs the store does not hold yet are read and their files copied before the store is locked
+    let new_uuids = match store_mutex.lock() {
+        Ok(store) => msgs.iter()
+            .filter(|(uuid, _)| !store.id_to_group_map.contains_key(uuid))
+            .map(|(uuid, _)| uuid.clone())
+            .collect::<Vec<Arc<Uuid>>>(),
+        Err(err) => return Err(api_error!(ErrTy::LockError, err))
+    };
+    let mut new_msgs = BTreeMap::new();
+    let mut copied_files = BTreeSet::new();
+    for uuid in new_uuids {
+        let msg = match backup.get(uuid.clone()) {
+            Ok(msg) => Ok(msg),
+            Err(error) => Err(api_error!(ErrTy::DatabaseError(error)))
+        }?;
+        if let (true, Some(file_storage_path)) = (exported_files.contains(&uuid), &file_storage_path) {
+            let src_file_path = get_file_path_from_id(&export_file_storage_path, &uuid);
+            let dest_file_path = get_file_path_from_id(file_storage_path, &uuid);
+            if let Err(error) = copy(&src_file_path, &dest_file_path) {
+                return Err(api_error!(ErrTy::CouldNotCopyFile, error));
+            }
+            copied_files.insert(uuid.clone());
+        }
+        new_msgs.insert(uuid, msg);
+    }
+
+    let (merge_result, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockError, err))
+        }?;
+        let merge_result = match store.merge(msgs) {
+            Ok(merge_result) => Ok(merge_result),
+            Err(error) => Err(api_error!(ErrTy::StoreError(error)))
+        }?;
+        let inserted = merge_result.inserted.iter().cloned().collect::<BTreeSet<Arc<Uuid>>>();
+        if let (Some(file_storage_mutex), Some(file_storage_path)) = (file_storage_option, &file_storage_path) {
+            let mut file_storage = match file_storage_mutex.lock() {
+                Ok(gaurd) => Ok(gaurd),
+                Err(err) => Err(api_error!(ErrTy::LockError, err))
+            }?;
+            for uuid in copied_files.iter() {
+                if inserted.contains(uuid) {
+                    file_storage.index.insert(uuid.clone());
+                } else if let Err(error) = rm_from_disk(file_storage_path, uuid) {
+                    return Err(api_error!(ErrTy::FileStorageError(error)));
+                }
+            }
+        }
+        let mut batch = Batch::new();
+        let mut recorded_changes = vec![];
+        for uuid in merge_result.msgs_removed.iter() {
+            batch.del(uuid.clone());
+            recorded_changes.push(Change::Prune { uuid: uuid.clone() });
+        }
+        for uuid in merge_result.inserted.iter() {
+            let byte_size = *byte_sizes.get(uuid).unwrap_or(&0);
+            // a msg that was removed from the store while the export was read has not been read yet
+            let msg = match new_msgs.remove(uuid) {
+                Some(msg) => msg,
+                None => match backup.get(uuid.clone()) {
+                    Ok(msg) => msg,
+                    Err(error) => return Err(api_error!(ErrTy::DatabaseError(error)))
+                }
+            };
+            batch.add(uuid.clone(), msg, byte_size);
+            recorded_changes.push(Change::Insert { uuid: uuid.clone(), byte_size });
+        }
+        let committed = database.commit(batch);
+        match stats_mutex.lock() {
+            Ok(mut stats) => {
+                stats.inserted += merge_result.inserted.len() as u64;
+                stats.pruned += merge_result.msgs_removed.len() as u64;
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockError, err))
+        };
+        match changes_mutex.lock() {
+            Ok(mut changes) => if let Err(err) = changes.record_all(recorded_changes) {
+                return Err(api_error!(ErrTy::ChangeFeedError(err)))
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockError, err))
+        };
+        (merge_result, committed)
+    };
+    if let Err(error) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(error)));
+    }
+    // the consumer groups that have passed merged msgs keep them as late
+    let saved = match store_mutex.lock() {
+        Ok(store) => merge_result.consumer_groups_behind.iter()
+            .filter_map(|name| store.consumer_groups.get(name).map(|consumer_group| database.put_cursors(name, consumer_group.to_cursors())))
+            .collect::<Vec<DbFuture<()>>>(),
+        Err(err) => return Err(api_error!(ErrTy::LockError, err))
+    };
+    for saved in saved {
+        if let Err(error) = saved.await {
+            return Err(api_error!(ErrTy::DatabaseError(error)));
+        }
+    }
+    if let Some(file_storage_mutex) = file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockError, err))
+        }?;
+        for uuid in merge_result.msgs_removed.iter() {
+            if let Err(error) = rm_from_file_storage(&mut file_storage, uuid) {
+                return Err(api_error!(ErrTy::FileStorageError(error)));
+            }
+        }
+    }
+    // let subscribers know about the new msgs
+    match notifier_mutex.lock() {
+        Ok(mut notifier) => for uuid in merge_result.inserted.iter() {
+            notifier.notify(uuid.clone(), *byte_sizes.get(uuid).unwrap_or(&0));
+        },
+        Err(err) => return Err(api_error!(ErrTy::LockError, err))
+    };
+    Ok(ImportResult {
+        inserted_count: merge_result.inserted.len(),
+        duplicate_count: merge_result.duplicates.len(),
+        refused_count: merge_result.refused.len(),
+        pruned_count: merge_result.msgs_removed.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::Notifier;
+    use crate::stats::Stats;
+    use crate::Database;
+    use futures::executor::block_on;
+    use msg_store::Store;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_database_plugin::{BlockingDb, Db};
+    use msg_store_database_redb_plugin::Redb;
+    use msg_store_uuid::Uuid;
+    use std::sync::Mutex;
+    use tempdir::TempDir;
+
+    use super::handle;
+
+    #[test]
+    fn should_keep_msgs_from_every_node() {
+        let export_dir = TempDir::new("import").unwrap();
+        let first = Uuid::from_string("1-1640000000-1-1").unwrap();
+        let second = Uuid::from_string("1-1640000000-1-2").unwrap();
+        {
+            let mut backup = Redb::new(&export_dir.path().join("redb")).unwrap();
+            backup.add(first.clone(), Bytes::from_static(b"first"), 5).unwrap();
+            backup.add(second.clone(), Bytes::from_static(b"second"), 6).unwrap();
+        }
+        let store_mx = Mutex::new(Store::new(Some(3), None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let import = || block_on(handle(&store_mx, &database, &None, &stats_mx, &notifier_mx, &changes_mx, export_dir.path())).unwrap();
+        let import_result = import();
+        assert_eq!(2, import_result.inserted_count);
+        assert_eq!(11, store_mx.lock().unwrap().byte_size);
+        assert_eq!(Bytes::from_static(b"first"), block_on(database.get(first)).unwrap());
+        assert_eq!(Bytes::from_static(b"second"), block_on(database.get(second)).unwrap());
+        // importing again changes nothing
+        let import_result = import();
+        assert_eq!(0, import_result.inserted_count);
+        assert_eq!(2, import_result.duplicate_count);
+        assert_eq!(2, stats_mx.lock().unwrap().inserted);
+        assert_eq!(11, store_mx.lock().unwrap().byte_size);
+    }
+    #[cfg(feature = "leveldb")]
+    #[test]
+    fn should_import_exports_kept_in_leveldb() {
+        use msg_store_database_leveldb_plugin::Leveldb;
+        let export_dir = TempDir::new("import-leveldb").unwrap();
+        let uuid = Uuid::from_string("1-1640000000-1-1").unwrap();
+        {
+            let mut backup = Leveldb::new(&export_dir.path().join("leveldb")).unwrap();
+            backup.add(uuid.clone(), Bytes::from_static(b"first"), 5).unwrap();
+        }
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let import_result = block_on(handle(&store_mx, &database, &None, &stats_mx, &notifier_mx, &changes_mx, export_dir.path())).unwrap();
+        assert_eq!(1, import_result.inserted_count);
+        assert_eq!(Bytes::from_static(b"first"), block_on(database.get(uuid)).unwrap());
+    }
+}
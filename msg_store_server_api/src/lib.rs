@@ -1,13 +1,20 @@
-use msg_store_database_plugin::Db;
+use msg_store_database_plugin::AsyncDb;
+pub mod changes;
+pub mod cluster;
+pub mod consumer_group;
 pub mod export;
 pub mod file_storage;
+pub mod forward;
+pub mod import;
 pub mod group;
 pub mod group_defaults;
 pub mod msg;
+pub mod notify;
+pub mod replication;
 pub mod stats;
 pub mod store;
 
-pub type Database = Box<dyn Db>;
+pub type Database = Box<dyn AsyncDb>;
 pub enum Either<A, B> {
     A(A),
     B(B)
@@ -112,10 +119,31 @@ macro_rules! config_error {
         pub node_id: Option<u16>,
         pub database: Option<String>,
         pub leveldb_path: Option<PathBuf>,
+        pub sqlite_path: Option<PathBuf>,
+        pub redb_path: Option<PathBuf>,
+        pub compression: Option<String>,
+        pub compression_threshold: Option<usize>,
+        pub compress_file_storage: Option<bool>,
+        pub byte_size_accounting: Option<String>,
+        pub encryption_key_path: Option<PathBuf>,
+        pub maintenance_interval: Option<u64>,
+        pub corruption_policy: Option<String>,
+        pub quarantine_path: Option<PathBuf>,
+        pub scrub_interval: Option<u64>,
         pub file_storage: Option<bool>,
         pub file_storage_path: Option<PathBuf>,
         pub max_byte_size: Option<u64>,
         pub groups: Option<Vec<GroupConfig>>,
+        pub change_log_path: Option<PathBuf>,
+        pub change_feed_capacity: Option<usize>,
+        pub change_log_retention: Option<u64>,
+        pub replicate_from: Option<String>,
+        pub replication_interval: Option<u64>,
+        pub forward_to: Option<String>,
+        pub forward_max_backoff: Option<u64>,
+        pub cluster: Option<Vec<String>>,
+        pub cluster_path: Option<PathBuf>,
+        pub ws_max_body_length: Option<u64>,
         pub no_update: Option<bool>,
         pub update: Option<bool>
     }
@@ -128,10 +156,31 @@ macro_rules! config_error {
                 node_id: None,
                 database: Some("mem".to_string()),
                 leveldb_path: None,
+                sqlite_path: None,
+                redb_path: None,
+                compression: None,
+                compression_threshold: None,
+                compress_file_storage: None,
+                byte_size_accounting: None,
+                encryption_key_path: None,
+                maintenance_interval: None,
+                corruption_policy: None,
+                quarantine_path: None,
+                scrub_interval: None,
                 file_storage: Some(false),
                 file_storage_path: None,
                 max_byte_size: None,
                 groups: None,
+                change_log_path: None,
+                change_feed_capacity: None,
+                change_log_retention: None,
+                replicate_from: None,
+                replication_interval: None,
+                forward_to: None,
+                forward_max_backoff: None,
+                cluster: None,
+                cluster_path: None,
+                ws_max_body_length: None,
                 no_update: None,
                 update: Some(true)
             }
@@ -168,10 +217,31 @@ macro_rules! config_error {
             self.node_id = configuration.node_id;
             self.database = configuration.database;
             self.leveldb_path = configuration.leveldb_path;
+            self.sqlite_path = configuration.sqlite_path;
+            self.redb_path = configuration.redb_path;
+            self.compression = configuration.compression;
+            self.compression_threshold = configuration.compression_threshold;
+            self.compress_file_storage = configuration.compress_file_storage;
+            self.byte_size_accounting = configuration.byte_size_accounting;
+            self.encryption_key_path = configuration.encryption_key_path;
+            self.maintenance_interval = configuration.maintenance_interval;
+            self.corruption_policy = configuration.corruption_policy;
+            self.quarantine_path = configuration.quarantine_path;
+            self.scrub_interval = configuration.scrub_interval;
             self.file_storage = configuration.file_storage;
             self.file_storage_path = configuration.file_storage_path;
             self.max_byte_size = configuration.max_byte_size;
             self.groups = configuration.groups;
+            self.change_log_path = configuration.change_log_path;
+            self.change_feed_capacity = configuration.change_feed_capacity;
+            self.change_log_retention = configuration.change_log_retention;
+            self.replicate_from = configuration.replicate_from;
+            self.replication_interval = configuration.replication_interval;
+            self.forward_to = configuration.forward_to;
+            self.forward_max_backoff = configuration.forward_max_backoff;
+            self.cluster = configuration.cluster;
+            self.cluster_path = configuration.cluster_path;
+            self.ws_max_body_length = configuration.ws_max_body_length;
             self.no_update = configuration.no_update;
         }
     }
@@ -1,13 +1,17 @@
 use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::file_storage::{
+    rm_from_disk,
     rm_from_file_storage,
-    add_to_file_storage,
+    write_to_disk,
     FileStorage
 };
+use crate::msg::frame::{self, BodyReader, FrameError, WireFormat};
+use crate::notify::Notifier;
 use crate::stats::Stats;
 use crate::file_storage::FileStorageError;
 use msg_store::{Store, StoreErrorTy};
-use msg_store_database_plugin::DatabaseError;
+use msg_store_database_plugin::{Batch, DatabaseError, DbFuture};
 use msg_store_uuid::Uuid;
 use bytes::{Bytes, BytesMut};
 use futures::{Stream, StreamExt};
@@ -28,6 +32,8 @@ pub enum MsgError {
     MsgExceedesGroupMax,
     MsgExceedesStoreMax,
     MsgLacksPriority,
+    MalformedFrame,
+    UnsupportedFrameVersion,
     CouldNotGetNextChunkFromPayload,
     CouldNotParseChunk
 }
@@ -44,6 +50,8 @@ impl Display for MsgError {
             Self::MsgExceedesGroupMax |
             Self::MsgExceedesStoreMax |
             Self::MsgLacksPriority |
+            Self::MalformedFrame |
+            Self::UnsupportedFrameVersion |
             Self::CouldNotGetNextChunkFromPayload |
             Self::CouldNotParseChunk => write!(f, "MSG_ERROR: {:#?}", self)
         }
@@ -52,6 +60,7 @@ impl Display for MsgError {
 
 #[derive(Debug)]
 pub enum AddErrorTy {
+    ChangeFeedError(ChangeFeedError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
     MsgError(MsgError),
@@ -62,6 +71,7 @@ pub enum AddErrorTy {
 impl Display for AddErrorTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::MsgError(err) => write!(f, "({})", err),
@@ -111,68 +121,184 @@ macro_rules! add_msg_error {
 
 pub trait Chunky: Stream<Item=Result<Bytes, &'static str>> + Unpin { }
 
+/// Skips any whitespace that precedes the header section
+fn trim_start(chunk: &[u8]) -> &[u8] {
+    match chunk.iter().position(|byte| !byte.is_ascii_whitespace()) {
+        Some(index) => &chunk[index..],
+        None => &[]
+    }
+}
+
+/// Parses the header section of a msg, e.g. priority=1&saveToFile=true
+#[allow(clippy::result_large_err)]
+fn parse_headers(header_section: &[u8]) -> Result<BTreeMap<String, String>, AddError> {
+    let header_section = match std::str::from_utf8(header_section) {
+        Ok(header_section) => Ok(header_section),
+        Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotParseChunk), error))
+    }?;
+    let mut metadata = BTreeMap::new();
+    for pair in header_section.split("&") {
+        let kv = pair.trim_end().trim_start().split("=").map(|txt| txt.to_string()).collect::<Vec<String>>();
+        let k = match kv.get(0) {
+            Some(k) => Ok(k.clone()),
+            None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedHeaders)))
+        }?;
+        let v = match kv.get(1) {
+            Some(v) => Ok(v.clone()),
+            None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedHeaders)))
+        }?;
+        metadata.insert(k, v);
+    }
+    Ok(metadata)
+}
+
+/// Reads the payload until the end of the header section is found
+/// 
+/// The header section is everything before the first '?'. Only the header section is
+/// required to be utf-8, the body is kept as raw bytes. Returns the parsed headers and
+/// the part of the body that was read along with the headers.
+async fn read_headers<T: Chunky>(payload: &mut T) -> Result<(BTreeMap<String, String>, BytesMut), AddError> {
+    let mut header_section = BytesMut::new();
+    let mut msg_chunk = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => Ok(chunk),
+            Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload), error))
+        }?;
+        let chunk = match header_section.is_empty() {
+            true => trim_start(&chunk),
+            false => &chunk
+        };
+        match chunk.iter().position(|byte| *byte == b'?') {
+            Some(index) => {
+                header_section.extend_from_slice(&chunk[..index]);
+                msg_chunk.extend_from_slice(&chunk[index + 1..]);
+                return Ok((parse_headers(&header_section)?, msg_chunk));
+            },
+            None => header_section.extend_from_slice(chunk)
+        }
+    }
+    Ok((BTreeMap::new(), msg_chunk))
+}
+
+fn frame_error(error: FrameError) -> AddError {
+    match error {
+        FrameError::UnsupportedVersion => add_msg_error!(AddErrorTy::MsgError(MsgError::UnsupportedFrameVersion)),
+        error => add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedFrame), error)
+    }
+}
+
+/// Reads the payload until the head of a frame is complete
+/// 
+/// Returns the headers, the part of the body that was read along with the head, and the
+/// length of the body.
+async fn read_frame_head<T: Chunky>(payload: &mut T) -> Result<(BTreeMap<String, String>, BytesMut, u64), AddError> {
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => Ok(chunk),
+            Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload), error))
+        }?;
+        buffer.extend_from_slice(&chunk);
+        let head = match frame::decode_head(&buffer) {
+            Ok(head) => Ok(head),
+            Err(error) => Err(frame_error(error))
+        }?;
+        if let Some((headers, body_len, head_len)) = head {
+            let msg_chunk = buffer.split_off(head_len);
+            if msg_chunk.len() as u64 > body_len {
+                return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedFrame), "Body exceeds length"));
+            }
+            return Ok((headers, msg_chunk, body_len));
+        }
+    }
+    Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedFrame), "Incomplete frame head"))
+}
+
+/// Reads the priority and body of a msg that is held in the database, without adding it
+///
+/// Used where the msg is added by other means, e.g. through the log of a cluster. Msgs that
+/// are saved to a file are refused with FileStorageNotConfigured.
+pub async fn read_msg<T: Chunky>(format: WireFormat, mut payload: T) -> Result<(u16, Bytes), AddError> {
+    let (mut metadata, mut msg_chunk, body_len) = match format {
+        WireFormat::QueryString => {
+            let (metadata, msg_chunk) = read_headers(&mut payload).await?;
+            (metadata, msg_chunk, None)
+        },
+        WireFormat::Framed => {
+            let (metadata, msg_chunk, body_len) = read_frame_head(&mut payload).await?;
+            (metadata, msg_chunk, Some(body_len))
+        }
+    };
+    let mut payload = match body_len {
+        Some(body_len) => BodyReader::new(payload, body_len - msg_chunk.len() as u64),
+        None => BodyReader::unbounded(payload)
+    };
+    if let Some(save_to_file_value) = metadata.get("saveToFile") {
+        if save_to_file_value.to_lowercase() == "true" {
+            return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::FileStorageNotConfigured)));
+        }
+    }
+    let priority: u16 = match metadata.remove("priority") {
+        Some(priority) => match priority.parse::<u16>() {
+            Ok(priority) => Ok(priority),
+            Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::InvalidPriority), error))
+        },
+        None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MissingPriority)))
+    }?;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => Ok(chunk),
+            Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload), error))
+        }?;
+        msg_chunk.extend_from_slice(&chunk);
+    }
+    Ok((priority, msg_chunk.freeze()))
+}
+
+/// Adds a msg to the store, the database and, if it is to be saved to a file, file storage
+///
+/// When stored byte sizes are counted, the byte size of the msg is the size it is stored at:
+/// msgs held in the database are counted by the size the database reports for them and msgs
+/// held in a file are counted by the size of the file.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle<T: Chunky>(
     store: &Mutex<Store>,
     file_storage: &Option<Mutex<FileStorage>>,
     stats: &Mutex<Stats>,
-    database: &Mutex<Database>,
+    database: &Database,
+    notifier: &Mutex<Notifier>,
+    changes: &Mutex<ChangeFeed>,
+    stored_byte_sizes: bool,
+    format: WireFormat,
     mut payload: T
 ) -> Result<Arc<Uuid>, AddError> {
 
-        let mut metadata_string = String::new();
-        let mut msg_chunk = BytesMut::new();
-        let mut metadata: BTreeMap<String, String> = BTreeMap::new();
+        let (mut metadata, mut msg_chunk, body_len) = match format {
+            WireFormat::QueryString => {
+                let (metadata, msg_chunk) = read_headers(&mut payload).await?;
+                (metadata, msg_chunk, None)
+            },
+            WireFormat::Framed => {
+                let (metadata, msg_chunk, body_len) = read_frame_head(&mut payload).await?;
+                (metadata, msg_chunk, Some(body_len))
+            }
+        };
+        let mut payload = match body_len {
+            Some(body_len) => BodyReader::new(payload, body_len - msg_chunk.len() as u64),
+            None => BodyReader::unbounded(payload)
+        };
         let mut save_to_file = false;
-    
-        while let Some(chunk) = payload.next().await {
-            let chunk = match chunk {
-                Ok(chunk) => Ok(chunk),
-                Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload), error))
-            }?;
-            let mut chunk_string = match String::from_utf8(chunk.to_vec()) {
-                Ok(chunk_string) => Ok(chunk_string),
-                Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotParseChunk), error))
-            }?;
-            chunk_string = chunk_string.trim_start().to_string();
-            // debug!("recieved chunk: {}", chunk_string);
-            metadata_string.push_str(&chunk_string);
-            if metadata_string.contains("?") {
-                // debug!("msg start found!");
-                if metadata_string.len() == 0 {
-                    return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MissingHeaders)));
-                }
-                match metadata_string.split_once("?") {
-                    Some((metadata_section, msg_section)) => {
-                        for pair in metadata_section.to_string().split("&").into_iter() {
-                            let kv = pair.trim_end().trim_start().split("=").map(|txt| txt.to_string()).collect::<Vec<String>>();
-                            let k = match kv.get(0) {
-                                Some(k) => Ok(k.clone()),
-                                None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedHeaders)))
-                            }?;
-                            let v = match kv.get(1) {
-                                Some(v) => Ok(v.clone()),
-                                None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MalformedHeaders)))
-                            }?;
-                            metadata.insert(k, v);
-                        };
-                        msg_chunk.extend_from_slice(msg_section.as_bytes());
-                    },
-                    None => {
-                        return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotParseChunk)))
-                    }
-                }
-                if let Some(save_to_file_value) = metadata.get("saveToFile") {
-                    if save_to_file_value.to_lowercase() == "true" {
-                        if let None = file_storage {
-                            while let Some(_chunk) = payload.next().await {
-    
-                            }
-                            return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::FileStorageNotConfigured)));
-                        }
-                        save_to_file = true;
+
+        if let Some(save_to_file_value) = metadata.get("saveToFile") {
+            if save_to_file_value.to_lowercase() == "true" {
+                if let None = file_storage {
+                    while let Some(_chunk) = payload.next().await {
+
                     }
+                    return Err(add_msg_error!(AddErrorTy::MsgError(MsgError::FileStorageNotConfigured)));
                 }
-                break;
+                save_to_file = true;
             }
         }
     
@@ -184,22 +310,27 @@ pub async fn handle<T: Chunky>(
             None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MissingPriority)))
         }?;
 
-        let (msg_byte_size, msg) = {
+        let (mut msg_byte_size, msg) = {
             if save_to_file == true {
-                if let Some(byte_size_override_str) = metadata.get("bytesizeOverride") {
-                    let msg_byte_size = match byte_size_override_str.parse::<u64>() {
+                let msg_byte_size = match metadata.get("bytesizeOverride") {
+                    Some(byte_size_override_str) => match byte_size_override_str.parse::<u64>() {
                         Ok(byte_size_override) => Ok(byte_size_override),
                         Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::InvalidBytesizeOverride), error))
-                    }?;
-                    let msg_parse = metadata
-                        .iter()
-                        .map(|(k, v)| format!("{}={}", k, v))
-                        .collect::<Vec<String>>()
-                        .join("&");
-                    Ok((msg_byte_size, msg_parse))
-                } else {
-                    Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MissingBytesizeOverride)))
-                }
+                    },
+                    // a frame declares the body length up front
+                    None => match body_len {
+                        Some(body_len) => Ok(body_len),
+                        None => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MissingBytesizeOverride)))
+                    }
+                }?;
+                let msg_parse = match format {
+                    WireFormat::QueryString => Bytes::from(frame::to_query_string(&metadata)),
+                    WireFormat::Framed => match frame::encode_headers(&metadata) {
+                        Ok(encoded) => encoded.freeze(),
+                        Err(error) => return Err(frame_error(error))
+                    }
+                };
+                Ok((msg_byte_size, msg_parse))
             } else {
                 while let Some(chunk) = payload.next().await {
                     let chunk = match chunk {
@@ -208,79 +339,161 @@ pub async fn handle<T: Chunky>(
                     }?;
                     msg_chunk.extend_from_slice(&chunk);
                 }
-                match String::from_utf8(msg_chunk.to_vec()) {
-                    Ok(msg) => Ok((msg.len() as u64, msg)),
-                    Err(error) => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::CouldNotParseChunk), error))
+                let msg = msg_chunk.split().freeze();
+                match stored_byte_sizes {
+                    true => match database.stored_size(msg.clone()).await {
+                        Ok(stored_size) => Ok((stored_size, msg)),
+                        Err(error) => Err(add_msg_error!(AddErrorTy::DatabaseError(error)))
+                    },
+                    false => Ok((msg.len() as u64, msg))
                 }
             }
         }?;
+        // a msg saved to a file is written before it is added to the store, so that the store
+        // is not locked while the payload is read
+        let file_uuid = if save_to_file {
+            let (file_storage_path, file_compression, keyring) = match file_storage {
+                Some(file_storage) => match file_storage.lock() {
+                    Ok(file_storage) => Ok((file_storage.path.clone(), file_storage.compression, file_storage.keyring.clone())),
+                    Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
+                },
+                None => Err(add_msg_error!(AddErrorTy::CouldNotFindFileStorage))
+            }?;
+            let uuid = match store.lock() {
+                Ok(mut store) => match store.uuid(priority) {
+                    Ok(uuid) => Ok(uuid),
+                    Err(error) => Err(add_msg_error!(AddErrorTy::StoreError(error.err_ty)))
+                },
+                Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
+            }?;
+            let file_size = match write_to_disk(&file_storage_path, &uuid, &msg_chunk, payload, &file_compression, &keyring).await {
+                Ok(file_size) => Ok(file_size),
+                Err(error) => {
+                    // a payload that was cut off leaves part of the file behind
+                    if let Err(error) = rm_from_disk(&file_storage_path, &uuid) {
+                        return Err(add_msg_error!(AddErrorTy::FileStorageError(error)));
+                    }
+                    Err(add_msg_error!(AddErrorTy::FileStorageError(error)))
+                }
+            }?;
+            if stored_byte_sizes {
+                msg_byte_size = file_size;
+            }
+            Some((file_storage_path, uuid))
+        } else {
+            None
+        };
         // Block the store to prevent read/write conflicts
-        let mut store = match store.lock() {
-            Ok(store) => Ok(store),
-            Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-        }?;
-        let mut database = match database.lock() {
-            Ok(database) => Ok(database),
-            Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-        }?;
-        let mut stats = match stats.lock() {
-            Ok(stats) => Ok(stats),
-            Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-        }?;
-        let add_result = {            
-            match store.add(priority, msg_byte_size) {
+        let (add_result, committed) = {
+            let mut store = match store.lock() {
+                Ok(store) => Ok(store),
+                Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
+            }?;
+            let add_result = match &file_uuid {
+                Some((_, uuid)) => store.add_with_uuid(uuid.clone(), msg_byte_size),
+                None => store.add(priority, msg_byte_size)
+            };
+            let add_result = match add_result {
                 Ok(add_result) => Ok(add_result),
-                Err(error) => match error.err_ty {
-                    StoreErrorTy::ExceedesStoreMax => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgExceedesStoreMax))),
-                    StoreErrorTy::ExceedesGroupMax => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgExceedesGroupMax))),
-                    StoreErrorTy::LacksPriority => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgLacksPriority))),
-                    error_ty => Err(add_msg_error!(AddErrorTy::StoreError(error_ty)))
+                Err(error) => {
+                    if let Some((file_storage_path, uuid)) = &file_uuid {
+                        if let Err(error) = rm_from_disk(file_storage_path, uuid) {
+                            return Err(add_msg_error!(AddErrorTy::FileStorageError(error)));
+                        }
+                    }
+                    match error.err_ty {
+                        StoreErrorTy::ExceedesStoreMax => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgExceedesStoreMax))),
+                        StoreErrorTy::ExceedesGroupMax => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgExceedesGroupMax))),
+                        StoreErrorTy::LacksPriority => Err(add_msg_error!(AddErrorTy::MsgError(MsgError::MsgLacksPriority))),
+                        error_ty => Err(add_msg_error!(AddErrorTy::StoreError(error_ty)))
+                    }
+                }
+            }?;
+            if let (Some(file_storage), Some((_, uuid))) = (file_storage, &file_uuid) {
+                match file_storage.lock() {
+                    Ok(mut file_storage) => file_storage.index.insert(uuid.clone()),
+                    Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+                };
+            }
+            // the pruned msgs are removed and the msg is added in one batch, the batch is
+            // started while the store is locked so that it keeps its place among other writes
+            let mut batch = Batch::new();
+            for uuid in add_result.msgs_removed.iter() {
+                batch.del(uuid.clone());
+            }
+            batch.add(add_result.uuid.clone(), msg, msg_byte_size);
+            let committed = database.commit(batch);
+            (add_result, committed)
+        };
+        if let Err(error) = committed.await {
+            // the msg was never added and the msgs it pruned are still in the database
+            match store.lock() {
+                Ok(mut store) => if let Err(error) = store.undo_add(add_result) {
+                    return Err(add_msg_error!(AddErrorTy::StoreError(error.err_ty)));
                 },
+                Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+            };
+            if let (Some(file_storage), Some((_, uuid))) = (file_storage, &file_uuid) {
+                match file_storage.lock() {
+                    Ok(mut file_storage) => if let Err(error) = rm_from_file_storage(&mut file_storage, uuid) {
+                        return Err(add_msg_error!(AddErrorTy::FileStorageError(error)));
+                    },
+                    Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+                };
             }
-        }?;
-        
-        // remove msgs from db
-        let mut deleted_count = 0;
-        for uuid in add_result.msgs_removed.into_iter() {
-            {
-                // TODO: remove this comment
-                // let mut database = match database.lock() {
-                //     Ok(database) => Ok(database),
-                //     Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-                // }?;
-                if let Err(error) = database.del(uuid.clone()) {
-                    return Err(add_msg_error!(AddErrorTy::DatabaseError(error)))
-                }
+            return Err(add_msg_error!(AddErrorTy::DatabaseError(error)));
+        }
+        // the changes are recorded once the batch is committed, a msg that was deleted in the
+        // meantime was recorded as deleted then and is not recorded as inserted after that
+        let saved = {
+            let store = match store.lock() {
+                Ok(store) => Ok(store),
+                Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
+            }?;
+            match stats.lock() {
+                Ok(mut stats) => {
+                    stats.pruned += add_result.msgs_removed.len() as u64;
+                    stats.inserted += 1;
+                },
+                Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+            };
+            let mut recorded_changes = add_result.msgs_removed.iter()
+                .map(|uuid| Change::Prune { uuid: uuid.clone() })
+                .collect::<Vec<Change>>();
+            if store.id_to_group_map.contains_key(&add_result.uuid) {
+                recorded_changes.push(Change::Insert { uuid: add_result.uuid.clone(), byte_size: msg_byte_size });
             }
-            if let Some(file_storage) = &file_storage {
-                let mut file_storage = match file_storage.lock() {
-                    Ok(file_storage) => Ok(file_storage),
-                    Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-                }?;
-                if let Err(error) = rm_from_file_storage(&mut file_storage, &uuid) {
-                    return Err(add_msg_error!(AddErrorTy::FileStorageError(error)));
-                }
+            match changes.lock() {
+                Ok(mut changes) => if let Err(error) = changes.record_all(recorded_changes) {
+                    return Err(add_msg_error!(AddErrorTy::ChangeFeedError(error)));
+                },
+                Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+            };
+            // the consumer groups that have passed the msg keep it as late
+            add_result.consumer_groups_behind.iter()
+                .filter_map(|name| store.consumer_groups.get(name).map(|consumer_group| database.put_cursors(name, consumer_group.to_cursors())))
+                .collect::<Vec<DbFuture<()>>>()
+        };
+        for saved in saved {
+            if let Err(error) = saved.await {
+                return Err(add_msg_error!(AddErrorTy::DatabaseError(error)));
             }
-            deleted_count += 1;
         }
-        stats.pruned += deleted_count;
-        stats.inserted += 1;
-        // add to file manager if needed
-        if save_to_file {
-            if let Some(file_storage) = file_storage {
-                let mut file_storage = match file_storage.lock() {
-                    Ok(file_storage) => Ok(file_storage),
-                    Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
-                }?;
-                if let Err(error) = add_to_file_storage(&mut file_storage, add_result.uuid.clone(), &msg_chunk, payload).await {
-                    return Err(add_msg_error!(AddErrorTy::FileStorageError(error)))
+        if let Some(file_storage) = &file_storage {
+            let mut file_storage = match file_storage.lock() {
+                Ok(file_storage) => Ok(file_storage),
+                Err(error) => Err(add_msg_error!(AddErrorTy::LockingError, error))
+            }?;
+            for uuid in add_result.msgs_removed.iter() {
+                if let Err(error) = rm_from_file_storage(&mut file_storage, uuid) {
+                    return Err(add_msg_error!(AddErrorTy::FileStorageError(error)));
                 }
-            } else {
-                return Err(add_msg_error!(AddErrorTy::CouldNotFindFileStorage));
             }
         }
-        if let Err(error) = database.add(add_result.uuid.clone(), Bytes::copy_from_slice(msg.as_bytes()), msg_byte_size) {
-            return Err(add_msg_error!(AddErrorTy::DatabaseError(error)));
-        }
+        // let subscribers know about the new msg
+        match notifier.lock() {
+            Ok(mut notifier) => notifier.notify(add_result.uuid.clone(), msg_byte_size),
+            Err(error) => return Err(add_msg_error!(AddErrorTy::LockingError, error))
+        };
         Ok(add_result.uuid)
 }
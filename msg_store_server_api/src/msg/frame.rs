@@ -0,0 +1,311 @@
+//! The length-prefixed framing format for msgs
+//!
+//! A frame consists of a header section followed by a body of explicit length.
+//! All integers are big endian.
+//!
+//! ```text
+//! version:      u8    (currently 1)
+//! header count: u16
+//! headers:      [key length: u16][key: utf-8][value length: u32][value: utf-8] * header count
+//! body length:  u64
+//! body:         [u8] * body length
+//! ```
+//!
+//! Header keys and values are not escaped, so they may contain any character including
+//! '?', '&' and '='. The same framing is used for requests and responses.
+use crate::msg::add::Chunky;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use futures::task::{Context, Poll};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::pin::Pin;
+
+/// The Content-Type used to select the framed format
+pub const CONTENT_TYPE: &str = "application/vnd.msg-store.frame";
+/// The current version of the framing format
+pub const VERSION: u8 = 1;
+/// The largest header section that will be accepted
+pub const MAX_HEADER_SECTION_SIZE: usize = 1_048_576;
+
+/// Decoded header keys and values
+pub type Headers = BTreeMap<String, String>;
+
+/// The wire formats a msg can be sent or received in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    /// The legacy format, e.g. priority=1&saveToFile=true?my message
+    QueryString,
+    /// The length-prefixed format described in this module
+    Framed
+}
+impl WireFormat {
+    /// Selects the format from a Content-Type or Accept header value
+    pub fn from_content_type(content_type: Option<&str>) -> WireFormat {
+        match content_type {
+            Some(content_type) if content_type.contains(CONTENT_TYPE) => WireFormat::Framed,
+            _ => WireFormat::QueryString
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    HeaderSectionTooLarge,
+    InvalidUtf8,
+    TooManyHeaders,
+    UnsupportedVersion
+}
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HeaderSectionTooLarge |
+            Self::InvalidUtf8 |
+            Self::TooManyHeaders |
+            Self::UnsupportedVersion => write!(f, "FRAME_ERROR: {:#?}", self)
+        }
+    }
+}
+
+/// Encodes the version, header count and headers
+pub fn encode_headers(headers: &Headers) -> Result<BytesMut, FrameError> {
+    if headers.len() > u16::MAX as usize {
+        return Err(FrameError::TooManyHeaders);
+    }
+    let mut bytes = BytesMut::new();
+    bytes.put_u8(VERSION);
+    bytes.put_u16(headers.len() as u16);
+    for (key, value) in headers.iter() {
+        if key.len() > u16::MAX as usize || value.len() > u32::MAX as usize {
+            return Err(FrameError::HeaderSectionTooLarge);
+        }
+        bytes.put_u16(key.len() as u16);
+        bytes.put_slice(key.as_bytes());
+        bytes.put_u32(value.len() as u32);
+        bytes.put_slice(value.as_bytes());
+    }
+    if bytes.len() > MAX_HEADER_SECTION_SIZE {
+        return Err(FrameError::HeaderSectionTooLarge);
+    }
+    Ok(bytes)
+}
+
+/// Encodes everything that precedes the body
+pub fn encode_head(headers: &Headers, body_len: u64) -> Result<Bytes, FrameError> {
+    let mut bytes = encode_headers(headers)?;
+    bytes.put_u64(body_len);
+    Ok(bytes.freeze())
+}
+
+/// Encodes a complete frame
+pub fn encode(headers: &Headers, body: &[u8]) -> Result<Bytes, FrameError> {
+    let head = encode_head(headers, body.len() as u64)?;
+    let mut bytes = BytesMut::with_capacity(head.len() + body.len());
+    bytes.put_slice(&head);
+    bytes.put_slice(body);
+    Ok(bytes.freeze())
+}
+
+fn read_string(buf: &mut &[u8], len: usize) -> Result<Option<String>, FrameError> {
+    if buf.remaining() < len {
+        return Ok(None);
+    }
+    let string = match std::str::from_utf8(&buf[..len]) {
+        Ok(string) => Ok(string.to_string()),
+        Err(_error) => Err(FrameError::InvalidUtf8)
+    }?;
+    buf.advance(len);
+    Ok(Some(string))
+}
+
+/// Decodes the version, header count and headers
+///
+/// Returns Ok(None) if more bytes are needed, otherwise the headers and the number of bytes
+/// that were consumed.
+pub fn decode_headers(bytes: &[u8]) -> Result<Option<(Headers, usize)>, FrameError> {
+    let mut buf = bytes;
+    if buf.remaining() < 1 {
+        return Ok(None);
+    }
+    if buf.get_u8() != VERSION {
+        return Err(FrameError::UnsupportedVersion);
+    }
+    if buf.remaining() < 2 {
+        return Ok(None);
+    }
+    let header_count = buf.get_u16();
+    let mut headers = BTreeMap::new();
+    for _ in 0..header_count {
+        if buf.remaining() < 2 {
+            return Ok(None);
+        }
+        let key_len = buf.get_u16() as usize;
+        let key = match read_string(&mut buf, key_len)? {
+            Some(key) => key,
+            None => return Ok(None)
+        };
+        if buf.remaining() < 4 {
+            return Ok(None);
+        }
+        let value_len = buf.get_u32() as usize;
+        if value_len > MAX_HEADER_SECTION_SIZE {
+            return Err(FrameError::HeaderSectionTooLarge);
+        }
+        let value = match read_string(&mut buf, value_len)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        headers.insert(key, value);
+    }
+    Ok(Some((headers, bytes.len() - buf.remaining())))
+}
+
+/// Decodes everything that precedes the body
+///
+/// Returns Ok(None) if more bytes are needed, otherwise the headers, the body length and the
+/// number of bytes that were consumed.
+pub fn decode_head(bytes: &[u8]) -> Result<Option<(Headers, u64, usize)>, FrameError> {
+    let (headers, headers_len) = match decode_headers(bytes)? {
+        Some(decoded) => decoded,
+        None => {
+            if bytes.len() > MAX_HEADER_SECTION_SIZE {
+                return Err(FrameError::HeaderSectionTooLarge);
+            }
+            return Ok(None)
+        }
+    };
+    let mut buf = &bytes[headers_len..];
+    if buf.remaining() < 8 {
+        return Ok(None);
+    }
+    let body_len = buf.get_u64();
+    Ok(Some((headers, body_len, headers_len + 8)))
+}
+
+/// Serializes headers in the legacy format, e.g. priority=1&saveToFile=true
+pub fn to_query_string(headers: &Headers) -> String {
+    headers
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// Decodes the headers held in the database for a file storage msg
+///
+/// Msgs added with the framed format have their headers encoded with encode_headers,
+/// while msgs added with the legacy format are stored as a query string.
+pub fn decode_stored_headers(bytes: &[u8]) -> Result<Headers, FrameError> {
+    if bytes.first() == Some(&VERSION) {
+        match decode_headers(bytes)? {
+            Some((headers, _headers_len)) => Ok(headers),
+            None => Err(FrameError::HeaderSectionTooLarge)
+        }
+    } else {
+        let query_string = match std::str::from_utf8(bytes) {
+            Ok(query_string) => Ok(query_string),
+            Err(_error) => Err(FrameError::InvalidUtf8)
+        }?;
+        let mut headers = BTreeMap::new();
+        for pair in query_string.split("&").filter(|pair| !pair.is_empty()) {
+            if let Some((k, v)) = pair.split_once("=") {
+                headers.insert(k.to_string(), v.to_string());
+            }
+        }
+        Ok(headers)
+    }
+}
+
+/// Yields the rest of a msg body from the payload
+///
+/// When the body length is known, exactly that many bytes are yielded and an error is
+/// returned if the payload ends early or contains more bytes than expected.
+pub struct BodyReader<T: Chunky> {
+    payload: T,
+    remaining: Option<u64>
+}
+impl<T: Chunky> BodyReader<T> {
+    /// Reads exactly `remaining` bytes
+    pub fn new(payload: T, remaining: u64) -> BodyReader<T> {
+        BodyReader {
+            payload,
+            remaining: Some(remaining)
+        }
+    }
+    /// Reads until the payload ends
+    pub fn unbounded(payload: T) -> BodyReader<T> {
+        BodyReader {
+            payload,
+            remaining: None
+        }
+    }
+}
+impl<T: Chunky> Stream for BodyReader<T> {
+    type Item = Result<Bytes, &'static str>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = match self.payload.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+            Poll::Ready(None) => {
+                if let Some(remaining) = self.remaining {
+                    if remaining != 0 {
+                        return Poll::Ready(Some(Err("IncompleteBody")));
+                    }
+                }
+                return Poll::Ready(None)
+            },
+            Poll::Pending => return Poll::Pending
+        };
+        if let Some(remaining) = self.remaining {
+            if chunk.len() as u64 > remaining {
+                return Poll::Ready(Some(Err("BodyExceedsLength")));
+            }
+            self.remaining = Some(remaining - chunk.len() as u64);
+        }
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+impl<T: Chunky> Chunky for BodyReader<T> { }
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_head, decode_stored_headers, encode, encode_headers, FrameError, VERSION};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn should_round_trip_headers_with_reserved_characters() {
+        let mut headers = BTreeMap::new();
+        headers.insert("priority".to_string(), "1".to_string());
+        headers.insert("fileName".to_string(), "a?b&c=d".to_string());
+        let frame = encode(&headers, b"?body").unwrap();
+        let (decoded, body_len, head_len) = decode_head(&frame).unwrap().unwrap();
+        assert_eq!(headers, decoded);
+        assert_eq!(5, body_len);
+        assert_eq!(b"?body", &frame[head_len..]);
+    }
+
+    #[test]
+    fn should_wait_for_more_bytes() {
+        let mut headers = BTreeMap::new();
+        headers.insert("priority".to_string(), "1".to_string());
+        let frame = encode(&headers, b"body").unwrap();
+        for len in 0..(frame.len() - 4) {
+            assert_eq!(None, decode_head(&frame[..len]).unwrap());
+        }
+    }
+
+    #[test]
+    fn should_reject_unknown_versions() {
+        assert_eq!(FrameError::UnsupportedVersion, decode_head(&[VERSION + 1, 0, 0]).err().unwrap());
+    }
+
+    #[test]
+    fn should_decode_stored_headers_in_either_format() {
+        let mut headers = BTreeMap::new();
+        headers.insert("bytesizeOverride".to_string(), "4".to_string());
+        headers.insert("saveToFile".to_string(), "true".to_string());
+        let encoded = encode_headers(&headers).unwrap();
+        assert_eq!(headers, decode_stored_headers(&encoded).unwrap());
+        assert_eq!(headers, decode_stored_headers(b"bytesizeOverride=4&saveToFile=true").unwrap());
+    }
+}
@@ -1,15 +1,18 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use crate::{Database, Either};
-use crate::file_storage::{get_buffer, FileStorage, FileStorageError};
+use crate::file_storage::{get_buffer, FileReader, FileStorage, FileStorageError, FileStorageErrorTy};
+use crate::msg::frame::{self, WireFormat};
+use crate::notify::{Notifier, PriorityRange};
 use msg_store::{Store, StoreError};
 use msg_store_uuid::Uuid;
-use msg_store_database_plugin::DatabaseError;
-use futures::stream::Stream;
+use msg_store_database_plugin::{DatabaseError, DatabaseErrorTy, DbStream, CHUNK_SIZE};
+use futures::future::{self, Future};
+use futures::stream::{Stream, StreamExt};
 use futures::task::{Context, Poll};
+use std::collections::BTreeMap;
 use std::fmt::Display;
-use std::fs::File;
 use std::pin::Pin;
-use std::io::{BufReader, Read};
+use std::io::Read;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
@@ -18,6 +21,7 @@ pub enum GetErrorTy {
     FileStorageError(FileStorageError),
     MsgError(MsgError),
     StoreError(StoreError),
+    CorruptMsg(Arc<Uuid>),
     CouldNotFindFileStorage,
     LockingError,
     CouldNotGetNextChunkFromPayload,
@@ -30,6 +34,7 @@ impl Display for GetErrorTy {
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::MsgError(err) => write!(f, "({})", err),
             Self::StoreError(err) => write!(f, "({})", err),
+            Self::CorruptMsg(uuid) => write!(f, "CorruptMsg({})", uuid.to_string()),
             Self::CouldNotFindFileStorage |
             Self::LockingError |
             Self::CouldNotGetNextChunkFromPayload |
@@ -76,21 +81,42 @@ macro_rules! get_msg_error {
 }
 
 
+/// Where the body of a `ReturnBody` is read from
+pub enum BodySource {
+    File(FileReader),
+    /// The chunks of a msg too large to be read from the database at once
+    Database(DbStream)
+}
+
 pub struct ReturnBody {
-    pub header: String,
-    pub msg: BufReader<File>,
+    pub uuid: Arc<Uuid>,
+    pub header: Bytes,
+    pub msg: BodySource,
     pub file_size: u64,
     pub bytes_read: u64,
     pub headers_sent: bool,
     pub msg_sent: bool
 }
 impl ReturnBody {
-    pub fn new(header: String, file_size: u64, msg: BufReader<File>) -> ReturnBody {
+    pub fn new(uuid: Arc<Uuid>, header: Bytes, file_size: u64, msg: FileReader) -> ReturnBody {
         ReturnBody {
+            uuid,
             header,
             file_size,
             bytes_read: 0,
-            msg,
+            msg: BodySource::File(msg),
+            headers_sent: false,
+            msg_sent: false
+        }
+    }
+    /// Streams a msg that is held in the database, `msg_len` is the length of the msg
+    pub fn from_database(uuid: Arc<Uuid>, header: Bytes, msg_len: u64, chunks: DbStream) -> ReturnBody {
+        ReturnBody {
+            uuid,
+            header,
+            file_size: msg_len,
+            bytes_read: 0,
+            msg: BodySource::Database(chunks),
             headers_sent: false,
             msg_sent: false
         }
@@ -99,43 +125,106 @@ impl ReturnBody {
 impl Stream for ReturnBody {
     type Item = Result<Bytes, GetError>;
     fn poll_next(
-        mut self: Pin<&mut Self>, 
-        _cx: &mut Context<'_>
+        self: Pin<&mut Self>, 
+        cx: &mut Context<'_>
     ) -> Poll<Option<Self::Item>> {
-        if self.msg_sent {
+        let body = self.get_mut();
+        if body.msg_sent {
             return Poll::Ready(None);
         }
-        if self.headers_sent {            
-            let limit = self.file_size - self.bytes_read;
-            if limit >= 665600 {
-                let mut buffer = [0; 665600];
-                if let Err(error) = self.msg.read(&mut buffer) {
-                    return Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotGetNextChunkFromPayload, error))));
-                }
-                {
-                    let mut body = self.as_mut().get_mut();
-                    body.bytes_read += 665600;
+        if !body.headers_sent {
+            body.headers_sent = true;
+            return Poll::Ready(Some(Ok(body.header.clone())));
+        }
+        let limit = body.file_size - body.bytes_read;
+        match &mut body.msg {
+            BodySource::File(file) => {
+                if limit >= 665600 {
+                    let mut buffer = [0; 665600];
+                    let bytes_read = match file.read(&mut buffer) {
+                        Ok(bytes_read) => bytes_read,
+                        Err(error) => {
+                            return Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotGetNextChunkFromPayload, error))));
+                        }
+                    };
+                    if bytes_read == 0 {
+                        return Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotGetNextChunkFromPayload, "Unexpected end of file"))));
+                    }
+                    body.bytes_read += bytes_read as u64;
+                    Poll::Ready(Some(Ok(Bytes::copy_from_slice(&buffer[..bytes_read]))))
+                } else if limit == 0 {
+                    Poll::Ready(None)
+                } else {
+                    let mut buffer = Vec::with_capacity(limit as usize);
+                    if let Err(error) = file.read_to_end(&mut buffer) {
+                        return Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))));
+                    };
+                    body.msg_sent = true;
+                    Poll::Ready(Some(Ok(Bytes::from(buffer))))
                 }
-                return Poll::Ready(Some(Ok(Bytes::copy_from_slice(&buffer))));
-            } else if limit == 0 {
-                return Poll::Ready(None);
-            } else {
-                let mut buffer = Vec::with_capacity(limit as usize);
-                if let Err(error) = self.msg.read_to_end(&mut buffer) {
-                    return Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))));
-                };
-                {
-                    let mut body = self.as_mut().get_mut();
+            },
+            BodySource::Database(chunks) => {
+                if limit == 0 {
                     body.msg_sent = true;
+                    return Poll::Ready(None);
+                }
+                match chunks.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        // a msg never gives more than its length
+                        let chunk = chunk.slice(..chunk.len().min(limit as usize));
+                        body.bytes_read += chunk.len() as u64;
+                        Poll::Ready(Some(Ok(chunk)))
+                    },
+                    Poll::Ready(Some(Err(error))) => {
+                        body.msg_sent = true;
+                        Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::DatabaseError(error)))))
+                    },
+                    Poll::Ready(None) => {
+                        body.msg_sent = true;
+                        Poll::Ready(Some(Err(get_msg_error!(GetErrorTy::CouldNotGetNextChunkFromPayload, "Unexpected end of msg"))))
+                    },
+                    Poll::Pending => Poll::Pending
                 }
-                return Poll::Ready(Some(Ok(Bytes::copy_from_slice(&buffer))));
-            }
-        } else {
-            {
-                let mut body = self.as_mut().get_mut();
-                body.headers_sent = true;
             }
-            Poll::Ready(Some(Ok(Bytes::copy_from_slice(&self.header.as_bytes()))))
+        }
+    }
+}
+
+/// A msg that is held entirely in the database
+/// 
+/// The header section is kept apart from the body so that the body can contain any bytes.
+/// The header is already encoded in the requested wire format, e.g. uuid=1-2-3-4? or the
+/// head of a frame.
+#[derive(Debug)]
+pub struct StoredMsg {
+    pub uuid: Arc<Uuid>,
+    pub header: Bytes,
+    pub msg: Bytes
+}
+impl StoredMsg {
+    pub fn new(uuid: Arc<Uuid>, header: Bytes, msg: Bytes) -> StoredMsg {
+        StoredMsg {
+            uuid,
+            header,
+            msg
+        }
+    }
+    /// Joins the header section and the body
+    pub fn to_bytes(&self) -> Bytes {
+        let mut bytes = BytesMut::with_capacity(self.header.len() + self.msg.len());
+        bytes.extend_from_slice(&self.header);
+        bytes.extend_from_slice(&self.msg);
+        bytes.freeze()
+    }
+}
+
+/// Encodes everything that precedes the body of a response
+fn encode_header(format: WireFormat, headers: &BTreeMap<String, String>, body_len: u64) -> Result<Bytes, GetError> {
+    match format {
+        WireFormat::QueryString => Ok(Bytes::from(format!("{}?", frame::to_query_string(headers)))),
+        WireFormat::Framed => match frame::encode_head(headers, body_len) {
+            Ok(head) => Ok(head),
+            Err(error) => Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))
         }
     }
 }
@@ -161,66 +250,170 @@ impl Display for MsgError {
     }
 }
 
+fn encode_uuid_header(format: WireFormat, uuid: &Uuid, body_len: u64) -> Result<Bytes, GetError> {
+    let mut headers = BTreeMap::new();
+    headers.insert("uuid".to_string(), uuid.to_string());
+    encode_header(format, &headers, body_len)
+}
 
+/// Gets a msg, skipping the msgs that are found to be corrupt
+///
+/// A corrupt msg is taken out of the store so that it is not read again, its data is removed
+/// by whoever takes the corrupt msgs from the `Corruption` of the database.
 pub async fn handle(
     store: &Mutex<Store>,
-    database_mutex: &Mutex<Database>,
+    database: &Database,
     file_storage_option: &Option<Mutex<FileStorage>>,
     uuid_option: Option<Arc<Uuid>>,
     priority_option: Option<u16>,
-    reverse_option: bool
-) -> Result<Option<Either<ReturnBody, String>>, GetError> {
-    let store = match store.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
-    }?;
-    let mut database = match database_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
-    }?;
-    let uuid = {
-        match store.get(uuid_option, priority_option, reverse_option) {
-            Ok(uuid) => match uuid {
-                Some(uuid) => Ok(uuid),
-                None => return Ok(None)
+    reverse_option: bool,
+    format: WireFormat
+) -> Result<Option<Either<ReturnBody, StoredMsg>>, GetError> {
+    loop {
+        match get(store, database, file_storage_option, uuid_option.clone(), priority_option, reverse_option, format).await {
+            Err(GetError { err_ty: GetErrorTy::CorruptMsg(uuid), .. }) => {
+                let mut store = match store.lock() {
+                    Ok(gaurd) => Ok(gaurd),
+                    Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
+                }?;
+                if let Err(error) = store.del(uuid) {
+                    return Err(get_msg_error!(GetErrorTy::StoreError(error)))
+                }
+                if uuid_option.is_some() {
+                    return Ok(None)
+                }
             },
-            Err(error) => Err(get_msg_error!(GetErrorTy::StoreError(error)))
+            result => return result
         }
-    }?;
-    let msg = {        
-        match database.get(uuid.clone()) {
-            Ok(msg) => Ok(msg),
-            Err(error) => Err(get_msg_error!(GetErrorTy::DatabaseError(error)))
-        }
-    }?;
-    if let Some(file_storage_mutex) = &file_storage_option {
-        let file_storage = match file_storage_mutex.lock() {
+    }
+}
+
+fn is_corrupt_msg(error: &DatabaseError) -> bool {
+    matches!(error.err_ty, DatabaseErrorTy::CorruptMsg)
+}
+
+async fn get(
+    store: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    uuid_option: Option<Arc<Uuid>>,
+    priority_option: Option<u16>,
+    reverse_option: bool,
+    format: WireFormat
+) -> Result<Option<Either<ReturnBody, StoredMsg>>, GetError> {
+    let (uuid, msg, file_option) = {
+        let store = match store.lock() {
             Ok(gaurd) => Ok(gaurd),
             Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
         }?;
-        if file_storage.index.contains(&uuid) {
-            let (file_buffer, file_size) = match get_buffer(&file_storage.path, &uuid) {
-                Ok(buffer_option) => Ok(buffer_option),
-                Err(error) => Err(get_msg_error!(GetErrorTy::FileStorageError(error)))
-            }?;
-            let msg_header = match String::from_utf8(msg.to_vec()) {
-                Ok(msg_header) => Ok(msg_header),
-                Err(error) => Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))
-            }?;
-            let body = ReturnBody::new(format!("uuid={}&{}?", uuid.to_string(), msg_header), file_size, file_buffer);
-            Ok(Some(Either::A(body)))
-        } else {
-            let msg = match String::from_utf8(msg.to_vec()) {
-                Ok(msg) => Ok(msg),
-                Err(error) => Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))
+        let uuid = {
+            match store.get(uuid_option, priority_option, reverse_option) {
+                Ok(uuid) => match uuid {
+                    Some(uuid) => Ok(uuid),
+                    None => return Ok(None)
+                },
+                Err(error) => Err(get_msg_error!(GetErrorTy::StoreError(error)))
+            }
+        }?;
+        // the file is opened and the msg is read before a later delete can remove them
+        let file_option = match &file_storage_option {
+            Some(file_storage_mutex) => {
+                let file_storage = match file_storage_mutex.lock() {
+                    Ok(gaurd) => Ok(gaurd),
+                    Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
+                }?;
+                if file_storage.index.contains(&uuid) {
+                    match get_buffer(&file_storage, &uuid) {
+                        Ok(buffer) => Some(buffer),
+                        Err(FileStorageError { err_ty: FileStorageErrorTy::CorruptMsg, .. }) => return Err(get_msg_error!(GetErrorTy::CorruptMsg(uuid))),
+                        Err(error) => return Err(get_msg_error!(GetErrorTy::FileStorageError(error)))
+                    }
+                } else {
+                    None
+                }
+            },
+            None => None
+        };
+        // a msg too large to be read at once is streamed from the database instead, its
+        // chunks are read as the body is sent
+        let streamed = match (&file_option, store.id_to_group_map.get(&uuid)) {
+            (None, Some(priority)) => store.groups_map.get(priority)
+                .and_then(|group| group.msgs_map.get(&uuid).cloned())
+                .filter(|byte_size| *byte_size > CHUNK_SIZE as u64),
+            _ => None
+        };
+        let msg = match streamed {
+            // the byte size can be the compressed size, so the length is read from the database
+            Some(_) => Either::B((database.msg_len(uuid.clone()), database.get_chunks(uuid.clone(), CHUNK_SIZE))),
+            None => Either::A(database.get(uuid.clone()))
+        };
+        (uuid, msg, file_option)
+    };
+    let msg = match msg {
+        Either::A(msg) => match msg.await {
+            Ok(msg) => Ok(msg),
+            Err(error) if is_corrupt_msg(&error) => Err(get_msg_error!(GetErrorTy::CorruptMsg(uuid.clone()))),
+            Err(error) => Err(get_msg_error!(GetErrorTy::DatabaseError(error)))
+        }?,
+        Either::B((msg_len, chunks)) => {
+            // the msg is checked as its length is read, before it is streamed
+            let msg_len = match msg_len.await {
+                Ok(msg_len) => Ok(msg_len),
+                Err(error) if is_corrupt_msg(&error) => Err(get_msg_error!(GetErrorTy::CorruptMsg(uuid.clone()))),
+                Err(error) => Err(get_msg_error!(GetErrorTy::DatabaseError(error)))
             }?;
-            Ok(Some(Either::B(format!("uuid={}?{}", uuid.to_string(), msg))))
+            let header = encode_uuid_header(format, &uuid, msg_len)?;
+            return Ok(Some(Either::A(ReturnBody::from_database(uuid, header, msg_len, chunks))));
         }
-    } else {
-        let msg = match String::from_utf8(msg.to_vec()) {
-            Ok(msg) => Ok(msg),
+    };
+    if let Some((file_buffer, file_size)) = file_option {
+        let mut headers = match frame::decode_stored_headers(&msg) {
+            Ok(headers) => Ok(headers),
             Err(error) => Err(get_msg_error!(GetErrorTy::CouldNotParseChunk, error))
         }?;
-        Ok(Some(Either::B(format!("uuid={}?{}", uuid.to_string(), msg))))
+        // the uuid always comes first in the legacy format
+        let header = match format {
+            WireFormat::QueryString if !headers.is_empty() => Bytes::from(format!("uuid={}&{}?", uuid.to_string(), frame::to_query_string(&headers))),
+            _ => {
+                headers.insert("uuid".to_string(), uuid.to_string());
+                encode_header(format, &headers, file_size)?
+            }
+        };
+        let body = ReturnBody::new(uuid, header, file_size, file_buffer);
+        Ok(Some(Either::A(body)))
+    } else {
+        let header = encode_uuid_header(format, &uuid, msg.len() as u64)?;
+        Ok(Some(Either::B(StoredMsg::new(uuid, header, msg))))
+    }
+}
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_wait<F: Future<Output = ()> + Unpin>(
+    store: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    notifier_mutex: &Mutex<Notifier>,
+    priority_option: Option<u16>,
+    reverse_option: bool,
+    format: WireFormat,
+    mut timeout: F
+) -> Result<Option<Either<ReturnBody, StoredMsg>>, GetError> {
+    let priorities = match priority_option {
+        Some(priority) => PriorityRange::from_options(Some(priority), None, None),
+        None => PriorityRange::all()
+    };
+    // subscribe before the first attempt so that no insert can be missed
+    let mut subscription = match notifier_mutex.lock() {
+        Ok(mut notifier) => Ok(notifier.subscribe(priorities, 1)),
+        Err(error) => Err(get_msg_error!(GetErrorTy::LockingError, error))
+    }?;
+    loop {
+        if let Some(msg) = handle(store, database, file_storage_option, None, priority_option, reverse_option, format).await? {
+            return Ok(Some(msg));
+        }
+        match future::select(subscription.next(), &mut timeout).await {
+            future::Either::Left((Some(_event), _)) => continue,
+            future::Either::Left((None, _)) |
+            future::Either::Right(((), _)) => return Ok(None)
+        }
     }
 }
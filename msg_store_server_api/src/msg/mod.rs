@@ -1,23 +1,35 @@
 pub mod add;
+pub mod frame;
 pub mod get;
 pub mod rm;
 
 #[cfg(test)]
 pub mod tests {
-    use bytes::Bytes;
+    use bytes::{Bytes, BytesMut};
     use msg_store::{Store, StoreDefaults, GroupDefaults};
-    use crate::file_storage::FileStorage;
+    use crate::file_storage::{get_file_path_from_id, reencrypt_file, FileStorage};
+    use crate::changes::{Change, ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::{Event, Notifier, PriorityRange};
     use crate::stats::Stats;
-    use msg_store_database_plugin::Db;
+    use crate::Database;
+    use msg_store_database_checksum_plugin::{seal, ChecksummedDb, Corruption, CorruptionPolicy, HEADER_LEN};
+    use msg_store_database_compressed_plugin::{Algorithm, CompressedDb, Compression};
+    use msg_store_database_encrypted_plugin::{key_id, EncryptedDb, Keyring};
+    use msg_store_database_plugin::{Batch, BlockingDb, Cursors, DatabaseError, DatabaseErrorTy, Db, Fetched, CHUNK_SIZE};
     use msg_store_database_in_memory_plugin::MemDb;
-    use futures::{Stream, StreamExt};
+    use futures::{FutureExt, Stream, StreamExt};
     use futures::executor::block_on;
-    use std::sync::Mutex;
+    use futures::future;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
     use std::task::Poll;
     use super::add::{handle as add_handle, Chunky, AddErrorTy, MsgError};
-    use super::get::{handle as get_handle, ReturnBody};
+    use super::frame::{self, WireFormat};
+    use super::get::{handle as get_handle, handle_wait as get_wait_handle, ReturnBody};
     use super::rm::handle as rm_handle;
     use tempdir::TempDir;
+    use msg_store_uuid::Uuid;
 
     pub struct FakePayload {
         pub msg: Bytes,
@@ -50,6 +62,35 @@ pub mod tests {
         };
     }
 
+    /// A database that can not commit batches or delete msgs
+    pub struct UncommittableDb(pub MemDb);
+    impl Db for UncommittableDb {
+        fn get(&mut self, uuid: Arc<Uuid>) -> Result<Bytes, DatabaseError> {
+            self.0.get(uuid)
+        }
+        fn add(&mut self, uuid: Arc<Uuid>, msg: Bytes, msg_byte_size: u64) -> Result<(), DatabaseError> {
+            self.0.add(uuid, msg, msg_byte_size)
+        }
+        fn del(&mut self, _uuid: Arc<Uuid>) -> Result<(), DatabaseError> {
+            Err(DatabaseError { err_ty: DatabaseErrorTy::CouldNotDeleteMsg, file: file!(), line: line!(), msg: None })
+        }
+        fn commit(&mut self, _batch: Batch) -> Result<(), DatabaseError> {
+            Err(DatabaseError { err_ty: DatabaseErrorTy::CouldNotCommitBatch, file: file!(), line: line!(), msg: None })
+        }
+        fn fetch(&mut self) -> Result<Fetched<'_>, DatabaseError> {
+            self.0.fetch()
+        }
+        fn put_cursors(&mut self, consumer_group: &str, cursors: Vec<Arc<Uuid>>) -> Result<(), DatabaseError> {
+            self.0.put_cursors(consumer_group, cursors)
+        }
+        fn del_cursors(&mut self, consumer_group: &str) -> Result<(), DatabaseError> {
+            self.0.del_cursors(consumer_group)
+        }
+        fn fetch_cursors(&mut self) -> Result<Cursors, DatabaseError> {
+            self.0.fetch_cursors()
+        }
+    }
+
     async fn convert_return_body_msg_to_string(mut return_body: ReturnBody) -> String {
         let mut payload_str = String::new();
         while let Some(chunk_rst) = return_body.next().await {
@@ -61,9 +102,11 @@ pub mod tests {
 
     #[test]
     fn should_add_get_and_rm_msg() {
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(MemDb::new()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         let tmp_dir = TempDir::new("should_add_get_and_rm_msg").unwrap();
         let file_storage_op = Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap()));
 
@@ -77,16 +120,19 @@ pub mod tests {
             &store_mx, 
             &file_storage_op,
             &stats_mx, 
-            &database_mx, 
+            &database, 
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             payload)).unwrap();
         
         // make insert assertions
         {
             let store = store_mx.lock().unwrap();                    // Lock the store
-            let mut database = database_mx.lock().unwrap();     // Lock the database
             let stats = stats_mx.lock().unwrap();                    // Lock the stats
             assert_eq!(msg_len, store.byte_size);                                    // The number of bytes in the store should match the msg len
-            let data = database.fetch().unwrap();                 // Get all data in the database
+            let data = block_on(database.fetch()).unwrap();                 // Get all data in the database
             assert_eq!(1, data.len());                                               // there should be 1 msg in the database
             assert_eq!(1, stats.inserted)                                            // the stats should reflect 1 msg insterted
         }
@@ -94,15 +140,16 @@ pub mod tests {
         // get a msg string
         let received_payload = block_on(get_handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &file_storage_op, 
             Some(uuid.clone()), 
             None, 
-            false)).unwrap().unwrap().b();
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().b();
         
         // make get assertions
         {
-            assert_eq!(format!("uuid={}?{}", uuid.to_string(), msg), received_payload);
+            assert_eq!(Bytes::from(format!("uuid={}?{}", uuid.to_string(), msg)), received_payload.to_bytes());
         }
 
         let payload_str = format!("priority=1&saveToFile=true&bytesizeOverride={}?{}",msg_len, msg);
@@ -113,7 +160,11 @@ pub mod tests {
             &store_mx, 
             &file_storage_op,
             &stats_mx, 
-            &database_mx, 
+            &database, 
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             payload)).unwrap();
         
         // make insert 'file' assertions
@@ -129,11 +180,12 @@ pub mod tests {
         // get a msg string
         let received_payload = block_on(get_handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &file_storage_op, 
             Some(uuid_stream.clone()), 
             None, 
-            false)).unwrap().unwrap().a();
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
         
         // make get assertions
         {
@@ -145,23 +197,22 @@ pub mod tests {
         // remove msgs
         block_on(rm_handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &file_storage_op, 
-            &stats_mx, uuid.clone())).unwrap();
+            &stats_mx, &changes_mx, uuid.clone())).unwrap();
 
             block_on(rm_handle(
             &store_mx, 
-            &database_mx, 
+            &database, 
             &file_storage_op, 
-            &stats_mx, uuid_stream.clone())).unwrap();
+            &stats_mx, &changes_mx, uuid_stream.clone())).unwrap();
         
         // make rm assertions
         {
             let store = store_mx.lock().unwrap();                    // Lock the store
-            let mut database = database_mx.lock().unwrap();     // Lock the database
             let stats = stats_mx.lock().unwrap();                    // Lock the stats
             assert_eq!(0, store.byte_size);                                          // The number of bytes in the store should match the msg len
-            let data = database.fetch().unwrap();                 // Get all data in the database
+            let data = block_on(database.fetch()).unwrap();                 // Get all data in the database
             assert_eq!(0, data.len());                                               // there should be 1 msg in the database
             assert_eq!(2, stats.deleted);                                            // the stats should reflect 1 msg insterted
             let file_path = {
@@ -172,10 +223,23 @@ pub mod tests {
             assert!(!file_path.exists())                                             // The file should not exist
         }
 
+        // make change feed assertions
+        {
+            let batch = changes_mx.lock().unwrap().read(1, 10).unwrap();
+            assert_eq!(vec![
+                Change::Insert { uuid: uuid.clone(), byte_size: msg_len },
+                Change::Insert { uuid: uuid_stream.clone(), byte_size: msg_len },
+                Change::Delete { uuid: uuid.clone() },
+                Change::Delete { uuid: uuid_stream.clone() }
+            ], batch.changes.into_iter().map(|entry| entry.change).collect::<Vec<Change>>());
+        }
+
         // reinitialize the store
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(MemDb::new()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         // update store
         {
             let mut store = store_mx.lock().unwrap();
@@ -192,7 +256,11 @@ pub mod tests {
             &store_mx, 
             &file_storage_op,
             &stats_mx, 
-            &database_mx, 
+            &database, 
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             payload)).unwrap();
         
         let payload = fake_payload!(payload_str);
@@ -200,27 +268,405 @@ pub mod tests {
             &store_mx, 
             &file_storage_op,
             &stats_mx, 
-            &database_mx, 
+            &database, 
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             payload)).unwrap();
 
         // make pruned assertions
         {
             let store = store_mx.lock().unwrap();                    // Lock the store
-            let mut database = database_mx.lock().unwrap();     // Lock the database
             let stats = stats_mx.lock().unwrap();                    // Lock the stats
             assert_eq!(msg_len, store.byte_size);                                    // The number of bytes in the store should match the msg len
-            let data = database.fetch().unwrap();                 // Get all data in the database
+            let data = block_on(database.fetch()).unwrap();                 // Get all data in the database
             assert_eq!(1, data.len());                                               // there should be 1 msg in the database
             assert_eq!(1, stats.pruned)                                              // the stats should reflect 1 msg insterted
         }
         
     }
 
+    #[test]
+    fn should_add_and_get_binary_msg() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+
+        // the body is not valid utf-8, starts with whitespace and contains the header delimiter
+        let msg: &[u8] = &[b' ', b'\n', 0, 159, 146, 150, b'?', b'&', b'=', 255];
+        let mut payload_bytes = b"priority=1?".to_vec();
+        payload_bytes.extend_from_slice(msg);
+        let payload = FakePayload { msg: Bytes::from(payload_bytes), done: false };
+
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &None,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            payload)).unwrap();
+
+        {
+            let store = store_mx.lock().unwrap();
+            assert_eq!(msg.len() as u64, store.byte_size);
+        }
+
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &None,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().b();
+
+        assert_eq!(Bytes::from(format!("uuid={}?", uuid.to_string())), received_payload.header);
+        assert_eq!(Bytes::copy_from_slice(msg), received_payload.msg);
+    }
+
+    #[test]
+    fn should_stream_large_msgs_from_the_database() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+
+        let msg = "a".repeat(CHUNK_SIZE * 2 + 1);
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &None,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!(format!("priority=1?{}", msg)))).unwrap();
+
+        let return_body = block_on(get_handle(
+            &store_mx,
+            &database,
+            &None,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
+        assert_eq!(msg.len() as u64, return_body.file_size);
+        let received_payload = block_on(convert_return_body_msg_to_string(return_body));
+        assert_eq!(format!("uuid={}?{}", uuid.to_string(), msg), received_payload);
+    }
+
+    #[test]
+    fn should_add_and_get_framed_msgs() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_add_and_get_framed_msgs").unwrap();
+        let file_storage_op = Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap()));
+
+        // the body contains the legacy delimiters
+        let msg: &[u8] = b"priority=2?a&b=c";
+        let mut headers = BTreeMap::new();
+        headers.insert("priority".to_string(), "1".to_string());
+        let payload = FakePayload { msg: frame::encode(&headers, msg).unwrap(), done: false };
+
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::Framed,
+            payload)).unwrap();
+
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::Framed)).unwrap().unwrap().b();
+        let received_bytes = received_payload.to_bytes();
+        let (received_headers, body_len, head_len) = frame::decode_head(&received_bytes).unwrap().unwrap();
+        assert_eq!(Some(&uuid.to_string()), received_headers.get("uuid"));
+        assert_eq!(msg.len() as u64, body_len);
+        assert_eq!(msg, &received_bytes[head_len..]);
+
+        // header values may contain the legacy delimiters, the byte size defaults to the body length
+        let mut headers = BTreeMap::new();
+        headers.insert("priority".to_string(), "1".to_string());
+        headers.insert("saveToFile".to_string(), "true".to_string());
+        headers.insert("fileName".to_string(), "a?b&c=d.txt".to_string());
+        let payload = FakePayload { msg: frame::encode(&headers, msg).unwrap(), done: false };
+
+        let uuid_stream = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::Framed,
+            payload)).unwrap();
+
+        {
+            let store = store_mx.lock().unwrap();
+            assert_eq!(2 * msg.len() as u64, store.byte_size);
+        }
+
+        let mut received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid_stream.clone()),
+            None,
+            false,
+            WireFormat::Framed)).unwrap().unwrap().a();
+        let mut received_bytes = BytesMut::new();
+        while let Some(chunk) = block_on(received_payload.next()) {
+            received_bytes.extend_from_slice(&chunk.unwrap());
+        }
+        let (received_headers, body_len, head_len) = frame::decode_head(&received_bytes).unwrap().unwrap();
+        assert_eq!(Some(&uuid_stream.to_string()), received_headers.get("uuid"));
+        assert_eq!(Some(&"a?b&c=d.txt".to_string()), received_headers.get("fileName"));
+        assert_eq!(msg.len() as u64, body_len);
+        assert_eq!(msg, &received_bytes[head_len..]);
+
+        // msgs added with either format can be read with the other
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().b();
+        assert_eq!(Bytes::from(format!("uuid={}?priority=2?a&b=c", uuid.to_string())), received_payload.to_bytes());
+    }
+
+    #[test]
+    fn should_reject_malformed_frames() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let mut headers = BTreeMap::new();
+        headers.insert("priority".to_string(), "1".to_string());
+        let frame = frame::encode(&headers, b"foo").unwrap();
+
+        let add = |bytes: Bytes| {
+            let payload = FakePayload { msg: bytes, done: false };
+            block_on(add_handle(
+                &store_mx,
+                &None,
+                &stats_mx,
+                &database,
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::Framed,
+                payload)).err().unwrap()
+        };
+
+        // truncated head
+        let add_err = add(frame.slice(..4));
+        assert!(matches!(add_err.err_ty, AddErrorTy::MsgError(MsgError::MalformedFrame)));
+        // truncated body
+        let add_err = add(frame.slice(..frame.len() - 1));
+        assert!(matches!(add_err.err_ty, AddErrorTy::MsgError(MsgError::CouldNotGetNextChunkFromPayload)));
+        // trailing bytes
+        let mut bytes = frame.to_vec();
+        bytes.push(0);
+        let add_err = add(Bytes::from(bytes));
+        assert!(matches!(add_err.err_ty, AddErrorTy::MsgError(MsgError::MalformedFrame)));
+        // unknown version
+        let mut bytes = frame.to_vec();
+        bytes[0] = frame::VERSION + 1;
+        let add_err = add(Bytes::from(bytes));
+        assert!(matches!(add_err.err_ty, AddErrorTy::MsgError(MsgError::UnsupportedFrameVersion)));
+
+        let store = store_mx.lock().unwrap();
+        assert_eq!(0, store.byte_size);
+    }
+
+    #[test]
+    fn should_notify_subscribers_of_added_msgs() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let mut subscription = notifier_mx.lock().unwrap().subscribe(PriorityRange::from_options(Some(2), None, None), 10);
+
+        for priority in 1..=2 {
+            block_on(add_handle(
+                &store_mx,
+                &None,
+                &stats_mx,
+                &database,
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
+                fake_payload!(format!("priority={}?foo", priority)))).unwrap();
+        }
+
+        match block_on(subscription.next()).unwrap() {
+            Event::Inserted(notification) => {
+                assert_eq!(2, notification.uuid.priority);
+                assert_eq!(3, notification.byte_size);
+            },
+            Event::Lagged(_count) => panic!("Subscription should not lag")
+        }
+    }
+
+    #[test]
+    fn should_not_keep_msgs_the_database_could_not_commit() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(UncommittableDb(MemDb::new()))));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_not_keep_msgs_the_database_could_not_commit").unwrap();
+        let file_storage_op = Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap()));
+        let mut subscription = notifier_mx.lock().unwrap().subscribe(PriorityRange::from_options(None, None, None), 10);
+
+        for payload in ["priority=1?my-msg", "priority=1&saveToFile=true&bytesizeOverride=6?my-msg"] {
+            let add_err = block_on(add_handle(
+                &store_mx,
+                &file_storage_op,
+                &stats_mx,
+                &database,
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
+                fake_payload!(payload))).err().unwrap();
+            assert!(matches!(add_err.err_ty, AddErrorTy::DatabaseError(_)));
+        }
+
+        let store = store_mx.lock().unwrap();
+        assert_eq!(0, store.byte_size);
+        assert!(store.id_to_group_map.is_empty());
+        assert_eq!(0, stats_mx.lock().unwrap().inserted);
+        assert_eq!(1, changes_mx.lock().unwrap().next_seq());
+        assert!(file_storage_op.as_ref().unwrap().lock().unwrap().index.is_empty());
+        assert_eq!(0, std::fs::read_dir(tmp_dir.path()).unwrap().count());
+        assert!(subscription.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn should_keep_the_msgs_an_uncommitted_msg_would_have_pruned() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(UncommittableDb(MemDb::new()))));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let uuid = {
+            let mut store = store_mx.lock().unwrap();
+            store.max_byte_size = Some(6);
+            store.add(1, 6).unwrap().uuid
+        };
+
+        let add_err = block_on(add_handle(
+            &store_mx,
+            &None,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1?my-msg"))).err().unwrap();
+        assert!(matches!(add_err.err_ty, AddErrorTy::DatabaseError(_)));
+
+        let store = store_mx.lock().unwrap();
+        assert_eq!(vec![uuid], store.id_to_group_map.keys().cloned().collect::<Vec<Arc<Uuid>>>());
+        assert_eq!(6, store.byte_size);
+        assert_eq!(6, store.groups_map.get(&1).unwrap().byte_size);
+        assert_eq!(0, stats_mx.lock().unwrap().pruned);
+    }
+
+    #[test]
+    fn should_wait_for_a_msg_to_be_added() {
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+
+        // the timeout elapses while the store is empty
+        let received = block_on(get_wait_handle(
+            &store_mx,
+            &database,
+            &None,
+            &notifier_mx,
+            None,
+            false,
+            WireFormat::QueryString,
+            future::ready(()))).unwrap();
+        assert!(received.is_none());
+
+        // a msg of another priority does not end the wait, the msg of the requested priority does
+        let (received, _, _) = block_on(async {
+            futures::join!(
+                get_wait_handle(
+                    &store_mx,
+                    &database,
+                    &None,
+                    &notifier_mx,
+                    Some(2),
+                    false,
+                    WireFormat::QueryString,
+                    future::pending()),
+                add_handle(
+                    &store_mx,
+                    &None,
+                    &stats_mx,
+                    &database,
+                    &notifier_mx,
+                    &changes_mx,
+                    false,
+                    WireFormat::QueryString,
+                    fake_payload!("priority=1?foo")),
+                add_handle(
+                    &store_mx,
+                    &None,
+                    &stats_mx,
+                    &database,
+                    &notifier_mx,
+                    &changes_mx,
+                    false,
+                    WireFormat::QueryString,
+                    fake_payload!("priority=2?bar"))
+            )
+        });
+        let received = received.unwrap().unwrap().b();
+        assert_eq!(2, received.uuid.priority);
+        assert_eq!(Bytes::from("bar"), received.msg);
+    }
+
     #[test]
     fn should_reject_messages() {
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(MemDb::new()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         let tmp_dir = TempDir::new("should_add_get_and_rm_msg").unwrap();
         let file_storage_op = Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap()));
 
@@ -231,7 +677,11 @@ pub mod tests {
                 &store_mx, 
                 &None,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::FileStorageNotConfigured, msg_err)
@@ -247,7 +697,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::InvalidBytesizeOverride, msg_err)
@@ -263,7 +717,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::InvalidPriority, msg_err)
@@ -279,7 +737,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MissingBytesizeOverride, msg_err)
@@ -295,7 +757,7 @@ pub mod tests {
         //         &store_mx, 
         //         &file_storage_op,
         //         &stats_mx, 
-        //         &database_mx, 
+        //         &database, 
         //         payload)).err().unwrap();
         //     if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
         //         assert_eq!(MsgError::MissingHeaders, msg_err)
@@ -311,7 +773,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MissingPriority, msg_err)
@@ -327,7 +793,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MalformedHeaders, msg_err)
@@ -338,7 +808,7 @@ pub mod tests {
 
         {
             let store_mx = {
-                let mut store = Store::new(None).unwrap();
+                let mut store = Store::new(None, None).unwrap();
                 store.update_group_defaults(1, &GroupDefaults { max_byte_size: Some(3) }).unwrap();
                 Mutex::new(store)
             };
@@ -348,7 +818,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MsgExceedesGroupMax, msg_err)
@@ -360,7 +834,7 @@ pub mod tests {
         {
             // should reject msg for exceeding the store max
             let store_mx = {
-                let mut store = Store::new(None).unwrap();
+                let mut store = Store::new(None, None).unwrap();
                 store.max_byte_size = Some(3);
                 Mutex::new(store)
             };
@@ -370,7 +844,11 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MsgExceedesStoreMax, msg_err)
@@ -382,7 +860,7 @@ pub mod tests {
         {
             // should reject msg for lacking priority
             let store_mx = {
-                let mut store = Store::new(None).unwrap();
+                let mut store = Store::new(None, None).unwrap();
                 store.max_byte_size = Some(3);
                 Mutex::new(store)
             };
@@ -392,14 +870,22 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).unwrap();
             let payload = fake_payload!("priority=1?foo");
             let add_err = block_on(add_handle(
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             if let AddErrorTy::MsgError(msg_err) = add_err.err_ty {
                 assert_eq!(MsgError::MsgLacksPriority, msg_err)
@@ -411,7 +897,7 @@ pub mod tests {
         {
             // should reject msg for exceeding the store max
             let store_mx = {
-                let mut store = Store::new(None).unwrap();
+                let mut store = Store::new(None, None).unwrap();
                 store.max_byte_size = Some(3);
                 Mutex::new(store)
             };
@@ -421,10 +907,230 @@ pub mod tests {
                 &store_mx, 
                 &file_storage_op,
                 &stats_mx, 
-                &database_mx, 
+                &database, 
+                &notifier_mx,
+                &changes_mx,
+                false,
+                WireFormat::QueryString,
                 payload)).err().unwrap();
             assert!(add_err.to_string().contains("ADD_MSG_ERROR: (MSG_ERROR: MsgExceedesStoreMax). "))
         }
 
     }
+    #[test]
+    fn should_count_compressed_msgs_by_their_stored_size() {
+        let compression = Compression::new(Algorithm::Zstd);
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(CompressedDb::new(MemDb::new(), compression))));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_count_compressed_msgs_by_their_stored_size").unwrap();
+        let mut file_storage = FileStorage::new(tmp_dir.path()).unwrap();
+        file_storage.compression = Some(compression);
+        let file_storage_op = Some(Mutex::new(file_storage));
+
+        // large enough to be streamed from the database
+        let msg = "{\"name\":\"my message\"},".repeat(CHUNK_SIZE / 10);
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            true,
+            WireFormat::QueryString,
+            fake_payload!(format!("priority=1?{}", msg)))).unwrap();
+        let uuid_file = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            true,
+            WireFormat::QueryString,
+            fake_payload!(format!("priority=1&saveToFile=true&bytesizeOverride={}?{}", msg.len(), msg)))).unwrap();
+        {
+            let store = store_mx.lock().unwrap();
+            assert!(store.byte_size < msg.len() as u64);
+        }
+
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().b();
+        assert_eq!(Bytes::from(format!("uuid={}?{}", uuid.to_string(), msg)), received_payload.to_bytes());
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid_file.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
+        assert_eq!(
+            format!("uuid={}&bytesizeOverride={}&saveToFile=true?{}", uuid_file.to_string(), msg.len(), msg),
+            block_on(convert_return_body_msg_to_string(received_payload)));
+
+        // a msg counted by its logical size is still streamed with its logical length
+        let uuid_logical = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!(format!("priority=1?{}", msg)))).unwrap();
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid_logical.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
+        assert_eq!(format!("uuid={}?{}", uuid_logical.to_string(), msg), block_on(convert_return_body_msg_to_string(received_payload)));
+    }    #[test]
+    fn should_encrypt_msgs_at_rest() {
+        let compression = Compression::new(Algorithm::Lz4);
+        let keyring = Keyring::new(vec![(1, [1u8; 32])]).unwrap();
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(CompressedDb::new(EncryptedDb::new(MemDb::new(), keyring.clone()), compression))));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_encrypt_msgs_at_rest").unwrap();
+        let mut file_storage = FileStorage::new(tmp_dir.path()).unwrap();
+        file_storage.compression = Some(compression);
+        file_storage.keyring = Some(keyring.clone());
+        let file_storage_op = Some(Mutex::new(file_storage));
+
+        // large enough to be written in more than one segment
+        let msg = "{\"name\":\"my message\"},".repeat(CHUNK_SIZE);
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!(format!("priority=1&saveToFile=true&bytesizeOverride={}?{}", msg.len(), msg)))).unwrap();
+        let file_path = get_file_path_from_id(tmp_dir.path(), &uuid);
+        let file = std::fs::read(&file_path).unwrap();
+        assert_eq!(Some(1), key_id(&file[HEADER_LEN..]));
+        assert!(!file.windows(18).any(|window| window == b"\"name\":\"my message".as_slice()));
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
+        assert_eq!(
+            format!("uuid={}&bytesizeOverride={}&saveToFile=true?{}", uuid.to_string(), msg.len(), msg),
+            block_on(convert_return_body_msg_to_string(received_payload)));
+
+        // once the key is rotated the file is re-encrypted with the new key
+        let rotated = Keyring::new(vec![(1, [1u8; 32]), (2, [2u8; 32])]).unwrap();
+        {
+            let mut file_storage = file_storage_op.as_ref().unwrap().lock().unwrap();
+            file_storage.keyring = Some(rotated);
+            assert!(reencrypt_file(&file_storage, &uuid).unwrap());
+            assert!(!reencrypt_file(&file_storage, &uuid).unwrap());
+        }
+        assert_eq!(Some(2), key_id(&std::fs::read(&file_path).unwrap()[HEADER_LEN..]));
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            Some(uuid.clone()),
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().a();
+        assert_eq!(
+            format!("uuid={}&bytesizeOverride={}&saveToFile=true?{}", uuid.to_string(), msg.len(), msg),
+            block_on(convert_return_body_msg_to_string(received_payload)));
+    }    #[test]
+    fn should_skip_corrupt_msgs() {
+        let corruption = Corruption::new(CorruptionPolicy::Delete);
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        // a msg whose value was damaged in the database
+        let mut mem_db = MemDb::new();
+        let uuid_db = {
+            let mut store = store_mx.lock().unwrap();
+            let uuid = store.uuid(1).unwrap();
+            let mut value = seal(b"my message").to_vec();
+            let last = value.len() - 1;
+            value[last] ^= 1;
+            mem_db.add(uuid.clone(), Bytes::from(value), 10).unwrap();
+            store.add_with_uuid(uuid.clone(), 10).unwrap();
+            uuid
+        };
+        let database: Database = Box::new(BlockingDb::new(Box::new(ChecksummedDb::new(mem_db, corruption.clone(), Duration::from_secs(60), None))));
+        let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
+        let tmp_dir = TempDir::new("should_skip_corrupt_msgs").unwrap();
+        let mut file_storage = FileStorage::new(tmp_dir.path()).unwrap();
+        file_storage.corruption = corruption.clone();
+        let file_storage_op = Some(Mutex::new(file_storage));
+
+        // a msg whose file was damaged
+        let uuid_file = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1&saveToFile=true&bytesizeOverride=10?my message"))).unwrap();
+        let file_path = get_file_path_from_id(tmp_dir.path(), &uuid_file);
+        let mut file = std::fs::read(&file_path).unwrap();
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        std::fs::write(&file_path, file).unwrap();
+        let uuid = block_on(add_handle(
+            &store_mx,
+            &file_storage_op,
+            &stats_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1?my message"))).unwrap();
+
+        let received_payload = block_on(get_handle(
+            &store_mx,
+            &database,
+            &file_storage_op,
+            None,
+            None,
+            false,
+            WireFormat::QueryString)).unwrap().unwrap().b();
+        assert_eq!(Bytes::from(format!("uuid={}?my message", uuid.to_string())), received_payload.to_bytes());
+        {
+            let store = store_mx.lock().unwrap();
+            assert!(!store.id_to_group_map.contains_key(&uuid_db));
+            assert!(!store.id_to_group_map.contains_key(&uuid_file));
+        }
+        let mut corrupt_uuids = corruption.take();
+        let mut expected = vec![uuid_db, uuid_file];
+        corrupt_uuids.sort();
+        expected.sort();
+        assert_eq!(expected, corrupt_uuids);
+    }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use crate::{
     Database,
+    changes::{Change, ChangeFeed, ChangeFeedError},
     stats::Stats
 };
 use crate::file_storage::{rm_from_file_storage, FileStorage, FileStorageError};
@@ -11,6 +12,7 @@ use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
 pub enum RemoveErrorTy {
+    ChangeFeedError(ChangeFeedError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
     StoreError(StoreError),
@@ -22,6 +24,7 @@ pub enum RemoveErrorTy {
 impl Display for RemoveErrorTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
             Self::StoreError(err) => write!(f, "({})", err),
@@ -72,26 +75,35 @@ macro_rules! rm_msg_error {
 
 pub async fn handle(
     store_mutex: &Mutex<Store>,
-    database_mutex: &Mutex<Database>,
+    database: &Database,
     file_storage_option: &Option<Mutex<FileStorage>>,
-    stats_mutex: &Mutex<Stats>, uuid: Arc<Uuid>
+    stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    uuid: Arc<Uuid>
 ) -> Result<(), RemoveError> {
-    let mut store = match store_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
-    }?;
-    let mut stats = match stats_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
-    }?;
-    let mut db = match database_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
-    }?;
-    if let Err(error) = store.del(uuid.clone()) {
-        return Err(rm_msg_error!(RemoveErrorTy::StoreError(error)))
-    }    
-    if let Err(error) = db.del(uuid.clone()) {
+    let deleted = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
+        }?;
+        let mut stats = match stats_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
+        }?;
+        if let Err(error) = store.del(uuid.clone()) {
+            return Err(rm_msg_error!(RemoveErrorTy::StoreError(error)))
+        }
+        let deleted = database.del(uuid.clone());
+        match changes_mutex.lock() {
+            Ok(mut changes) => if let Err(error) = changes.record(Change::Delete { uuid: uuid.clone() }) {
+                return Err(rm_msg_error!(RemoveErrorTy::ChangeFeedError(error)));
+            },
+            Err(err) => return Err(rm_msg_error!(RemoveErrorTy::LockingError, err))
+        };
+        stats.deleted += 1;
+        deleted
+    };
+    if let Err(error) = deleted.await {
         return Err(rm_msg_error!(RemoveErrorTy::DatabaseError(error)));
     }
     if let Some(file_storage_mutex) = &file_storage_option {
@@ -103,6 +115,5 @@ pub async fn handle(
             return Err(rm_msg_error!(RemoveErrorTy::FileStorageError(error)))
         }
     }
-    stats.deleted += 1;
     Ok(())
 }
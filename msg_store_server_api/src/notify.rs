@@ -0,0 +1,233 @@
+//! Notifications of inserted msgs for subscribers
+//!
+//! Every subscriber has its own bounded channel. When a subscriber falls behind and its
+//! channel is full, further notifications are dropped for it and counted instead, so a slow
+//! subscriber can never make memory grow without bound. The count is delivered as a
+//! Lagged event once the subscriber has caught up.
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::task::{Context, Poll};
+use futures::{Stream, StreamExt};
+use msg_store_uuid::Uuid;
+use serde_json::{json, Value};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The number of notifications that are buffered for a subscriber by default
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// The inclusive range of priorities a subscriber is interested in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityRange {
+    pub start: u16,
+    pub end: u16
+}
+impl PriorityRange {
+    /// Covers every priority
+    pub fn all() -> PriorityRange {
+        PriorityRange {
+            start: u16::MIN,
+            end: u16::MAX
+        }
+    }
+    /// Builds the range from either a single priority or a min and/or max priority
+    pub fn from_options(priority: Option<u16>, min_priority: Option<u16>, max_priority: Option<u16>) -> PriorityRange {
+        if let Some(priority) = priority {
+            return PriorityRange {
+                start: priority,
+                end: priority
+            };
+        }
+        PriorityRange {
+            start: min_priority.unwrap_or(u16::MIN),
+            end: max_priority.unwrap_or(u16::MAX)
+        }
+    }
+    pub fn contains(&self, priority: u16) -> bool {
+        self.start <= priority && priority <= self.end
+    }
+}
+
+/// A msg that was inserted into the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub uuid: Arc<Uuid>,
+    pub byte_size: u64
+}
+impl Notification {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "uuid": self.uuid.to_string(),
+            "priority": self.uuid.priority,
+            "byteSize": self.byte_size
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Inserted(Notification),
+    /// The number of notifications that were dropped because the subscriber fell behind
+    Lagged(u64)
+}
+impl Event {
+    /// The name of the event, e.g. for server-sent events
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Inserted(_notification) => "msg",
+            Self::Lagged(_count) => "lagged"
+        }
+    }
+    pub fn to_json(&self) -> Value {
+        let mut value = match self {
+            Self::Inserted(notification) => notification.to_json(),
+            Self::Lagged(count) => json!({ "dropped": count })
+        };
+        value["event"] = Value::from(self.name());
+        value
+    }
+}
+
+struct Subscriber {
+    priorities: PriorityRange,
+    sender: Sender<Notification>,
+    dropped: Arc<AtomicU64>
+}
+
+/// Fans out notifications of inserted msgs to subscribers
+#[derive(Default)]
+pub struct Notifier {
+    subscribers: Vec<Subscriber>
+}
+impl Notifier {
+    pub fn new() -> Notifier {
+        Notifier {
+            subscribers: vec![]
+        }
+    }
+    /// Subscribes to msgs inserted within the priority range from now on
+    ///
+    /// At most `capacity` notifications are buffered for the subscription.
+    pub fn subscribe(&mut self, priorities: PriorityRange, capacity: usize) -> Subscription {
+        let (sender, receiver) = channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.subscribers.push(Subscriber {
+            priorities,
+            sender,
+            dropped: dropped.clone()
+        });
+        Subscription {
+            receiver,
+            dropped
+        }
+    }
+    /// Notifies every interested subscriber, subscribers that have gone away are removed
+    pub fn notify(&mut self, uuid: Arc<Uuid>, byte_size: u64) {
+        self.subscribers.retain_mut(|subscriber| {
+            if !subscriber.priorities.contains(uuid.priority) {
+                return !subscriber.sender.is_closed();
+            }
+            let notification = Notification {
+                uuid: uuid.clone(),
+                byte_size
+            };
+            match subscriber.sender.try_send(notification) {
+                Ok(()) => true,
+                Err(error) if error.is_full() => {
+                    subscriber.dropped.fetch_add(1, Ordering::SeqCst);
+                    true
+                },
+                Err(_error) => false
+            }
+        });
+    }
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+}
+
+/// A stream of events for inserted msgs
+pub struct Subscription {
+    receiver: Receiver<Notification>,
+    dropped: Arc<AtomicU64>
+}
+impl Stream for Subscription {
+    type Item = Event;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.receiver.poll_next_unpin(cx) {
+            Poll::Ready(Some(notification)) => Poll::Ready(Some(Event::Inserted(notification))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                // the buffered notifications precede the dropped ones
+                let dropped = self.dropped.swap(0, Ordering::SeqCst);
+                if dropped != 0 {
+                    Poll::Ready(Some(Event::Lagged(dropped)))
+                } else {
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Notifier, PriorityRange};
+    use futures::executor::block_on;
+    use futures::{FutureExt, StreamExt};
+    use msg_store_uuid::Uuid;
+
+    #[test]
+    fn should_only_notify_subscribers_of_the_priority() {
+        let mut notifier = Notifier::new();
+        let mut subscription = notifier.subscribe(PriorityRange::from_options(Some(2), None, None), 10);
+        let mut range_subscription = notifier.subscribe(PriorityRange::from_options(None, Some(1), None), 10);
+        let uuid_1 = Uuid::from_string("1-1-0-0").unwrap();
+        let uuid_2 = Uuid::from_string("2-1-0-0").unwrap();
+        notifier.notify(uuid_1.clone(), 3);
+        notifier.notify(uuid_2.clone(), 4);
+
+        let event = block_on(subscription.next()).unwrap();
+        if let Event::Inserted(notification) = event {
+            assert_eq!(uuid_2, notification.uuid);
+            assert_eq!(4, notification.byte_size);
+        } else {
+            panic!("Expected an insert");
+        }
+        assert!(subscription.next().now_or_never().is_none());
+
+        let event = block_on(range_subscription.next()).unwrap();
+        assert!(matches!(event, Event::Inserted(notification) if notification.uuid == uuid_1));
+        let event = block_on(range_subscription.next()).unwrap();
+        assert!(matches!(event, Event::Inserted(notification) if notification.uuid == uuid_2));
+    }
+
+    #[test]
+    fn should_drop_notifications_for_slow_subscribers() {
+        let mut notifier = Notifier::new();
+        let mut subscription = notifier.subscribe(PriorityRange::all(), 1);
+        for sequence in 0..10 {
+            notifier.notify(Uuid::from_string(&format!("1-1-{}-0", sequence)).unwrap(), 1);
+        }
+        let mut inserted = 0;
+        let mut dropped = 0;
+        while let Some(Some(event)) = subscription.next().now_or_never() {
+            match event {
+                Event::Inserted(_notification) => inserted += 1,
+                Event::Lagged(count) => dropped += count
+            }
+        }
+        assert!(inserted < 10);
+        assert_eq!(10, inserted + dropped);
+    }
+
+    #[test]
+    fn should_remove_dropped_subscriptions() {
+        let mut notifier = Notifier::new();
+        let subscription = notifier.subscribe(PriorityRange::all(), 1);
+        assert_eq!(1, notifier.subscriber_count());
+        drop(subscription);
+        notifier.notify(Uuid::from_string("1-1-0-0").unwrap(), 1);
+        assert_eq!(0, notifier.subscriber_count());
+    }
+}
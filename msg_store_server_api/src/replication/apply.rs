@@ -0,0 +1,363 @@
+use bytes::Bytes;
+use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
+use crate::file_storage::{rm_from_disk, rm_from_file_storage, write_to_disk, FileStorage, FileStorageError};
+use crate::msg::add::Chunky;
+use crate::notify::Notifier;
+use crate::stats::Stats;
+use msg_store::{Store, StoreError};
+use msg_store_database_plugin::{Batch, DatabaseError, DbFuture};
+use msg_store_uuid::Uuid;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
+    DatabaseError(DatabaseError),
+    FileStorageError(FileStorageError),
+    StoreError(StoreError),
+    FileStorageNotConfigured,
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::FileStorageError(err) => write!(f, "({})", err),
+            Self::StoreError(err) => write!(f, "({})", err),
+            Self::FileStorageNotConfigured |
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "APPLY_CHANGE_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "APPLY_CHANGE_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// Removes every msg and consumer group, so that a snapshot can be applied
+///
+/// The removed msgs are recorded as deletes, they are not counted in the stats.
+pub async fn reset(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    changes_mutex: &Mutex<ChangeFeed>
+) -> Result<(), ApiError> {
+    let (uuids, cursors_deleted, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let names = store.consumer_groups.keys().cloned().collect::<Vec<String>>();
+        let mut cursors_deleted = vec![];
+        for name in names.iter() {
+            if let Err(err) = store.del_consumer_group(name) {
+                return Err(api_error!(ErrTy::StoreError(err)));
+            }
+            cursors_deleted.push(database.del_cursors(name));
+        }
+        let uuids = store.id_to_group_map.keys().cloned().collect::<Vec<Arc<Uuid>>>();
+        let mut batch = Batch::new();
+        for uuid in uuids.iter() {
+            if let Err(err) = store.del(uuid.clone()) {
+                return Err(api_error!(ErrTy::StoreError(err)));
+            }
+            batch.del(uuid.clone());
+        }
+        let committed = database.commit(batch);
+        let recorded_changes = uuids.iter().map(|uuid| Change::Delete { uuid: uuid.clone() }).collect();
+        if let Err(err) = changes.record_all(recorded_changes) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)));
+        }
+        (uuids, cursors_deleted, committed)
+    };
+    for deleted in cursors_deleted {
+        if let Err(err) = deleted.await {
+            return Err(api_error!(ErrTy::DatabaseError(err)));
+        }
+    }
+    if let Err(err) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    if let Some(file_storage_mutex) = file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for uuid in uuids.iter() {
+            if let Err(err) = rm_from_file_storage(&mut file_storage, uuid) {
+                return Err(api_error!(ErrTy::FileStorageError(err)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the consumer groups of a snapshot
+pub async fn consumer_groups(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    consumer_groups: Vec<(String, Vec<Arc<Uuid>>)>
+) -> Result<(), ApiError> {
+    let mut saved = vec![];
+    {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for (name, cursors) in consumer_groups {
+            if let Err(err) = store.add_consumer_group(&name, cursors.clone()) {
+                return Err(api_error!(ErrTy::StoreError(err)));
+            }
+            saved.push(database.put_cursors(&name, cursors));
+        }
+    }
+    for saved in saved {
+        if let Err(err) = saved.await {
+            return Err(api_error!(ErrTy::DatabaseError(err)));
+        }
+    }
+    Ok(())
+}
+
+/// Adds a msg with the uuid it was given by the primary
+///
+/// `stored` is the value to hold in the database. When the msg is held in a file, the
+/// contents of the file are read from the payload.
+#[allow(clippy::too_many_arguments)]
+pub async fn insert<T: Chunky>(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    notifier_mutex: &Mutex<Notifier>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    uuid: Arc<Uuid>,
+    byte_size: u64,
+    stored: Bytes,
+    file_payload_option: Option<T>
+) -> Result<(), ApiError> {
+    // write the file before anything is locked, it is removed again if the msg can not be added
+    let file_storage_path = match file_payload_option {
+        Some(file_payload) => {
+            let file_storage_mutex = match file_storage_option {
+                Some(file_storage_mutex) => Ok(file_storage_mutex),
+                None => Err(api_error!(ErrTy::FileStorageNotConfigured))
+            }?;
+            let (file_storage_path, compression, keyring) = match file_storage_mutex.lock() {
+                Ok(file_storage) => (file_storage.path.clone(), file_storage.compression, file_storage.keyring.clone()),
+                Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+            };
+            if let Err(err) = write_to_disk(&file_storage_path, &uuid, &[], file_payload, &compression, &keyring).await {
+                return Err(api_error!(ErrTy::FileStorageError(err)));
+            }
+            Some(file_storage_path)
+        },
+        None => None
+    };
+    let (add_result, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let add_result = match store.add_with_uuid(uuid.clone(), byte_size) {
+            Ok(add_result) => add_result,
+            Err(err) => {
+                if let Some(file_storage_path) = &file_storage_path {
+                    if let Err(err) = rm_from_disk(file_storage_path, &uuid) {
+                        return Err(api_error!(ErrTy::FileStorageError(err)));
+                    }
+                }
+                return Err(api_error!(ErrTy::StoreError(err)));
+            }
+        };
+        if let (Some(file_storage_mutex), Some(_)) = (file_storage_option, &file_storage_path) {
+            match file_storage_mutex.lock() {
+                Ok(mut file_storage) => file_storage.index.insert(uuid.clone()),
+                Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+            };
+        }
+        let mut batch = Batch::new();
+        for uuid_removed in add_result.msgs_removed.iter() {
+            batch.del(uuid_removed.clone());
+        }
+        batch.add(uuid.clone(), stored, byte_size);
+        let committed = database.commit(batch);
+        (add_result, committed)
+    };
+    if let Err(err) = committed.await {
+        // the msg was never added and the msgs it pruned are still in the database
+        match store_mutex.lock() {
+            Ok(mut store) => if let Err(err) = store.undo_add(add_result) {
+                return Err(api_error!(ErrTy::StoreError(err)));
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+        };
+        if let (Some(file_storage_mutex), Some(_)) = (file_storage_option, &file_storage_path) {
+            match file_storage_mutex.lock() {
+                Ok(mut file_storage) => if let Err(err) = rm_from_file_storage(&mut file_storage, &uuid) {
+                    return Err(api_error!(ErrTy::FileStorageError(err)));
+                },
+                Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+            };
+        }
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    // the changes are recorded once the batch is committed, a msg that was deleted in the
+    // meantime was recorded as deleted then and is not recorded as inserted after that
+    let saved = {
+        let store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        match stats_mutex.lock() {
+            Ok(mut stats) => {
+                stats.pruned += add_result.msgs_removed.len() as u64;
+                stats.inserted += 1;
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+        };
+        let mut recorded_changes = add_result.msgs_removed.iter().map(|uuid| Change::Prune { uuid: uuid.clone() }).collect::<Vec<Change>>();
+        if store.id_to_group_map.contains_key(&uuid) {
+            recorded_changes.push(Change::Insert { uuid: uuid.clone(), byte_size });
+        }
+        match changes_mutex.lock() {
+            Ok(mut changes) => if let Err(err) = changes.record_all(recorded_changes) {
+                return Err(api_error!(ErrTy::ChangeFeedError(err)));
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+        };
+        // the consumer groups that have passed the msg keep it as late
+        add_result.consumer_groups_behind.iter()
+            .filter_map(|name| store.consumer_groups.get(name).map(|consumer_group| database.put_cursors(name, consumer_group.to_cursors())))
+            .collect::<Vec<DbFuture<()>>>()
+    };
+    for saved in saved {
+        if let Err(err) = saved.await {
+            return Err(api_error!(ErrTy::DatabaseError(err)));
+        }
+    }
+    if let Some(file_storage_mutex) = file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        for uuid_removed in add_result.msgs_removed.iter() {
+            if let Err(err) = rm_from_file_storage(&mut file_storage, uuid_removed) {
+                return Err(api_error!(ErrTy::FileStorageError(err)));
+            }
+        }
+    }
+    match notifier_mutex.lock() {
+        Ok(mut notifier) => notifier.notify(uuid, byte_size),
+        Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+    };
+    Ok(())
+}
+
+/// Removes a msg that was deleted or pruned by the primary
+///
+/// Msgs that are already gone are ignored, e.g. when the replica pruned them itself.
+pub async fn remove(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
+    uuid: Arc<Uuid>,
+    pruned: bool
+) -> Result<(), ApiError> {
+    // the msg is removed from the database first, so that it stays in the store if that fails
+    let deleted = {
+        let store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        if !store.id_to_group_map.contains_key(&uuid) {
+            return Ok(());
+        }
+        database.del(uuid.clone())
+    };
+    if let Err(err) = deleted.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)));
+    }
+    {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        // the msg may have been removed while it was deleted from the database
+        if !store.id_to_group_map.contains_key(&uuid) {
+            return Ok(());
+        }
+        if let Err(err) = store.del(uuid.clone()) {
+            return Err(api_error!(ErrTy::StoreError(err)));
+        }
+        let change = match stats_mutex.lock() {
+            Ok(mut stats) => if pruned {
+                stats.pruned += 1;
+                Change::Prune { uuid: uuid.clone() }
+            } else {
+                stats.deleted += 1;
+                Change::Delete { uuid: uuid.clone() }
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+        };
+        match changes_mutex.lock() {
+            Ok(mut changes) => if let Err(err) = changes.record(change) {
+                return Err(api_error!(ErrTy::ChangeFeedError(err)));
+            },
+            Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+        };
+    }
+    if let Some(file_storage_mutex) = file_storage_option {
+        let mut file_storage = match file_storage_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        if let Err(err) = rm_from_file_storage(&mut file_storage, &uuid) {
+            return Err(api_error!(ErrTy::FileStorageError(err)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! Copies the state of a primary to a replica
+//!
+//! A replica bootstraps from a snapshot of the primary, fetches each msg of the snapshot,
+//! then follows the change feed of the primary from the sequence number of the snapshot.
+pub mod apply;
+pub mod msg;
+pub mod snapshot;
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::changes::{Change, ChangeFeed, DEFAULT_CAPACITY};
+    use crate::fake_payload;
+    use crate::file_storage::{get_buffer, FileStorage};
+    use crate::msg::add::handle as add_handle;
+    use crate::msg::frame::WireFormat;
+    use crate::msg::rm::handle as rm_handle;
+    use crate::msg::tests::{FakePayload, UncommittableDb};
+    use crate::notify::Notifier;
+    use crate::stats::Stats;
+    use futures::executor::block_on;
+    use msg_store::Store;
+    use crate::Database;
+    use msg_store_database_plugin::BlockingDb;
+    use msg_store_database_in_memory_plugin::MemDb;
+    use msg_store_uuid::Uuid;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use super::apply::{insert, remove, reset, ErrTy};
+    use super::msg::handle as msg_handle;
+    use super::snapshot::handle as snapshot_handle;
+    use tempdir::TempDir;
+
+    struct Node {
+        store_mx: Mutex<Store>,
+        database: Database,
+        file_storage_op: Option<Mutex<FileStorage>>,
+        stats_mx: Mutex<Stats>,
+        notifier_mx: Mutex<Notifier>,
+        changes_mx: Mutex<ChangeFeed>
+    }
+    impl Node {
+        fn new(tmp_dir: &TempDir) -> Node {
+            Node {
+                store_mx: Mutex::new(Store::new(None, None).unwrap()),
+                database: Box::new(BlockingDb::new(Box::new(MemDb::new()))),
+                file_storage_op: Some(Mutex::new(FileStorage::new(tmp_dir.path()).unwrap())),
+                stats_mx: Mutex::new(Stats::new()),
+                notifier_mx: Mutex::new(Notifier::new()),
+                changes_mx: Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY))
+            }
+        }
+    }
+
+    /// Copies a msg from the primary to the replica
+    fn copy_msg(primary: &Node, replica: &Node, uuid: std::sync::Arc<Uuid>) {
+        let msg = block_on(msg_handle(&primary.store_mx, &primary.database, &primary.file_storage_op, uuid)).unwrap().unwrap();
+        let file_payload = msg.file.map(|(mut file, _file_size)| {
+            let mut contents = vec![];
+            file.read_to_end(&mut contents).unwrap();
+            FakePayload { msg: Bytes::from(contents), done: false }
+        });
+        block_on(insert(
+            &replica.store_mx,
+            &replica.database,
+            &replica.file_storage_op,
+            &replica.stats_mx,
+            &replica.notifier_mx,
+            &replica.changes_mx,
+            msg.uuid,
+            msg.byte_size,
+            msg.stored,
+            file_payload)).unwrap();
+    }
+
+    #[test]
+    fn should_copy_the_primary_to_the_replica() {
+        let primary_dir = TempDir::new("should_copy_the_primary_to_the_replica-primary").unwrap();
+        let replica_dir = TempDir::new("should_copy_the_primary_to_the_replica-replica").unwrap();
+        let primary = Node::new(&primary_dir);
+        let replica = Node::new(&replica_dir);
+
+        let add = |payload: &str| block_on(add_handle(
+            &primary.store_mx,
+            &primary.file_storage_op,
+            &primary.stats_mx,
+            &primary.database,
+            &primary.notifier_mx,
+            &primary.changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!(payload))).unwrap();
+        let foo = add("priority=1?foo");
+        let file = add("priority=2&saveToFile=true&bytesizeOverride=4&fileName=a.txt?file");
+
+        // the replica has a msg of its own that the snapshot replaces
+        block_on(add_handle(
+            &replica.store_mx,
+            &replica.file_storage_op,
+            &replica.stats_mx,
+            &replica.database,
+            &replica.notifier_mx,
+            &replica.changes_mx,
+            false,
+            WireFormat::QueryString,
+            fake_payload!("priority=1?stale"))).unwrap();
+
+        // bootstrap
+        let snapshot = block_on(snapshot_handle(&primary.store_mx, &primary.changes_mx)).unwrap();
+        assert_eq!(3, snapshot.seq);
+        assert_eq!(2, snapshot.msgs.len());
+        block_on(reset(&replica.store_mx, &replica.database, &replica.file_storage_op, &replica.changes_mx)).unwrap();
+        for snapshot_msg in snapshot.msgs.iter() {
+            copy_msg(&primary, &replica, Uuid::from_string(&snapshot_msg.uuid).unwrap());
+        }
+
+        // follow the change feed
+        add("priority=1?bar");
+        block_on(rm_handle(&primary.store_mx, &primary.database, &primary.file_storage_op, &primary.stats_mx, &primary.changes_mx, foo.clone())).unwrap();
+        let batch = primary.changes_mx.lock().unwrap().read(snapshot.seq, 10).unwrap();
+        for entry in batch.changes {
+            match entry.change {
+                Change::Insert { uuid, .. } => copy_msg(&primary, &replica, uuid),
+                Change::Delete { uuid } => block_on(remove(&replica.store_mx, &replica.database, &replica.file_storage_op, &replica.stats_mx, &replica.changes_mx, uuid, false)).unwrap(),
+                change => panic!("Unexpected change {:?}", change)
+            }
+        }
+
+        let primary_msgs = block_on(primary.database.fetch()).unwrap();
+        let replica_msgs = block_on(replica.database.fetch()).unwrap();
+        assert_eq!(primary_msgs, replica_msgs);
+        assert_eq!(2, replica_msgs.len());
+        assert!(replica.file_storage_op.as_ref().unwrap().lock().unwrap().index.contains(&file));
+        let mut contents = String::new();
+        get_buffer(&replica.file_storage_op.as_ref().unwrap().lock().unwrap(), &file).unwrap().0.read_to_string(&mut contents).unwrap();
+        assert_eq!("file", contents);
+        assert_eq!(replica.store_mx.lock().unwrap().byte_size, primary.store_mx.lock().unwrap().byte_size);
+    }
+
+    #[test]
+    fn should_keep_the_replica_as_it_was_when_a_change_can_not_be_saved() {
+        let replica_dir = TempDir::new("should_keep_the_replica_as_it_was_when_a_change_can_not_be_saved").unwrap();
+        let mut replica = Node::new(&replica_dir);
+        replica.database = Box::new(BlockingDb::new(Box::new(UncommittableDb(MemDb::new()))));
+        let uuid = {
+            let mut store = replica.store_mx.lock().unwrap();
+            store.max_byte_size = Some(3);
+            store.add(1, 3).unwrap().uuid
+        };
+
+        // the insert would prune the msg
+        let new_uuid = Uuid::from_string("1-1-0-1").unwrap();
+        let inserted = block_on(insert(
+            &replica.store_mx,
+            &replica.database,
+            &replica.file_storage_op,
+            &replica.stats_mx,
+            &replica.notifier_mx,
+            &replica.changes_mx,
+            new_uuid,
+            3,
+            Bytes::from("foo"),
+            None::<FakePayload>));
+        assert!(matches!(inserted.err().unwrap().err_ty, ErrTy::DatabaseError(_)));
+
+        let removed = block_on(remove(&replica.store_mx, &replica.database, &replica.file_storage_op, &replica.stats_mx, &replica.changes_mx, uuid.clone(), false));
+        assert!(matches!(removed.err().unwrap().err_ty, ErrTy::DatabaseError(_)));
+
+        assert_eq!(vec![uuid], replica.store_mx.lock().unwrap().id_to_group_map.keys().cloned().collect::<Vec<Arc<Uuid>>>());
+        assert_eq!(3, replica.store_mx.lock().unwrap().byte_size);
+        let stats = replica.stats_mx.lock().unwrap();
+        assert_eq!((0, 0, 0), (stats.inserted, stats.pruned, stats.deleted));
+        assert_eq!(1, replica.changes_mx.lock().unwrap().next_seq());
+    }
+}
@@ -0,0 +1,125 @@
+use bytes::Bytes;
+use crate::Database;
+use crate::file_storage::{get_buffer, FileReader, FileStorage, FileStorageError};
+use msg_store::Store;
+use msg_store_database_plugin::DatabaseError;
+use msg_store_uuid::Uuid;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum ErrTy {
+    DatabaseError(DatabaseError),
+    FileStorageError(FileStorageError),
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DatabaseError(err) => write!(f, "({})", err),
+            Self::FileStorageError(err) => write!(f, "({})", err),
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_REPLICA_MSG_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_REPLICA_MSG_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+/// A msg as it is held by the store, the database and file storage
+pub struct ReplicaMsg {
+    pub uuid: Arc<Uuid>,
+    pub byte_size: u64,
+    /// The value held in the database, the headers of the msg when it is held in a file
+    pub stored: Bytes,
+    /// The contents of the file holding the msg and their length
+    pub file: Option<(FileReader, u64)>
+}
+
+/// Gets a msg exactly as it is stored, so that a replica can store it the same way
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    database: &Database,
+    file_storage_option: &Option<Mutex<FileStorage>>,
+    uuid: Arc<Uuid>
+) -> Result<Option<ReplicaMsg>, ApiError> {
+    let (byte_size, stored, file) = {
+        let store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let byte_size = match store.id_to_group_map.get(&uuid) {
+            Some(priority) => match store.groups_map.get(priority) {
+                Some(group) => match group.msgs_map.get(&uuid) {
+                    Some(byte_size) => *byte_size,
+                    None => return Ok(None)
+                },
+                None => return Ok(None)
+            },
+            None => return Ok(None)
+        };
+        let stored = database.get(uuid.clone());
+        let file = match file_storage_option {
+            Some(file_storage_mutex) => {
+                let file_storage = match file_storage_mutex.lock() {
+                    Ok(gaurd) => Ok(gaurd),
+                    Err(err) => Err(api_error!(ErrTy::LockingError, err))
+                }?;
+                if file_storage.index.contains(&uuid) {
+                    match get_buffer(&file_storage, &uuid) {
+                        Ok(file) => Some(file),
+                        Err(err) => return Err(api_error!(ErrTy::FileStorageError(err)))
+                    }
+                } else {
+                    None
+                }
+            },
+            None => None
+        };
+        (byte_size, stored, file)
+    };
+    let stored = match stored.await {
+        Ok(stored) => Ok(stored),
+        Err(err) => Err(api_error!(ErrTy::DatabaseError(err)))
+    }?;
+    Ok(Some(ReplicaMsg {
+        uuid,
+        byte_size,
+        stored,
+        file
+    }))
+}
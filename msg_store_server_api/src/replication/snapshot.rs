@@ -0,0 +1,134 @@
+use crate::changes::ChangeFeed;
+use crate::consumer_group::get::ConsumerGroup;
+use msg_store::Store;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub enum ErrTy {
+    LockingError
+}
+impl Display for ErrTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LockingError => write!(f, "{:#?}", self)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub err_ty: ErrTy,
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: Option<String>
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(msg) = &self.msg {
+            write!(f, "GET_SNAPSHOT_ERROR: {}. file: {}, line: {}, msg: {}", self.err_ty, self.file, self.line, msg)
+        } else {
+            write!(f, "GET_SNAPSHOT_ERROR: {}. file: {}, line: {}.", self.err_ty, self.file, self.line)
+        }
+    }   
+}
+
+macro_rules! api_error {
+    ($err_ty:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: None
+        }
+    };
+    ($err_ty:expr, $msg:expr) => {
+        ApiError {
+            err_ty: $err_ty,
+            file: file!(),
+            line: line!(),
+            msg: Some($msg.to_string())
+        }
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotGroup {
+    pub priority: u16,
+    pub max_byte_size: Option<u64>
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotMsg {
+    pub uuid: String,
+    pub byte_size: u64
+}
+
+/// The state of the store at a point in the change feed
+///
+/// Only the uuids of the msgs are included, the msgs themselves are fetched one by one.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    /// The sequence number of the first change that is not part of the snapshot
+    pub seq: u64,
+    pub max_byte_size: Option<u64>,
+    pub groups: Vec<SnapshotGroup>,
+    pub consumer_groups: Vec<ConsumerGroup>,
+    pub msgs: Vec<SnapshotMsg>
+}
+
+pub async fn handle(
+    store_mutex: &Mutex<Store>,
+    changes_mutex: &Mutex<ChangeFeed>
+) -> Result<Snapshot, ApiError> {
+    let store = match store_mutex.lock() {
+        Ok(gaurd) => Ok(gaurd),
+        Err(err) => Err(api_error!(ErrTy::LockingError, err))
+    }?;
+    // every change is recorded while the store is locked, so the sequence number matches the store
+    let seq = match changes_mutex.lock() {
+        Ok(changes) => changes.next_seq(),
+        Err(err) => return Err(api_error!(ErrTy::LockingError, err))
+    };
+    let groups = store
+        .group_defaults
+        .iter()
+        .map(|(priority, defaults)| SnapshotGroup {
+            priority: *priority,
+            max_byte_size: defaults.max_byte_size
+        })
+        .collect::<Vec<SnapshotGroup>>();
+    let consumer_groups = store
+        .consumer_groups
+        .iter()
+        .map(|(name, consumer_group)| ConsumerGroup {
+            name: name.clone(),
+            cursors: consumer_group
+                .to_cursors()
+                .iter()
+                .map(|uuid| uuid.to_string())
+                .collect::<Vec<String>>()
+        })
+        .collect::<Vec<ConsumerGroup>>();
+    let msgs = store
+        .groups_map
+        .values()
+        .flat_map(|group| group.msgs_map.iter())
+        .map(|(uuid, byte_size)| SnapshotMsg {
+            uuid: uuid.to_string(),
+            byte_size: *byte_size
+        })
+        .collect::<Vec<SnapshotMsg>>();
+    Ok(Snapshot {
+        seq,
+        max_byte_size: store.max_byte_size,
+        groups,
+        consumer_groups,
+        msgs
+    })
+}
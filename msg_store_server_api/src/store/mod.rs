@@ -6,15 +6,19 @@ pub mod tests {
     
     use bytes::Bytes;
     use crate::{stats::Stats, fake_payload};
+    use crate::changes::{ChangeFeed, DEFAULT_CAPACITY};
+    use crate::notify::Notifier;
     use crate::config::StoreConfig;
     use crate::file_storage::FileStorage;
     use crate::store::set::handle as set_handle;
     use crate::store::get::handle as get_handle;
     use crate::msg::tests::FakePayload;
     use crate::msg::add::handle as add_handle;
+    use crate::msg::frame::WireFormat;
     use futures::executor::block_on;
     use msg_store::Store;
-    use msg_store_database_plugin::Db;
+    use crate::Database;
+    use msg_store_database_plugin::BlockingDb;
     use msg_store_database_in_memory_plugin::MemDb;
     use std::fs::read_to_string;
     use std::sync::Mutex;
@@ -23,9 +27,11 @@ pub mod tests {
     #[test]
     pub fn should_put_defaults_in_store() {
 
-        let store_mx = Mutex::new(Store::new(None).unwrap());
-        let database_mx: Mutex<Box<dyn Db>> = Mutex::new(Box::new(MemDb::new()));
+        let store_mx = Mutex::new(Store::new(None, None).unwrap());
+        let database: Database = Box::new(BlockingDb::new(Box::new(MemDb::new())));
         let stats_mx = Mutex::new(Stats::new());
+        let notifier_mx = Mutex::new(Notifier::new());
+        let changes_mx = Mutex::new(ChangeFeed::new(DEFAULT_CAPACITY));
         let config_mx = Mutex::new(StoreConfig::new());
         let config_dir = TempDir::new("should_put_defaults_in_store-config-path").unwrap();
         let config_path = {
@@ -39,9 +45,10 @@ pub mod tests {
         // set group config
         block_on(set_handle(
             &store_mx,
-            &database_mx,
+            &database,
             &file_storage_op,
             &stats_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             Some(10)
@@ -59,7 +66,11 @@ pub mod tests {
             &store_mx,
             &file_storage_op,
             &stats_mx,
-            &database_mx,
+            &database,
+            &notifier_mx,
+            &changes_mx,
+            false,
+            WireFormat::QueryString,
             fake_payload
         )).unwrap();
 
@@ -83,9 +94,10 @@ pub mod tests {
         // should prune msg in store
         block_on(set_handle(
             &store_mx,
-            &database_mx,
+            &database,
             &file_storage_op,
             &stats_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             Some(3)
@@ -95,8 +107,7 @@ pub mod tests {
             let store = store_mx.lock().unwrap();
             assert_eq!(store.max_byte_size.unwrap(), 3);
             assert_eq!(store.byte_size, 0);
-            let mut database = database_mx.lock().unwrap();
-            assert_eq!(database.fetch().unwrap().len(), 0);
+            assert_eq!(block_on(database.fetch()).unwrap().len(), 0);
             let stats = stats_mx.lock().unwrap();
             assert_eq!(stats.pruned, 1);
             let config_json: StoreConfig = serde_json::from_str(&read_to_string(&config_path.as_path()).unwrap()).unwrap();
@@ -105,9 +116,10 @@ pub mod tests {
 
         block_on(set_handle(
             &store_mx,
-            &database_mx,
+            &database,
             &file_storage_op,
             &stats_mx,
+            &changes_mx,
             &config_mx,
             &Some(config_path.to_path_buf()),
             None
@@ -1,15 +1,17 @@
 use msg_store::{Store, StoreDefaults, StoreError};
-use msg_store_database_plugin::DatabaseError;
+use msg_store_database_plugin::{Batch, DatabaseError};
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::sync::Mutex;
 use crate::Database;
+use crate::changes::{Change, ChangeFeed, ChangeFeedError};
 use crate::config::{StoreConfig, update_config, ConfigError};
 use crate::file_storage::{FileStorage, rm_from_file_storage, FileStorageError};
 use crate::stats::Stats;
 
 #[derive(Debug)]
 pub enum ErrTy {
+    ChangeFeedError(ChangeFeedError),
     ConfigError(ConfigError),
     DatabaseError(DatabaseError),
     FileStorageError(FileStorageError),
@@ -19,6 +21,7 @@ pub enum ErrTy {
 impl Display for ErrTy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::ChangeFeedError(err) => write!(f, "({})", err),
             Self::ConfigError(err) => write!(f, "({})", err),
             Self::DatabaseError(err) => write!(f, "({})", err),
             Self::FileStorageError(err) => write!(f, "({})", err),
@@ -65,63 +68,72 @@ macro_rules! api_error {
     };
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle(
     store_mutex: &Mutex<Store>,
-    database_mx: &Mutex<Database>,
+    database: &Database,
     file_storage_option: &Option<Mutex<FileStorage>>,
     stats_mutex: &Mutex<Stats>,
+    changes_mutex: &Mutex<ChangeFeed>,
     store_config_mutex: &Mutex<StoreConfig>,
     store_config_path_option: &Option<PathBuf>,
     max_byte_size: Option<u64>
 ) -> Result<(), ApiError> {
-    let mut store = match store_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut database = match database_mx.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut stats = match stats_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let mut config = match store_config_mutex.lock() {
-        Ok(gaurd) => Ok(gaurd),
-        Err(err) => Err(api_error!(ErrTy::LockingError, err))
-    }?;
-    let (prune_count, pruned_uuids) = {
-        store.max_byte_size = max_byte_size;
-        let defaults = StoreDefaults {
-            max_byte_size,
-        };
-        match store.update_store_defaults(&defaults) {
-            Ok((_bytes_removed, _groups_removed, msgs_removed)) => Ok((msgs_removed.len() as u64, msgs_removed)),
-            Err(err) => Err(api_error!(ErrTy::StoreError(err)))
-        }
-    }?;
-    {
-        for uuid in &pruned_uuids {
-            if let Err(err) = database.del(uuid.clone()) {
-                return Err(api_error!(ErrTy::DatabaseError(err)))
+    let (pruned_uuids, committed) = {
+        let mut store = match store_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut stats = match stats_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let (prune_count, pruned_uuids) = {
+            store.max_byte_size = max_byte_size;
+            let defaults = StoreDefaults {
+                max_byte_size,
+            };
+            match store.update_store_defaults(&defaults) {
+                Ok((_bytes_removed, _groups_removed, msgs_removed)) => Ok((msgs_removed.len() as u64, msgs_removed)),
+                Err(err) => Err(api_error!(ErrTy::StoreError(err)))
             }
+        }?;
+        let mut batch = Batch::new();
+        for uuid in &pruned_uuids {
+            batch.del(uuid.clone());
         }
+        let committed = database.commit(batch);
+        stats.pruned += prune_count;
+        let mut changes = match changes_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
+        let mut recorded_changes = vec![Change::StoreDefaults { max_byte_size }];
+        recorded_changes.extend(pruned_uuids.iter().map(|uuid| Change::Prune { uuid: uuid.clone() }));
+        if let Err(err) = changes.record_all(recorded_changes) {
+            return Err(api_error!(ErrTy::ChangeFeedError(err)))
+        }
+        (pruned_uuids, committed)
+    };
+    if let Err(err) = committed.await {
+        return Err(api_error!(ErrTy::DatabaseError(err)))
     }
     if let Some(file_storage_mutex) = file_storage_option {
         let mut file_storage = match file_storage_mutex.lock() {
             Ok(gaurd) => Ok(gaurd),
             Err(err) => Err(api_error!(ErrTy::LockingError, err))
         }?;
-        for uuid in pruned_uuids {
-            if let Err(err) = rm_from_file_storage(&mut file_storage, &uuid) {
+        for uuid in pruned_uuids.iter() {
+            if let Err(err) = rm_from_file_storage(&mut file_storage, uuid) {
                 return Err(api_error!(ErrTy::FileStorageError(err)))
             }
         }
     }
     {
-        stats.pruned += prune_count;
-    }
-    {
+        let mut config = match store_config_mutex.lock() {
+            Ok(gaurd) => Ok(gaurd),
+            Err(err) => Err(api_error!(ErrTy::LockingError, err))
+        }?;
         config.max_byte_size = max_byte_size;
         if let Err(err) = update_config(&mut config, store_config_path_option) {
             return Err(api_error!(ErrTy::ConfigError(err)))
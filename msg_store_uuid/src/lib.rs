@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
-use std::fmt::Display;
+use std::fmt::{Debug, Display};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::time::{
     SystemTime,
     UNIX_EPOCH
@@ -12,6 +13,7 @@ pub enum UuidErrorTy {
     InvalidTimestamp,
     InvalidSequence,
     InvalidNodeId,
+    InvalidVersion,
     InvalidFormat
 }
 impl Display for UuidErrorTy {
@@ -21,6 +23,7 @@ impl Display for UuidErrorTy {
             Self::InvalidTimestamp |
             Self::InvalidSequence |
             Self::InvalidNodeId |
+            Self::InvalidVersion |
             Self::InvalidFormat => write!(f, "{:#?}", self)
         }
     }
@@ -62,19 +65,44 @@ macro_rules! uuid_error {
     };
 }
 
+/// The format of a uuid
+///
+/// Uuids keep the format they were given in, so the ids of msgs stored by older versions
+/// still parse and are written back the same way.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum UuidVersion {
+    /// priority-timestamp-sequence-node_id, with the timestamp in seconds
+    V1,
+    /// v2-priority-timestamp-sequence-node_id, with the timestamp in microseconds
+    V2
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Uuid {
     pub priority: u16,
+    /// Since the unix epoch, in seconds for V1 and microseconds for V2
     pub timestamp: u64,
     pub sequence: u32,
-    pub node_id: u16
+    pub node_id: u16,
+    pub version: UuidVersion
 }
 impl Uuid {
     pub fn to_string(&self) -> String {
-        format!("{}-{}-{}-{}", self.priority, self.timestamp, self.sequence, self.node_id)
+        match self.version {
+            UuidVersion::V1 => format!("{}-{}-{}-{}", self.priority, self.timestamp, self.sequence, self.node_id),
+            UuidVersion::V2 => format!("v2-{}-{}-{}-{}", self.priority, self.timestamp, self.sequence, self.node_id)
+        }
     }
     pub fn from_string(id: &str) -> Result<Arc<Uuid>, UuidError> {
-        let split_str = id.split("-").collect::<Vec<&str>>();
+        let mut split_str = id.split("-").collect::<Vec<&str>>();
+        let version = if split_str[0].starts_with('v') {
+            match split_str.remove(0) {
+                "v2" => UuidVersion::V2,
+                version => return Err(uuid_error!(UuidErrorTy::InvalidVersion, version))
+            }
+        } else {
+            UuidVersion::V1
+        };
         if split_str.len() != 4 {
             return Err(uuid_error!(UuidErrorTy::InvalidFormat));
         }
@@ -106,35 +134,30 @@ impl Uuid {
             priority,
             timestamp,
             sequence,
-            node_id
+            node_id,
+            version
         }))
     }
+    /// The timestamp in microseconds, whatever the version
+    pub fn timestamp_micros(&self) -> u128 {
+        match self.version {
+            UuidVersion::V1 => self.timestamp as u128 * 1_000_000,
+            UuidVersion::V2 => self.timestamp as u128
+        }
+    }
 }
 
 impl PartialOrd for Uuid {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.priority > other.priority {
-            Some(Ordering::Greater)
-        } else if self.priority < other.priority {
-            Some(Ordering::Less)
-        } else {
-            if self.timestamp < other.timestamp {
-                Some(Ordering::Greater)
-            } else if self.timestamp > other.timestamp {
-                Some(Ordering::Less)
-            } else {
-                if self.sequence < other.sequence {
-                    Some(Ordering::Greater)
-                } else if self.sequence > other.sequence {
-                    Some(Ordering::Less)
-                } else {
-                    Some(Ordering::Equal)
-                }
-            }
-        }
+        Some(self.cmp(other))
     }
 }
 
+/// Higher priorities are greater, then older uuids are greater
+///
+/// Uuids from different nodes can share a priority, timestamp and sequence, the lower
+/// node_id is then greater so that msgs from every node are kept apart. Timestamps of
+/// every version are compared in microseconds.
 impl Ord for Uuid {
     fn cmp(&self, other: &Self) -> Ordering {
         if self.priority > other.priority {
@@ -142,9 +165,10 @@ impl Ord for Uuid {
         } else if self.priority < other.priority {
             Ordering::Less
         } else {
-            if self.timestamp < other.timestamp {
+            let (timestamp, other_timestamp) = (self.timestamp_micros(), other.timestamp_micros());
+            if timestamp < other_timestamp {
                 Ordering::Greater
-            } else if self.timestamp > other.timestamp {
+            } else if timestamp > other_timestamp {
                 Ordering::Less
             } else {
                 if self.sequence < other.sequence {
@@ -152,7 +176,7 @@ impl Ord for Uuid {
                 } else if self.sequence > other.sequence {
                     Ordering::Less
                 } else {
-                    Ordering::Equal
+                    other.node_id.cmp(&self.node_id).then(other.version.cmp(&self.version))
                 }
             }
         }
@@ -207,49 +231,135 @@ macro_rules! uuid_manager_error {
     };
 }
 
+/// The source of the time for uuids
+///
+/// The system clock is used unless another clock is given, e.g. a `ManualClock` in tests.
+pub trait Clock: Debug + Send + Sync {
+    /// The time since the unix epoch, in microseconds
+    fn now_micros(&self) -> Result<u64, UuidManagerError>;
+}
+
+/// Reads the time from the system
+#[derive(Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now_micros(&self) -> Result<u64, UuidManagerError> {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => Ok(duration.as_micros() as u64),
+            Err(error) => Err(uuid_manager_error!(UuidManagerErrorTy::SystemTimeError, error))
+        }
+    }
+}
+
+/// A clock that only moves when it is told to
+///
+/// # Example
+/// ```
+/// use msg_store_uuid::{ManualClock, UuidManager};
+/// use std::sync::Arc;
+///
+/// let clock = Arc::new(ManualClock::new(1_000));
+/// let mut uuid_manager = UuidManager::new(None, Some(clock.clone())).unwrap();
+/// assert_eq!(1_000, uuid_manager.next(1).unwrap().timestamp);
+/// clock.advance(5);
+/// assert_eq!(1_005, uuid_manager.next(1).unwrap().timestamp);
+///
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    micros: AtomicU64
+}
+impl ManualClock {
+    pub fn new(micros: u64) -> ManualClock {
+        ManualClock { micros: AtomicU64::new(micros) }
+    }
+    pub fn set(&self, micros: u64) {
+        self.micros.store(micros, AtomicOrdering::SeqCst);
+    }
+    pub fn advance(&self, micros: u64) {
+        self.micros.fetch_add(micros, AtomicOrdering::SeqCst);
+    }
+}
+impl Clock for ManualClock {
+    fn now_micros(&self) -> Result<u64, UuidManagerError> {
+        Ok(self.micros.load(AtomicOrdering::SeqCst))
+    }
+}
+
+/// Gives V2 uuids that only ever increase in time
+///
+/// When the clock goes backwards, e.g. when it is set by NTP, the last timestamp is kept and
+/// the sequence counts on until the clock catches up. When the sequence runs out, the
+/// timestamp is moved on by a microsecond.
 #[derive(Debug)]
 pub struct UuidManager {
+    /// The timestamp of the last uuid, in microseconds
     pub timestamp: u64,
     pub sequence: u32,
-    pub node_id: u16
+    pub node_id: u16,
+    pub clock: Arc<dyn Clock>
 }
 impl UuidManager {
     pub fn default() -> Result<UuidManager, UuidManagerError> {
-        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
-            Ok(duration) => Ok(duration.as_secs()),
-            Err(error) => Err(uuid_manager_error!(UuidManagerErrorTy::SystemTimeError, error))
-        }?;
-        Ok(UuidManager {
-            timestamp,
-            sequence: 1,
-            node_id: 0
-        })
+        UuidManager::new(None, None)
     }
-    pub fn new(node_id: Option<u16>) -> Result<UuidManager, UuidManagerError> {
+    /// Creates a uuid manager, reading the time from the system clock if no clock is given
+    pub fn new(node_id: Option<u16>, clock: Option<Arc<dyn Clock>>) -> Result<UuidManager, UuidManagerError> {
         let node_id = match node_id {
             Some(node_id) => node_id,
             None => 0
         };
-        let mut manager = UuidManager::default()?;
-        manager.node_id = node_id;
-        Ok(manager)
+        let clock: Arc<dyn Clock> = match clock {
+            Some(clock) => clock,
+            None => Arc::new(SystemClock)
+        };
+        // the first uuid takes the timestamp of the clock
+        let timestamp = clock.now_micros()?.saturating_sub(1);
+        Ok(UuidManager {
+            timestamp,
+            sequence: 1,
+            node_id,
+            clock
+        })
     }
     pub fn next(&mut self, priority: u16) -> Result<Arc<Uuid>, UuidManagerError> {
-        let current_timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
-            Ok(duration) => Ok(duration.as_secs()),
-            Err(error) => Err(uuid_manager_error!(UuidManagerErrorTy::SystemTimeError, error))
-        }?;
-        if current_timestamp != self.timestamp {
-            self.timestamp = current_timestamp;
-            self.sequence = 1;
-        } else {
-            self.sequence += 1;
-        }
+        let current_timestamp = self.clock.now_micros()?;
+        self.advance(current_timestamp);
         Ok(Arc::new(Uuid {
             priority,
             timestamp: self.timestamp,
             sequence: self.sequence,
-            node_id: self.node_id           
+            node_id: self.node_id,
+            version: UuidVersion::V2
         }))
     }
+    /// Resumes after a uuid given before a restart, so that it is never given again
+    ///
+    /// The uuids of msgs that are loaded from a database are passed on start up. Uuids that
+    /// are older than the last uuid given are ignored.
+    pub fn resume_after(&mut self, uuid: &Uuid) {
+        let timestamp = match u64::try_from(uuid.timestamp_micros()) {
+            Ok(timestamp) => timestamp,
+            Err(_) => return
+        };
+        if (timestamp, uuid.sequence) > (self.timestamp, self.sequence) {
+            self.timestamp = timestamp;
+            self.sequence = uuid.sequence;
+        }
+    }
+    /// Moves on to the next timestamp and sequence given the current time
+    pub fn advance(&mut self, current_timestamp: u64) {
+        if current_timestamp > self.timestamp {
+            self.timestamp = current_timestamp;
+            self.sequence = 1;
+        } else {
+            match self.sequence.checked_add(1) {
+                Some(sequence) => self.sequence = sequence,
+                None => {
+                    self.timestamp += 1;
+                    self.sequence = 1;
+                }
+            }
+        }
+    }
 }